@@ -1,30 +1,41 @@
-use bitcoin::Amount;
-use serde::Deserialize;
-use std::{str::FromStr, sync::Arc};
+use awc::http::{header, StatusCode};
+use bitcoin::{Amount, Script, ScriptBuf, Txid};
+use bitcoincore_rpc::{json, RpcApi};
+use serde::{de::DeserializeOwned, Deserialize};
+use std::{fmt, str::FromStr, sync::Arc, time::Duration};
 
 use crate::{
-    config::BtcUtxoProvider,
+    config::{BTCConfig, BtcUtxoProvider},
     db::{BtcBalance, BtcUtxo, Repo},
+    tx::fee::FeePriority,
 };
 
 #[derive(Clone)]
 pub enum UtxoClient {
     Local(Arc<Repo>),
     CryptoApis(CryptoApisClient),
+    BitcoindScan(BitcoindScanClient),
 }
 
 impl UtxoClient {
-    pub fn new(cfg: BtcUtxoProvider, db: Arc<Repo>) -> Self {
-        match cfg.mode.as_str() {
-            "cryptoapis" => Self::CryptoApis(CryptoApisClient::new(&cfg.api_key)),
-            _ => Self::Local(db),
+    pub fn new(cfg: &BTCConfig, db: Arc<Repo>) -> anyhow::Result<Self> {
+        let provider = &cfg.utxo_provider;
+        match provider.mode.as_str() {
+            BtcUtxoProvider::CRYPTOAPIS => Ok(Self::CryptoApis(CryptoApisClient::new(
+                &provider.api_key,
+                cfg.get_network(),
+            )?)),
+            BtcUtxoProvider::BITCOIND => Ok(Self::BitcoindScan(BitcoindScanClient::new(cfg)?)),
+            _ => Ok(Self::Local(db)),
         }
     }
 
-    pub async fn get_fee(&self) -> anyhow::Result<u64> {
+    /// sats/byte for a confirmation within `blocks`
+    pub async fn get_fee_for_target(&self, blocks: u16) -> anyhow::Result<u64> {
         match self {
             Self::Local(_db) => Ok(37),
-            Self::CryptoApis(ca_client) => ca_client.get_fee().await,
+            Self::CryptoApis(ca_client) => ca_client.get_fee_for_target(blocks).await,
+            Self::BitcoindScan(node) => node.get_fee_for_target(blocks).await,
         }
     }
 
@@ -32,6 +43,7 @@ impl UtxoClient {
         match self {
             Self::Local(db) => Ok(db.get_btc_balance(address).await?),
             Self::CryptoApis(ca_client) => ca_client.get_balance(address).await,
+            Self::BitcoindScan(node) => node.get_balance(address).await,
         }
     }
 
@@ -46,50 +58,219 @@ impl UtxoClient {
                 .select_btc_utxo_with_pagination(Some(address.to_owned()), "ASC", limit, offset)
                 .await?),
             Self::CryptoApis(ca_client) => ca_client.get_utxo(address, limit, offset).await,
+            Self::BitcoindScan(node) => node.get_utxo(address, limit, offset).await,
         }
     }
 }
 
+/// Reads utxos straight from the node with `scantxoutset`.
+///
+/// It needs neither txindex nor a wallet, but each call scans the whole utxo set,
+/// which takes a while, so `btc.rpc_timeout` should be raised (120+ seconds),
+/// and the node allows only one scan at a time, so the scans of all clones are serialized.
+#[derive(Clone)]
+pub struct BitcoindScanClient {
+    rpc: Arc<bitcoincore_rpc::Client>,
+    net: bitcoin::Network,
+    scan_lock: Arc<tokio::sync::Mutex<()>>,
+}
+
+impl BitcoindScanClient {
+    pub fn new(cfg: &BTCConfig) -> anyhow::Result<Self> {
+        Ok(Self {
+            rpc: Arc::new(cfg.rpc_client()?),
+            net: cfg.get_network(),
+            scan_lock: Arc::new(tokio::sync::Mutex::new(())),
+        })
+    }
+
+    // returns sats/byte
+    pub async fn get_fee_for_target(&self, blocks: u16) -> anyhow::Result<u64> {
+        let rpc = self.rpc.clone();
+        let res =
+            tokio::task::spawn_blocking(move || rpc.estimate_smart_fee(blocks, None)).await??;
+        let Some(fee_rate) = res.fee_rate else {
+            anyhow::bail!("node can't estimate fee: errors={:?}", res.errors)
+        };
+
+        // fee_rate is per kvB
+        Ok((fee_rate.to_sat() + 999) / 1000)
+    }
+
+    pub async fn get_balance(&self, address: &str) -> anyhow::Result<BtcBalance> {
+        let unspents = self.scan(address).await?;
+        let balance: u64 = unspents.iter().map(|u| u.amount.to_sat()).sum();
+
+        Ok(BtcBalance {
+            address: address.to_owned(),
+            id: 0,
+            balance: balance as i64,
+        })
+    }
+
+    pub async fn get_utxo(
+        &self,
+        address: &str,
+        limit: i32,
+        offset: i32,
+    ) -> anyhow::Result<Vec<BtcUtxo>> {
+        let mut unspents = self.scan(address).await?;
+        unspents.sort_by_key(|u| (u.height, u.txid, u.vout));
+
+        let result = unspents
+            .iter()
+            .skip(offset.max(0) as usize)
+            .take(limit.max(0) as usize)
+            .map(|u| BtcUtxo {
+                id: 0,
+                block: u.height as i64,
+                tx_id: 0,
+                tx_hash: u.txid.to_string(),
+                output_n: u.vout as i32,
+                address: address.to_owned(),
+                pk_script: u.script_pub_key.to_hex_string(),
+                amount: u.amount.to_sat() as i64,
+                spend: false,
+                spent_block: None,
+                coinbase: u.coinbase,
+            })
+            .collect();
+        Ok(result)
+    }
+
+    async fn scan(&self, address: &str) -> anyhow::Result<Vec<ScannedUtxo>> {
+        let address = bitcoin::Address::from_str(address)?.require_network(self.net)?;
+        let rpc = self.rpc.clone();
+        let descriptor = json::ScanTxOutRequest::Single(format!("addr({})", address));
+        let args = [
+            serde_json::Value::from("start"),
+            serde_json::to_value([descriptor])?,
+        ];
+
+        // a second scan would be refused by the node while one is running
+        let _scan = self.scan_lock.lock().await;
+        let res: ScanResult =
+            tokio::task::spawn_blocking(move || rpc.call("scantxoutset", &args)).await??;
+        Ok(res.unspents)
+    }
+}
+
+/// `scantxoutset` result, read directly as `json::Utxo` lacks `coinbase`
+#[derive(Debug, Deserialize)]
+struct ScanResult {
+    unspents: Vec<ScannedUtxo>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ScannedUtxo {
+    txid: Txid,
+    vout: u32,
+    script_pub_key: ScriptBuf,
+    #[serde(with = "bitcoin::amount::serde::as_btc")]
+    amount: Amount,
+    height: u64,
+    /// reported since bitcoind 25
+    #[serde(default)]
+    coinbase: bool,
+}
+
+const CRYPTOAPIS_URL: &str = "https://rest.cryptoapis.io/blockchain-data/bitcoin";
+/// retries of a request which failed to send or was answered with 429 or 5xx
+const MAX_RETRIES: u32 = 3;
+/// delay before the first retry, doubled on each next one unless the api sends `Retry-After`
+const RETRY_BASE_DELAY: Duration = Duration::from_millis(500);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(30);
+
+/// returned by `CryptoApisClient` when a request keeps failing after the retries
+#[derive(Debug)]
+pub enum CryptoApisError {
+    Request {
+        attempts: u32,
+        error: String,
+    },
+    Status {
+        attempts: u32,
+        status: u16,
+        body: String,
+    },
+}
+
+impl fmt::Display for CryptoApisError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Request { attempts, error } => write!(
+                f,
+                "cryptoapis request failed after {} attempts: {}",
+                attempts, error
+            ),
+            Self::Status {
+                attempts,
+                status,
+                body,
+            } => write!(
+                f,
+                "cryptoapis responded with {} after {} attempts: {}",
+                status, attempts, body
+            ),
+        }
+    }
+}
+
+impl std::error::Error for CryptoApisError {}
+
 #[derive(Clone)]
 pub struct CryptoApisClient {
     api_key: String,
+    net: bitcoin::Network,
+    base_url: String,
+    max_retries: u32,
+    retry_base_delay: Duration,
 }
 
 impl CryptoApisClient {
-    pub fn new(api_key: &str) -> Self {
-        Self {
+    pub fn new(api_key: &str, net: bitcoin::Network) -> anyhow::Result<Self> {
+        Ok(Self {
             api_key: api_key.to_owned(),
-        }
+            net,
+            base_url: Self::base_url(net)?,
+            max_retries: MAX_RETRIES,
+            retry_base_delay: RETRY_BASE_DELAY,
+        })
+    }
+
+    /// blockchain data api of the network, cryptoapis serves only mainnet and testnet
+    pub fn base_url(net: bitcoin::Network) -> anyhow::Result<String> {
+        let network = match net {
+            bitcoin::Network::Bitcoin => "mainnet",
+            bitcoin::Network::Testnet => "testnet",
+            _ => anyhow::bail!("cryptoapis doesn't support the {} network", net),
+        };
+        Ok(format!("{}/{}", CRYPTOAPIS_URL, network))
+    }
+
+    pub fn with_base_url(mut self, base_url: &str) -> Self {
+        self.base_url = base_url.trim_end_matches('/').to_owned();
+        self
     }
+
+    pub fn with_retries(mut self, max_retries: u32, base_delay: Duration) -> Self {
+        self.max_retries = max_retries;
+        self.retry_base_delay = base_delay;
+        self
+    }
+
     // returns sats/byte
-    pub async fn get_fee(&self) -> anyhow::Result<u64> {
-        let client = awc::Client::default();
-        let url =
-            "https://rest.cryptoapis.io/blockchain-data/bitcoin/mainnet/mempool/fees?context=rdx";
-        let mut resp = client
-            .get(url)
-            .insert_header(("X-Api-Key", self.api_key.clone()))
-            .send()
-            .await
-            .unwrap();
-
-        let val = resp.json::<FeeRootResult>().await?;
-        let btc_per_byte = val.data.item.fast;
-        let fee = bitcoin::Amount::from_btc(f64::from_str(&btc_per_byte)?)?;
+    pub async fn get_fee_for_target(&self, blocks: u16) -> anyhow::Result<u64> {
+        let val: FeeRootResult = self.get_json("/mempool/fees?context=rdx").await?;
+        let btc_per_byte = val.data.item.for_target(blocks);
+        let fee = bitcoin::Amount::from_btc(f64::from_str(btc_per_byte)?)?;
         Ok(fee.to_sat())
     }
 
     pub async fn get_balance(&self, address: &str) -> anyhow::Result<BtcBalance> {
-        let client = awc::Client::default();
-        let url = format!("https://rest.cryptoapis.io/blockchain-data/bitcoin/mainnet/addresses/{}/balance?context=rdx", address);
-        let mut resp = client
-            .get(&url)
-            .insert_header(("X-Api-Key", self.api_key.clone()))
-            .send()
-            .await
-            .unwrap();
-
-        let val = resp.json::<BalanceResponse>().await?;
+        let path = format!("/addresses/{}/balance?context=rdx", address);
+        let val: BalanceResponse = self.get_json(&path).await?;
         let balance_str = val.data.item.confirmed_balance.amount;
         let balance = Amount::from_str_in(&balance_str, bitcoin::Denomination::Bitcoin)?;
 
@@ -106,46 +287,113 @@ impl CryptoApisClient {
         limit: i32,
         offset: i32,
     ) -> anyhow::Result<Vec<BtcUtxo>> {
-        let client = awc::Client::default();
-        let url = format!("https://rest.cryptoapis.io/blockchain-data/bitcoin/mainnet/addresses/{}/unspent-outputs?context=rdx&limit={}&offset={}", address, limit, offset);
-        let mut resp = client
-            .get(&url)
-            .insert_header(("X-Api-Key", self.api_key.clone()))
-            .send()
-            .await
-            .unwrap();
-
-        let val = resp.json::<UtxoResponse>().await?;
-        let sender_btc_address =
-            bitcoin::Address::from_str(address)?.require_network(bitcoin::Network::Bitcoin)?;
-        let pk_script = sender_btc_address.script_pubkey().to_hex_string();
-
-        let result: Vec<BtcUtxo> = val
-            .data
+        let path = format!(
+            "/addresses/{}/unspent-outputs?context=rdx&limit={}&offset={}",
+            address, limit, offset
+        );
+        let val: UtxoResponse = self.get_json(&path).await?;
+        let address_script = address_script(address, self.net)?;
+
+        val.data
             .items
             .iter()
-            .map(|e| -> BtcUtxo {
-                let amount = Amount::from_str_in(&e.amount, bitcoin::Denomination::Bitcoin)
-                    .unwrap()
-                    .to_sat();
+            .map(|e| -> anyhow::Result<BtcUtxo> {
+                let amount =
+                    Amount::from_str_in(&e.amount, bitcoin::Denomination::Bitcoin)?.to_sat();
 
-                BtcUtxo {
+                Ok(BtcUtxo {
                     id: 0,
                     block: 0,
                     tx_id: 0,
                     tx_hash: e.transaction_id.clone(),
                     output_n: e.index as i32,
                     address: e.address.clone(),
-                    pk_script: pk_script.clone(),
+                    pk_script: e.pk_script(&address_script)?,
                     amount: amount as i64,
                     spend: false,
-                }
+                    spent_block: None,
+                    // the api doesn't report it, nor the block to check the maturity against
+                    coinbase: false,
+                })
             })
-            .collect();
-        Ok(result)
+            .collect()
+    }
+
+    /// GET `path` of the blockchain data api, retrying network errors, 429 and 5xx
+    async fn get_json<T: DeserializeOwned>(&self, path: &str) -> anyhow::Result<T> {
+        let client = awc::Client::default();
+        let url = format!("{}{}", self.base_url, path);
+
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            let res = client
+                .get(&url)
+                .insert_header(("X-Api-Key", self.api_key.clone()))
+                .send()
+                .await;
+
+            let retry_after = match res {
+                Ok(mut resp) if resp.status().is_success() => return Ok(resp.json::<T>().await?),
+                Ok(mut resp) => {
+                    let status = resp.status();
+                    if !is_retryable(status) || attempt > self.max_retries {
+                        let body = resp
+                            .body()
+                            .await
+                            .map(|b| String::from_utf8_lossy(&b).into_owned())
+                            .unwrap_or_default();
+                        return Err(CryptoApisError::Status {
+                            attempts: attempt,
+                            status: status.as_u16(),
+                            body,
+                        }
+                        .into());
+                    }
+                    warn!("cryptoapis responded with {}: url={}", status, url);
+                    resp.headers()
+                        .get(header::RETRY_AFTER)
+                        .and_then(|v| v.to_str().ok())
+                        .map(str::to_owned)
+                }
+                Err(err) => {
+                    if attempt > self.max_retries {
+                        return Err(CryptoApisError::Request {
+                            attempts: attempt,
+                            error: err.to_string(),
+                        }
+                        .into());
+                    }
+                    warn!("cryptoapis request failed: url={} error={}", url, err);
+                    None
+                }
+            };
+
+            let delay = retry_delay(attempt, self.retry_base_delay, retry_after.as_deref());
+            tokio::time::sleep(delay).await;
+        }
     }
 }
 
+fn address_script(address: &str, net: bitcoin::Network) -> anyhow::Result<ScriptBuf> {
+    Ok(bitcoin::Address::from_str(address)?
+        .require_network(net)?
+        .script_pubkey())
+}
+
+fn is_retryable(status: StatusCode) -> bool {
+    status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error()
+}
+
+/// `Retry-After` in seconds if sent, exponential backoff otherwise
+fn retry_delay(attempt: u32, base_delay: Duration, retry_after: Option<&str>) -> Duration {
+    let delay = match retry_after.and_then(|v| v.trim().parse::<u64>().ok()) {
+        Some(secs) => Duration::from_secs(secs),
+        None => base_delay.saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1))),
+    };
+    delay.min(MAX_RETRY_DELAY)
+}
+
 #[derive(Debug, Clone, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct BalanceResponse {
@@ -202,6 +450,28 @@ pub struct Utxo {
     pub is_confirmed: bool,
     pub timestamp: i64,
     pub transaction_id: String,
+    /// hex encoded, not included by every api version
+    #[serde(default)]
+    pub script_pub_key: Option<String>,
+}
+
+impl Utxo {
+    /// script of the output if the api sent it, the script of the queried address otherwise
+    pub fn pk_script(&self, address_script: &Script) -> anyhow::Result<String> {
+        match self.script_pub_key.as_deref() {
+            Some(hex) if !hex.is_empty() => Ok(ScriptBuf::from_hex(hex)
+                .map_err(|e| {
+                    anyhow::anyhow!(
+                        "invalid script of utxo {}:{}: {}",
+                        self.transaction_id,
+                        self.index,
+                        e
+                    )
+                })?
+                .to_hex_string()),
+            _ => Ok(address_script.to_hex_string()),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -227,3 +497,196 @@ pub struct Fee {
     pub slow: String,
     pub standard: String,
 }
+
+impl Fee {
+    /// the bucket which confirms within `blocks`
+    pub fn for_target(&self, blocks: u16) -> &str {
+        if blocks <= FeePriority::Priority.target_blocks() {
+            &self.fast
+        } else if blocks <= FeePriority::Normal.target_blocks() {
+            &self.standard
+        } else {
+            &self.slow
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    /// serves the given raw responses, one per connection, and counts the requests
+    async fn mock_server(
+        responses: Vec<&'static str>,
+    ) -> (String, std::sync::Arc<std::sync::atomic::AtomicUsize>) {
+        use std::sync::{atomic::AtomicUsize, atomic::Ordering, Arc};
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let hits = Arc::new(AtomicUsize::new(0));
+        let counter = hits.clone();
+        actix_web::rt::spawn(async move {
+            for resp in responses {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut req = Vec::new();
+                let mut buf = [0u8; 1024];
+                while !req.windows(4).any(|w| w == b"\r\n\r\n") {
+                    let n = stream.read(&mut buf).await.unwrap();
+                    if n == 0 {
+                        break;
+                    }
+                    req.extend_from_slice(&buf[..n]);
+                }
+                counter.fetch_add(1, Ordering::SeqCst);
+                stream.write_all(resp.as_bytes()).await.unwrap();
+                stream.shutdown().await.unwrap();
+            }
+        });
+        (url, hits)
+    }
+
+    #[actix_web::test]
+    async fn rate_limited_request_is_retried() {
+        use super::{CryptoApisClient, CryptoApisError};
+        use std::{sync::atomic::Ordering, time::Duration};
+
+        const RATE_LIMITED: &str = "HTTP/1.1 429 Too Many Requests\r\nRetry-After: 0\r\nContent-Length: 0\r\nConnection: close\r\n\r\n";
+        const UNAVAILABLE: &str = "HTTP/1.1 503 Service Unavailable\r\nContent-Length: 4\r\nConnection: close\r\n\r\ndown";
+        const FEES: &str = "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: 145\r\nConnection: close\r\n\r\n{\"apiVersion\":\"2\",\"requestId\":\"1\",\"context\":\"rdx\",\"data\":{\"item\":{\"unit\":\"BTC\",\"fast\":\"0.00000012\",\"slow\":\"0.00000004\",\"standard\":\"0.00000008\"}}}";
+
+        let (url, hits) = mock_server(vec![RATE_LIMITED, FEES]).await;
+        let client = CryptoApisClient::new("key", bitcoin::Network::Bitcoin)
+            .unwrap()
+            .with_base_url(&url)
+            .with_retries(2, Duration::ZERO);
+        assert_eq!(client.get_fee_for_target(2).await.unwrap(), 12);
+        assert_eq!(hits.load(Ordering::SeqCst), 2);
+
+        let (url, hits) = mock_server(vec![UNAVAILABLE, UNAVAILABLE, UNAVAILABLE]).await;
+        let client = CryptoApisClient::new("key", bitcoin::Network::Bitcoin)
+            .unwrap()
+            .with_base_url(&url)
+            .with_retries(2, Duration::ZERO);
+        let err = client.get_fee_for_target(2).await.unwrap_err();
+        match err.downcast_ref::<CryptoApisError>() {
+            Some(CryptoApisError::Status {
+                attempts, status, ..
+            }) => assert_eq!((*attempts, *status), (3, 503)),
+            _ => panic!("unexpected error: {}", err),
+        }
+        assert_eq!(hits.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn retry_delay_honors_retry_after() {
+        use super::{retry_delay, MAX_RETRY_DELAY};
+        use std::time::Duration;
+
+        let base = Duration::from_millis(500);
+        assert_eq!(retry_delay(1, base, None), base);
+        assert_eq!(retry_delay(3, base, None), Duration::from_secs(2));
+        assert_eq!(retry_delay(3, base, Some("7")), Duration::from_secs(7));
+        // http dates aren't supported, the backoff is used instead
+        assert_eq!(
+            retry_delay(1, base, Some("Wed, 21 Oct 2015 07:28:00 GMT")),
+            base
+        );
+        assert_eq!(retry_delay(1, base, Some("3600")), MAX_RETRY_DELAY);
+        assert_eq!(retry_delay(30, base, None), MAX_RETRY_DELAY);
+    }
+
+    #[test]
+    fn base_url_follows_network() {
+        use super::CryptoApisClient;
+        use bitcoin::Network;
+
+        assert_eq!(
+            CryptoApisClient::base_url(Network::Bitcoin).unwrap(),
+            "https://rest.cryptoapis.io/blockchain-data/bitcoin/mainnet"
+        );
+        assert_eq!(
+            CryptoApisClient::base_url(Network::Testnet).unwrap(),
+            "https://rest.cryptoapis.io/blockchain-data/bitcoin/testnet"
+        );
+        assert!(CryptoApisClient::base_url(Network::Regtest).is_err());
+        assert!(CryptoApisClient::new("key", Network::Signet).is_err());
+    }
+
+    #[test]
+    fn utxo_script_prefers_api_response() {
+        use super::{address_script, Utxo};
+        use bitcoin::Network;
+
+        let testnet_address = "tb1qw508d6qejxtdg4y5r3zarvary0c5xw7kxpjzsx";
+        let script = address_script(testnet_address, Network::Testnet).unwrap();
+        assert_eq!(
+            script.to_hex_string(),
+            "0014751e76e8199196d454941c45d1b3a323f1433bd6"
+        );
+        assert!(address_script(testnet_address, Network::Bitcoin).is_err());
+
+        let utxo: Utxo = serde_json::from_str(
+            r#"{"address":"tb1qw508d6qejxtdg4y5r3zarvary0c5xw7kxpjzsx","amount":"0.0001","index":1,
+            "isAvailable":true,"isConfirmed":true,"timestamp":0,"transactionId":"ab"}"#,
+        )
+        .unwrap();
+        assert_eq!(utxo.pk_script(&script).unwrap(), script.to_hex_string());
+
+        // the address may hold outputs of another script type
+        let p2tr = "5120a60869f0dbcf1dc659c9cecbaf8050135ea9e8cdc487053f1dc6880949dc684c";
+        let utxo = Utxo {
+            script_pub_key: Some(p2tr.to_owned()),
+            ..utxo
+        };
+        assert_eq!(utxo.pk_script(&script).unwrap(), p2tr);
+
+        let utxo = Utxo {
+            script_pub_key: Some("zz".to_owned()),
+            ..utxo
+        };
+        assert!(utxo.pk_script(&script).is_err());
+    }
+
+    #[test]
+    fn scanned_utxo_reports_coinbase() {
+        use super::ScanResult;
+
+        let res: ScanResult = serde_json::from_str(
+            r#"{"success":true,"txouts":2,"height":850000,"unspents":[
+            {"txid":"4a5e1e4baab89f3a32518a88c31bc87f618f76673e2cc77ab2127b7afdeda33b","vout":0,
+            "scriptPubKey":"0014751e76e8199196d454941c45d1b3a323f1433bd6","desc":"addr(bc1q)",
+            "amount":50.0,"coinbase":true,"height":849900},
+            {"txid":"4a5e1e4baab89f3a32518a88c31bc87f618f76673e2cc77ab2127b7afdeda33b","vout":1,
+            "scriptPubKey":"0014751e76e8199196d454941c45d1b3a323f1433bd6","desc":"addr(bc1q)",
+            "amount":0.00001,"height":849950}],"total_amount":50.00001}"#,
+        )
+        .unwrap();
+        assert!(res.unspents[0].coinbase);
+        assert_eq!(res.unspents[0].amount.to_sat(), 5_000_000_000);
+        // older nodes don't send the field
+        assert!(!res.unspents[1].coinbase);
+        assert_eq!(res.unspents[1].amount.to_sat(), 1_000);
+    }
+
+    #[test]
+    fn fee_priority_selects_cryptoapis_bucket() {
+        use super::Fee;
+        use crate::tx::fee::FeePriority;
+
+        let fee = Fee {
+            unit: "BTC".to_owned(),
+            fast: "0.00000012".to_owned(),
+            slow: "0.00000004".to_owned(),
+            standard: "0.00000008".to_owned(),
+        };
+        let bucket = |p: FeePriority| fee.for_target(p.target_blocks());
+        assert_eq!(bucket(FeePriority::Priority), "0.00000012");
+        assert_eq!(bucket(FeePriority::Normal), "0.00000008");
+        assert_eq!(bucket(FeePriority::Economy), "0.00000004");
+        assert_eq!(bucket(FeePriority::default()), "0.00000008");
+
+        // bitcoind gets the target itself
+        assert_eq!(FeePriority::Priority.target_blocks(), 2);
+        assert_eq!(fee.for_target(1), "0.00000012");
+        assert_eq!(fee.for_target(144), "0.00000004");
+    }
+}
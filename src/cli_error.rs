@@ -0,0 +1,127 @@
+use std::fmt;
+
+/// Failure categories of the CLI subcommands, each one exits with its own code:
+///
+/// | code | failure                            |
+/// |------|------------------------------------|
+/// | 1    | anything else                      |
+/// | 2    | config can't be read or parsed     |
+/// | 3    | bitcoin node RPC call failed       |
+/// | 4    | not enough funds to build the tx   |
+/// | 5    | the node rejected the tx broadcast |
+/// | 6    | invalid arguments                  |
+#[derive(Debug)]
+pub enum CliError {
+    Config(String),
+    Rpc(String),
+    InsufficientFunds(String),
+    BroadcastRejected(String),
+    Validation(String),
+}
+
+impl CliError {
+    pub const EXIT_OTHER: i32 = 1;
+    pub const EXIT_CONFIG: i32 = 2;
+    pub const EXIT_RPC: i32 = 3;
+    pub const EXIT_INSUFFICIENT_FUNDS: i32 = 4;
+    pub const EXIT_BROADCAST_REJECTED: i32 = 5;
+    pub const EXIT_VALIDATION: i32 = 6;
+
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Self::Config(_) => Self::EXIT_CONFIG,
+            Self::Rpc(_) => Self::EXIT_RPC,
+            Self::InsufficientFunds(_) => Self::EXIT_INSUFFICIENT_FUNDS,
+            Self::BroadcastRejected(_) => Self::EXIT_BROADCAST_REJECTED,
+            Self::Validation(_) => Self::EXIT_VALIDATION,
+        }
+    }
+
+    /// only an error response of the node is a rejection, the rest are failed rpc calls
+    pub fn broadcast(err: bitcoincore_rpc::Error) -> Self {
+        use bitcoincore_rpc::jsonrpc;
+
+        match err {
+            bitcoincore_rpc::Error::JsonRpc(jsonrpc::Error::Rpc(_)) => {
+                Self::BroadcastRejected(err.to_string())
+            }
+            _ => Self::Rpc(err.to_string()),
+        }
+    }
+}
+
+impl fmt::Display for CliError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Config(msg) => write!(f, "config error: {}", msg),
+            Self::Rpc(msg) => write!(f, "rpc error: {}", msg),
+            Self::InsufficientFunds(msg) => write!(f, "insufficient funds: {}", msg),
+            Self::BroadcastRejected(msg) => write!(f, "broadcast rejected: {}", msg),
+            Self::Validation(msg) => write!(f, "validation error: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for CliError {}
+
+/// exit code for an error returned by a subcommand
+pub fn exit_code(err: &anyhow::Error) -> i32 {
+    if let Some(e) = err.downcast_ref::<CliError>() {
+        return e.exit_code();
+    }
+    if err.downcast_ref::<bitcoincore_rpc::Error>().is_some() {
+        return CliError::EXIT_RPC;
+    }
+    CliError::EXIT_OTHER
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn errors_map_to_exit_codes() {
+        use super::{exit_code, CliError};
+
+        let err = anyhow::Error::from(CliError::InsufficientFunds("in=1 out=2".to_owned()));
+        assert_eq!(exit_code(&err), CliError::EXIT_INSUFFICIENT_FUNDS);
+
+        let err = anyhow::Error::from(CliError::Config("bad toml".to_owned()))
+            .context("can't start the indexer");
+        assert_eq!(exit_code(&err), CliError::EXIT_CONFIG);
+
+        let err = anyhow::Error::from(bitcoincore_rpc::Error::ReturnedError("x".to_owned()));
+        assert_eq!(exit_code(&err), CliError::EXIT_RPC);
+
+        assert_eq!(exit_code(&anyhow::anyhow!("other")), CliError::EXIT_OTHER);
+    }
+
+    #[test]
+    fn only_node_rejections_are_broadcast_errors() {
+        use super::CliError;
+        use bitcoincore_rpc::jsonrpc;
+
+        let rejected =
+            bitcoincore_rpc::Error::JsonRpc(jsonrpc::Error::Rpc(jsonrpc::error::RpcError {
+                code: -26,
+                message: "min relay fee not met".to_owned(),
+                data: None,
+            }));
+        assert_eq!(
+            CliError::broadcast(rejected).exit_code(),
+            CliError::EXIT_BROADCAST_REJECTED
+        );
+
+        let unreachable = bitcoincore_rpc::Error::Io(std::io::Error::new(
+            std::io::ErrorKind::ConnectionRefused,
+            "connection refused",
+        ));
+        assert_eq!(
+            CliError::broadcast(unreachable).exit_code(),
+            CliError::EXIT_RPC
+        );
+        let returned = bitcoincore_rpc::Error::ReturnedError("x".to_owned());
+        assert_eq!(
+            CliError::broadcast(returned).exit_code(),
+            CliError::EXIT_RPC
+        );
+    }
+}
@@ -1,5 +1,10 @@
+use bitcoincore_rpc::jsonrpc;
 use serde::Deserialize;
+use std::fmt;
 use std::fs;
+use std::time::Duration;
+
+use crate::cli_error::CliError;
 
 #[derive(Deserialize, Clone, Debug)]
 pub struct Config {
@@ -9,6 +14,21 @@ pub struct Config {
     pub redis: RedisConfig,
     pub indexers: IndexersConfig,
     pub signature_provider: SignatureProvider,
+    #[serde(default)]
+    pub watchdog: TxWatchdogConfig,
+    #[serde(default)]
+    pub consistency: ConsistencyCheckConfig,
+    #[serde(default)]
+    pub webhooks: WebhookConfig,
+}
+
+impl Config {
+    /// values which parse but can't work, checked once at load
+    pub fn validate(&self) -> Result<(), String> {
+        self.api.validate()?;
+        self.btc.validate()?;
+        Ok(())
+    }
 }
 
 #[derive(Deserialize, Clone, Debug)]
@@ -16,6 +36,117 @@ pub struct APIConfig {
     pub listen_address: String,
     pub port: i32,
     pub cors_domain: String,
+    /// confirmations required for a utxo to count into the confirmed balance
+    pub min_confirmations: Option<i64>,
+    /// log level of the per-request log lines, `off` disables them, default is `info`
+    pub request_log: Option<String>,
+    #[serde(default)]
+    pub max_page_size: PageLimitsConfig,
+    #[serde(default)]
+    pub timeouts: RequestTimeoutsConfig,
+    /// base units a balance needs to count as a holder in the holders list, holder counts and
+    /// the top runes, default is 1. Smaller balances are still indexed and served by the balance
+    /// endpoints, so the holders of a rune no longer add up to its circulating supply.
+    pub min_holder_balance: Option<u64>,
+    /// default format of the pool prices: `number` (f64) or `string` (exact decimal),
+    /// `?prices=` overrides it per request, default is number
+    pub prices: Option<String>,
+    /// max distance in percent between the rate of an add-liquidity deposit and the pool price,
+    /// deposits further off are rejected, default is 1.0
+    pub max_liquidity_rate_delta: Option<f64>,
+}
+
+/// Largest `limit` accepted by list endpoints, larger requests are rejected with 400.
+#[derive(Deserialize, Clone, Debug, Default)]
+pub struct PageLimitsConfig {
+    pub holders: Option<i32>,
+    pub utxos: Option<i32>,
+}
+
+impl PageLimitsConfig {
+    pub const DEFAULT_HOLDERS: i32 = 500;
+    pub const DEFAULT_UTXOS: i32 = 200;
+
+    pub fn holders(&self) -> i32 {
+        self.holders.unwrap_or(Self::DEFAULT_HOLDERS).max(1)
+    }
+
+    pub fn utxos(&self) -> i32 {
+        self.utxos.unwrap_or(Self::DEFAULT_UTXOS).max(1)
+    }
+}
+
+/// Seconds a heavy endpoint may run before it's answered with 504, 0 disables the limit.
+/// A query still running then is cancelled by `db.statement_timeout` only.
+#[derive(Deserialize, Clone, Debug, Default)]
+pub struct RequestTimeoutsConfig {
+    /// paginated lists: holders, utxos, snapshots, balances at a height
+    pub lists: Option<u64>,
+    /// utxo selection and tx building
+    pub utxo_selection: Option<u64>,
+}
+
+impl RequestTimeoutsConfig {
+    pub const DEFAULT_SECS: u64 = 30;
+
+    pub fn lists(&self) -> Option<Duration> {
+        Self::timeout(self.lists)
+    }
+
+    pub fn utxo_selection(&self) -> Option<Duration> {
+        Self::timeout(self.utxo_selection)
+    }
+
+    fn timeout(secs: Option<u64>) -> Option<Duration> {
+        match secs.unwrap_or(Self::DEFAULT_SECS) {
+            0 => None,
+            secs => Some(Duration::from_secs(secs)),
+        }
+    }
+}
+
+impl APIConfig {
+    pub const DEFAULT_MIN_CONFIRMATIONS: i64 = 1;
+    pub const DEFAULT_MAX_LIQUIDITY_RATE_DELTA: f64 = 1.0;
+
+    pub fn min_confirmations(&self) -> i64 {
+        self.min_confirmations
+            .unwrap_or(Self::DEFAULT_MIN_CONFIRMATIONS)
+            .max(1)
+    }
+
+    /// zero balances are never holders
+    pub fn min_holder_balance(&self) -> u64 {
+        self.min_holder_balance.unwrap_or(1).max(1)
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        match self.prices.as_deref() {
+            None | Some("number") | Some("string") => Ok(()),
+            Some(prices) => Err(format!(
+                "api.prices must be number or string, got {}",
+                prices
+            )),
+        }
+    }
+
+    pub fn string_prices(&self) -> bool {
+        self.prices.as_deref() == Some("string")
+    }
+
+    pub fn max_liquidity_rate_delta(&self) -> f64 {
+        self.max_liquidity_rate_delta
+            .unwrap_or(Self::DEFAULT_MAX_LIQUIDITY_RATE_DELTA)
+            .max(0.0)
+    }
+
+    pub fn request_log_level(&self) -> Option<log::Level> {
+        match self.request_log.as_deref() {
+            None => Some(log::Level::Info),
+            Some("off") => None,
+            Some(level) => Some(level.parse().unwrap_or(log::Level::Info)),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -29,10 +160,44 @@ pub struct BTCConfig {
     pub address: String,
     pub rpc_user: String,
     pub rpc_password: String,
+    /// http timeout of the bitcoind rpc calls in seconds, default is 60
+    pub rpc_timeout: Option<u64>,
+    /// sats attached to every rune bearing output, default is 600
+    pub rune_output_value: Option<u64>,
+    /// multiplier of the fee estimated for unsigned txs, covers the size of the signatures, default is 2.0
+    pub fee_safety_factor: Option<f64>,
+    /// skips utxos spent by our submitted txs which aren't mined yet, default is true
+    pub exclude_pending_spends: Option<bool>,
+    /// skips coinbase outputs with less than 100 confirmations, default is true.
+    /// The local and bitcoind (25+) utxo providers know which outputs are coinbase ones, cryptoapis doesn't.
+    pub skip_immature_coinbase: Option<bool>,
+    /// order of the outputs of built txs: fixed, bip69_outputs or random_change, default is fixed.
+    /// The inputs are never reordered, so bip69_outputs txs aren't fully BIP69.
+    pub output_order: Option<String>,
+    /// max size of the runestone script in bytes, should match the node's -datacarriersize, default is 83
+    pub max_runestone_size: Option<usize>,
+    /// version of built txs, 1 or 2, default is 2. Etching reveals keep 2 for their relative lock.
+    pub tx_version: Option<i32>,
+    /// lock time of built txs: zero, a fixed height or timestamp, or tip for the node's
+    /// block height against fee sniping (asks bitcoind at `address`), default is zero
+    pub lock_time: Option<String>,
     pub utxo_provider: BtcUtxoProvider,
 }
 
 impl BTCConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if let Some(factor) = self.fee_safety_factor {
+            // also false for NaN
+            if !(factor.is_finite() && factor >= 1.0) {
+                return Err(format!(
+                    "btc.fee_safety_factor must be a number of at least 1.0, got {}",
+                    factor
+                ));
+            }
+        }
+        Ok(())
+    }
+
     pub fn get_network(&self) -> bitcoin::Network {
         let Some(net) = self.network.clone() else {
             return bitcoin::Network::Bitcoin;
@@ -45,45 +210,574 @@ impl BTCConfig {
             _ => bitcoin::Network::Bitcoin,
         }
     }
+
+    pub fn rune_output_value(&self) -> u64 {
+        self.rune_output_value
+            .unwrap_or(crate::tx::runes_txs::RUNES_OUT_VALUE)
+    }
+
+    pub fn fee_safety_factor(&self) -> f64 {
+        self.fee_safety_factor
+            .unwrap_or(crate::tx::fee::DEFAULT_SAFETY_FACTOR)
+    }
+
+    pub fn exclude_pending_spends(&self) -> bool {
+        self.exclude_pending_spends.unwrap_or(true)
+    }
+
+    pub fn skip_immature_coinbase(&self) -> bool {
+        self.skip_immature_coinbase.unwrap_or(true)
+    }
+
+    pub fn max_runestone_size(&self) -> usize {
+        self.max_runestone_size
+            .unwrap_or(crate::tx::pool_txs::DEFAULT_MAX_RUNESTONE_SIZE)
+    }
+
+    pub fn output_order(&self) -> anyhow::Result<crate::tx::pool_txs::OutputOrder> {
+        match &self.output_order {
+            Some(order) => order.parse(),
+            None => Ok(Default::default()),
+        }
+    }
+
+    pub fn tx_version(&self) -> anyhow::Result<i32> {
+        match self
+            .tx_version
+            .unwrap_or(crate::tx::runes_txs::DEFAULT_TX_VERSION)
+        {
+            version @ 1..=2 => Ok(version),
+            version => anyhow::bail!("non standard tx version ({})", version),
+        }
+    }
+
+    pub fn lock_time(&self) -> anyhow::Result<crate::tx::pool_txs::LockTimePolicy> {
+        match &self.lock_time {
+            Some(lock_time) => lock_time.parse(),
+            None => Ok(Default::default()),
+        }
+    }
+
+    /// Creates a bitcoind rpc client with the configured timeout,
+    /// so a hung request fails and gets retried instead of stalling the caller.
+    pub fn rpc_client(&self) -> anyhow::Result<bitcoincore_rpc::Client> {
+        let timeout = Duration::from_secs(self.rpc_timeout.unwrap_or(60));
+        let transport = jsonrpc::simple_http::SimpleHttpTransport::builder()
+            .url(&self.address)?
+            .auth(self.rpc_user.clone(), Some(self.rpc_password.clone()))
+            .timeout(timeout)
+            .build();
+
+        Ok(bitcoincore_rpc::Client::from_jsonrpc(
+            jsonrpc::Client::with_transport(transport),
+        ))
+    }
 }
 
 #[derive(Deserialize, Clone, Debug)]
 pub struct DBConfig {
     pub dsn: String,
     pub automigrate: bool,
+    /// longer name filters are truncated, default is 64
+    pub max_name_filter_len: Option<usize>,
+    /// seconds after which postgres cancels a query of the API pool, migrations and
+    /// the indexers aren't limited. Default is 0 which disables it
+    pub statement_timeout: Option<u64>,
+    /// how many bulk operations (cache warm-up, snapshot, export and batch endpoints) may
+    /// query the db at once, default is 10. The permits are per pool: the API pool and
+    /// the indexers' pool each have their own.
+    pub bulk_permits: Option<usize>,
+    /// connections of the indexers' pool, also used by the commands, default is 20
+    pub pool_size: Option<u32>,
+    /// connections of the API pool, default is 70. The server opens both pools and
+    /// refuses to start when they exceed postgres' `max_connections` minus
+    /// `superuser_reserved_connections` (97 by default)
+    pub api_pool_size: Option<u32>,
+}
+
+impl DBConfig {
+    pub fn pool_size(&self) -> u32 {
+        self.pool_size.unwrap_or(20).max(1)
+    }
+
+    pub fn api_pool_size(&self) -> u32 {
+        self.api_pool_size.unwrap_or(70).max(1)
+    }
+
+    pub fn bulk_permits(&self) -> usize {
+        self.bulk_permits.unwrap_or(10)
+    }
+
+    pub fn statement_timeout(&self) -> Option<Duration> {
+        match self.statement_timeout.unwrap_or(0) {
+            0 => None,
+            secs => Some(Duration::from_secs(secs)),
+        }
+    }
 }
 
 #[derive(Deserialize, Clone, Debug)]
 pub struct IndexersConfig {
     pub btc_starting_height: i64,
     pub runes_starting_height: i64,
-    pub handle_edicts: bool,
+    /// default is true. When disabled only etchings and mints are indexed: a tx with edicts
+    /// doesn't move its input runes, they stay on the spent utxos, so the balances
+    /// diverge from the canonical runes state.
+    pub handle_edicts: Option<bool>,
+    /// default is false, an edict of a rune missing from the index burns all the tx inputs.
+    /// When enabled such edicts are ignored and the inputs go to the change output, as ord does.
+    pub skip_unknown_edict_runes: Option<bool>,
     pub disable_rune_log: bool,
     pub btc_watchlist: Vec<String>,
     pub runes_watchlist: Vec<String>,
+    /// how many recent indexer anomalies to keep, default is 1000
+    pub anomalies_retention: Option<i64>,
+    /// skips the btc utxo indexer, takes effect only with an external utxo provider
+    pub disable_btc_indexer: Option<bool>,
+    #[serde(default)]
+    pub etching_policy: EtchingPolicy,
+    /// runes inserted by reset-db, default is the reserved UNCOMMON•GOODS
+    pub seed_runes: Option<Vec<SeedRune>>,
+    /// blocks between runes index checkpoints, default is 1000, 0 disables them
+    pub checkpoint_interval: Option<i64>,
+    /// stores the inscription revealed by etching txs as the rune metadata, default is false,
+    /// adds witness parsing to every etching
+    pub index_inscriptions: Option<bool>,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct SeedRune {
+    /// hex of the runestone script with the etching, as stored in `runes.raw_data`
+    pub runestone: String,
+    pub block: i64,
+    #[serde(default)]
+    pub tx_id: i32,
+}
+
+/// Etchings violating the policy are indexed as invalid and their inputs are burned.
+/// The default policy accepts everything.
+#[derive(Deserialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct EtchingPolicy {
+    pub max_divisibility: Option<u8>,
+    /// rune names without spacers, `*` matches any sequence of letters
+    pub banned_names: Vec<String>,
+}
+
+impl EtchingPolicy {
+    /// describes the violated rule
+    pub fn violation(&self, rune: &str, divisibility: u8) -> Option<String> {
+        if let Some(max) = self.max_divisibility {
+            if divisibility > max {
+                return Some(format!("divisibility {} is above {}", divisibility, max));
+            }
+        }
+
+        self.banned_names
+            .iter()
+            .find(|pattern| wildcard_match(&pattern.to_uppercase(), rune))
+            .map(|pattern| format!("name matches banned pattern {}", pattern))
+    }
+}
+
+fn wildcard_match(pattern: &str, name: &str) -> bool {
+    let Some((prefix, rest)) = pattern.split_once('*') else {
+        return pattern == name;
+    };
+    let Some(mut name) = name.strip_prefix(prefix) else {
+        return false;
+    };
+
+    let mut parts: Vec<&str> = rest.split('*').collect();
+    let suffix = parts.pop().unwrap_or_default();
+    for part in parts {
+        match name.find(part) {
+            Some(i) => name = &name[i + part.len()..],
+            None => return false,
+        }
+    }
+    name.len() >= suffix.len() && name.ends_with(suffix)
+}
+
+impl IndexersConfig {
+    /// the local btc indexer is required when btc utxos and balances are served from the db
+    pub fn btc_indexer_enabled(&self, provider: &BtcUtxoProvider) -> bool {
+        provider.is_local() || !self.disable_btc_indexer.unwrap_or(false)
+    }
+
+    pub fn handle_edicts(&self) -> bool {
+        self.handle_edicts.unwrap_or(true)
+    }
+
+    pub fn skip_unknown_edict_runes(&self) -> bool {
+        self.skip_unknown_edict_runes.unwrap_or(false)
+    }
+
+    pub fn checkpoint_interval(&self) -> i64 {
+        self.checkpoint_interval.unwrap_or(1000).max(0)
+    }
+
+    pub fn index_inscriptions(&self) -> bool {
+        self.index_inscriptions.unwrap_or(false)
+    }
+
+    /// fails when a configured seed doesn't decode to an etching
+    pub fn seed_runes(&self) -> anyhow::Result<Vec<crate::db::Rune>> {
+        let Some(seeds) = &self.seed_runes else {
+            return Ok(vec![crate::db::reserved_rune()]);
+        };
+        seeds
+            .iter()
+            .map(|s| crate::db::seed_rune(&s.runestone, s.block, s.tx_id))
+            .collect()
+    }
+}
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct TxWatchdogConfig {
+    /// seconds a submitted tx may stay unknown to the node before the failure policy applies
+    pub pending_tx_max_age: i64,
+    /// seconds after which a pending tx is failed even if rebroadcasts keep succeeding
+    pub pending_tx_hard_max_age: i64,
+    /// number of consecutive rejected rebroadcasts before the tx is failed, 0 disables rebroadcast
+    pub max_rebroadcast_failures: u32,
+    /// failed processing ticks of a confirmed tx before it's moved to dead-letter, 0 retries forever
+    pub max_process_attempts: u32,
+}
+
+impl Default for TxWatchdogConfig {
+    fn default() -> Self {
+        Self {
+            pending_tx_max_age: 3600,
+            pending_tx_hard_max_age: 24 * 3600,
+            max_rebroadcast_failures: 0,
+            max_process_attempts: 10,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct WebhookConfig {
+    /// events are POSTed here as JSON, empty disables the webhooks
+    pub url: String,
+    /// key of the HMAC-SHA256 signature of the delivery timestamp and body, deliveries aren't signed if empty
+    pub secret: String,
+    /// etching, mint, transfer or tx_confirmed, all of them if empty
+    pub events: Vec<String>,
+    /// transfer events are sent only for runes received by these addresses
+    pub watched_addresses: Vec<String>,
+    /// delivery attempts before the event goes to the dead-letter table
+    pub max_attempts: u32,
+    /// seconds before the first retry, doubled for every next one
+    pub retry_delay: u64,
+    /// seconds
+    pub timeout: u64,
+    /// events waiting for delivery, events over it go straight to the dead-letter table
+    pub queue_size: usize,
+}
+
+impl WebhookConfig {
+    pub fn enabled(&self) -> bool {
+        !self.url.is_empty()
+    }
+}
+
+impl Default for WebhookConfig {
+    fn default() -> Self {
+        Self {
+            url: String::new(),
+            secret: String::new(),
+            events: Vec::new(),
+            watched_addresses: Vec::new(),
+            max_attempts: 5,
+            retry_delay: 2,
+            timeout: 10,
+            queue_size: 10_000,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct ConsistencyCheckConfig {
+    pub enabled: bool,
+    /// number of random runes checked per round
+    pub sample_size: i32,
+    /// seconds between rounds
+    pub interval: u64,
+}
+
+impl Default for ConsistencyCheckConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            sample_size: 20,
+            interval: 300,
+        }
+    }
 }
 
 #[derive(Deserialize, Clone, Debug)]
 pub struct SignatureProvider {
     pub local: LocalSigner,
+    /// additional signers, e.g. for distinct pool and fee addresses
+    #[serde(default)]
+    pub signers: Vec<LocalSigner>,
+    /// addresses the service may sign inputs for, only the local signers if empty
+    #[serde(default)]
+    pub signable_addresses: Vec<String>,
 }
 
-#[derive(Deserialize, Clone, Debug)]
+impl SignatureProvider {
+    /// `local` first, then the additional signers
+    pub fn local_signers(&self) -> Vec<LocalSigner> {
+        let mut signers = vec![self.local.clone()];
+        signers.extend(self.signers.iter().cloned());
+        signers
+    }
+}
+
+#[derive(Deserialize, Clone)]
 pub struct LocalSigner {
     pub address: String,
+    /// hex secret, `env:VAR_NAME` or `file:/path`, resolved by `secret()` when the signer is built
     pub secret_key: String,
     pub mode: String,
 }
 
+impl fmt::Debug for LocalSigner {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LocalSigner")
+            .field("address", &self.address)
+            .field("secret_key", &"<redacted>")
+            .field("mode", &self.mode)
+            .finish()
+    }
+}
+
+impl LocalSigner {
+    const SECRET_LEN: usize = 32;
+
+    /// the hex secret, an `env:` or `file:` reference is read only here,
+    /// so commands without a signer don't need it. Errors never include the secret itself
+    pub fn secret(&self) -> Result<String, CliError> {
+        let secret = match self.secret_key.split_once(':') {
+            Some(("env", var)) => std::env::var(var).map_err(|e| {
+                CliError::Config(format!(
+                    "signer({}) secret_key env var {}: {}",
+                    self.address, var, e
+                ))
+            })?,
+            Some(("file", path)) => fs::read_to_string(path).map_err(|e| {
+                CliError::Config(format!(
+                    "signer({}) secret_key file {}: {}",
+                    self.address, path, e
+                ))
+            })?,
+            _ => self.secret_key.clone(),
+        };
+
+        let secret = secret.trim();
+        let valid =
+            secret.len() == Self::SECRET_LEN * 2 && secret.chars().all(|c| c.is_ascii_hexdigit());
+        if !valid {
+            return Err(CliError::Config(format!(
+                "signer({}) secret_key must be {} hex encoded bytes",
+                self.address,
+                Self::SECRET_LEN
+            )));
+        }
+
+        Ok(secret.to_owned())
+    }
+}
+
 #[derive(Deserialize, Clone, Debug)]
 pub struct BtcUtxoProvider {
     pub mode: String,
     pub api_key: String,
 }
 
-pub fn read_config(path: &str) -> Result<Config, std::io::Error> {
-    let contents = fs::read_to_string(path)?;
+impl BtcUtxoProvider {
+    pub const CRYPTOAPIS: &'static str = "cryptoapis";
+    pub const BITCOIND: &'static str = "bitcoind";
+
+    /// btc utxos and balances are served from the btc indexer tables
+    pub fn is_local(&self) -> bool {
+        self.mode != Self::CRYPTOAPIS && self.mode != Self::BITCOIND
+    }
+}
+
+pub fn read_config(path: &str) -> Result<Config, CliError> {
+    let contents = fs::read_to_string(path)
+        .map_err(|e| CliError::Config(format!("can't read {}: {}", path, e)))?;
+
+    let cfg: Config = toml::from_str(&contents)
+        .map_err(|e| CliError::Config(format!("can't parse {}: {}", path, e)))?;
+    cfg.validate()
+        .map_err(|e| CliError::Config(format!("{}: {}", path, e)))?;
+
+    Ok(cfg)
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn etching_policy_rejects_banned_names() {
+        use super::EtchingPolicy;
+
+        assert_eq!(EtchingPolicy::default().violation("BITCOINSCAM", 38), None);
+
+        let policy = EtchingPolicy {
+            max_divisibility: None,
+            banned_names: vec![
+                "*scam*".to_owned(),
+                "BITCOIN".to_owned(),
+                "FAKE*COIN".to_owned(),
+            ],
+        };
+        assert!(policy.violation("BITCOINSCAM", 0).is_some());
+        assert!(policy.violation("SCAM", 0).is_some());
+        assert!(policy.violation("BITCOIN", 0).is_some());
+        assert!(policy.violation("FAKEDOGECOIN", 0).is_some());
+        assert!(policy.violation("FAKECOIN", 0).is_some());
+
+        assert_eq!(policy.violation("BITCOINS", 0), None);
+        assert_eq!(policy.violation("FAKECOINS", 0), None);
+        assert_eq!(policy.violation("UNCOMMONGOODS", 0), None);
+    }
+
+    #[test]
+    fn etching_policy_rejects_over_divisibility() {
+        use super::EtchingPolicy;
+
+        let policy = EtchingPolicy {
+            max_divisibility: Some(8),
+            banned_names: vec![],
+        };
+        assert_eq!(policy.violation("RUNE", 8), None);
+        assert!(policy.violation("RUNE", 9).is_some());
+        assert!(policy.violation("RUNE", 38).is_some());
+    }
+
+    #[test]
+    fn signer_secret_is_resolved() {
+        use super::LocalSigner;
+
+        let secret = "01".repeat(32);
+        let signer = |secret_key: &str| LocalSigner {
+            address: "bc1p...".to_owned(),
+            secret_key: secret_key.to_owned(),
+            mode: "taproot".to_owned(),
+        };
+
+        assert_eq!(signer(&secret).secret().unwrap(), secret);
+
+        std::env::set_var("RUNES_DEX_TEST_SIGNER_SECRET", &secret);
+        let s = signer("env:RUNES_DEX_TEST_SIGNER_SECRET");
+        assert_eq!(s.secret().unwrap(), secret);
+
+        let path = std::env::temp_dir().join("runes_dex_test_signer_secret");
+        std::fs::write(&path, format!("{}\n", secret)).unwrap();
+        let s = signer(&format!("file:{}", path.display()));
+        assert_eq!(s.secret().unwrap(), secret);
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(signer("env:RUNES_DEX_TEST_MISSING_VAR").secret().is_err());
+        assert!(signer(&format!("file:{}", path.display()))
+            .secret()
+            .is_err());
+
+        // invalid secrets aren't echoed back
+        let short = "ab".repeat(31);
+        let err = signer(&short).secret().unwrap_err().to_string();
+        assert!(err.contains("must be 32 hex encoded bytes"));
+        assert!(!err.contains(&short));
+        assert!(signer(&"zz".repeat(32)).secret().is_err());
+
+        assert!(!format!("{:?}", signer(&secret)).contains(&secret));
+    }
+
+    #[test]
+    fn zero_balances_are_never_holders() {
+        use super::APIConfig;
+
+        let cfg: APIConfig = toml::from_str(
+            r#"
+            listen_address = "0.0.0.0"
+            port = 8080
+            cors_domain = "*"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(cfg.min_holder_balance(), 1);
 
-    let config: Config = toml::from_str(&contents).unwrap();
-    Ok(config)
+        let cfg = APIConfig {
+            min_holder_balance: Some(0),
+            ..cfg
+        };
+        assert_eq!(cfg.min_holder_balance(), 1);
+        let cfg = APIConfig {
+            min_holder_balance: Some(10_000),
+            ..cfg
+        };
+        assert_eq!(cfg.min_holder_balance(), 10_000);
+    }
+
+    #[test]
+    fn api_prices_are_checked_at_load() {
+        use super::APIConfig;
+
+        let cfg: APIConfig = toml::from_str(
+            r#"
+            listen_address = "0.0.0.0"
+            port = 8080
+            cors_domain = "*"
+            "#,
+        )
+        .unwrap();
+        // a typo would silently fall back to number prices
+        for (prices, valid) in [
+            (None, true),
+            (Some("number"), true),
+            (Some("string"), true),
+            (Some("String"), false),
+            (Some("decimal"), false),
+        ] {
+            let cfg = APIConfig {
+                prices: prices.map(str::to_owned),
+                ..cfg.clone()
+            };
+            assert_eq!(cfg.validate().is_ok(), valid, "{:?}", prices);
+        }
+    }
+
+    #[test]
+    fn fee_safety_factor_is_checked_at_load() {
+        use super::BTCConfig;
+
+        let cfg = |factor: &str| -> BTCConfig {
+            toml::from_str(&format!(
+                r#"
+                address = "127.0.0.1:8332"
+                rpc_user = "user"
+                rpc_password = "password"
+                utxo_provider = {{ mode = "local", api_key = "" }}
+                {}
+                "#,
+                factor
+            ))
+            .unwrap()
+        };
+
+        for valid in ["", "fee_safety_factor = 1.0", "fee_safety_factor = 2.5"] {
+            assert!(cfg(valid).validate().is_ok(), "{}", valid);
+        }
+        for invalid in ["nan", "-1.0", "0.5", "0.0", "inf"] {
+            let line = format!("fee_safety_factor = {}", invalid);
+            assert!(cfg(&line).validate().is_err(), "{}", line);
+        }
+    }
 }
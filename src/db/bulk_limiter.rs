@@ -0,0 +1,69 @@
+use tokio::sync::{Semaphore, SemaphorePermit};
+
+/// Caps how many bulk operations (cache warm-up, snapshot and batch endpoints)
+/// query the db at once, so they leave pool connections to the interactive endpoints.
+pub struct BulkLimiter {
+    permits: Semaphore,
+    size: usize,
+}
+
+impl BulkLimiter {
+    pub fn new(permits: usize) -> Self {
+        // zero permits would block bulk operations forever
+        let size = permits.max(1);
+        Self {
+            permits: Semaphore::new(size),
+            size,
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// waits until one of the bulk operations holding a permit is done,
+    /// hold the permit only while issuing the queries
+    pub async fn acquire(&self) -> SemaphorePermit<'_> {
+        self.permits
+            .acquire()
+            .await
+            .expect("bulk limiter semaphore is never closed")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[tokio::test]
+    async fn bulk_ops_dont_exceed_permits() {
+        use super::BulkLimiter;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::Arc;
+        use std::time::Duration;
+
+        let limiter = Arc::new(BulkLimiter::new(3));
+        let active = Arc::new(AtomicUsize::new(0));
+        let max_active = Arc::new(AtomicUsize::new(0));
+
+        let tasks: Vec<_> = (0..20)
+            .map(|_| {
+                let (limiter, active, max_active) =
+                    (limiter.clone(), active.clone(), max_active.clone());
+                tokio::spawn(async move {
+                    let _permit = limiter.acquire().await;
+                    let now = active.fetch_add(1, Ordering::SeqCst) + 1;
+                    max_active.fetch_max(now, Ordering::SeqCst);
+                    tokio::time::sleep(Duration::from_millis(10)).await;
+                    active.fetch_sub(1, Ordering::SeqCst);
+                })
+            })
+            .collect();
+        for t in tasks {
+            t.await.unwrap();
+        }
+
+        assert_eq!(max_active.load(Ordering::SeqCst), limiter.size());
+        assert_eq!(active.load(Ordering::SeqCst), 0);
+
+        assert_eq!(BulkLimiter::new(0).size(), 1);
+    }
+}
@@ -0,0 +1,11 @@
+CREATE TABLE IF NOT EXISTS indexer_anomalies (
+    id BIGSERIAL PRIMARY KEY,
+    block BIGINT NOT NULL,
+    tx_id INTEGER NOT NULL,
+    tx_hash VARCHAR NOT NULL,
+    kind VARCHAR NOT NULL,
+    reason VARCHAR NOT NULL,
+    created_at BIGINT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS indexer_anomalies_id_desc_idx ON indexer_anomalies (id DESC);
@@ -0,0 +1 @@
+ALTER TABLE submitted_txs ADD COLUMN IF NOT EXISTS attempts INTEGER NOT NULL DEFAULT 0;
@@ -0,0 +1,5 @@
+ALTER TABLE runes ADD COLUMN IF NOT EXISTS last_activity BIGINT NOT NULL DEFAULT 0;
+
+UPDATE runes SET last_activity = timestamp WHERE last_activity = 0;
+
+CREATE INDEX IF NOT EXISTS runes_last_activity_idx ON runes (last_activity DESC);
@@ -0,0 +1 @@
+CREATE INDEX IF NOT EXISTS runes_log_action_id_idx ON runes_log (action, id DESC);
@@ -0,0 +1,9 @@
+CREATE TABLE IF NOT EXISTS runes_commitments (
+    id BIGSERIAL PRIMARY KEY,
+    rune VARCHAR NOT NULL UNIQUE,
+    commitment_tx VARCHAR NOT NULL,
+    vout INTEGER NOT NULL,
+    pk_script VARCHAR NOT NULL,
+    commit_block BIGINT NOT NULL,
+    confirmations BIGINT NOT NULL
+);
@@ -0,0 +1 @@
+CREATE INDEX IF NOT EXISTS runes_utxos_outpoint_idx ON runes_utxos (tx_hash, output_n);
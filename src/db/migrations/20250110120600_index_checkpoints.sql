@@ -0,0 +1,11 @@
+CREATE TABLE IF NOT EXISTS index_checkpoints (
+    id BIGSERIAL PRIMARY KEY,
+    indexer VARCHAR NOT NULL,
+    height BIGINT NOT NULL,
+    block_hash VARCHAR NOT NULL,
+    runes_count BIGINT NOT NULL,
+    utxos_count BIGINT NOT NULL,
+    utxos_sum VARCHAR NOT NULL,
+    created_at BIGINT NOT NULL,
+    UNIQUE (indexer, height)
+);
@@ -0,0 +1,3 @@
+ALTER TABLE runes_utxos ADD COLUMN IF NOT EXISTS spent_block BIGINT;
+
+CREATE INDEX IF NOT EXISTS runes_utxos_rune_block_idx ON runes_utxos (rune, block);
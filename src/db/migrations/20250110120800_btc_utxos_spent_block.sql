@@ -0,0 +1 @@
+ALTER TABLE btc_utxos ADD COLUMN IF NOT EXISTS spent_block BIGINT;
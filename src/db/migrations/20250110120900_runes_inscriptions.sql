@@ -0,0 +1,9 @@
+CREATE TABLE IF NOT EXISTS runes_inscriptions (
+    id BIGSERIAL PRIMARY KEY,
+    rune VARCHAR NOT NULL UNIQUE,
+    inscription_id VARCHAR NOT NULL,
+    content_type VARCHAR,
+    content_length BIGINT NOT NULL,
+    content_hash VARCHAR NOT NULL,
+    block BIGINT NOT NULL
+);
@@ -0,0 +1,3 @@
+ALTER TABLE btc_utxos ADD COLUMN IF NOT EXISTS coinbase BOOLEAN NOT NULL DEFAULT false;
+-- the coinbase is always the first tx of the block
+UPDATE btc_utxos SET coinbase = true WHERE tx_id = 0;
@@ -0,0 +1,8 @@
+CREATE TABLE IF NOT EXISTS webhook_dead_letters (
+    id BIGSERIAL PRIMARY KEY,
+    event VARCHAR NOT NULL,
+    payload TEXT NOT NULL,
+    attempts INTEGER NOT NULL,
+    error VARCHAR NOT NULL,
+    created_at BIGINT NOT NULL
+);
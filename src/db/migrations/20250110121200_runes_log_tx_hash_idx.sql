@@ -0,0 +1 @@
+CREATE INDEX IF NOT EXISTS runes_log_tx_hash_idx ON runes_log (tx_hash);
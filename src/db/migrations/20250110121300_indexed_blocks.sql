@@ -0,0 +1,13 @@
+CREATE TABLE IF NOT EXISTS indexed_blocks (
+    indexer VARCHAR NOT NULL,
+    height BIGINT NOT NULL,
+    block_hash VARCHAR NOT NULL,
+    tx_count BIGINT NOT NULL,
+    etchings BIGINT,
+    mints BIGINT,
+    edicts BIGINT,
+    cenotaphs BIGINT,
+    burned_txs BIGINT,
+    indexed_at BIGINT NOT NULL,
+    PRIMARY KEY (indexer, height)
+);
@@ -0,0 +1,3 @@
+ALTER TABLE runes_log ADD COLUMN IF NOT EXISTS block BIGINT;
+
+CREATE INDEX IF NOT EXISTS runes_log_block_idx ON runes_log (block);
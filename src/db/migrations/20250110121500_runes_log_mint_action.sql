@@ -0,0 +1,8 @@
+-- mints were logged as 'etching' before RuneLog::MINT became 'mint',
+-- only the rows of the etching tx itself are etchings
+UPDATE runes_log SET action = 'mint'
+WHERE action = 'etching'
+  AND NOT EXISTS (
+    SELECT 1 FROM runes
+    WHERE runes.rune = runes_log.rune AND runes.etching_tx = runes_log.tx_hash
+  );
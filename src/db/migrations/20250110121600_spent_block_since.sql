@@ -0,0 +1,10 @@
+-- spends indexed before runes_utxos.spent_block was added have no height,
+-- snapshots below the height indexed at that point are incomplete
+CREATE TABLE IF NOT EXISTS spent_block_since (
+    indexer VARCHAR PRIMARY KEY,
+    height BIGINT NOT NULL
+);
+
+INSERT INTO spent_block_since (indexer, height)
+SELECT indexer, height FROM last_indexed_block
+ON CONFLICT DO NOTHING;
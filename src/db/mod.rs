@@ -1,45 +1,270 @@
 use sqlx::migrate::Migrator;
-use sqlx::postgres::PgPoolOptions;
+use sqlx::postgres::{PgArguments, PgPoolOptions};
 use sqlx::prelude::FromRow;
+use sqlx::query::Query;
+use sqlx::Executor;
 use sqlx::{PgPool, Postgres, QueryBuilder, Result};
+use std::time::Duration;
 
 use crate::config::DBConfig;
 
+mod bulk_limiter;
 mod models;
 mod seed_data;
 
+pub use bulk_limiter::BulkLimiter;
 pub use models::*;
-
-use seed_data::*;
+pub use seed_data::{reserved_rune, seed_rune};
 
 static MIGRATOR: Migrator = sqlx::migrate!("src/db/migrations");
 
+/// opens the db and migrates it, with `automigrate` off the schema must already be up to date
 pub async fn open_postgres_db(config: DBConfig) -> Result<Repo> {
+    let automigrate = config.automigrate;
+    let repo = connect_postgres_db(config).await?;
+    if automigrate {
+        repo.migrate().await?;
+    } else {
+        repo.check_schema_version().await?;
+    }
+    Ok(repo)
+}
+
+/// opens the db without migrating or checking the schema, for commands which recreate it
+pub async fn connect_postgres_db(config: DBConfig) -> Result<Repo> {
+    let pool_size = config.pool_size();
+    connect(config, pool_size, None).await
+}
+
+/// a pool for the API requests with the `statement_timeout` of the config,
+/// open it after `open_postgres_db` so migrations run without the timeout.
+/// Fails when it doesn't fit in postgres' `max_connections` next to the indexers' pool.
+pub async fn connect_api_db(config: DBConfig) -> Result<Repo> {
+    let statement_timeout = config.statement_timeout();
+    let pools = config.pool_size() + config.api_pool_size();
+    let api_pool_size = config.api_pool_size();
+    let repo = connect(config, api_pool_size, statement_timeout).await?;
+    repo.check_max_connections(pools).await?;
+    Ok(repo)
+}
+
+async fn connect(
+    config: DBConfig,
+    max_connections: u32,
+    statement_timeout: Option<Duration>,
+) -> Result<Repo> {
     let pool = PgPoolOptions::new()
-        .max_connections(100)
+        .max_connections(max_connections)
+        .after_connect(move |conn, _meta| {
+            Box::pin(async move {
+                if let Some(timeout) = statement_timeout {
+                    conn.execute(
+                        format!("SET statement_timeout = {}", timeout.as_millis()).as_str(),
+                    )
+                    .await?;
+                }
+                Ok(())
+            })
+        })
         .connect(&config.dsn)
         .await?;
-    let repo = Repo { pool };
-    if config.automigrate {
-        repo.migrate().await?;
-    }
+    let repo = Repo {
+        pool,
+        max_name_filter_len: config
+            .max_name_filter_len
+            .unwrap_or(DEFAULT_MAX_NAME_FILTER_LEN),
+        bulk: BulkLimiter::new(config.bulk_permits()),
+    };
     Ok(repo)
 }
 
+/// outpoints as the `tx_hash` and `output_n` arrays bound to `UNNEST`
+fn outpoint_columns(outpoints: &[bitcoin::OutPoint]) -> (Vec<String>, Vec<i32>) {
+    outpoints
+        .iter()
+        .map(|o| (o.txid.to_string(), o.vout as i32))
+        .unzip()
+}
+
+/// the query was cancelled by the `statement_timeout` of the pool
+pub fn is_statement_timeout(err: &sqlx::Error) -> bool {
+    match err {
+        sqlx::Error::Database(e) => e.code().as_deref() == Some(QUERY_CANCELED),
+        _ => false,
+    }
+}
+
+/// postgres `query_canceled` error code
+const QUERY_CANCELED: &str = "57014";
+
+#[derive(FromRow)]
+struct Version {
+    version: i64,
+}
+
+#[derive(FromRow)]
+struct Exists {
+    exists: bool,
+}
+
 #[derive(FromRow)]
 struct Count {
     count: i64,
 }
 
+#[derive(FromRow)]
+struct Total {
+    total: String,
+}
+
+#[derive(FromRow)]
+struct Sum {
+    sum: i64,
+}
+
+#[derive(FromRow)]
+struct RuneTotal {
+    rune: String,
+    total: String,
+}
+
+#[derive(FromRow)]
+struct AddressTotal {
+    address: String,
+    total: String,
+}
+
+#[derive(FromRow)]
+struct BucketTotal {
+    bucket: i32,
+    count: i64,
+    total: String,
+}
+
+const DEFAULT_MAX_NAME_FILTER_LEN: usize = 64;
+
 pub struct Repo {
     pub pool: PgPool,
+    max_name_filter_len: usize,
+    bulk: BulkLimiter,
+}
+
+/// escapes LIKE metacharacters so user input matches literally
+fn escape_like(filter: &str) -> String {
+    let mut res = String::with_capacity(filter.len());
+    for c in filter.chars() {
+        if c == '\\' || c == '%' || c == '_' {
+            res.push('\\');
+        }
+        res.push(c);
+    }
+    res
 }
 
 impl Repo {
+    /// permit for bulk db work, interactive queries don't take one
+    pub async fn bulk_permit(&self) -> tokio::sync::SemaphorePermit<'_> {
+        self.bulk.acquire().await
+    }
+
+    /// builds an ILIKE pattern from the user provided filter
+    fn name_pattern(&self, filter: &str, prefix: bool) -> String {
+        let filter: String = filter.chars().take(self.max_name_filter_len).collect();
+        if prefix {
+            format!("{}%", escape_like(&filter))
+        } else {
+            format!("%{}%", escape_like(&filter))
+        }
+    }
+
     pub async fn migrate(&self) -> Result<()> {
         MIGRATOR.run(&self.pool).await?;
         Ok(())
     }
+    /// fails when `connections` don't fit in the connection slots of the server,
+    /// the ones reserved for superusers aren't available to the pools
+    pub async fn check_max_connections(&self, connections: u32) -> Result<()> {
+        let (max, reserved): (String, String) = sqlx::query_as(
+            "SELECT current_setting('max_connections'), \
+             current_setting('superuser_reserved_connections')",
+        )
+        .fetch_one(&self.pool)
+        .await?;
+        let available = available_connections(&max, &reserved).ok_or_else(|| {
+            sqlx::Error::Configuration(
+                format!(
+                    "can't read max_connections={} superuser_reserved_connections={}",
+                    max, reserved
+                )
+                .into(),
+            )
+        })?;
+
+        if connections > available {
+            return Err(sqlx::Error::Configuration(
+                format!(
+                    "the db pools need {} connections, postgres allows {} \
+                     (max_connections={} superuser_reserved_connections={}), \
+                     lower db.pool_size and db.api_pool_size or raise max_connections",
+                    connections, available, max, reserved
+                )
+                .into(),
+            ));
+        }
+        info!(
+            "DB pools connections={} available={}",
+            connections, available
+        );
+        Ok(())
+    }
+
+    /// fails when migrations known to this build haven't been applied to the db
+    pub async fn check_schema_version(&self) -> Result<()> {
+        let known: Vec<i64> = MIGRATOR.iter().map(|m| m.version).collect();
+        let applied = self.applied_migrations().await?;
+        let expected = known.iter().max().copied().unwrap_or_default();
+        let current = applied.iter().max().copied().unwrap_or_default();
+
+        let missing = missing_migrations(&known, &applied);
+        if missing.is_empty() {
+            info!("DB schema version={} expected={}", current, expected);
+            return Ok(());
+        }
+
+        error!(
+            "DB schema is behind: version={} expected={} missing={:?}",
+            current, expected, missing
+        );
+        Err(sqlx::Error::Configuration(
+            format!(
+                "database schema version {} is behind the expected {}, {} migrations are missing, \
+                 apply them or enable db.automigrate",
+                current,
+                expected,
+                missing.len()
+            )
+            .into(),
+        ))
+    }
+
+    async fn applied_migrations(&self) -> Result<Vec<i64>> {
+        let table = sqlx::query_as::<_, Exists>(
+            "SELECT to_regclass('_sqlx_migrations') IS NOT NULL AS exists",
+        )
+        .fetch_one(&self.pool)
+        .await?;
+        if !table.exists {
+            return Ok(Vec::new());
+        }
+
+        let result = sqlx::query_as::<_, Version>(
+            "SELECT version FROM _sqlx_migrations WHERE success ORDER BY version",
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(result.into_iter().map(|v| v.version).collect())
+    }
+
     pub async fn reset_schema(&self) -> Result<()> {
         let _ = sqlx::query("DROP SCHEMA public CASCADE")
             .execute(&self.pool)
@@ -52,9 +277,59 @@ impl Repo {
         Ok(())
     }
 
-    pub async fn insert_seed_data(&self) -> Result<()> {
-        let rune_row = reserved_rune();
-        self.insert_rune(&rune_row).await?;
+    /// `table` is interpolated into the query, pass only known table names
+    pub async fn count_table_rows(&self, table: &str) -> Result<i64> {
+        let result =
+            sqlx::query_as::<_, Count>(&format!("SELECT count(*) as count FROM {}", table))
+                .fetch_one(&self.pool)
+                .await?;
+        Ok(result.count)
+    }
+
+    /// `tables` are interpolated into the query, pass only known table names
+    pub async fn truncate_tables(&self, tables: &[&str]) -> Result<()> {
+        let _ = sqlx::query(&format!("TRUNCATE TABLE {}", tables.join(", ")))
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn reset_btc_balances(&self) -> Result<()> {
+        let _ = sqlx::query("UPDATE btc_watchlist SET balance = 0")
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    /// version of the latest applied migration
+    pub async fn schema_version(&self) -> Result<i64> {
+        let result = sqlx::query_as::<_, Count>(
+            "SELECT COALESCE(MAX(version), 0) as count FROM _sqlx_migrations WHERE success = true",
+        )
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(result.count)
+    }
+
+    /// all reads of the export see the same state, the indexer can keep running
+    pub async fn begin_snapshot_export(&self) -> Result<SnapshotExport> {
+        let mut tx = self.pool.begin().await?;
+        let _ = sqlx::query("SET TRANSACTION ISOLATION LEVEL REPEATABLE READ, READ ONLY")
+            .execute(&mut *tx)
+            .await?;
+        Ok(SnapshotExport { tx })
+    }
+
+    pub async fn begin_snapshot_import(&self) -> Result<SnapshotImport> {
+        Ok(SnapshotImport {
+            tx: self.pool.begin().await?,
+        })
+    }
+
+    pub async fn insert_seed_data(&self, seed_runes: &[Rune]) -> Result<()> {
+        for rune in seed_runes.iter() {
+            self.insert_rune(rune).await?;
+        }
         Ok(())
     }
 
@@ -69,6 +344,17 @@ impl Repo {
         Ok(result)
     }
 
+    /// first height with complete spend heights, 0 if the index was built with `spent_block`
+    pub async fn get_spent_block_since(&self, indexer_id: &str) -> Result<i64> {
+        let result = sqlx::query_as::<_, Count>(
+            "SELECT COALESCE((SELECT height FROM spent_block_since WHERE indexer = $1), 0) as count",
+        )
+        .bind(indexer_id)
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(result.count)
+    }
+
     pub async fn get_last_indexed_blocks(&self) -> Result<Vec<LastIndexedBlock>> {
         let result = sqlx::query_as::<_, LastIndexedBlock>("SELECT * FROM last_indexed_block")
             .fetch_all(&self.pool)
@@ -77,15 +363,6 @@ impl Repo {
         Ok(result)
     }
 
-    pub async fn update_last_indexed_block(&self, height: i64, indexer_id: &str) -> Result<()> {
-        let _result = sqlx::query("UPDATE last_indexed_block SET height = $1 WHERE indexer = $2")
-            .bind(height)
-            .bind(indexer_id)
-            .execute(&self.pool)
-            .await?;
-        Ok(())
-    }
-
     pub async fn get_rune(&self, rune: &str) -> Result<Rune> {
         let result = sqlx::query_as::<_, Rune>("SELECT * FROM runes WHERE rune = $1")
             .bind(rune)
@@ -95,6 +372,79 @@ impl Repo {
         Ok(result)
     }
 
+    pub async fn insert_rune_commitment(&self, row: &RuneCommitment) -> Result<()> {
+        let _ = sqlx::query(
+            "INSERT INTO runes_commitments (rune, commitment_tx, vout, pk_script, commit_block, confirmations)
+             VALUES ($1, $2, $3, $4, $5, $6) ON CONFLICT DO NOTHING",
+        )
+        .bind(&row.rune)
+        .bind(&row.commitment_tx)
+        .bind(row.vout)
+        .bind(&row.pk_script)
+        .bind(row.commit_block)
+        .bind(row.confirmations)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn get_rune_commitment(&self, rune: &str) -> Result<RuneCommitment> {
+        let result =
+            sqlx::query_as::<_, RuneCommitment>("SELECT * FROM runes_commitments WHERE rune = $1")
+                .bind(rune)
+                .fetch_one(&self.pool)
+                .await?;
+
+        Ok(result)
+    }
+
+    pub async fn insert_rune_inscription(&self, row: &RuneInscription) -> Result<()> {
+        let _ = sqlx::query(
+            "INSERT INTO runes_inscriptions (rune, inscription_id, content_type, content_length, content_hash, block)
+             VALUES ($1, $2, $3, $4, $5, $6) ON CONFLICT DO NOTHING",
+        )
+        .bind(&row.rune)
+        .bind(&row.inscription_id)
+        .bind(&row.content_type)
+        .bind(row.content_length)
+        .bind(&row.content_hash)
+        .bind(row.block)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn get_rune_inscription(&self, rune: &str) -> Result<RuneInscription> {
+        let result = sqlx::query_as::<_, RuneInscription>(
+            "SELECT * FROM runes_inscriptions WHERE rune = $1",
+        )
+        .bind(rune)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(result)
+    }
+
+    pub async fn select_runes_etched_in_tx(&self, tx_hash: &str) -> Result<Vec<Rune>> {
+        let result = sqlx::query_as::<_, Rune>("SELECT * FROM runes WHERE etching_tx = $1")
+            .bind(tx_hash)
+            .fetch_all(&self.pool)
+            .await?;
+
+        Ok(result)
+    }
+
+    pub async fn select_random_runes(&self, limit: i32) -> Result<Vec<Rune>> {
+        let result = sqlx::query_as::<_, Rune>("SELECT * FROM runes ORDER BY random() LIMIT $1")
+            .bind(limit)
+            .fetch_all(&self.pool)
+            .await?;
+
+        Ok(result)
+    }
+
     pub async fn get_rune_by_id(&self, block: i64, tx: i32) -> Result<Rune> {
         let result =
             sqlx::query_as::<_, Rune>("SELECT * FROM runes WHERE block = $1 AND tx_id = $2")
@@ -112,16 +462,27 @@ impl Repo {
         limit: i32,
         offset: i32,
         name: Option<String>,
+    ) -> Result<Vec<Rune>> {
+        self.list_runes_filtered(order, limit, offset, name, false)
+            .await
+    }
+
+    /// `active_only` skips runes without circulating supply, e.g. fully burned ones
+    pub async fn list_runes_filtered(
+        &self,
+        order: &str,
+        limit: i32,
+        offset: i32,
+        name: Option<String>,
+        active_only: bool,
     ) -> Result<Vec<Rune>> {
         let mut q: QueryBuilder<Postgres> = QueryBuilder::new("SELECT * FROM runes ");
-        if let Some(np) = name {
-            let p = format!("%{}%", np);
-            q.push(" WHERE rune ILIKE ");
-            q.push_bind(p.clone());
-        }
+        self.push_runes_filter(&mut q, name, active_only);
 
         if order == "DESC" {
             q.push(" ORDER BY block DESC, tx_id DESC ");
+        } else if order == "ACTIVITY" {
+            q.push(" ORDER BY last_activity DESC, block DESC, tx_id DESC ");
         } else {
             q.push(" ORDER BY block ASC, tx_id ASC ");
         }
@@ -134,71 +495,135 @@ impl Repo {
         Ok(result)
     }
 
+    /// runes ranked by `holders`, `supply` (in circulation) or `mints`, ties go to the older rune,
+    /// holders are the balances >= `min_holder_balance`
+    pub async fn select_top_runes(
+        &self,
+        by: &str,
+        limit: i32,
+        min_holder_balance: &str,
+    ) -> Result<Vec<TopRune>> {
+        let q = match by {
+            "holders" => {
+                "SELECT runes.*, h.holders FROM runes JOIN (
+                    SELECT rune, count(*) as holders FROM runes_balances
+                    WHERE balance::NUMERIC >= $2::NUMERIC GROUP BY rune
+                ) h ON h.rune = runes.rune
+                ORDER BY h.holders DESC, runes.block ASC, runes.tx_id ASC LIMIT $1"
+            }
+            "supply" => {
+                "SELECT *, NULL::BIGINT as holders FROM runes
+                ORDER BY in_circulation::NUMERIC DESC, block ASC, tx_id ASC LIMIT $1"
+            }
+            _ => {
+                "SELECT *, NULL::BIGINT as holders FROM runes
+                ORDER BY mints DESC, block ASC, tx_id ASC LIMIT $1"
+            }
+        };
+
+        let mut q = sqlx::query_as::<_, TopRune>(q).bind(limit);
+        if by == "holders" {
+            q = q.bind(min_holder_balance);
+        }
+        let result = q.fetch_all(&self.pool).await?;
+        Ok(result)
+    }
+
     pub async fn count_runes(&self, name_filter: Option<String>) -> Result<i64> {
+        self.count_runes_filtered(name_filter, false).await
+    }
+
+    pub async fn count_runes_filtered(
+        &self,
+        name_filter: Option<String>,
+        active_only: bool,
+    ) -> Result<i64> {
         let mut q: QueryBuilder<Postgres> =
             QueryBuilder::new("SELECT count(*) as count FROM runes ");
-        if let Some(np) = name_filter {
-            let p = format!("%{}%", np);
-            q.push(" WHERE rune ILIKE ");
-            q.push_bind(p.clone());
-        }
+        self.push_runes_filter(&mut q, name_filter, active_only);
 
         let result = q.build_query_as::<Count>().fetch_one(&self.pool).await?;
 
         Ok(result.count)
     }
 
-    pub async fn search_runes(&self, pattern: &str) -> Result<Vec<Rune>> {
-        let q = "SELECT * FROM runes WHERE rune ILIKE $1 ORDER BY block ASC, tx_id ASC LIMIT 50";
-        let p = format!("{}%", pattern);
+    fn push_runes_filter(
+        &self,
+        q: &mut QueryBuilder<Postgres>,
+        name: Option<String>,
+        active_only: bool,
+    ) {
+        let mut sep = " WHERE ";
+        if let Some(np) = name {
+            let p = self.name_pattern(&np, false);
+            q.push(sep);
+            q.push(" rune ILIKE ");
+            q.push_bind(p);
+            sep = " AND ";
+        }
+        if active_only {
+            q.push(sep);
+            q.push(" in_circulation::NUMERIC > 0 ");
+        }
+    }
+
+    pub async fn search_runes(&self, pattern: &str, limit: i32) -> Result<Vec<Rune>> {
+        let q = "SELECT * FROM runes WHERE rune ILIKE $1 ORDER BY block ASC, tx_id ASC LIMIT $2";
+        let p = self.name_pattern(pattern, true);
         let result = sqlx::query_as::<_, Rune>(q)
             .bind(&p)
+            .bind(limit)
             .fetch_all(&self.pool)
             .await?;
         Ok(result)
     }
 
-    pub async fn insert_rune(&self, rune: &Rune) -> Result<()> {
-        let _ = sqlx::query(
-            "INSERT INTO runes (
-                    rune,
-                    display_name,
-                    symbol,
-                    block,
-                    tx_id,
-                    mints,
-                    max_supply,
-                    minted,
-                    in_circulation,
-                    divisibility,
-                    turbo,
-                    timestamp,
-                    etching_tx,
-                    commitment_tx,
-                    raw_data,
-                    premine,
-                    burned)
-                  VALUES($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17)",
-        )
-        .bind(&rune.rune)
-        .bind(&rune.display_name)
-        .bind(&rune.symbol)
-        .bind(rune.block)
-        .bind(rune.tx_id)
-        .bind(rune.mints)
-        .bind(&rune.max_supply)
-        .bind(&rune.minted)
-        .bind(&rune.in_circulation)
-        .bind(rune.divisibility)
-        .bind(rune.turbo)
-        .bind(rune.timestamp)
-        .bind(&rune.etching_tx)
-        .bind(&rune.commitment_tx)
-        .bind(&rune.raw_data)
-        .bind(&rune.premine)
-        .bind(&rune.burned)
-        .execute(&self.pool)
+    pub async fn select_runes_in_block_range(&self, from: i64, to: i64) -> Result<Vec<Rune>> {
+        let result = sqlx::query_as::<_, Rune>(
+            "SELECT * FROM runes WHERE block BETWEEN $1 AND $2 ORDER BY block ASC, tx_id ASC",
+        )
+        .bind(from)
+        .bind(to)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(result)
+    }
+
+    /// Number of rune outputs created per rune and action in the block range: `etching` for
+    /// the premine, `mint`, or `transfer` for edict and change outputs. Mints are told apart
+    /// by their rune log entry, with the log disabled they count as transfers.
+    pub async fn select_rune_activity_in_block_range(
+        &self,
+        from: i64,
+        to: i64,
+    ) -> Result<Vec<RuneBlockActivity>> {
+        let result = sqlx::query_as::<_, RuneBlockActivity>(
+            "SELECT u.rune,
+                CASE
+                    WHEN u.tx_hash = r.etching_tx THEN 'etching'
+                    WHEN EXISTS (
+                        SELECT 1 FROM runes_log l
+                        WHERE l.tx_hash = u.tx_hash AND l.rune = u.rune AND l.action = $3
+                    ) THEN 'mint'
+                    ELSE 'transfer'
+                END as action,
+                count(*) as outputs
+             FROM runes_utxos u LEFT JOIN runes r ON r.rune = u.rune
+             WHERE u.block BETWEEN $1 AND $2
+             GROUP BY u.rune, action ORDER BY outputs DESC, u.rune ASC, action ASC",
+        )
+        .bind(from)
+        .bind(to)
+        .bind(RuneLog::MINT)
+        .fetch_all(&self.pool)
         .await?;
+        Ok(result)
+    }
+
+    pub async fn insert_rune(&self, rune: &Rune) -> Result<()> {
+        let _ = insert_rune_query(rune, rune.timestamp)
+            .execute(&self.pool)
+            .await?;
 
         Ok(())
     }
@@ -236,52 +661,75 @@ impl Repo {
             .await?;
         Ok(())
     }
+
+    /// moves `last_activity` of the rune forward to the given block time
+    pub async fn touch_rune_activity(&self, rune: &str, timestamp: i64) -> Result<()> {
+        let _ = sqlx::query(
+            "UPDATE runes SET last_activity = GREATEST(last_activity, $2) WHERE rune = $1",
+        )
+        .bind(rune)
+        .bind(timestamp)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
     pub async fn insert_rune_log(&self, entry: &RuneLog) -> Result<()> {
         let _ = sqlx::query(
-            "INSERT INTO runes_log (tx_hash, rune, address, action, value)
-             VALUES($1, $2, $3, $4, $5)",
+            "INSERT INTO runes_log (tx_hash, rune, address, action, value, block)
+             VALUES($1, $2, $3, $4, $5, $6)",
         )
         .bind(&entry.tx_hash)
         .bind(&entry.rune)
         .bind(&entry.address)
         .bind(&entry.action)
         .bind(&entry.value)
+        .bind(entry.block)
         .execute(&self.pool)
         .await?;
 
         Ok(())
     }
 
-    pub async fn insert_rune_utxo(&self, rb: &RuneUtxo) -> Result<()> {
-        let _ = sqlx::query(
-            "INSERT INTO runes_utxos (
-              block, tx_id, tx_hash, output_n, rune, address, pk_script, amount, btc_amount, spend)
-             VALUES($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)",
+    pub async fn select_rune_log_by_tx(&self, tx_hash: &str) -> Result<Vec<RuneLog>> {
+        let result = sqlx::query_as::<_, RuneLog>(
+            "SELECT * FROM runes_log WHERE tx_hash = $1 ORDER BY id ASC",
         )
-        .bind(rb.block)
-        .bind(rb.tx_id)
-        .bind(&rb.tx_hash)
-        .bind(rb.output_n)
-        .bind(&rb.rune)
-        .bind(&rb.address)
-        .bind(&rb.pk_script)
-        .bind(&rb.amount)
-        .bind(rb.btc_amount)
-        .bind(rb.spend)
-        .execute(&self.pool)
+        .bind(tx_hash)
+        .fetch_all(&self.pool)
         .await?;
 
-        Ok(())
+        Ok(result)
     }
 
-    pub async fn count_runes_utxo(&self, rune: &str, address: Option<String>) -> Result<i64> {
-        let mut q: QueryBuilder<Postgres> =
-            QueryBuilder::new("SELECT count(*) as count FROM runes_utxos WHERE spend = false ");
-        q.push(" AND rune = ");
-        q.push_bind(rune);
+    pub async fn select_global_rune_log(
+        &self,
+        action: Option<String>,
+        limit: i32,
+        offset: i32,
+    ) -> Result<Vec<RuneLog>> {
+        let mut q: QueryBuilder<Postgres> = QueryBuilder::new("SELECT * FROM runes_log ");
+        if let Some(a) = action {
+            q.push(" WHERE action = ");
+            q.push_bind(a);
+        }
 
-        if let Some(a) = address {
-            q.push(" AND address = ");
+        q.push(" ORDER BY id DESC ");
+        q.push(" LIMIT ");
+        q.push_bind(limit);
+        q.push(" OFFSET ");
+        q.push_bind(offset);
+
+        let result = q.build_query_as::<RuneLog>().fetch_all(&self.pool).await?;
+        Ok(result)
+    }
+
+    pub async fn count_global_rune_log(&self, action: Option<String>) -> Result<i64> {
+        let mut q: QueryBuilder<Postgres> =
+            QueryBuilder::new("SELECT count(*) as count FROM runes_log ");
+        if let Some(a) = action {
+            q.push(" WHERE action = ");
             q.push_bind(a);
         }
 
@@ -289,51 +737,172 @@ impl Repo {
         Ok(result.count)
     }
 
-    pub async fn select_runes_utxo_with_pagination(
-        &self,
-        rune: &str,
-        address: Option<String>,
-        order: &str,
-        limit: i32,
-        offset: i32,
-    ) -> Result<Vec<RuneUtxo>> {
-        let mut q: QueryBuilder<Postgres> =
-            QueryBuilder::new("SELECT * FROM runes_utxos WHERE spend = false ");
-
-        q.push(" AND rune = ");
-        q.push_bind(rune);
+    pub async fn insert_rune_utxo(&self, rb: &RuneUtxo) -> Result<()> {
+        let _ = insert_rune_utxo_query(rb).execute(&self.pool).await?;
 
-        if let Some(a) = address {
-            q.push(" AND address = ");
-            q.push_bind(a);
-        }
+        Ok(())
+    }
 
-        if order == "DESC" {
-            q.push(" ORDER BY block DESC, tx_id DESC  ");
-        } else {
-            q.push(" ORDER BY block ASC, tx_id ASC ");
-        }
-        q.push(" LIMIT ");
-        q.push_bind(limit);
-        q.push(" OFFSET ");
-        q.push_bind(offset);
+    pub async fn count_runes_utxo(&self, rune: &str, address: Option<String>) -> Result<i64> {
+        let mut q = count_runes_utxo_query(rune, address);
+        let result = q.build_query_as::<Count>().fetch_one(&self.pool).await?;
+        Ok(result.count)
+    }
 
+    pub async fn select_runes_utxo_with_pagination(
+        &self,
+        rune: &str,
+        address: Option<String>,
+        order: &str,
+        limit: i32,
+        offset: i32,
+    ) -> Result<Vec<RuneUtxo>> {
+        let mut q = select_runes_utxo_query(rune, address, order, limit, offset);
         let result = q.build_query_as::<RuneUtxo>().fetch_all(&self.pool).await?;
         Ok(result)
     }
 
-    pub async fn spent_rune_utxo(&self, rune: &str, tx_hash: &str, vout: i32) -> Result<()> {
-        let _ =
-            sqlx::query("UPDATE runes_utxos SET spend = true WHERE tx_hash = $1 AND output_n = $2 AND rune = $3")
-                .bind(tx_hash)
-                .bind(vout)
-            .bind(rune)
-                .execute(&self.pool)
-                .await?;
+    /// returns the next batch of unspent rune utxos with id greater than `after_id`
+    pub async fn select_runes_utxo_batch(
+        &self,
+        rune: &str,
+        after_id: i64,
+        limit: i32,
+    ) -> Result<Vec<RuneUtxo>> {
+        let result = sqlx::query_as::<_, RuneUtxo>(
+            "SELECT * FROM runes_utxos WHERE spend = false AND rune = $1 AND id > $2
+             ORDER BY id ASC LIMIT $3",
+        )
+        .bind(rune)
+        .bind(after_id)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(result)
+    }
+
+    /// all rune utxos of the outpoint, one outpoint can carry several runes
+    pub async fn get_rune_utxos_by_outpoint(
+        &self,
+        tx_hash: &str,
+        vout: i32,
+    ) -> Result<Vec<RuneUtxo>> {
+        let result = sqlx::query_as::<_, RuneUtxo>(
+            "SELECT * FROM runes_utxos WHERE tx_hash = $1 AND output_n = $2 ORDER BY rune ASC",
+        )
+        .bind(tx_hash)
+        .bind(vout)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(result)
+    }
+
+    /// `spent_block` is the block of the spending tx
+    pub async fn spent_rune_utxo(
+        &self,
+        rune: &str,
+        tx_hash: &str,
+        vout: i32,
+        spent_block: i64,
+    ) -> Result<()> {
+        let _ = sqlx::query(
+            "UPDATE runes_utxos SET spend = true, spent_block = $4
+            WHERE tx_hash = $1 AND output_n = $2 AND rune = $3",
+        )
+        .bind(tx_hash)
+        .bind(vout)
+        .bind(rune)
+        .bind(spent_block)
+        .execute(&self.pool)
+        .await?;
 
         Ok(())
     }
 
+    /// Rune utxos which existed and were unspent at the end of block `height`.
+    /// Spends indexed before `spent_block` was recorded have no height, such utxos
+    /// are treated as spent, so heights below `get_spent_block_since` are incomplete.
+    pub async fn select_runes_utxo_at_height(
+        &self,
+        rune: &str,
+        height: i64,
+        limit: i32,
+        offset: i32,
+    ) -> Result<Vec<RuneUtxo>> {
+        let result = sqlx::query_as::<_, RuneUtxo>(
+            "SELECT * FROM runes_utxos
+            WHERE rune = $1 AND block <= $2 AND (spend = false OR spent_block > $2)
+            ORDER BY block ASC, tx_id ASC, output_n ASC LIMIT $3 OFFSET $4",
+        )
+        .bind(rune)
+        .bind(height)
+        .bind(limit)
+        .bind(offset)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(result)
+    }
+
+    pub async fn count_runes_utxo_at_height(&self, rune: &str, height: i64) -> Result<i64> {
+        let result = sqlx::query_as::<_, Count>(
+            "SELECT count(*) as count FROM runes_utxos
+            WHERE rune = $1 AND block <= $2 AND (spend = false OR spent_block > $2)",
+        )
+        .bind(rune)
+        .bind(height)
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(result.count)
+    }
+
+    /// per address sum of the rune utxos unspent at the end of block `height`,
+    /// addresses without such utxos are omitted
+    pub async fn sum_runes_utxo_at_height(
+        &self,
+        rune: &str,
+        height: i64,
+        addresses: &[String],
+    ) -> Result<Vec<(String, String)>> {
+        let result = sqlx::query_as::<_, AddressTotal>(
+            "SELECT address, COALESCE(SUM(amount::NUMERIC), 0)::TEXT as total FROM runes_utxos
+            WHERE rune = $1 AND address = ANY($3) AND block <= $2
+              AND (spend = false OR spent_block > $2)
+            GROUP BY address",
+        )
+        .bind(rune)
+        .bind(height)
+        .bind(addresses)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(result.into_iter().map(|r| (r.address, r.total)).collect())
+    }
+
+    /// unspent utxos of the rune grouped by amount, as `(bucket, count, total)` of the non-empty
+    /// buckets: bucket 0 is below `bounds[0]`, bucket i is `[bounds[i-1], bounds[i])`
+    pub async fn count_runes_utxo_by_amount(
+        &self,
+        rune: &str,
+        bounds: &[String],
+    ) -> Result<Vec<(i32, i64, String)>> {
+        let result = sqlx::query_as::<_, BucketTotal>(
+            "SELECT width_bucket(amount::NUMERIC, $2::TEXT[]::NUMERIC[]) as bucket, count(*) as count,
+              COALESCE(SUM(amount::NUMERIC), 0)::TEXT as total
+            FROM runes_utxos WHERE rune = $1 AND spend = false
+            GROUP BY bucket ORDER BY bucket",
+        )
+        .bind(rune)
+        .bind(bounds)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(result
+            .into_iter()
+            .map(|r| (r.bucket, r.count, r.total))
+            .collect())
+    }
+
     pub async fn insert_runes_balance(
         &self,
         rune: &str,
@@ -418,6 +987,135 @@ impl Repo {
         Ok(result)
     }
 
+    pub async fn count_runes_holders(&self, rune: &str, min_balance: &str) -> Result<i64> {
+        let result = sqlx::query_as::<_, Count>(
+            "SELECT count(*) as count FROM runes_balances
+            WHERE rune = $1 AND balance::NUMERIC >= $2::NUMERIC",
+        )
+        .bind(rune)
+        .bind(min_balance)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(result.count)
+    }
+
+    /// `select_runes_holders` in address order, the holders page of the api
+    pub async fn select_runes_holders_by_address(
+        &self,
+        rune: &str,
+        min_balance: &str,
+        limit: i32,
+        offset: i32,
+    ) -> Result<Vec<RunesBalance>> {
+        let result = sqlx::query_as::<_, RunesBalance>(
+            "SELECT * FROM runes_balances WHERE rune = $1 AND balance::NUMERIC >= $2::NUMERIC
+            ORDER BY address ASC LIMIT $3 OFFSET $4",
+        )
+        .bind(rune)
+        .bind(min_balance)
+        .bind(limit)
+        .bind(offset)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(result)
+    }
+
+    /// holders of the rune with balance >= `min_balance`, largest first
+    pub async fn select_runes_holders(
+        &self,
+        rune: &str,
+        min_balance: &str,
+        limit: i32,
+        offset: i32,
+    ) -> Result<Vec<RunesBalance>> {
+        let result = sqlx::query_as::<_, RunesBalance>(
+            "SELECT * FROM runes_balances WHERE rune = $1 AND balance::NUMERIC >= $2::NUMERIC
+            ORDER BY balance::NUMERIC DESC, address ASC LIMIT $3 OFFSET $4",
+        )
+        .bind(rune)
+        .bind(min_balance)
+        .bind(limit)
+        .bind(offset)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(result)
+    }
+
+    /// balance of every address of the rune recomputed from `runes_log`: etchings, mints and
+    /// incomes add, expences and burns subtract. Burns logged without an address end up as `""`.
+    pub async fn replay_runes_log(&self, rune: &str) -> Result<Vec<(String, String)>> {
+        let result = sqlx::query_as::<_, AddressTotal>(
+            "SELECT address, COALESCE(SUM(CASE WHEN action = ANY($2) THEN value::NUMERIC
+                ELSE -value::NUMERIC END), 0)::TEXT as total
+            FROM runes_log WHERE rune = $1 AND (action = ANY($2) OR action = ANY($3))
+            GROUP BY address ORDER BY address ASC",
+        )
+        .bind(rune)
+        .bind(&[RuneLog::ETCHING, RuneLog::MINT, RuneLog::INCOME][..])
+        .bind(&[RuneLog::EXPENCE, RuneLog::BURN][..])
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(result.into_iter().map(|r| (r.address, r.total)).collect())
+    }
+
+    /// sum of all balances of the rune as a decimal string
+    pub async fn sum_runes_balances(&self, rune: &str) -> Result<String> {
+        let result = sqlx::query_as::<_, Total>(
+            "SELECT COALESCE(SUM(balance::NUMERIC), 0)::TEXT as total FROM runes_balances WHERE rune = $1",
+        )
+        .bind(rune)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(result.total)
+    }
+
+    /// sum of all unspent utxos of the rune as a decimal string
+    pub async fn sum_unspent_runes_utxo(&self, rune: &str) -> Result<String> {
+        let result = sqlx::query_as::<_, Total>(
+            "SELECT COALESCE(SUM(amount::NUMERIC), 0)::TEXT as total FROM runes_utxos WHERE rune = $1 AND spend = false",
+        )
+        .bind(rune)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(result.total)
+    }
+
+    /// per rune sum of the address's unspent utxos created above `block`
+    pub async fn sum_runes_utxo_above_block(
+        &self,
+        address: &str,
+        block: i64,
+    ) -> Result<Vec<(String, String)>> {
+        let result = sqlx::query_as::<_, RuneTotal>(
+            "SELECT rune, COALESCE(SUM(amount::NUMERIC), 0)::TEXT as total FROM runes_utxos
+            WHERE address = $1 AND spend = false AND block > $2 GROUP BY rune",
+        )
+        .bind(address)
+        .bind(block)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(result.into_iter().map(|r| (r.rune, r.total)).collect())
+    }
+
+    /// sum of the address's unspent btc utxos created above `block`
+    pub async fn sum_btc_utxo_above_block(&self, address: &str, block: i64) -> Result<i64> {
+        let result = sqlx::query_as::<_, Sum>(
+            "SELECT COALESCE(SUM(amount), 0)::BIGINT as sum FROM btc_utxos
+            WHERE address = $1 AND spend = false AND block > $2",
+        )
+        .bind(address)
+        .bind(block)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(result.sum)
+    }
+
     pub async fn insert_btc_balance(&self, address: &str) -> Result<()> {
         let _ = sqlx::query(
             "INSERT INTO btc_watchlist (address, balance) VALUES ($1, 0) ON CONFLICT DO NOTHING",
@@ -458,8 +1156,8 @@ impl Repo {
     pub async fn insert_btc_utxo(&self, rb: &BtcUtxo) -> Result<()> {
         let _ = sqlx::query(
             "INSERT INTO btc_utxos (
-              block, tx_id, tx_hash, output_n, address, pk_script, amount, spend)
-             VALUES($1, $2, $3, $4, $5, $6, $7, $8)",
+              block, tx_id, tx_hash, output_n, address, pk_script, amount, spend, coinbase)
+             VALUES($1, $2, $3, $4, $5, $6, $7, $8, $9)",
         )
         .bind(rb.block)
         .bind(rb.tx_id)
@@ -469,6 +1167,7 @@ impl Repo {
         .bind(&rb.pk_script)
         .bind(rb.amount)
         .bind(rb.spend)
+        .bind(rb.coinbase)
         .execute(&self.pool)
         .await?;
 
@@ -526,6 +1225,46 @@ impl Repo {
         Ok(result)
     }
 
+    /// unspent utxos of `address` among `outpoints`, looked up in one query
+    pub async fn select_unspent_btc_utxos_at(
+        &self,
+        address: &str,
+        outpoints: &[bitcoin::OutPoint],
+    ) -> Result<Vec<BtcUtxo>> {
+        let (tx_hashes, vouts) = outpoint_columns(outpoints);
+        let result = sqlx::query_as::<_, BtcUtxo>(
+            "SELECT * FROM btc_utxos WHERE address = $1 AND spend = false
+            AND (tx_hash, output_n) IN (SELECT * FROM UNNEST($2::text[], $3::int4[]))",
+        )
+        .bind(address)
+        .bind(tx_hashes)
+        .bind(vouts)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(result)
+    }
+
+    /// unspent rune utxos of `address` among `outpoints`, looked up in one query
+    pub async fn select_unspent_rune_utxos_at(
+        &self,
+        address: &str,
+        outpoints: &[bitcoin::OutPoint],
+    ) -> Result<Vec<RuneUtxo>> {
+        let (tx_hashes, vouts) = outpoint_columns(outpoints);
+        let result = sqlx::query_as::<_, RuneUtxo>(
+            "SELECT * FROM runes_utxos WHERE address = $1 AND spend = false
+            AND (tx_hash, output_n) IN (SELECT * FROM UNNEST($2::text[], $3::int4[]))",
+        )
+        .bind(address)
+        .bind(tx_hashes)
+        .bind(vouts)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(result)
+    }
+
     pub async fn get_btc_utxo(&self, tx_hash: &str, vout: i32) -> Result<BtcUtxo> {
         let result = sqlx::query_as::<_, BtcUtxo>(
             "SELECT * FROM btc_utxos WHERE tx_hash = $1 AND output_n = $2",
@@ -538,13 +1277,22 @@ impl Repo {
         Ok(result)
     }
 
-    pub async fn spent_btc_utxo(&self, tx_hash: &str, vout: i32) -> Result<(), sqlx::Error> {
-        let _ =
-            sqlx::query("UPDATE btc_utxos SET spend = true WHERE tx_hash = $1 AND output_n = $2")
-                .bind(tx_hash)
-                .bind(vout)
-                .execute(&self.pool)
-                .await?;
+    /// `spent_block` is the block of the spending tx
+    pub async fn spent_btc_utxo(
+        &self,
+        tx_hash: &str,
+        vout: i32,
+        spent_block: i64,
+    ) -> Result<(), sqlx::Error> {
+        let _ = sqlx::query(
+            "UPDATE btc_utxos SET spend = true, spent_block = $3
+            WHERE tx_hash = $1 AND output_n = $2",
+        )
+        .bind(tx_hash)
+        .bind(vout)
+        .bind(spent_block)
+        .execute(&self.pool)
+        .await?;
 
         Ok(())
     }
@@ -558,7 +1306,7 @@ impl Repo {
     ) -> Result<Vec<TradingPair>> {
         let mut q: QueryBuilder<Postgres> = QueryBuilder::new("SELECT * FROM trading_pair ");
         if let Some(np) = name {
-            let p = format!("{}%", np);
+            let p = self.name_pattern(&np, true);
             q.push(" WHERE base_asset ILIKE ");
             q.push_bind(p.clone());
         }
@@ -584,7 +1332,7 @@ impl Repo {
         let mut q: QueryBuilder<Postgres> =
             QueryBuilder::new("SELECT count(*) as count FROM trading_pair ");
         if let Some(np) = name_filter {
-            let p = format!("{}%", np);
+            let p = self.name_pattern(&np, true);
             q.push(" WHERE base_asset ILIKE ");
             q.push_bind(p.clone());
         }
@@ -629,6 +1377,21 @@ impl Repo {
         Ok(result)
     }
 
+    /// reads the pair and locks its row until `tx` ends,
+    /// so concurrent balance updates can't overwrite each other
+    pub async fn get_trading_pair_for_update(
+        &self,
+        tx: &mut sqlx::Transaction<'_, Postgres>,
+        id: i64,
+    ) -> Result<TradingPair> {
+        let result =
+            sqlx::query_as::<_, TradingPair>("SELECT * FROM trading_pair WHERE id = $1 FOR UPDATE")
+                .bind(id)
+                .fetch_one(&mut **tx)
+                .await?;
+        Ok(result)
+    }
+
     pub async fn update_trading_pair(
         &self,
         tx: &mut sqlx::Transaction<'_, Postgres>,
@@ -665,6 +1428,26 @@ impl Repo {
         Ok(result)
     }
 
+    /// reads the provider and locks its row until `tx` ends
+    pub async fn get_liquidity_provider_for_update(
+        &self,
+        tx: &mut sqlx::Transaction<'_, Postgres>,
+        pair_id: i64,
+        address: &str,
+    ) -> Result<LiquidityProvider> {
+        let result = sqlx::query_as::<_, LiquidityProvider>(
+            "SELECT * FROM liquidity_providers
+             WHERE trading_pair = $1 AND (base_address = $2 OR quote_address = $2)
+             FOR UPDATE",
+        )
+        .bind(pair_id)
+        .bind(address)
+        .fetch_one(&mut **tx)
+        .await?;
+
+        Ok(result)
+    }
+
     pub async fn update_liquidity_provider(
         &self,
         tx: &mut sqlx::Transaction<'_, Postgres>,
@@ -704,22 +1487,47 @@ impl Repo {
         Ok(())
     }
 
-    pub async fn update_liquidity_change_request(
+    /// quote amount of completed swaps of the pair since the given timestamp
+    pub async fn sum_swap_volume(&self, trading_pair: i64, since: i64) -> Result<String> {
+        let result = sqlx::query_as::<_, Total>(
+            "SELECT COALESCE(SUM(quote_amount::NUMERIC), 0)::TEXT as total FROM liquidity_change_requests
+             WHERE trading_pair = $1 AND status = $2 AND action IN ($3, $4) AND updated_at >= $5",
+        )
+        .bind(trading_pair)
+        .bind(LiquidityChangeRequest::STATUS_DONE)
+        .bind(LiquidityChangeRequest::SWAP_DIRECT)
+        .bind(LiquidityChangeRequest::SWAP_REVERSE)
+        .bind(since)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(result.total)
+    }
+
+    /// sets the final status of a request which isn't done or failed yet, `tx_hash`
+    /// is kept if `None`. Returns false if it was resolved in the meantime
+    pub async fn resolve_liquidity_change_request(
         &self,
         tx: &mut sqlx::Transaction<'_, Postgres>,
         request_id: &str,
-        tx_hash: &str,
+        tx_hash: Option<&str>,
         status: &str,
-    ) -> Result<()> {
-        let _ = sqlx::query("UPDATE liquidity_change_requests SET tx_hash = $1, status = $2, updated_at = $3 WHERE req_uid = $4")
-            .bind(tx_hash)
-            .bind(status)
-            .bind(chrono::Utc::now().timestamp())
-            .bind(request_id)
-            .execute(&mut **tx)
-            .await?;
-
-        Ok(())
+    ) -> Result<bool> {
+        let result = sqlx::query(
+            "UPDATE liquidity_change_requests
+            SET status = $1, updated_at = $2, tx_hash = COALESCE($6, tx_hash)
+            WHERE req_uid = $3 AND status NOT IN ($4, $5)",
+        )
+        .bind(status)
+        .bind(chrono::Utc::now().timestamp())
+        .bind(request_id)
+        .bind(LiquidityChangeRequest::STATUS_DONE)
+        .bind(LiquidityChangeRequest::STATUS_FAILED)
+        .bind(tx_hash)
+        .execute(&mut **tx)
+        .await?;
+
+        Ok(result.rows_affected() == 1)
     }
 
     pub async fn get_liquidity_change_request(
@@ -772,6 +1580,30 @@ impl Repo {
         Ok(())
     }
 
+    /// increments the number of failed processing attempts and returns the new value
+    pub async fn increment_submitted_tx_attempts(&self, tx_hash: &str) -> Result<i32> {
+        let (attempts,): (i32,) = sqlx::query_as(
+            "UPDATE submitted_txs SET attempts = attempts + 1, updated_at = $1
+             WHERE tx_hash = $2 RETURNING attempts",
+        )
+        .bind(chrono::Utc::now().timestamp())
+        .bind(tx_hash)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(attempts)
+    }
+
+    pub async fn get_submitted_tx(&self, tx_hash: &str) -> Result<Transaction> {
+        let result =
+            sqlx::query_as::<_, Transaction>("SELECT * FROM submitted_txs WHERE tx_hash = $1")
+                .bind(tx_hash)
+                .fetch_one(&self.pool)
+                .await?;
+
+        Ok(result)
+    }
+
     pub async fn select_pending_txs(&self) -> Result<Vec<Transaction>> {
         let result = sqlx::query_as::<_, Transaction>(
             "SELECT * FROM submitted_txs WHERE status = 'pending'",
@@ -781,4 +1613,1271 @@ impl Repo {
 
         Ok(result)
     }
+
+    pub async fn insert_indexer_anomaly(&self, row: &IndexerAnomaly) -> Result<()> {
+        let _ = sqlx::query(
+            "INSERT INTO indexer_anomalies (block, tx_id, tx_hash, kind, reason, created_at)
+             VALUES ($1, $2, $3, $4, $5, $6)",
+        )
+        .bind(row.block)
+        .bind(row.tx_id)
+        .bind(&row.tx_hash)
+        .bind(&row.kind)
+        .bind(&row.reason)
+        .bind(row.created_at)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn insert_webhook_dead_letter(&self, row: &WebhookDeadLetter) -> Result<()> {
+        let _ = sqlx::query(
+            "INSERT INTO webhook_dead_letters (event, payload, attempts, error, created_at)
+             VALUES ($1, $2, $3, $4, $5)",
+        )
+        .bind(&row.event)
+        .bind(&row.payload)
+        .bind(row.attempts)
+        .bind(&row.error)
+        .bind(row.created_at)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn select_indexer_anomalies(&self, limit: i32) -> Result<Vec<IndexerAnomaly>> {
+        let result = sqlx::query_as::<_, IndexerAnomaly>(
+            "SELECT * FROM indexer_anomalies ORDER BY id DESC LIMIT $1",
+        )
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(result)
+    }
+
+    /// runes, runes utxos and their sum created up to `height` (inclusive)
+    pub async fn get_index_state(&self, height: i64) -> Result<IndexState> {
+        let result = sqlx::query_as::<_, IndexState>(
+            "SELECT (SELECT count(*) FROM runes WHERE block <= $1) as runes_count,
+                (SELECT count(*) FROM runes_utxos WHERE block <= $1) as utxos_count,
+                (SELECT COALESCE(SUM(amount::NUMERIC), 0)::TEXT FROM runes_utxos WHERE block <= $1) as utxos_sum",
+        )
+        .bind(height)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(result)
+    }
+
+    pub async fn insert_index_checkpoint(&self, row: &IndexCheckpoint) -> Result<()> {
+        let _ = sqlx::query(
+            "INSERT INTO index_checkpoints
+                (indexer, height, block_hash, runes_count, utxos_count, utxos_sum, created_at)
+             VALUES ($1, $2, $3, $4, $5, $6, $7)
+             ON CONFLICT (indexer, height) DO UPDATE SET block_hash = $3, runes_count = $4,
+                utxos_count = $5, utxos_sum = $6, created_at = $7",
+        )
+        .bind(&row.indexer)
+        .bind(row.height)
+        .bind(&row.block_hash)
+        .bind(row.runes_count)
+        .bind(row.utxos_count)
+        .bind(&row.utxos_sum)
+        .bind(row.created_at)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// moves the indexer height to the block together with its `indexed_blocks` row,
+    /// a reindexed height replaces the previous hash and stats
+    pub async fn finish_block(&self, row: &IndexedBlock) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+        let _ = sqlx::query("UPDATE last_indexed_block SET height = $1 WHERE indexer = $2")
+            .bind(row.height)
+            .bind(&row.indexer)
+            .execute(&mut *tx)
+            .await?;
+        let _ = sqlx::query(
+            "INSERT INTO indexed_blocks (indexer, height, block_hash, tx_count,
+                etchings, mints, edicts, cenotaphs, burned_txs, indexed_at)
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
+             ON CONFLICT (indexer, height) DO UPDATE SET block_hash = $3, tx_count = $4,
+                etchings = $5, mints = $6, edicts = $7, cenotaphs = $8, burned_txs = $9,
+                indexed_at = $10",
+        )
+        .bind(&row.indexer)
+        .bind(row.height)
+        .bind(&row.block_hash)
+        .bind(row.tx_count)
+        .bind(row.etchings)
+        .bind(row.mints)
+        .bind(row.edicts)
+        .bind(row.cenotaphs)
+        .bind(row.burned_txs)
+        .bind(row.indexed_at)
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await
+    }
+
+    /// the blocks every indexer processed at `height`
+    pub async fn select_indexed_blocks(&self, height: i64) -> Result<Vec<IndexedBlock>> {
+        let result = sqlx::query_as::<_, IndexedBlock>(
+            "SELECT * FROM indexed_blocks WHERE height = $1 ORDER BY indexer",
+        )
+        .bind(height)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(result)
+    }
+
+    /// the most recent checkpoints at or below `height`, newest first
+    pub async fn select_index_checkpoints(
+        &self,
+        indexer_id: &str,
+        height: i64,
+        limit: i32,
+    ) -> Result<Vec<IndexCheckpoint>> {
+        let result = sqlx::query_as::<_, IndexCheckpoint>(
+            "SELECT * FROM index_checkpoints WHERE indexer = $1 AND height <= $2
+             ORDER BY height DESC LIMIT $3",
+        )
+        .bind(indexer_id)
+        .bind(height)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(result)
+    }
+
+    /// Drops the runes index back to the end of `height` in one db transaction: runes,
+    /// utxos, log, checkpoints and block rows above it are deleted, utxos spent above it
+    /// are unspent again and the balances and supply of the touched runes are recomputed
+    /// from the unspent utxos. Spends and log entries recorded before their block was
+    /// stored can't be rewound.
+    pub async fn rewind_runes_index(&self, indexer_id: &str, height: i64) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+
+        let _ = sqlx::query(
+            "CREATE TEMP TABLE rewound_balances ON COMMIT DROP AS
+             SELECT DISTINCT address, rune FROM runes_utxos WHERE block > $1 OR spent_block > $1",
+        )
+        .bind(height)
+        .execute(&mut *tx)
+        .await?;
+
+        // mints are only known from the log
+        let _ = sqlx::query(
+            "UPDATE runes r SET mints = r.mints - l.mints,
+                minted = (r.minted::NUMERIC - l.minted)::TEXT
+             FROM (SELECT rune, count(*) AS mints, SUM(value::NUMERIC) AS minted
+                FROM runes_log WHERE action = $2 AND block > $1 GROUP BY rune) l
+             WHERE r.rune = l.rune",
+        )
+        .bind(height)
+        .bind(RuneLog::MINT)
+        .execute(&mut *tx)
+        .await?;
+
+        for query in [
+            "DELETE FROM runes_log WHERE block > $1",
+            "DELETE FROM runes_utxos WHERE block > $1",
+            "UPDATE runes_utxos SET spend = false, spent_block = NULL WHERE spent_block > $1",
+            "DELETE FROM runes_commitments WHERE rune IN (SELECT rune FROM runes WHERE block > $1)",
+            "DELETE FROM runes_inscriptions WHERE block > $1",
+            "DELETE FROM runes WHERE block > $1",
+        ] {
+            let _ = sqlx::query(query).bind(height).execute(&mut *tx).await?;
+        }
+
+        let _ = sqlx::query(
+            "DELETE FROM runes_balances b USING rewound_balances r
+             WHERE b.address = r.address AND b.rune = r.rune",
+        )
+        .execute(&mut *tx)
+        .await?;
+        let _ = sqlx::query(
+            "INSERT INTO runes_balances (address, rune, balance)
+             SELECT u.address, u.rune, SUM(u.amount::NUMERIC)::TEXT FROM runes_utxos u
+             JOIN rewound_balances r ON u.address = r.address AND u.rune = r.rune
+             WHERE u.spend = false GROUP BY u.address, u.rune",
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        // the unspent utxos hold the circulating supply, the rest of it is burned.
+        // `minted` includes the premine
+        let _ = sqlx::query(
+            "UPDATE runes r SET in_circulation = COALESCE(u.amount, 0)::TEXT,
+                burned = (r.minted::NUMERIC - COALESCE(u.amount, 0))::TEXT
+             FROM (SELECT DISTINCT rune FROM rewound_balances) t
+             LEFT JOIN (SELECT rune, SUM(amount::NUMERIC) AS amount FROM runes_utxos
+                WHERE spend = false GROUP BY rune) u ON u.rune = t.rune
+             WHERE r.rune = t.rune",
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        for query in [
+            "DELETE FROM index_checkpoints WHERE indexer = $1 AND height > $2",
+            "DELETE FROM indexed_blocks WHERE indexer = $1 AND height > $2",
+            "UPDATE last_indexed_block SET height = $2 WHERE indexer = $1",
+        ] {
+            let _ = sqlx::query(query)
+                .bind(indexer_id)
+                .bind(height)
+                .execute(&mut *tx)
+                .await?;
+        }
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// keeps only the `keep` most recent anomalies
+    pub async fn prune_indexer_anomalies(&self, keep: i64) -> Result<()> {
+        let _ = sqlx::query(
+            "DELETE FROM indexer_anomalies WHERE id <= (
+                SELECT id FROM indexer_anomalies ORDER BY id DESC OFFSET $1 LIMIT 1)",
+        )
+        .bind(keep)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+}
+
+/// Loads an index snapshot in a single db transaction, nothing is written until `commit`.
+pub struct SnapshotExport {
+    tx: sqlx::Transaction<'static, Postgres>,
+}
+
+impl SnapshotExport {
+    pub async fn schema_version(&mut self) -> Result<i64> {
+        let result = sqlx::query_as::<_, Count>(
+            "SELECT COALESCE(MAX(version), 0) as count FROM _sqlx_migrations WHERE success = true",
+        )
+        .fetch_one(&mut *self.tx)
+        .await?;
+        Ok(result.count)
+    }
+
+    pub async fn last_indexed_blocks(&mut self) -> Result<Vec<LastIndexedBlock>> {
+        let result = sqlx::query_as::<_, LastIndexedBlock>("SELECT * FROM last_indexed_block")
+            .fetch_all(&mut *self.tx)
+            .await?;
+        Ok(result)
+    }
+
+    pub async fn runes_batch(&mut self, after_id: i64, limit: i32) -> Result<Vec<RuneSnapshotRow>> {
+        let result = sqlx::query_as::<_, RuneSnapshotRow>(
+            "SELECT * FROM runes WHERE id > $1 ORDER BY id ASC LIMIT $2",
+        )
+        .bind(after_id)
+        .bind(limit)
+        .fetch_all(&mut *self.tx)
+        .await?;
+        Ok(result)
+    }
+
+    pub async fn unspent_runes_utxo_batch(
+        &mut self,
+        after_id: i64,
+        limit: i32,
+    ) -> Result<Vec<RuneUtxo>> {
+        let result = sqlx::query_as::<_, RuneUtxo>(
+            "SELECT * FROM runes_utxos WHERE spend = false AND id > $1 ORDER BY id ASC LIMIT $2",
+        )
+        .bind(after_id)
+        .bind(limit)
+        .fetch_all(&mut *self.tx)
+        .await?;
+        Ok(result)
+    }
+
+    pub async fn runes_balances_batch(
+        &mut self,
+        after_id: i64,
+        limit: i32,
+    ) -> Result<Vec<RunesBalance>> {
+        let result = sqlx::query_as::<_, RunesBalance>(
+            "SELECT * FROM runes_balances WHERE id > $1 ORDER BY id ASC LIMIT $2",
+        )
+        .bind(after_id)
+        .bind(limit)
+        .fetch_all(&mut *self.tx)
+        .await?;
+        Ok(result)
+    }
+
+    pub async fn runes_commitments_batch(
+        &mut self,
+        after_id: i64,
+        limit: i32,
+    ) -> Result<Vec<RuneCommitment>> {
+        let result = sqlx::query_as::<_, RuneCommitment>(
+            "SELECT * FROM runes_commitments WHERE id > $1 ORDER BY id ASC LIMIT $2",
+        )
+        .bind(after_id)
+        .bind(limit)
+        .fetch_all(&mut *self.tx)
+        .await?;
+        Ok(result)
+    }
+
+    pub async fn runes_log_batch(&mut self, after_id: i64, limit: i32) -> Result<Vec<RuneLog>> {
+        let result = sqlx::query_as::<_, RuneLog>(
+            "SELECT * FROM runes_log WHERE id > $1 ORDER BY id ASC LIMIT $2",
+        )
+        .bind(after_id)
+        .bind(limit)
+        .fetch_all(&mut *self.tx)
+        .await?;
+        Ok(result)
+    }
+
+    pub async fn runes_inscriptions_batch(
+        &mut self,
+        after_id: i64,
+        limit: i32,
+    ) -> Result<Vec<RuneInscription>> {
+        let result = sqlx::query_as::<_, RuneInscription>(
+            "SELECT * FROM runes_inscriptions WHERE id > $1 ORDER BY id ASC LIMIT $2",
+        )
+        .bind(after_id)
+        .bind(limit)
+        .fetch_all(&mut *self.tx)
+        .await?;
+        Ok(result)
+    }
+
+    pub async fn index_checkpoints_batch(
+        &mut self,
+        after_id: i64,
+        limit: i32,
+    ) -> Result<Vec<IndexCheckpoint>> {
+        let result = sqlx::query_as::<_, IndexCheckpoint>(
+            "SELECT * FROM index_checkpoints WHERE id > $1 ORDER BY id ASC LIMIT $2",
+        )
+        .bind(after_id)
+        .bind(limit)
+        .fetch_all(&mut *self.tx)
+        .await?;
+        Ok(result)
+    }
+
+    /// the table has no id, the rows of each indexer are paged by height
+    pub async fn indexed_blocks_batch(
+        &mut self,
+        indexer: &str,
+        after_height: i64,
+        limit: i32,
+    ) -> Result<Vec<IndexedBlock>> {
+        let result = sqlx::query_as::<_, IndexedBlock>(
+            "SELECT * FROM indexed_blocks WHERE indexer = $1 AND height > $2
+             ORDER BY height ASC LIMIT $3",
+        )
+        .bind(indexer)
+        .bind(after_height)
+        .bind(limit)
+        .fetch_all(&mut *self.tx)
+        .await?;
+        Ok(result)
+    }
+
+    /// read only, nothing to commit
+    pub async fn finish(self) -> Result<()> {
+        self.tx.rollback().await
+    }
+}
+
+pub struct SnapshotImport {
+    tx: sqlx::Transaction<'static, Postgres>,
+}
+
+impl SnapshotImport {
+    /// the seed data is replaced by the snapshot
+    pub async fn clear_runes(&mut self) -> Result<()> {
+        let _ = sqlx::query("DELETE FROM runes")
+            .execute(&mut *self.tx)
+            .await?;
+        Ok(())
+    }
+
+    /// keeps the `last_activity` of the snapshot, the rune lists are sorted by it
+    pub async fn insert_rune(&mut self, row: &RuneSnapshotRow) -> Result<()> {
+        let _ = insert_rune_query(&row.rune, row.last_activity)
+            .execute(&mut *self.tx)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn insert_rune_log(&mut self, entry: &RuneLog) -> Result<()> {
+        let _ = sqlx::query(
+            "INSERT INTO runes_log (tx_hash, rune, address, action, value, block)
+             VALUES($1, $2, $3, $4, $5, $6)",
+        )
+        .bind(&entry.tx_hash)
+        .bind(&entry.rune)
+        .bind(&entry.address)
+        .bind(&entry.action)
+        .bind(&entry.value)
+        .bind(entry.block)
+        .execute(&mut *self.tx)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn insert_rune_inscription(&mut self, row: &RuneInscription) -> Result<()> {
+        let _ = sqlx::query(
+            "INSERT INTO runes_inscriptions (rune, inscription_id, content_type, content_length, content_hash, block)
+             VALUES ($1, $2, $3, $4, $5, $6)",
+        )
+        .bind(&row.rune)
+        .bind(&row.inscription_id)
+        .bind(&row.content_type)
+        .bind(row.content_length)
+        .bind(&row.content_hash)
+        .bind(row.block)
+        .execute(&mut *self.tx)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn insert_index_checkpoint(&mut self, row: &IndexCheckpoint) -> Result<()> {
+        let _ = sqlx::query(
+            "INSERT INTO index_checkpoints
+                (indexer, height, block_hash, runes_count, utxos_count, utxos_sum, created_at)
+             VALUES ($1, $2, $3, $4, $5, $6, $7)",
+        )
+        .bind(&row.indexer)
+        .bind(row.height)
+        .bind(&row.block_hash)
+        .bind(row.runes_count)
+        .bind(row.utxos_count)
+        .bind(&row.utxos_sum)
+        .bind(row.created_at)
+        .execute(&mut *self.tx)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn insert_indexed_block(&mut self, row: &IndexedBlock) -> Result<()> {
+        let _ = sqlx::query(
+            "INSERT INTO indexed_blocks (indexer, height, block_hash, tx_count,
+                etchings, mints, edicts, cenotaphs, burned_txs, indexed_at)
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)",
+        )
+        .bind(&row.indexer)
+        .bind(row.height)
+        .bind(&row.block_hash)
+        .bind(row.tx_count)
+        .bind(row.etchings)
+        .bind(row.mints)
+        .bind(row.edicts)
+        .bind(row.cenotaphs)
+        .bind(row.burned_txs)
+        .bind(row.indexed_at)
+        .execute(&mut *self.tx)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn insert_rune_utxo(&mut self, utxo: &RuneUtxo) -> Result<()> {
+        let _ = insert_rune_utxo_query(utxo).execute(&mut *self.tx).await?;
+        Ok(())
+    }
+
+    pub async fn insert_runes_balance(&mut self, balance: &RunesBalance) -> Result<()> {
+        let _ =
+            sqlx::query("INSERT INTO runes_balances (address, rune, balance) VALUES($1, $2, $3)")
+                .bind(&balance.address)
+                .bind(&balance.rune)
+                .bind(&balance.balance)
+                .execute(&mut *self.tx)
+                .await?;
+        Ok(())
+    }
+
+    pub async fn insert_rune_commitment(&mut self, row: &RuneCommitment) -> Result<()> {
+        let _ = sqlx::query(
+            "INSERT INTO runes_commitments (rune, commitment_tx, vout, pk_script, commit_block, confirmations)
+             VALUES ($1, $2, $3, $4, $5, $6)",
+        )
+        .bind(&row.rune)
+        .bind(&row.commitment_tx)
+        .bind(row.vout)
+        .bind(&row.pk_script)
+        .bind(row.commit_block)
+        .bind(row.confirmations)
+        .execute(&mut *self.tx)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn set_last_indexed_block(&mut self, block: &LastIndexedBlock) -> Result<()> {
+        let res = sqlx::query("UPDATE last_indexed_block SET height = $1 WHERE indexer = $2")
+            .bind(block.height)
+            .bind(&block.indexer)
+            .execute(&mut *self.tx)
+            .await?;
+        if res.rows_affected() == 0 {
+            let _ = sqlx::query("INSERT INTO last_indexed_block (indexer, height) VALUES ($1, $2)")
+                .bind(&block.indexer)
+                .bind(block.height)
+                .execute(&mut *self.tx)
+                .await?;
+        }
+        // the snapshot has no spent utxos, the index can't be rewound below it
+        let _ = sqlx::query(
+            "INSERT INTO spent_block_since (indexer, height) VALUES ($1, $2)
+             ON CONFLICT (indexer) DO UPDATE SET height = $2",
+        )
+        .bind(&block.indexer)
+        .bind(block.height)
+        .execute(&mut *self.tx)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn commit(self) -> Result<()> {
+        self.tx.commit().await
+    }
+}
+
+fn insert_rune_query(rune: &Rune, last_activity: i64) -> Query<'_, Postgres, PgArguments> {
+    sqlx::query(
+        "INSERT INTO runes (
+                rune,
+                display_name,
+                symbol,
+                block,
+                tx_id,
+                mints,
+                max_supply,
+                minted,
+                in_circulation,
+                divisibility,
+                turbo,
+                timestamp,
+                etching_tx,
+                commitment_tx,
+                raw_data,
+                premine,
+                burned,
+                last_activity)
+              VALUES($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17, $18)",
+    )
+    .bind(&rune.rune)
+    .bind(&rune.display_name)
+    .bind(&rune.symbol)
+    .bind(rune.block)
+    .bind(rune.tx_id)
+    .bind(rune.mints)
+    .bind(&rune.max_supply)
+    .bind(&rune.minted)
+    .bind(&rune.in_circulation)
+    .bind(rune.divisibility)
+    .bind(rune.turbo)
+    .bind(rune.timestamp)
+    .bind(&rune.etching_tx)
+    .bind(&rune.commitment_tx)
+    .bind(&rune.raw_data)
+    .bind(&rune.premine)
+    .bind(&rune.burned)
+    .bind(last_activity)
+}
+
+/// unspent utxos of `rune`, shared by the count and the page queries so the totals match the pages
+fn push_runes_utxo_select<'a>(
+    q: &mut QueryBuilder<'a, Postgres>,
+    rune: &'a str,
+    address: Option<String>,
+) {
+    q.push("SELECT * FROM runes_utxos WHERE spend = false ");
+    q.push(" AND rune = ");
+    q.push_bind(rune);
+
+    if let Some(a) = address {
+        q.push(" AND address = ");
+        q.push_bind(a);
+    }
+}
+
+fn count_runes_utxo_query(rune: &str, address: Option<String>) -> QueryBuilder<'_, Postgres> {
+    let mut q = QueryBuilder::new("SELECT count(*) as count FROM (");
+    push_runes_utxo_select(&mut q, rune, address);
+    q.push(") AS utxos");
+    q
+}
+
+fn select_runes_utxo_query(
+    rune: &str,
+    address: Option<String>,
+    order: &str,
+    limit: i32,
+    offset: i32,
+) -> QueryBuilder<'_, Postgres> {
+    let mut q = QueryBuilder::new("");
+    push_runes_utxo_select(&mut q, rune, address);
+
+    // output_n and id break the ties of a tx's outputs, so pages don't overlap
+    if order == "DESC" {
+        q.push(" ORDER BY block DESC, tx_id DESC, output_n DESC, id DESC ");
+    } else {
+        q.push(" ORDER BY block ASC, tx_id ASC, output_n ASC, id ASC ");
+    }
+    q.push(" LIMIT ");
+    q.push_bind(limit);
+    q.push(" OFFSET ");
+    q.push_bind(offset);
+    q
+}
+
+fn insert_rune_utxo_query(rb: &RuneUtxo) -> Query<'_, Postgres, PgArguments> {
+    sqlx::query(
+        "INSERT INTO runes_utxos (
+          block, tx_id, tx_hash, output_n, rune, address, pk_script, amount, btc_amount, spend,
+          spent_block)
+         VALUES($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)",
+    )
+    .bind(rb.block)
+    .bind(rb.tx_id)
+    .bind(&rb.tx_hash)
+    .bind(rb.output_n)
+    .bind(&rb.rune)
+    .bind(&rb.address)
+    .bind(&rb.pk_script)
+    .bind(&rb.amount)
+    .bind(rb.btc_amount)
+    .bind(rb.spend)
+    .bind(rb.spent_block)
+}
+
+/// the connections left to clients other than superusers
+fn available_connections(max_connections: &str, reserved: &str) -> Option<u32> {
+    let max: u32 = max_connections.parse().ok()?;
+    let reserved: u32 = reserved.parse().ok()?;
+    Some(max.saturating_sub(reserved))
+}
+
+fn missing_migrations(known: &[i64], applied: &[i64]) -> Vec<i64> {
+    known
+        .iter()
+        .filter(|v| !applied.contains(v))
+        .copied()
+        .collect()
+}
+
+/// the config of the scratch db in `TEST_DB_DSN`,
+/// `None` when it isn't set and the db tests are skipped
+#[cfg(test)]
+pub fn scratch_db_config() -> Option<DBConfig> {
+    let dsn = std::env::var("TEST_DB_DSN").ok()?;
+    Some(DBConfig {
+        dsn,
+        automigrate: false,
+        max_name_filter_len: None,
+        statement_timeout: None,
+        bulk_permits: None,
+        pool_size: None,
+        api_pool_size: None,
+    })
+}
+
+#[cfg(test)]
+pub async fn connect_scratch_db() -> Option<Repo> {
+    let cfg = scratch_db_config()?;
+    Some(connect_postgres_db(cfg).await.unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn pools_fit_in_available_connections() {
+        use super::available_connections;
+        use crate::config::DBConfig;
+
+        // postgres' defaults leave 97 connections
+        let available = available_connections("100", "3").unwrap();
+        assert_eq!(available, 97);
+        assert_eq!(available_connections("2", "3"), Some(0));
+        assert_eq!(available_connections("", "3"), None);
+
+        let cfg = |pool_size, api_pool_size| DBConfig {
+            dsn: String::new(),
+            automigrate: false,
+            max_name_filter_len: None,
+            statement_timeout: None,
+            bulk_permits: None,
+            pool_size,
+            api_pool_size,
+        };
+        let defaults = cfg(None, None);
+        assert!(defaults.pool_size() + defaults.api_pool_size() <= available);
+        let large = cfg(Some(20), Some(80));
+        assert!(large.pool_size() + large.api_pool_size() > available);
+    }
+
+    #[test]
+    fn schema_behind_migrations_is_detected() {
+        use super::missing_migrations;
+
+        let known = [20250110120000, 20250110120100, 20250110120200];
+        assert!(missing_migrations(&known, &known).is_empty());
+        // the db may be ahead when an older build runs against it
+        assert!(missing_migrations(&known[..2], &known).is_empty());
+
+        assert_eq!(
+            missing_migrations(&known, &known[..2]),
+            vec![20250110120200]
+        );
+        assert_eq!(missing_migrations(&known, &[]), known.to_vec());
+        // a skipped migration in the middle is missing too
+        assert_eq!(
+            missing_migrations(&known, &[20250110120000, 20250110120200]),
+            vec![20250110120100]
+        );
+    }
+
+    #[test]
+    fn like_metacharacters_are_escaped() {
+        use super::escape_like;
+
+        assert_eq!(escape_like("UNCOMMON"), "UNCOMMON");
+        // `%` and `_` must match literally instead of acting as wildcards
+        assert_eq!(escape_like("100%"), "100\\%");
+        assert_eq!(escape_like("A_B"), "A\\_B");
+        assert_eq!(escape_like("%%"), "\\%\\%");
+        assert_eq!(escape_like("A\\B"), "A\\\\B");
+    }
+
+    #[test]
+    fn checkpoint_mismatch_is_described() {
+        use super::{IndexCheckpoint, IndexState};
+
+        let state = IndexState {
+            runes_count: 10,
+            utxos_count: 120,
+            utxos_sum: "5000".to_owned(),
+        };
+        let checkpoint = IndexCheckpoint::new("rune_etchings", 840_000, "00ab", state.clone());
+        assert_eq!(checkpoint.mismatch("00ab", &state), None);
+
+        let reason = checkpoint.mismatch("00cd", &state).unwrap();
+        assert!(reason.starts_with("block hash changed"));
+
+        let corrupted = IndexState {
+            utxos_sum: "4999".to_owned(),
+            ..state
+        };
+        let reason = checkpoint.mismatch("00ab", &corrupted).unwrap();
+        assert!(reason.starts_with("state changed"));
+    }
+
+    #[test]
+    fn runes_utxo_count_uses_the_page_filter() {
+        use super::{count_runes_utxo_query, select_runes_utxo_query};
+
+        for address in [Some("bc1p26g4".to_owned()), None] {
+            let count = count_runes_utxo_query("UNCOMMONGOODS", address.clone());
+            let page = select_runes_utxo_query("UNCOMMONGOODS", address.clone(), "DESC", 10, 20);
+
+            let filter = page.sql().split(" ORDER BY").next().unwrap();
+            assert!(filter.contains("spend = false"));
+            assert_eq!(filter.contains("address = $2"), address.is_some());
+            assert_eq!(
+                count.sql(),
+                format!("SELECT count(*) as count FROM ({}) AS utxos", filter)
+            );
+        }
+    }
+
+    /// needs a scratch db in `TEST_DB_DSN`, skipped otherwise
+    #[tokio::test]
+    async fn slow_query_hits_statement_timeout() {
+        use super::{connect_api_db, connect_postgres_db, is_statement_timeout, scratch_db_config};
+        use crate::config::DBConfig;
+
+        let Some(cfg) = scratch_db_config() else {
+            return;
+        };
+        let cfg = DBConfig {
+            statement_timeout: Some(1),
+            ..cfg
+        };
+        let repo = connect_api_db(cfg.clone()).await.unwrap();
+
+        let err = sqlx::query("SELECT pg_sleep(2)")
+            .execute(&repo.pool)
+            .await
+            .unwrap_err();
+        assert!(is_statement_timeout(&err), "{}", err);
+
+        // the connection stays usable
+        sqlx::query("SELECT 1").execute(&repo.pool).await.unwrap();
+
+        // only the API pool is limited
+        let repo = connect_postgres_db(cfg).await.unwrap();
+        sqlx::query("SELECT pg_sleep(2)")
+            .execute(&repo.pool)
+            .await
+            .unwrap();
+    }
+
+    /// needs a scratch db in `TEST_DB_DSN`, skipped otherwise
+    #[tokio::test]
+    async fn rewind_drops_the_index_above_the_height() {
+        use super::{connect_scratch_db, IndexCheckpoint, IndexState, Rune, RuneLog, RuneUtxo};
+
+        let Some(repo) = connect_scratch_db().await else {
+            return;
+        };
+        let (indexer, base) = ("rewind_test", 900_000_000);
+        repo.rewind_runes_index(indexer, base).await.unwrap();
+
+        let (old, new, address) = ("REWINDTESTOLD", "REWINDTESTNEW", "bc1prewind");
+        // OLD has a premine of 100 on top of its two mints
+        for (rune, block, premine, minted) in [
+            (old, base + 100, "100", "300"),
+            (new, base + 300, "0", "200"),
+        ] {
+            repo.insert_rune(&Rune {
+                rune: rune.to_owned(),
+                block,
+                mints: 2,
+                max_supply: "1000".to_owned(),
+                premine: premine.to_owned(),
+                burned: "0".to_owned(),
+                minted: minted.to_owned(),
+                in_circulation: minted.to_owned(),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        }
+        let utxo = |rune: &str, tx_hash: &str, block, spent_block| RuneUtxo {
+            block,
+            tx_hash: tx_hash.to_owned(),
+            rune: rune.to_owned(),
+            address: address.to_owned(),
+            amount: "100".to_owned(),
+            spend: spent_block.is_some(),
+            spent_block,
+            ..Default::default()
+        };
+        // OLD: premined and minted at 100, minted at 300, the first mint was spent at 300
+        for u in [
+            utxo(old, "a0", base + 100, None),
+            utxo(old, "a1", base + 100, Some(base + 300)),
+            utxo(old, "a2", base + 300, None),
+            utxo(new, "b1", base + 300, None),
+        ] {
+            repo.insert_rune_utxo(&u).await.unwrap();
+        }
+        for (rune, balance) in [(old, "200"), (new, "100")] {
+            repo.insert_runes_balance(rune, address, balance)
+                .await
+                .unwrap();
+        }
+        for (rune, tx_hash, action, block) in [
+            (old, "a0", RuneLog::ETCHING, base + 100),
+            (old, "a1", RuneLog::MINT, base + 100),
+            (old, "a2", RuneLog::MINT, base + 300),
+            (old, "a1", RuneLog::EXPENCE, base + 300),
+        ] {
+            repo.insert_rune_log(&RuneLog {
+                tx_hash: tx_hash.to_owned(),
+                rune: rune.to_owned(),
+                address: address.to_owned(),
+                action: action.to_owned(),
+                value: "100".to_owned(),
+                block: Some(block),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        }
+        for height in [base + 100, base + 300] {
+            let state = IndexState {
+                utxos_sum: "0".to_owned(),
+                ..Default::default()
+            };
+            repo.insert_index_checkpoint(&IndexCheckpoint::new(indexer, height, "00", state))
+                .await
+                .unwrap();
+        }
+
+        repo.rewind_runes_index(indexer, base + 200).await.unwrap();
+
+        assert!(repo.get_rune(new).await.is_err());
+        let rune = repo.get_rune(old).await.unwrap();
+        assert_eq!(
+            (
+                rune.mints,
+                rune.minted.as_str(),
+                rune.in_circulation.as_str()
+            ),
+            (1, "200", "200")
+        );
+        // the premine is counted once
+        assert_eq!(rune.burned, "0");
+
+        let utxos = repo.get_rune_utxos_by_outpoint("a1", 0).await.unwrap();
+        assert_eq!(utxos.len(), 1);
+        assert!(!utxos[0].spend && utxos[0].spent_block.is_none());
+        assert!(repo
+            .get_rune_utxos_by_outpoint("a2", 0)
+            .await
+            .unwrap()
+            .is_empty());
+
+        assert_eq!(
+            repo.get_rune_balance(address, old).await.unwrap().balance,
+            "200"
+        );
+        assert!(repo.get_rune_balance(address, new).await.is_err());
+        assert_eq!(repo.select_rune_log_by_tx("a1").await.unwrap().len(), 1);
+        assert!(repo.select_rune_log_by_tx("a2").await.unwrap().is_empty());
+        let checkpoints = repo
+            .select_index_checkpoints(indexer, i64::MAX, 10)
+            .await
+            .unwrap();
+        assert_eq!(checkpoints.len(), 1);
+        assert_eq!(checkpoints[0].height, base + 100);
+
+        repo.rewind_runes_index(indexer, base).await.unwrap();
+    }
+
+    /// needs a scratch db in `TEST_DB_DSN`, skipped otherwise
+    #[tokio::test]
+    async fn snapshot_export_reads_one_state() {
+        use super::{connect_scratch_db, Rune, RuneCommitment};
+
+        let Some(repo) = connect_scratch_db().await else {
+            return;
+        };
+        let (before, after) = ("SNAPSHOTTESTBEFORE", "SNAPSHOTTESTAFTER");
+        sqlx::query("DELETE FROM runes_commitments WHERE rune IN ($1, $2)")
+            .bind(before)
+            .bind(after)
+            .execute(&repo.pool)
+            .await
+            .unwrap();
+        sqlx::query("DELETE FROM runes WHERE rune IN ($1, $2)")
+            .bind(before)
+            .bind(after)
+            .execute(&repo.pool)
+            .await
+            .unwrap();
+
+        let insert = |rune: &'static str| {
+            let repo = &repo;
+            async move {
+                repo.insert_rune(&Rune {
+                    rune: rune.to_owned(),
+                    ..Default::default()
+                })
+                .await
+                .unwrap();
+                repo.insert_rune_commitment(&RuneCommitment {
+                    rune: rune.to_owned(),
+                    ..Default::default()
+                })
+                .await
+                .unwrap();
+            }
+        };
+        insert(before).await;
+
+        let mut export = repo.begin_snapshot_export().await.unwrap();
+        let runes = export.runes_batch(0, i32::MAX).await.unwrap();
+        // written while exporting, not part of the snapshot
+        insert(after).await;
+        let commitments = export.runes_commitments_batch(0, i32::MAX).await.unwrap();
+        export.finish().await.unwrap();
+
+        assert!(runes.iter().any(|r| r.rune == before));
+        assert!(commitments.iter().any(|c| c.rune == before));
+        assert!(!commitments.iter().any(|c| c.rune == after));
+    }
+
+    /// needs a scratch db in `TEST_DB_DSN`, skipped otherwise
+    #[tokio::test]
+    async fn outpoint_lookup_returns_every_rune() {
+        use super::{connect_scratch_db, RuneUtxo};
+
+        let Some(repo) = connect_scratch_db().await else {
+            return;
+        };
+        let tx_hash = "outpoint_lookup_test";
+        sqlx::query("DELETE FROM runes_utxos WHERE tx_hash = $1")
+            .bind(tx_hash)
+            .execute(&repo.pool)
+            .await
+            .unwrap();
+
+        for (rune, output_n) in [
+            ("OUTPOINTTESTB", 0),
+            ("OUTPOINTTESTA", 0),
+            ("OUTPOINTTESTA", 1),
+        ] {
+            repo.insert_rune_utxo(&RuneUtxo {
+                tx_hash: tx_hash.to_owned(),
+                output_n,
+                rune: rune.to_owned(),
+                amount: "100".to_owned(),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        }
+
+        let utxos = repo.get_rune_utxos_by_outpoint(tx_hash, 0).await.unwrap();
+        let runes: Vec<(&str, i32)> = utxos
+            .iter()
+            .map(|u| (u.rune.as_str(), u.output_n))
+            .collect();
+        assert_eq!(runes, vec![("OUTPOINTTESTA", 0), ("OUTPOINTTESTB", 0)]);
+    }
+
+    /// needs a scratch db in `TEST_DB_DSN`, skipped otherwise
+    #[tokio::test]
+    async fn finished_block_moves_the_height_with_its_row() {
+        use super::{connect_scratch_db, IndexedBlock, LastIndexedBlock};
+
+        let Some(repo) = connect_scratch_db().await else {
+            return;
+        };
+        let (indexer, base) = ("finish_block_test", 910_000_000);
+        let mut import = repo.begin_snapshot_import().await.unwrap();
+        import
+            .set_last_indexed_block(&LastIndexedBlock {
+                indexer: indexer.to_owned(),
+                height: base,
+            })
+            .await
+            .unwrap();
+        import.commit().await.unwrap();
+        repo.rewind_runes_index(indexer, base).await.unwrap();
+
+        let block = |height, hash: &str| IndexedBlock::new(indexer, height, hash, 3);
+        repo.finish_block(&block(base + 1, "01")).await.unwrap();
+        assert_eq!(
+            repo.get_last_indexed_block(indexer).await.unwrap().height,
+            base + 1
+        );
+        let rows = repo.select_indexed_blocks(base + 1).await.unwrap();
+        assert!(rows
+            .iter()
+            .any(|r| r.indexer == indexer && r.block_hash == "01"));
+
+        // postgres refuses the NUL byte, the height stays with the stored row
+        let err = repo.finish_block(&block(base + 2, "\0")).await;
+        assert!(err.is_err());
+        assert_eq!(
+            repo.get_last_indexed_block(indexer).await.unwrap().height,
+            base + 1
+        );
+        assert!(!repo
+            .select_indexed_blocks(base + 2)
+            .await
+            .unwrap()
+            .iter()
+            .any(|r| r.indexer == indexer));
+
+        // a reorg drops the rows above the rewind height
+        repo.finish_block(&block(base + 2, "02")).await.unwrap();
+        repo.rewind_runes_index(indexer, base + 1).await.unwrap();
+        assert!(!repo
+            .select_indexed_blocks(base + 2)
+            .await
+            .unwrap()
+            .iter()
+            .any(|r| r.indexer == indexer));
+        assert!(repo
+            .select_indexed_blocks(base + 1)
+            .await
+            .unwrap()
+            .iter()
+            .any(|r| r.indexer == indexer));
+    }
+
+    /// needs a scratch db in `TEST_DB_DSN`, skipped otherwise
+    #[tokio::test]
+    async fn utxo_pages_of_one_tx_dont_overlap() {
+        use super::{connect_scratch_db, RuneUtxo};
+
+        let Some(repo) = connect_scratch_db().await else {
+            return;
+        };
+        let rune = "PAGINGTIETEST";
+        sqlx::query("DELETE FROM runes_utxos WHERE rune = $1")
+            .bind(rune)
+            .execute(&repo.pool)
+            .await
+            .unwrap();
+        // every output of a single tx ties on block and tx_id
+        for output_n in [3, 0, 4, 1, 2] {
+            repo.insert_rune_utxo(&RuneUtxo {
+                block: 840_000,
+                tx_id: 7,
+                tx_hash: "pagingtietest".to_owned(),
+                output_n,
+                rune: rune.to_owned(),
+                address: "bc1ppagingtie".to_owned(),
+                amount: "1".to_owned(),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        }
+
+        for (order, expected) in [("ASC", [0, 1, 2, 3, 4]), ("DESC", [4, 3, 2, 1, 0])] {
+            let mut outputs = Vec::new();
+            for page in 0..3 {
+                let utxos = repo
+                    .select_runes_utxo_with_pagination(rune, None, order, 2, page * 2)
+                    .await
+                    .unwrap();
+                outputs.extend(utxos.iter().map(|u| u.output_n));
+            }
+            assert_eq!(outputs, expected, "{}", order);
+        }
+    }
+
+    /// needs a scratch db in `TEST_DB_DSN`, skipped otherwise
+    #[tokio::test]
+    async fn block_range_activity_is_labeled_by_action() {
+        use super::{connect_scratch_db, Rune, RuneLog, RuneUtxo};
+
+        let Some(repo) = connect_scratch_db().await else {
+            return;
+        };
+        let (rune, base) = ("ACTIVITYLABELTEST", 930_000_000);
+        for query in [
+            "DELETE FROM runes_utxos WHERE rune = $1",
+            "DELETE FROM runes_log WHERE rune = $1",
+            "DELETE FROM runes WHERE rune = $1",
+        ] {
+            sqlx::query(query)
+                .bind(rune)
+                .execute(&repo.pool)
+                .await
+                .unwrap();
+        }
+        repo.insert_rune(&Rune {
+            rune: rune.to_owned(),
+            block: base,
+            etching_tx: "activityetching".to_owned(),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        repo.insert_rune_log(&RuneLog {
+            tx_hash: "activitymint".to_owned(),
+            rune: rune.to_owned(),
+            address: "bc1pactivity".to_owned(),
+            action: RuneLog::MINT.to_owned(),
+            value: "1".to_owned(),
+            block: Some(base + 1),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        // the premine, a mint, and a transfer with its change
+        for (tx_hash, output_n, block) in [
+            ("activityetching", 1, base),
+            ("activitymint", 1, base + 1),
+            ("activitytransfer", 1, base + 2),
+            ("activitytransfer", 2, base + 2),
+        ] {
+            repo.insert_rune_utxo(&RuneUtxo {
+                block,
+                tx_hash: tx_hash.to_owned(),
+                output_n,
+                rune: rune.to_owned(),
+                address: "bc1pactivity".to_owned(),
+                amount: "1".to_owned(),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        }
+
+        let activity = repo
+            .select_rune_activity_in_block_range(base, base + 2)
+            .await
+            .unwrap();
+        let labeled: Vec<(&str, i64)> = activity
+            .iter()
+            .filter(|a| a.rune == rune)
+            .map(|a| (a.action.as_str(), a.outputs))
+            .collect();
+        assert_eq!(labeled, vec![("transfer", 2), ("etching", 1), ("mint", 1)]);
+    }
+
+    /// needs a scratch db in `TEST_DB_DSN`, skipped otherwise
+    #[tokio::test]
+    async fn name_filters_match_literally_up_to_the_max_len() {
+        use super::{connect_postgres_db, scratch_db_config, Repo, Rune};
+        use crate::config::DBConfig;
+
+        async fn search(repo: &Repo, filter: &str) -> Vec<String> {
+            let mut found: Vec<String> = repo
+                .search_runes(filter, 100)
+                .await
+                .unwrap()
+                .into_iter()
+                .map(|r| r.rune)
+                .filter(|r| r.starts_with("LIKEESCAPE"))
+                .collect();
+            found.sort();
+            found
+        }
+
+        let Some(cfg) = scratch_db_config() else {
+            return;
+        };
+        let repo = connect_postgres_db(DBConfig {
+            max_name_filter_len: Some(10),
+            ..cfg
+        })
+        .await
+        .unwrap();
+        let runes = ["LIKEESCAPEXTEST", "LIKEESCAPEYTEST"];
+        for rune in runes {
+            sqlx::query("DELETE FROM runes WHERE rune = $1")
+                .bind(rune)
+                .execute(&repo.pool)
+                .await
+                .unwrap();
+            repo.insert_rune(&Rune {
+                rune: rune.to_owned(),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        }
+        // `%` and `_` aren't wildcards
+        assert!(search(&repo, "LIKEESCAP_").await.is_empty());
+        assert!(search(&repo, "%ESCAPE").await.is_empty());
+        // filters are cut to 10 characters, `LIKEESCAPE` matches both
+        assert_eq!(search(&repo, "LIKEESCAPEXTEST").await, runes);
+        assert_eq!(
+            repo.count_runes(Some("LIKEESCAP%".to_owned()))
+                .await
+                .unwrap(),
+            0
+        );
+    }
 }
@@ -1,10 +1,10 @@
 use std::str::FromStr;
 
 use bitcoin::{OutPoint, Txid};
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use sqlx::prelude::FromRow;
 
-#[derive(Default, Clone, Debug, FromRow, Serialize)]
+#[derive(Default, Clone, Debug, FromRow, Serialize, Deserialize)]
 pub struct LastIndexedBlock {
     pub indexer: String,
     pub height: i64,
@@ -25,9 +25,19 @@ impl Transaction {
     pub const STATUS_PENDING: &'static str = "pending";
     pub const STATUS_MINED: &'static str = "mined";
     pub const STATUS_FAILED: &'static str = "failed";
+    pub const STATUS_DEAD_LETTER: &'static str = "dead-letter";
+
+    /// outpoints spent by the raw tx
+    pub fn spent_outpoints(&self) -> anyhow::Result<Vec<OutPoint>> {
+        use bitcoin::hashes::hex::FromHex;
+
+        let bytes = Vec::<u8>::from_hex(&self.raw_data)?;
+        let tx: bitcoin::Transaction = bitcoin::consensus::deserialize(&bytes)?;
+        Ok(tx.input.iter().map(|i| i.previous_output).collect())
+    }
 }
 
-#[derive(Default, Clone, Debug, FromRow, Serialize)]
+#[derive(Default, Clone, Debug, FromRow, Serialize, Deserialize)]
 pub struct Rune {
     pub id: i64,
     pub rune: String,
@@ -49,6 +59,15 @@ pub struct Rune {
     pub raw_data: Vec<u8>,
 }
 
+/// `Rune` with its `last_activity`, which the other rune queries don't read
+#[derive(Default, Clone, Debug, FromRow, Serialize, Deserialize)]
+pub struct RuneSnapshotRow {
+    #[sqlx(flatten)]
+    #[serde(flatten)]
+    pub rune: Rune,
+    pub last_activity: i64,
+}
+
 #[derive(Default, Clone, Debug, FromRow, Serialize)]
 pub struct RuneShortRow {
     pub rune: String,
@@ -61,6 +80,14 @@ pub struct RuneShortRow {
 }
 
 #[derive(Default, Clone, Debug, FromRow, Serialize)]
+pub struct RuneBlockActivity {
+    pub rune: String,
+    /// `etching`, `mint` or `transfer`
+    pub action: String,
+    pub outputs: i64,
+}
+
+#[derive(Default, Clone, Debug, FromRow, Serialize, Deserialize)]
 pub struct RunesBalance {
     pub id: i64,
     pub address: String,
@@ -68,7 +95,7 @@ pub struct RunesBalance {
     pub balance: String,
 }
 
-#[derive(Default, Clone, Debug, FromRow, Serialize)]
+#[derive(Default, Clone, Debug, FromRow, Serialize, Deserialize)]
 pub struct RuneUtxo {
     pub id: i64,
     pub block: i64,
@@ -81,6 +108,8 @@ pub struct RuneUtxo {
     pub amount: String,
     pub btc_amount: i64,
     pub spend: bool,
+    /// block of the spending tx, unknown for utxos spent before it was recorded
+    pub spent_block: Option<i64>,
 }
 
 impl RuneUtxo {
@@ -93,7 +122,7 @@ impl RuneUtxo {
     }
 }
 
-#[derive(Default, Clone, Debug, FromRow)]
+#[derive(Default, Clone, Debug, FromRow, Serialize, Deserialize)]
 pub struct RuneLog {
     pub id: i64,
     pub tx_hash: String,
@@ -101,13 +130,17 @@ pub struct RuneLog {
     pub address: String,
     pub action: String,
     pub value: String,
+    /// block of the tx, unknown for entries logged before it was recorded
+    pub block: Option<i64>,
 }
 
 impl RuneLog {
     pub const ETCHING: &'static str = "etching";
-    pub const MINT: &'static str = "etching";
+    pub const MINT: &'static str = "mint";
     pub const INCOME: &'static str = "income";
     pub const EXPENCE: &'static str = "expence";
+    /// runes allocated to an OP_RETURN output, logged without an address
+    pub const BURN: &'static str = "burn";
 }
 
 #[derive(Default, Clone, Debug, FromRow, Serialize)]
@@ -128,6 +161,9 @@ pub struct BtcUtxo {
     pub pk_script: String,
     pub amount: i64,
     pub spend: bool,
+    pub spent_block: Option<i64>,
+    /// output of a coinbase tx, spendable only after `COINBASE_MATURITY` blocks
+    pub coinbase: bool,
 }
 impl BtcUtxo {
     pub fn out_point(&self) -> anyhow::Result<OutPoint> {
@@ -215,4 +251,165 @@ impl LiquidityChangeRequest {
     pub fn is_rm_liquidity(&self) -> bool {
         self.action.as_str() == Self::REMOVE_LIQUIDITY
     }
+
+    pub fn is_resolved(&self) -> bool {
+        self.status == Self::STATUS_DONE || self.status == Self::STATUS_FAILED
+    }
+}
+
+/// the taproot output spent by the etching tx to reveal the rune commitment
+#[derive(Default, Clone, Debug, FromRow, Serialize, Deserialize)]
+pub struct RuneCommitment {
+    pub id: i64,
+    pub rune: String,
+    pub commitment_tx: String,
+    pub vout: i32,
+    pub pk_script: String,
+    pub commit_block: i64,
+    /// confirmations of the commitment tx at the etching block
+    pub confirmations: i64,
+}
+
+/// inscription revealed by the etching tx, e.g. the rune logo,
+/// the content itself is served by an ord instance
+#[derive(Default, Clone, Debug, FromRow, Serialize, Deserialize)]
+pub struct RuneInscription {
+    pub id: i64,
+    pub rune: String,
+    /// `{etching_tx}i0`
+    pub inscription_id: String,
+    pub content_type: Option<String>,
+    pub content_length: i64,
+    /// sha256 of the content, hex
+    pub content_hash: String,
+    pub block: i64,
+}
+
+/// webhook event which couldn't be delivered
+#[derive(Default, Clone, Debug, FromRow, Serialize)]
+pub struct WebhookDeadLetter {
+    pub id: i64,
+    pub event: String,
+    /// JSON body of the request
+    pub payload: String,
+    pub attempts: i32,
+    /// of the last attempt
+    pub error: String,
+    pub created_at: i64,
+}
+
+#[derive(Default, Clone, Debug, FromRow, Serialize)]
+pub struct IndexerAnomaly {
+    pub id: i64,
+    pub block: i64,
+    pub tx_id: i32,
+    pub tx_hash: String,
+    pub kind: String,
+    pub reason: String,
+    pub created_at: i64,
+}
+
+impl IndexerAnomaly {
+    pub const INVALID_ETCHING: &'static str = "invalid_etching";
+    pub const INVALID_MINT: &'static str = "invalid_mint";
+    pub const INVALID_EDICTS: &'static str = "invalid_edicts";
+    pub const INVALID_ALLOCATION: &'static str = "invalid_allocation";
+    pub const CENOTAPH: &'static str = "cenotaph";
+    pub const MULTIPLE_RUNESTONES: &'static str = "multiple_runestones";
+}
+
+/// a rune of the leaderboard, `holders` is counted only when ranking by holders
+#[derive(Default, Clone, Debug, FromRow)]
+pub struct TopRune {
+    #[sqlx(flatten)]
+    pub rune: Rune,
+    pub holders: Option<i64>,
+}
+
+/// Runes state created up to `height`. Utxos are never deleted, spending only flags them,
+/// so the state of an already indexed height doesn't change while the indexer moves on.
+#[derive(Default, Clone, Debug, PartialEq, FromRow)]
+pub struct IndexState {
+    pub runes_count: i64,
+    pub utxos_count: i64,
+    pub utxos_sum: String,
+}
+
+/// Hash of a block an indexer processed, with its stats.
+/// The runes stats are set only by the runes indexer.
+#[derive(Default, Clone, Debug, FromRow, Serialize, Deserialize)]
+pub struct IndexedBlock {
+    pub indexer: String,
+    pub height: i64,
+    pub block_hash: String,
+    pub tx_count: i64,
+    pub etchings: Option<i64>,
+    pub mints: Option<i64>,
+    pub edicts: Option<i64>,
+    pub cenotaphs: Option<i64>,
+    pub burned_txs: Option<i64>,
+    pub indexed_at: i64,
+}
+
+impl IndexedBlock {
+    pub fn new(indexer: &str, height: i64, block_hash: &str, tx_count: usize) -> Self {
+        Self {
+            indexer: indexer.to_owned(),
+            height,
+            block_hash: block_hash.to_owned(),
+            tx_count: tx_count as i64,
+            indexed_at: chrono::Utc::now().timestamp(),
+            ..Default::default()
+        }
+    }
+}
+
+#[derive(Default, Clone, Debug, FromRow, Serialize, Deserialize)]
+pub struct IndexCheckpoint {
+    pub id: i64,
+    pub indexer: String,
+    pub height: i64,
+    pub block_hash: String,
+    pub runes_count: i64,
+    pub utxos_count: i64,
+    pub utxos_sum: String,
+    pub created_at: i64,
+}
+
+impl IndexCheckpoint {
+    pub fn new(indexer: &str, height: i64, block_hash: &str, state: IndexState) -> Self {
+        Self {
+            id: 0,
+            indexer: indexer.to_owned(),
+            height,
+            block_hash: block_hash.to_owned(),
+            runes_count: state.runes_count,
+            utxos_count: state.utxos_count,
+            utxos_sum: state.utxos_sum,
+            created_at: chrono::Utc::now().timestamp(),
+        }
+    }
+
+    /// describes how the current state of the height differs from the recorded one
+    pub fn mismatch(&self, block_hash: &str, state: &IndexState) -> Option<String> {
+        if self.block_hash != block_hash {
+            return Some(format!(
+                "block hash changed: recorded={} current={}",
+                self.block_hash, block_hash
+            ));
+        }
+
+        let recorded = IndexState {
+            runes_count: self.runes_count,
+            utxos_count: self.utxos_count,
+            utxos_sum: self.utxos_sum.clone(),
+        };
+        if &recorded != state {
+            return Some(format!(
+                "state changed: recorded={:?} current={:?}",
+                recorded, state
+            ));
+        }
+        None
+    }
 }
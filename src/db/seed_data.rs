@@ -1,9 +1,10 @@
-use bitcoin::{hashes::Hash, Txid};
-use ordinals::{Etching, Runestone, SpacedRune, Terms};
+use bitcoin::{absolute::LockTime, hashes::Hash, ScriptBuf, Transaction, TxOut, Txid};
+use ordinals::{Artifact, Etching, Runestone, SpacedRune, Terms};
 use std::str::FromStr;
 
 use super::models::Rune;
 
+/// the reserved rune etched at the runes activation, the default seed
 pub fn reserved_rune() -> Rune {
     let sp = SpacedRune::from_str("UNCOMMON•GOODS").unwrap();
     let etching = Etching {
@@ -26,6 +27,48 @@ pub fn reserved_rune() -> Rune {
         pointer: None,
         edicts: Vec::new(),
     };
+
+    etched_rune(&runestone, &etching, sp, 1, 0)
+}
+
+/// rune etched by the hex encoded runestone script, like the ones stored in `runes.raw_data`
+pub fn seed_rune(runestone_hex: &str, block: i64, tx_id: i32) -> anyhow::Result<Rune> {
+    let tx = Transaction {
+        version: 2,
+        lock_time: LockTime::ZERO,
+        input: Vec::new(),
+        output: vec![TxOut {
+            value: 0,
+            script_pubkey: ScriptBuf::from_hex(runestone_hex)?,
+        }],
+    };
+    let Some(Artifact::Runestone(runestone)) = Runestone::decipher(&tx) else {
+        anyhow::bail!("seed {} isn't a valid runestone", runestone_hex)
+    };
+    let Some(etching) = runestone.etching else {
+        anyhow::bail!("seed runestone {} has no etching", runestone_hex)
+    };
+    let Some(rune) = etching.rune else {
+        anyhow::bail!("seed etching {} has no rune name", runestone_hex)
+    };
+    if etching.supply().is_none() {
+        anyhow::bail!("seed etching of {} has an inconsistent supply", rune)
+    }
+
+    let spaced = SpacedRune {
+        rune,
+        spacers: etching.spacers.unwrap_or_default(),
+    };
+    Ok(etched_rune(&runestone, &etching, spaced, block, tx_id))
+}
+
+fn etched_rune(
+    runestone: &Runestone,
+    etching: &Etching,
+    sp: SpacedRune,
+    block: i64,
+    tx_id: i32,
+) -> Rune {
     let max_supply = etching.supply().unwrap_or_default();
     let premine = etching.premine.unwrap_or_default();
 
@@ -34,10 +77,10 @@ pub fn reserved_rune() -> Rune {
         rune: sp.rune.to_string(),
         display_name: sp.to_string(),
         symbol: etching.symbol.unwrap_or('¤').to_string(),
-        block: 1,
-        tx_id: 0,
+        block,
+        tx_id,
         mints: 0,
-        premine: "0".to_string(),
+        premine: premine.to_string(),
         burned: "0".to_string(),
         max_supply: max_supply.to_string(),
         minted: premine.to_string(),
@@ -50,3 +93,43 @@ pub fn reserved_rune() -> Rune {
         raw_data: runestone.encipher().into_bytes(),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn seed_runes_are_decoded() {
+        use super::{reserved_rune, seed_rune};
+        use bitcoin::ScriptBuf;
+        use ordinals::{Etching, Rune, Runestone};
+        use std::str::FromStr;
+
+        let reserved = reserved_rune();
+        let hex = ScriptBuf::from_bytes(reserved.raw_data.clone()).to_hex_string();
+        let seed = seed_rune(&hex, 1, 0).unwrap();
+        assert_eq!(seed.rune, "UNCOMMONGOODS");
+        assert_eq!(seed.display_name, reserved.display_name);
+        assert_eq!(seed.symbol, reserved.symbol);
+        assert_eq!(seed.max_supply, reserved.max_supply);
+        assert_eq!(seed.raw_data, reserved.raw_data);
+
+        let runestone = Runestone {
+            etching: Some(Etching {
+                rune: Some(Rune::from_str("TESTSEED").unwrap()),
+                premine: Some(1_000),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let seed = seed_rune(&runestone.encipher().to_hex_string(), 2, 1).unwrap();
+        assert_eq!((seed.block, seed.tx_id), (2, 1));
+        assert_eq!(seed.premine, "1000");
+        assert_eq!(seed.in_circulation, "1000");
+
+        assert!(seed_rune("zz", 1, 0).is_err());
+        // OP_RETURN without the runes magic
+        assert!(seed_rune("6a", 1, 0).is_err());
+        // a runestone without an etching
+        let runestone = Runestone::default();
+        assert!(seed_rune(&runestone.encipher().to_hex_string(), 1, 0).is_err());
+    }
+}
@@ -1,12 +1,16 @@
 use std::str::FromStr;
+use std::sync::Arc;
 
 use bitcoin::{Network, ScriptBuf, Txid};
-use bitcoincore_rpc::{bitcoin, Auth, Client, RawTx, RpcApi};
+use bitcoincore_rpc::{bitcoin, RawTx, RpcApi};
 use clap::Parser;
 use ordinals::{Etching, Rune, SpacedRune, Terms};
 
 use crate::{
+    btc_utxo::UtxoClient,
+    cli_error::CliError,
     db,
+    tx::fee::effective_fee_rate,
     tx::runes_txs::{RunesTxBuilder, COMMITMENT_OUT_VALUE},
     tx::signer::{AddressMode, PKSigner},
     tx::utxo::Utxo,
@@ -33,6 +37,10 @@ pub struct EtchingCmd {
 
     #[arg(long, default_value_t = false)]
     submit_etch: bool,
+
+    /// Value of each commitment output in sats, it pays the rune output and the etching fee
+    #[arg(long, default_value_t = COMMITMENT_OUT_VALUE)]
+    commitment_value: u64,
 }
 
 impl EtchingCmd {
@@ -42,7 +50,7 @@ impl EtchingCmd {
         let net = cfg.btc.get_network();
         let signer = PKSigner::new_from_secret(
             net,
-            &cfg.signature_provider.local.secret_key,
+            &cfg.signature_provider.local.secret()?,
             AddressMode::new_from_str(&cfg.signature_provider.local.mode),
         )?;
 
@@ -53,7 +61,10 @@ impl EtchingCmd {
 
         println!();
 
-        let utxo = repo.select_btc_utxo(&signer.address.to_string()).await?;
+        let utxo_provider = UtxoClient::new(&cfg.btc, Arc::new(repo))?;
+        let utxo = utxo_provider
+            .get_utxo(&signer.address.to_string(), 100, 0)
+            .await?;
 
         let mut etching = csv_to_etching(RuneCSVRow {
             name: "BOB•MINTING•BLOODY•RUNES".to_string(),
@@ -72,7 +83,18 @@ impl EtchingCmd {
 
         let change_address = signer.address.clone();
         let commitment_pubkey = signer.xonly_pubkey();
-        let builder = RunesTxBuilder::new(signer.net, commitment_pubkey, change_address, self.fee);
+        let rpc = cfg.btc.rpc_client()?;
+        let lock_time = cfg.btc.lock_time()?.resolve_from_node(&rpc)?;
+        let builder = RunesTxBuilder::new(signer.net, commitment_pubkey, change_address, self.fee)
+            .with_rune_output_value(cfg.btc.rune_output_value())
+            .with_fee_safety_factor(cfg.btc.fee_safety_factor())
+            .with_tx_version(cfg.btc.tx_version()?)
+            .with_max_runestone_size(cfg.btc.max_runestone_size())
+            .with_output_order(cfg.btc.output_order()?)
+            .with_lock_time(lock_time);
+        let commitment_value = builder
+            .commitment_output_value(&etching, self.commitment_value)
+            .map_err(|e| CliError::Validation(e.to_string()))?;
         let utxo = utxo
             .iter()
             .map(|e| Utxo {
@@ -84,24 +106,30 @@ impl EtchingCmd {
             .collect::<Vec<Utxo>>();
 
         let (unsigned_commit_tx, commit_tx_outs, parent_outs) =
-            builder.create_commitment_tx(etching_list.clone(), utxo, COMMITMENT_OUT_VALUE);
+            builder.create_commitment_tx(etching_list.clone(), utxo, commitment_value)?;
 
+        let in_value: u64 = parent_outs.iter().map(|o| o.value).sum();
         let commit_tx = signer.sign_tx(&unsigned_commit_tx, parent_outs)?;
         let commitment_txid = commit_tx.txid();
+        let commit_fee = in_value - commit_tx.output.iter().map(|o| o.value).sum::<u64>();
 
         println!("COMMIT TXID ->> {}", commit_tx.txid());
         println!("COMMIT TXID ->> {}", commitment_txid);
         println!("COMMIT RAW_TX ->> {}", commit_tx.raw_hex());
+        println!(
+            "COMMIT FEE ->> fee={} vsize={} fee_rate={:.2} safety_factor={}",
+            commit_fee,
+            commit_tx.vsize(),
+            effective_fee_rate(commit_fee, commit_tx.vsize()),
+            cfg.btc.fee_safety_factor()
+        );
 
         println!();
 
-        let rpc = Client::new(
-            &cfg.btc.address,
-            Auth::UserPass(cfg.btc.rpc_user.clone(), cfg.btc.rpc_password.clone()),
-        )?;
-
         if self.submit {
-            let tx_id = rpc.send_raw_transaction(commit_tx.raw_hex())?;
+            let tx_id = rpc
+                .send_raw_transaction(commit_tx.raw_hex())
+                .map_err(CliError::broadcast)?;
             println!("COMMIT TX ACCEPTED ->> {}", tx_id);
             // } else {
             // println!("{:#?}", commit_tx);
@@ -131,7 +159,7 @@ impl EtchingCmd {
                 commitment_out.clone(),
                 commitment_txid,
                 signer.address.clone(),
-            );
+            )?;
             println!(
                 "COMMITMENT_ADDRESS ->> {}",
                 commitment_out.commit_tx_address
@@ -143,7 +171,9 @@ impl EtchingCmd {
             println!("ETCHING RAW_TX ->> {}", signed_etching_tx.raw_hex());
 
             if self.submit_etch {
-                let tx_id = rpc.send_raw_transaction(signed_etching_tx.raw_hex())?;
+                let tx_id = rpc
+                    .send_raw_transaction(signed_etching_tx.raw_hex())
+                    .map_err(CliError::broadcast)?;
                 println!("ETCHING TX ACCEPTED ->> {}", tx_id);
                 // } else {
                 // println!("{:#?}", signed_etching_tx);
@@ -0,0 +1,89 @@
+use crate::{db, utils::format_amount};
+
+const BATCH_SIZE: i32 = 1000;
+
+#[derive(Debug, clap::Parser)]
+pub struct HoldersCmd {
+    /// rune name, without spacers
+    #[arg(long)]
+    rune: String,
+
+    /// skip holders with a smaller balance
+    #[arg(long)]
+    min_balance: Option<String>,
+
+    /// print JSON instead of a table
+    #[arg(long, default_value_t = false)]
+    json: bool,
+}
+
+impl HoldersCmd {
+    pub async fn run(&self, config_path: &str) -> anyhow::Result<()> {
+        let min_balance = match &self.min_balance {
+            Some(value) => value
+                .parse::<u128>()
+                .map_err(|e| anyhow::anyhow!("invalid min_balance {}: {}", value, e))?,
+            None => 0,
+        }
+        .to_string();
+
+        let mut cfg = crate::config::read_config(config_path)?;
+        cfg.db.automigrate = false;
+        let repo = db::open_postgres_db(cfg.db).await?;
+        let divisibility = repo.get_rune(&self.rune).await?.divisibility;
+
+        let mut holders = Vec::new();
+        loop {
+            let batch = repo
+                .select_runes_holders(&self.rune, &min_balance, BATCH_SIZE, holders.len() as i32)
+                .await?;
+            let done = batch.len() < BATCH_SIZE as usize;
+            holders.extend(batch);
+            if done {
+                break;
+            }
+        }
+
+        if self.json {
+            let list: Vec<serde_json::Value> = holders
+                .iter()
+                .map(|h| {
+                    serde_json::json!({
+                        "address": h.address,
+                        "balance": h.balance,
+                        "display_balance": display(&h.balance, divisibility),
+                    })
+                })
+                .collect();
+            println!("{}", serde_json::to_string_pretty(&list)?);
+            return Ok(());
+        }
+
+        let width = holders
+            .iter()
+            .map(|h| h.address.len())
+            .max()
+            .unwrap_or(0)
+            .max("ADDRESS".len());
+        println!("{:<width$}  BALANCE", "ADDRESS", width = width);
+        for h in holders.iter() {
+            println!(
+                "{:<width$}  {}",
+                h.address,
+                display(&h.balance, divisibility),
+                width = width
+            );
+        }
+        println!();
+        println!("{} holders of {}", holders.len(), self.rune);
+
+        Ok(())
+    }
+}
+
+fn display(balance: &str, divisibility: i32) -> String {
+    match balance.parse::<u128>() {
+        Ok(raw) => format_amount(raw, divisibility),
+        Err(_) => balance.to_owned(),
+    }
+}
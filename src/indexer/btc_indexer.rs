@@ -1,5 +1,5 @@
 use bitcoin::{Transaction, TxIn};
-use bitcoincore_rpc::{Auth, Client, RpcApi};
+use bitcoincore_rpc::{Client, RpcApi};
 use std::sync::Arc;
 use std::time::Duration;
 use tokio::{task::JoinHandle, time::sleep};
@@ -7,7 +7,7 @@ use tokio_util::sync::CancellationToken;
 
 use crate::{config, db, service::BtcIndexCache};
 
-static BTC_INDEXER_ID: &str = "btc_indexer";
+pub static BTC_INDEXER_ID: &str = "btc_indexer";
 
 pub struct TxInfo {
     pub block: i64,
@@ -32,11 +32,7 @@ impl BtcIndexer {
         repo: Arc<db::Repo>,
     ) -> Self {
         let net = btc_cfg.get_network();
-        let rpc = Client::new(
-            &btc_cfg.address,
-            Auth::UserPass(btc_cfg.rpc_user.clone(), btc_cfg.rpc_password.clone()),
-        )
-        .unwrap();
+        let rpc = btc_cfg.rpc_client().unwrap();
 
         Self {
             net,
@@ -115,17 +111,11 @@ impl BtcIndexer {
                 };
             }
 
-            if let Some(hash) = indexer.index_block(current_block).await {
-                match indexer
-                    .repo
-                    .update_last_indexed_block(current_block, BTC_INDEXER_ID)
-                    .await
-                {
-                    Ok(_) => (),
-                    Err(err) => {
-                        error!("Can't get BTC block error={}, hash={}", err, hash);
-                    }
-                };
+            if let Some((hash, tx_count)) = indexer.index_block(current_block).await {
+                let row = db::IndexedBlock::new(BTC_INDEXER_ID, current_block, &hash, tx_count);
+                if !super::finish_block(&indexer.repo, &row, &stop_signal).await {
+                    break;
+                }
 
                 current_block += 1;
             }
@@ -144,7 +134,7 @@ impl BtcIndexer {
         }
     }
 
-    async fn index_block(&mut self, height: i64) -> Option<String> {
+    async fn index_block(&mut self, height: i64) -> Option<(String, usize)> {
         let block_hash = match self.rpc.get_block_hash(height as u64) {
             Ok(hash) => hash,
             Err(err) => {
@@ -180,12 +170,12 @@ impl BtcIndexer {
             self.handle_btc_payments(&tx_info).await;
         }
 
-        Some(block_hash.to_string())
+        Some((block_hash.to_string(), block.txdata.len()))
     }
 
     async fn handle_btc_payments(&mut self, tx_info: &TxInfo) {
         for input in tx_info.tx.input.iter() {
-            self.spent_btc_utxo(input).await;
+            self.spent_btc_utxo(input, tx_info.block).await;
         }
 
         for (vout, out) in tx_info.tx.output.iter().enumerate() {
@@ -213,6 +203,8 @@ impl BtcIndexer {
                 pk_script: out.script_pubkey.to_hex_string(),
                 amount: out.value as i64,
                 spend: false,
+                spent_block: None,
+                coinbase: tx_info.tx.is_coin_base(),
             };
 
             if let Err(err) = self.repo.insert_btc_utxo(&btc_utxo).await {
@@ -221,7 +213,7 @@ impl BtcIndexer {
         }
     }
 
-    async fn spent_btc_utxo(&mut self, input: &TxIn) -> Option<()> {
+    async fn spent_btc_utxo(&mut self, input: &TxIn, block: i64) -> Option<()> {
         let parent_txid = input.previous_output.txid.to_string();
         let vout = input.previous_output.vout as i32;
 
@@ -229,7 +221,7 @@ impl BtcIndexer {
             return None;
         };
 
-        if let Err(err) = self.repo.spent_btc_utxo(&parent_txid, vout).await {
+        if let Err(err) = self.repo.spent_btc_utxo(&parent_txid, vout, block).await {
             error!(
                 "failed to mark rune utxo as spend: error={} tx_hash={} vout={}",
                 err, parent_txid, vout
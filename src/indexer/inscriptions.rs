@@ -0,0 +1,142 @@
+use bitcoin::hashes::{sha256, Hash};
+use bitcoin::{opcodes, script::Instruction, Script, Transaction};
+
+const PROTOCOL_ID: &[u8] = b"ord";
+const BODY_TAG: &[u8] = &[];
+const CONTENT_TYPE_TAG: &[u8] = &[1];
+
+/// Inscription envelope of the ord protocol:
+/// `OP_FALSE OP_IF "ord" [tag value]... [OP_0 body...] OP_ENDIF`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Envelope {
+    pub content_type: Option<String>,
+    pub body: Vec<u8>,
+}
+
+/// first inscription of the tx with its id `{txid}i0`, envelopes are looked up
+/// in the tapscripts of the inputs in order
+pub fn first_inscription(tx: &Transaction) -> Option<(String, Envelope)> {
+    tx.input
+        .iter()
+        .filter_map(|input| input.witness.tapscript())
+        .find_map(parse_envelope)
+        .map(|envelope| (format!("{}i0", tx.txid()), envelope))
+}
+
+pub fn content_hash(body: &[u8]) -> String {
+    sha256::Hash::hash(body).to_string()
+}
+
+pub fn parse_envelope(tapscript: &Script) -> Option<Envelope> {
+    // an invalid instruction ends the script, the envelope must be terminated before it
+    let instructions: Vec<Instruction> = tapscript.instructions().map_while(Result::ok).collect();
+    // the envelope may follow e.g. the key check of the script
+    let start = instructions.windows(3).position(is_envelope_start)? + 3;
+
+    let mut envelope = Envelope::default();
+    let mut in_body = false;
+    let mut tag: Option<Vec<u8>> = None;
+    for instruction in &instructions[start..] {
+        let push = match instruction {
+            Instruction::Op(op) if *op == opcodes::all::OP_ENDIF => return Some(envelope),
+            Instruction::PushBytes(p) => p.as_bytes().to_vec(),
+            Instruction::Op(op) => pushnum_bytes(*op)?,
+        };
+
+        if in_body {
+            envelope.body.extend(push);
+            continue;
+        }
+
+        match tag.take() {
+            None if push == BODY_TAG => in_body = true,
+            None => tag = Some(push),
+            Some(t) => {
+                // repeated and unknown fields are ignored
+                if t == CONTENT_TYPE_TAG && envelope.content_type.is_none() {
+                    envelope.content_type = Some(String::from_utf8_lossy(&push).into_owned());
+                }
+            }
+        }
+    }
+
+    // an envelope without OP_ENDIF is invalid
+    None
+}
+
+/// `OP_FALSE OP_IF "ord"`
+fn is_envelope_start(instructions: &[Instruction]) -> bool {
+    match instructions {
+        [Instruction::PushBytes(empty), Instruction::Op(op), Instruction::PushBytes(id)] => {
+            empty.is_empty() && *op == opcodes::all::OP_IF && id.as_bytes() == PROTOCOL_ID
+        }
+        _ => false,
+    }
+}
+
+/// ord accepts OP_1..OP_16 as one byte pushes
+fn pushnum_bytes(op: opcodes::All) -> Option<Vec<u8>> {
+    let code = op.to_u8();
+    let first = opcodes::all::OP_PUSHNUM_1.to_u8();
+    let last = opcodes::all::OP_PUSHNUM_16.to_u8();
+    (first..=last)
+        .contains(&code)
+        .then(|| vec![code - first + 1])
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn envelope_is_parsed() {
+        use super::{content_hash, parse_envelope};
+        use bitcoin::{opcodes, script::Builder, ScriptBuf};
+
+        let envelope = |builder: Builder| {
+            builder
+                .push_opcode(opcodes::OP_FALSE)
+                .push_opcode(opcodes::all::OP_IF)
+                .push_slice(b"ord")
+        };
+        let script = envelope(
+            Builder::new()
+                .push_slice([7u8; 32])
+                .push_opcode(opcodes::all::OP_CHECKSIG),
+        )
+        .push_opcode(opcodes::all::OP_PUSHNUM_1)
+        .push_slice(b"image/png")
+        .push_opcode(opcodes::OP_FALSE)
+        .push_slice(b"\x89PNG")
+        .push_slice(b"data")
+        .push_opcode(opcodes::all::OP_ENDIF)
+        .into_script();
+
+        let parsed = parse_envelope(&script).unwrap();
+        assert_eq!(parsed.content_type.as_deref(), Some("image/png"));
+        assert_eq!(parsed.body, b"\x89PNGdata");
+        assert_eq!(content_hash(&parsed.body).len(), 64);
+
+        // the content type may be pushed as data too, other fields are skipped
+        let script = envelope(Builder::new())
+            .push_slice([5u8])
+            .push_slice(b"meta")
+            .push_slice([1u8])
+            .push_slice(b"application/json")
+            .push_opcode(opcodes::all::OP_ENDIF)
+            .into_script();
+        let parsed = parse_envelope(&script).unwrap();
+        assert_eq!(parsed.content_type.as_deref(), Some("application/json"));
+        assert!(parsed.body.is_empty());
+
+        // unterminated or of another protocol
+        let script = envelope(Builder::new()).push_slice(b"x").into_script();
+        assert_eq!(parse_envelope(&script), None);
+        let script = Builder::new()
+            .push_opcode(opcodes::OP_FALSE)
+            .push_opcode(opcodes::all::OP_IF)
+            .push_slice(b"brc")
+            .push_opcode(opcodes::all::OP_ENDIF)
+            .into_script();
+        assert_eq!(parse_envelope(&script), None);
+        assert_eq!(parse_envelope(&ScriptBuf::new()), None);
+    }
+}
@@ -1,5 +1,33 @@
 mod btc_indexer;
+mod inscriptions;
 mod runes_indexer;
 
-pub use btc_indexer::BtcIndexer;
-pub use runes_indexer::EtchingIndexer;
+pub use btc_indexer::{BtcIndexer, BTC_INDEXER_ID};
+pub use runes_indexer::{
+    is_valid_etched_name, minimum_etchable_rune, simulate_runes, EtchingIndexer, RuneTxsStats,
+    RunesOutcome, SharedRuneTxsStats, ETCHING_INDEXER_ID,
+};
+
+/// stores the processed block and moves the indexer height in one transaction. The block's
+/// changes are applied by then, so a failed write is retried instead of indexing it again.
+/// `false` when cancelled before the block is stored
+async fn finish_block(
+    db: &crate::db::Repo,
+    row: &crate::db::IndexedBlock,
+    cancel: &tokio_util::sync::CancellationToken,
+) -> bool {
+    loop {
+        match db.finish_block(row).await {
+            Ok(()) => return true,
+            Err(err) => error!(
+                "Can't record indexed block, retrying: error={} indexer={} height={}",
+                err, row.indexer, row.height
+            ),
+        }
+
+        tokio::select! {
+            _ = tokio::time::sleep(std::time::Duration::from_secs(1)) => {}
+            _ = cancel.cancelled() => return false,
+        }
+    }
+}
@@ -1,16 +1,20 @@
 use bitcoin::Txid;
 use bitcoin::{opcodes, script::Instruction, Address, Transaction, TxOut};
-use bitcoincore_rpc::{Auth, Client, RpcApi};
-use ordinals::{Artifact, Edict, RuneId, Runestone, SpacedRune};
+use bitcoincore_rpc::{Client, RpcApi};
+use ordinals::{Artifact, Edict, Etching, Flaw, RuneId, Runestone, SpacedRune};
+use serde::Serialize;
 use std::collections::{HashMap, HashSet};
 use std::str::FromStr;
+use std::sync::Arc;
 use std::time::Duration;
+use tokio::sync::RwLock;
 use tokio::{task::JoinHandle, time::sleep};
 use tokio_util::sync::CancellationToken;
 
+use super::inscriptions;
 use crate::{config, db, service::entities, service::StateProvider};
 
-static ETCHING_INDEXER_ID: &str = "rune_etchings";
+pub static ETCHING_INDEXER_ID: &str = "rune_etchings";
 
 pub struct TxInfo {
     pub block: i64,
@@ -20,8 +24,8 @@ pub struct TxInfo {
     pub tx: Transaction,
 }
 
-#[derive(Default, Debug, Clone)]
-struct RuneTxsStats {
+#[derive(Default, Debug, Clone, Serialize)]
+pub struct RuneTxsStats {
     etches: u64,
     invalid_etches: u64,
     edicts: u64,
@@ -32,6 +36,36 @@ struct RuneTxsStats {
     cenotaphs: u64,
 }
 
+impl RuneTxsStats {
+    fn add(&mut self, other: &RuneTxsStats) {
+        self.etches += other.etches;
+        self.invalid_etches += other.invalid_etches;
+        self.edicts += other.edicts;
+        self.invalid_edicts += other.invalid_edicts;
+        self.mints += other.mints;
+        self.invalid_mints += other.invalid_mints;
+        self.burned_txs += other.burned_txs;
+        self.cenotaphs += other.cenotaphs;
+    }
+
+    fn indexed_block(&self, height: i64, block_hash: &str, tx_count: usize) -> db::IndexedBlock {
+        db::IndexedBlock {
+            etchings: Some(self.etches as i64),
+            mints: Some(self.mints as i64),
+            edicts: Some(self.edicts as i64),
+            cenotaphs: Some(self.cenotaphs as i64),
+            burned_txs: Some(self.burned_txs as i64),
+            ..db::IndexedBlock::new(ETCHING_INDEXER_ID, height, block_hash, tx_count)
+        }
+    }
+}
+
+pub type SharedRuneTxsStats = Arc<RwLock<RuneTxsStats>>;
+
+const DEFAULT_ANOMALIES_RETENTION: i64 = 1000;
+/// how many checkpoints are checked on start when the latest one doesn't match
+const CHECKPOINTS_TO_VERIFY: i32 = 10;
+
 pub struct EtchingIndexer {
     net: bitcoin::Network,
     cfg: config::IndexersConfig,
@@ -42,6 +76,9 @@ pub struct EtchingIndexer {
     filter_runes: bool,
     runes_watchlist: HashSet<String>,
     runes_ids_watchlist: HashSet<RuneId>,
+    block_etchings: BlockEtchings,
+    // cumulative stats since the indexer start
+    total_stats: SharedRuneTxsStats,
 }
 
 #[derive(Debug, Clone, Default)]
@@ -58,11 +95,7 @@ impl EtchingIndexer {
         service_repo: StateProvider,
     ) -> Self {
         let net = cfg.get_network();
-        let rpc = Client::new(
-            &cfg.address,
-            Auth::UserPass(cfg.rpc_user.clone(), cfg.rpc_password.clone()),
-        )
-        .unwrap();
+        let rpc = cfg.rpc_client().unwrap();
 
         Self {
             net,
@@ -72,10 +105,17 @@ impl EtchingIndexer {
             pending_txs: HashSet::new(),
             runes_ids_watchlist: HashSet::new(),
             runes_watchlist: HashSet::new(),
+            block_etchings: BlockEtchings::default(),
             filter_runes: !icfg.runes_watchlist.is_empty(),
+            total_stats: SharedRuneTxsStats::default(),
         }
     }
 
+    /// the cumulative stats since the indexer start, served by `GET /admin/indexer/stats`
+    pub fn total_stats(&self) -> SharedRuneTxsStats {
+        self.total_stats.clone()
+    }
+
     pub fn start(self, cancel: CancellationToken) -> JoinHandle<()> {
         // todo: use spawn_blocking
         tokio::spawn(self.run(cancel.clone()))
@@ -94,6 +134,15 @@ impl EtchingIndexer {
             Err(_) => 0,
         };
 
+        let last_block = match indexer.verify_checkpoints(last_block).await {
+            Ok(height) => height,
+            Err(err) => {
+                error!("Index checkpoint verification failed: {}", err);
+                error!("Indexing stopped");
+                return;
+            }
+        };
+
         let first_block = if last_block > indexer.cfg.runes_starting_height {
             last_block
         } else {
@@ -114,6 +163,10 @@ impl EtchingIndexer {
             best_block, first_block
         );
 
+        if !indexer.cfg.handle_edicts() {
+            warn!("!!! indexers.handle_edicts is disabled: txs with edicts don't move runes, the runes index is NOT canonical and its balances diverge from ord !!!");
+        }
+
         if indexer.filter_runes {
             for rune_name in indexer.cfg.runes_watchlist.iter() {
                 match indexer.service_repo.db().get_rune(rune_name).await {
@@ -157,23 +210,37 @@ impl EtchingIndexer {
                 };
             }
 
-            if let Some((hash, tx_count, stats)) = indexer.index_block(current_block).await {
-                match indexer
-                    .service_repo
-                    .db()
-                    .update_last_indexed_block(current_block, ETCHING_INDEXER_ID)
-                    .await
-                {
-                    Ok(_) => (),
-                    Err(err) => {
-                        error!("Can't get BTC block error={}, hash={}", err, hash);
-                    }
-                };
+            let indexed = match indexer.index_block(current_block).await {
+                Ok(indexed) => indexed,
+                Err(err) => {
+                    error!(
+                        "Can't revert the block for a retry: height={} error={}",
+                        current_block, err
+                    );
+                    error!("Indexing stopped");
+                    return;
+                }
+            };
+            if let Some((hash, tx_count, stats)) = indexed {
+                let row = stats.indexed_block(current_block, &hash, tx_count);
+                if !super::finish_block(&indexer.service_repo.db(), &row, &stop_signal).await {
+                    break;
+                }
+                indexer.service_repo.send_block_events(&hash);
+                if is_checkpoint_height(current_block, indexer.cfg.checkpoint_interval()) {
+                    indexer.record_checkpoint(current_block, &hash).await;
+                }
                 info!(
                     "Processed new block: height={} hash={} tx_count={}",
                     current_block, hash, tx_count
                 );
                 info!("Block stats: {:?}", stats);
+                {
+                    let mut total_stats = indexer.total_stats.write().await;
+                    total_stats.add(&stats);
+                    info!("Total stats: {:?}", total_stats);
+                }
+                indexer.prune_anomalies().await;
 
                 current_block += 1;
             }
@@ -191,12 +258,16 @@ impl EtchingIndexer {
         }
     }
 
-    async fn index_block(&mut self, height: i64) -> Option<(String, usize, RuneTxsStats)> {
+    /// `None` if the block has to be retried, an error if it can't be
+    async fn index_block(
+        &mut self,
+        height: i64,
+    ) -> anyhow::Result<Option<(String, usize, RuneTxsStats)>> {
         let block_hash = match self.rpc.get_block_hash(height as u64) {
             Ok(hash) => hash,
             Err(err) => {
                 error!("Can't get BTC block hash error={}, height={}", err, height);
-                return None;
+                return Ok(None);
             }
         };
 
@@ -204,7 +275,7 @@ impl EtchingIndexer {
             Ok(block) => block,
             Err(err) => {
                 error!("Can't get BTC block error={}, hash={}", err, block_hash);
-                return None;
+                return Ok(None);
             }
         };
 
@@ -217,26 +288,39 @@ impl EtchingIndexer {
 
         self.fetch_pending_txs().await;
 
-        let mut stats = RuneTxsStats::default();
-        for (txi, tx) in block.txdata.iter().enumerate() {
-            let tx_info = TxInfo {
-                block: height,
-                tx_n: txi as i32,
-                txid: tx.txid().to_string(),
-                tx: tx.clone(),
-                timestamp: block.header.time as i64,
-            };
+        let Some(stats) = self.index_block_txs(height, &block).await? else {
+            return Ok(None);
+        };
+        Ok(Some((block_hash.to_string(), block.txdata.len(), stats)))
+    }
 
-            if tx.is_coin_base() {
-                continue;
+    /// indexes the txs of the block one by one, later txs see the rune outputs of earlier ones.
+    /// A failed tx reverts the txs of the block stored before it, so the retry starts clean.
+    async fn index_block_txs(
+        &mut self,
+        height: i64,
+        block: &bitcoin::Block,
+    ) -> anyhow::Result<Option<RuneTxsStats>> {
+        let mut stats = RuneTxsStats::default();
+        self.block_etchings.start_block();
+        for tx_info in block_tx_infos(height, block) {
+            if let Err(err) = self.extract_runestone(&tx_info, &mut stats).await {
+                // the block is retried, a skipped input would leave its runes unspent
+                error!(
+                    "Can't index tx error={} block={}:{} tx={}",
+                    err, tx_info.block, tx_info.tx_n, tx_info.txid
+                );
+                // the spends of the earlier txs are stored, applying them again would lose their runes
+                self.service_repo
+                    .rewind(ETCHING_INDEXER_ID, height - 1)
+                    .await?;
+                return Ok(None);
             }
 
-            self.extract_runestone(&tx_info, &mut stats).await;
-
             self.check_pending_txs(&tx_info).await;
         }
 
-        Some((block_hash.to_string(), block.txdata.len(), stats))
+        Ok(Some(stats))
     }
 
     async fn fetch_pending_txs(&mut self) {
@@ -279,27 +363,54 @@ impl EtchingIndexer {
                 },
                 &mut RuneTxsStats::default(),
             )
-            .await;
+            .await?;
         }
+        self.service_repo.send_block_events(&block_hash.to_string());
 
         Ok(())
     }
 
-    async fn extract_runestone(&mut self, tx_info: &TxInfo, stats: &mut RuneTxsStats) {
+    async fn extract_runestone(
+        &mut self,
+        tx_info: &TxInfo,
+        stats: &mut RuneTxsStats,
+    ) -> anyhow::Result<()> {
         let first_rune_height = ordinals::Rune::first_rune_height(self.net);
         if (first_rune_height as i64) > tx_info.block {
-            return;
+            return Ok(());
         }
 
-        let input_runes_amounts = self.collect_and_spend_runes_inputs(&tx_info.tx).await;
-        let mut allocated_runes: Vec<HashMap<String, Allocation>> =
-            vec![HashMap::new(); tx_info.tx.output.len()];
+        let artifact = Runestone::decipher(&tx_info.tx);
+        let input_runes_amounts = if skips_input_runes(self.cfg.handle_edicts(), &artifact) {
+            debug!(
+                "Tx with edicts skipped, its input runes aren't moved: block={}:{} tx={}",
+                tx_info.block, tx_info.tx_n, tx_info.txid
+            );
+            HashMap::new()
+        } else {
+            self.collect_and_spend_runes_inputs(tx_info).await?
+        };
+
+        // like ord, only the first runestone counts, the others are just reported
+        let runestones = count_runestone_outputs(&tx_info.tx);
+        if runestones > 1 {
+            debug!(
+                "Tx with {} runestones, the first one is used: block={}:{} tx={}",
+                runestones, tx_info.block, tx_info.tx_n, tx_info.txid
+            );
+            self.record_anomaly(
+                tx_info,
+                db::IndexerAnomaly::MULTIPLE_RUNESTONES,
+                format!("{} runestone outputs", runestones),
+            )
+            .await;
+        }
 
-        let artifact = match Runestone::decipher(&tx_info.tx) {
+        let artifact = match artifact {
             Some(a) => a,
             None => {
                 self.burn_all_inputs(tx_info, input_runes_amounts).await;
-                return;
+                return Ok(());
             }
         };
 
@@ -309,99 +420,328 @@ impl EtchingIndexer {
                     "CENOTAPH was made: block={}:{} tx={} {:?}",
                     tx_info.block, tx_info.tx_n, tx_info.txid, cenotaph
                 );
+                if let Some(Flaw::UnrecognizedEvenTag | Flaw::UnrecognizedFlag) = cenotaph.flaw {
+                    // the spec requires such runestones to be cenotaphs, but it's also
+                    // what a protocol upgrade looks like to an outdated indexer
+                    warn!(
+                        "CENOTAPH with unrecognized protocol field, indexer may be outdated: block={}:{} tx={} flaw={:?} raw={}",
+                        tx_info.block,
+                        tx_info.tx_n,
+                        tx_info.txid,
+                        cenotaph.flaw,
+                        raw_runestone_hex(&tx_info.tx)
+                    );
+                }
                 stats.cenotaphs += 1;
                 stats.burned_txs += 1;
+                self.record_anomaly(
+                    tx_info,
+                    db::IndexerAnomaly::CENOTAPH,
+                    format!("{:?}", cenotaph.flaw),
+                )
+                .await;
                 self.burn_all_inputs(tx_info, input_runes_amounts).await;
             }
             Artifact::Runestone(runestone) => {
+                let unknown_tags = unknown_odd_tags(&tx_info.tx);
+                if !unknown_tags.is_empty() {
+                    warn!(
+                        "RUNESTONE with unknown odd tags {:?}, indexer may be outdated: block={}:{} tx={} raw={}",
+                        unknown_tags,
+                        tx_info.block,
+                        tx_info.tx_n,
+                        tx_info.txid,
+                        raw_runestone_hex(&tx_info.tx)
+                    );
+                }
+
+                // the etched rune and the mint are stored only after the whole runestone
+                // turns out valid, so invalid txs don't leave phantom runes or mints behind
+                let mut runes = RunestoneRunes::default();
+                let mut etched_rune = None;
                 if !self.filter_runes && runestone.etching.is_some() {
-                    if !self
-                        .handle_rune_etching(tx_info, &runestone, &mut allocated_runes)
-                        .await
-                    {
+                    etched_rune = self.handle_rune_etching(tx_info, &runestone).await;
+                    let Some((rune_row, _)) = etched_rune.as_ref() else {
                         stats.invalid_etches += 1;
                         stats.burned_txs += 1;
+                        self.record_anomaly(
+                            tx_info,
+                            db::IndexerAnomaly::INVALID_ETCHING,
+                            format!("{:?}", runestone.etching),
+                        )
+                        .await;
                         self.burn_all_inputs(tx_info, input_runes_amounts).await;
-                        return;
+                        return Ok(());
                     };
-                    stats.etches += 1;
+                    runes.etching = Some(rune_row.rune.clone());
                 }
+
+                let mut minted_rune = None;
                 if let Some(mint) = runestone.mint {
-                    if !self
-                        .handle_mint(tx_info, mint, runestone.pointer, &mut allocated_runes)
-                        .await
-                    {
+                    minted_rune = self.handle_mint(tx_info, mint, runestone.pointer).await;
+                    let Some((rune_info, amount)) = minted_rune.as_ref() else {
                         stats.invalid_mints += 1;
                         stats.burned_txs += 1;
+                        self.record_anomaly(
+                            tx_info,
+                            db::IndexerAnomaly::INVALID_MINT,
+                            format!("mint={} pointer={:?}", mint, runestone.pointer),
+                        )
+                        .await;
 
                         self.burn_all_inputs(tx_info, input_runes_amounts).await;
-                        return;
+                        return Ok(());
                     };
-                    stats.mints += 1;
+                    runes.mint = Some((rune_info.rune.clone(), *amount));
                 }
 
-                if !runestone.edicts.is_empty() {
-                    let len = runestone.edicts.len() as u64;
-                    if !self
-                        .handle_rune_edicts(tx_info, runestone.edicts, &mut allocated_runes)
-                        .await
-                    {
-                        stats.invalid_edicts += len;
-                        stats.burned_txs += 1;
+                let edicts = edicts_to_handle(self.cfg.handle_edicts(), runestone.edicts.clone());
+                let Some(edict_runes) = self.handle_rune_edicts(tx_info, &edicts).await? else {
+                    stats.invalid_edicts += edicts.len() as u64;
+                    stats.burned_txs += 1;
+                    self.record_anomaly(
+                        tx_info,
+                        db::IndexerAnomaly::INVALID_EDICTS,
+                        format!("{:?}", edicts),
+                    )
+                    .await;
 
+                    self.burn_all_inputs(tx_info, input_runes_amounts).await;
+                    return Ok(());
+                };
+                runes.edicts = edict_runes;
+
+                let allocation = match allocate_runestone(
+                    self.net,
+                    &tx_info.tx,
+                    &runestone,
+                    &edicts,
+                    &input_runes_amounts,
+                    &runes,
+                    self.cfg.skip_unknown_edict_runes(),
+                ) {
+                    Ok(allocation) => allocation,
+                    Err((kind, reason)) => {
+                        match kind {
+                            db::IndexerAnomaly::INVALID_MINT => stats.invalid_mints += 1,
+                            db::IndexerAnomaly::INVALID_EDICTS => {
+                                stats.invalid_edicts += edicts.len() as u64
+                            }
+                            _ if etched_rune.is_some() => stats.invalid_etches += 1,
+                            _ => {}
+                        }
+                        stats.burned_txs += 1;
+                        self.record_anomaly(tx_info, kind, reason).await;
                         self.burn_all_inputs(tx_info, input_runes_amounts).await;
-                        return;
-                    };
+                        return Ok(());
+                    }
+                };
+                stats.edicts += edicts.len() as u64;
+
+                if let Some((mut rune_info, amount)) = minted_rune {
+                    rune_info.add_mint(amount);
+                    if let Err(err) = self.service_repo.update_rune_mint(&rune_info).await {
+                        error!(
+                            "Can't update rune mint: error={} rune={}",
+                            err, rune_info.rune
+                        );
+                    }
+                    stats.mints += 1;
+                }
 
-                    stats.edicts += len;
+                if let Some((mut rune_row, commitment)) = etched_rune {
+                    if allocation.unallocated_premine > 0 {
+                        let premine = u128::from_str(&rune_row.premine).unwrap_or_default();
+                        warn!(
+                            "RUNE({}) premine has no target output, {} burned block={}:{}",
+                            rune_row.rune,
+                            allocation.unallocated_premine,
+                            tx_info.block,
+                            tx_info.tx_n
+                        );
+                        rune_row.burned = allocation.unallocated_premine.to_string();
+                        rune_row.in_circulation =
+                            (premine - allocation.unallocated_premine).to_string();
+                    }
+                    if let Err(err) = self.service_repo.store_new_rune(&rune_row).await {
+                        error!("Can't insert rune: error={} rune={:?}", err, rune_row);
+                    }
+                    self.block_etchings.insert(tx_info, &rune_row.rune);
+                    if let Some(commitment) = commitment {
+                        let res = self
+                            .service_repo
+                            .db()
+                            .insert_rune_commitment(&commitment)
+                            .await;
+                        if let Err(err) = res {
+                            error!(
+                                "Can't insert rune commitment: error={} rune={}",
+                                err, rune_row.rune
+                            );
+                        }
+                    }
+                    if self.cfg.index_inscriptions() {
+                        self.store_inscription(tx_info, &rune_row.rune).await;
+                    }
+                    stats.etches += 1;
                 }
 
                 if !self
                     .apply_allocations(
                         &input_runes_amounts,
-                        &allocated_runes,
+                        &allocation.outputs,
                         tx_info,
                         runestone.pointer,
                     )
                     .await
                 {
                     stats.burned_txs += 1;
+                    self.record_anomaly(
+                        tx_info,
+                        db::IndexerAnomaly::INVALID_ALLOCATION,
+                        format!("inputs={:?}", input_runes_amounts),
+                    )
+                    .await;
                     self.burn_all_inputs(tx_info, input_runes_amounts).await;
                 }
             }
         }
+
+        Ok(())
+    }
+
+    async fn record_anomaly(&self, tx_info: &TxInfo, kind: &str, reason: String) {
+        let row = db::IndexerAnomaly {
+            id: 0,
+            block: tx_info.block,
+            tx_id: tx_info.tx_n,
+            tx_hash: tx_info.txid.clone(),
+            kind: kind.to_string(),
+            reason,
+            created_at: chrono::Utc::now().timestamp(),
+        };
+
+        if let Err(err) = self.service_repo.db().insert_indexer_anomaly(&row).await {
+            error!(
+                "Can't store indexer anomaly: error={} tx={} kind={}",
+                err, tx_info.txid, kind
+            );
+        }
+    }
+
+    async fn record_checkpoint(&self, height: i64, block_hash: &str) {
+        let db = self.service_repo.db();
+        let res = match db.get_index_state(height).await {
+            Ok(state) => {
+                let row = db::IndexCheckpoint::new(ETCHING_INDEXER_ID, height, block_hash, state);
+                db.insert_index_checkpoint(&row).await
+            }
+            Err(err) => Err(err),
+        };
+        match res {
+            Ok(_) => info!("Index checkpoint recorded: height={}", height),
+            Err(err) => error!(
+                "Can't record index checkpoint: error={} height={}",
+                err, height
+            ),
+        }
+    }
+
+    /// Checks the state behind the resume point against the recent checkpoints and
+    /// rewinds the index to the last one still matching, returns the height to resume from.
+    async fn verify_checkpoints(&mut self, last_block: i64) -> anyhow::Result<i64> {
+        let db = self.service_repo.db();
+        let checkpoints = db
+            .select_index_checkpoints(ETCHING_INDEXER_ID, last_block, CHECKPOINTS_TO_VERIFY)
+            .await?;
+
+        let rpc = &self.rpc;
+        let good = last_good_checkpoint(&checkpoints, |checkpoint| {
+            let db = db.clone();
+            async move {
+                let block_hash = rpc.get_block_hash(checkpoint.height as u64)?;
+                let state = db.get_index_state(checkpoint.height).await?;
+                Ok(checkpoint.mismatch(&block_hash.to_string(), &state))
+            }
+        })
+        .await?;
+
+        let Some(height) = good else {
+            return Ok(last_block);
+        };
+        if self.cfg.disable_rune_log {
+            // the mints above the height are known only from the log
+            anyhow::bail!(
+                "the last good checkpoint is at {}, the index can't be rewound to it without the runes log",
+                height
+            );
+        }
+        warn!(
+            "Rewinding the runes index to the last good checkpoint: height={} last_block={}",
+            height, last_block
+        );
+        self.service_repo.rewind(ETCHING_INDEXER_ID, height).await?;
+        info!("Runes index rewound: height={}", height);
+        Ok(height)
+    }
+
+    async fn prune_anomalies(&self) {
+        let keep = self
+            .cfg
+            .anomalies_retention
+            .unwrap_or(DEFAULT_ANOMALIES_RETENTION);
+        if let Err(err) = self.service_repo.db().prune_indexer_anomalies(keep).await {
+            error!("Can't prune indexer anomalies: error={}", err);
+        }
     }
 
     async fn handle_rune_etching(
         &mut self,
         tx_info: &TxInfo,
         runestone: &Runestone,
-        allocated_runes: &mut [HashMap<String, Allocation>],
-    ) -> bool {
-        let Some(etching) = runestone.etching else {
-            return false;
-        };
+    ) -> Option<(db::Rune, Option<db::RuneCommitment>)> {
+        let etching = runestone.etching?;
 
-        let (commitment_tx, rune) = if let Some(rune) = etching.rune {
-            let height = ordinals::Height(tx_info.block as u32);
-            let minimum = ordinals::Rune::minimum_at_height(self.net, height);
-
-            let Some(comitment_tx) = self.validate_commitment(tx_info, rune) else {
-                return false;
-            };
+        let (commitment, rune) = if let Some(rune) = etching.rune {
+            let mut commitment = self.validate_commitment(tx_info, rune)?;
 
-            if rune < minimum || rune.is_reserved() {
-                return false;
+            if !is_valid_etched_name(self.net, tx_info.block, rune) {
+                return None;
             }
 
-            (comitment_tx, rune)
+            commitment.rune = rune.to_string();
+            (Some(commitment), rune)
         } else {
             (
-                "".to_string(),
+                None,
                 ordinals::Rune::reserved(tx_info.block as u64, tx_info.tx_n as u32),
             )
         };
 
+        let divisibility = etching.divisibility.unwrap_or_default();
+        if let Some(reason) = self
+            .cfg
+            .etching_policy
+            .violation(&rune.to_string(), divisibility)
+        {
+            warn!(
+                "RUNE({}) violates the etching policy: {}. Invalid etching block={}:{}",
+                rune, reason, tx_info.block, tx_info.tx_n
+            );
+            return None;
+        }
+
+        let max_supply = match etching_supply(&etching) {
+            Ok(supply) => supply,
+            Err(reason) => {
+                warn!(
+                    "RUNE({}) has inconsistent supply: {}. Invalid etching block={}:{}",
+                    rune, reason, tx_info.block, tx_info.tx_n
+                );
+                return None;
+            }
+        };
+
         if self
             .service_repo
             .db()
@@ -413,7 +753,7 @@ impl EtchingIndexer {
                 "Rune with such name({}) already exists. Invalid etching block={}:{}",
                 rune, tx_info.block, tx_info.tx_n
             );
-            return false;
+            return None;
         };
 
         debug!(
@@ -434,7 +774,6 @@ impl EtchingIndexer {
             spacers: etching.spacers.unwrap_or_default(),
         };
 
-        let max_supply = etching.supply().unwrap_or_default();
         let premine = etching.premine.unwrap_or_default();
 
         let rune_row = db::Rune {
@@ -450,118 +789,60 @@ impl EtchingIndexer {
             premine: premine.to_string(),
             burned: "0".to_string(),
             in_circulation: premine.to_string(),
-            divisibility: etching.divisibility.unwrap_or_default() as i32,
+            divisibility: divisibility as i32,
             turbo: etching.turbo,
             timestamp: tx_info.timestamp,
             etching_tx: tx_info.txid.to_string(),
-            commitment_tx,
+            commitment_tx: commitment
+                .as_ref()
+                .map(|c| c.commitment_tx.clone())
+                .unwrap_or_default(),
             raw_data: runestone.encipher().into_bytes(),
         };
 
-        if let Err(err) = self.service_repo.store_new_rune(&rune_row).await {
-            error!("Can't insert rune: error={} rune={:?}", err, rune_row);
-            return true;
-        }
-
-        if premine == 0 {
-            return true;
-        }
-
-        if let Some(vout) = extract_premine_address(runestone, &tx_info.tx) {
-            let al = allocated_runes[vout as usize]
-                .entry(rune_row.rune.clone())
-                .or_default();
-            al.etching += premine;
-            return true;
-        }
-        if runestone.edicts.is_empty() {
-            return false;
-        }
-
-        let mut has_some_outs = false;
-        for edict in runestone.edicts.iter() {
-            if edict.id.block != 0 || edict.id.tx != 0 {
-                continue;
-            }
-            has_some_outs = true;
-            if edict.output as usize == tx_info.tx.output.len() {
-                // note that this allows `output == tx.output.len()`, which means to divide
-                // amount between all non-OP_RETURN outputs
-                let outs = get_non_opreturn_outputs(&tx_info.tx);
-
-                let amount = edict.amount / outs.len() as u128;
-                for (vout, _out) in outs.iter() {
-                    let al = allocated_runes[*vout as usize]
-                        .entry(rune.to_string())
-                        .or_default();
-                    al.etching += amount;
-                }
-            } else {
-                let vout = edict.output;
-
-                let al = allocated_runes[vout as usize]
-                    .entry(rune.to_string())
-                    .or_default();
-                al.etching += edict.amount;
-            }
-        }
-
-        has_some_outs
+        Some((rune_row, commitment))
     }
 
+    /// the minted rune and the mint amount, the mint is stored by the caller
+    /// once the whole runestone turns out valid
     async fn handle_mint(
         &mut self,
         tx_info: &TxInfo,
         rune_id: RuneId,
         pointer: Option<u32>,
-        allocated_runes: &mut [HashMap<String, Allocation>],
-    ) -> bool {
+    ) -> Option<(entities::RuneEntity, u128)> {
         debug!(
             "RUNE was minted: block={}:{} tx={} {:?}:{:?}",
             tx_info.block, tx_info.tx_n, tx_info.txid, rune_id, pointer,
         );
 
         if self.filter_runes && !self.runes_ids_watchlist.contains(&rune_id) {
-            return false;
+            return None;
         }
 
-        let Ok(mut rune_info) = self
+        let Ok(rune_info) = self
             .service_repo
             .get_rune_by_id(rune_id.block as i64, rune_id.tx as i32)
             .await
         else {
-            return false;
+            return None;
         };
 
         let Some(terms) = rune_info.terms else {
-            return false;
+            return None;
         };
 
         let amount = terms.amount.unwrap_or_default();
-        let Some(vout) = get_change_output(&tx_info.tx, pointer) else {
-            warn!(
-                "RUNE mint tx has no change output block={}:{} tx={}",
-                tx_info.block, tx_info.tx_n, tx_info.txid
-            );
-            return false;
-        };
-
-        rune_info.add_mint(amount);
-        let _ = self.service_repo.update_rune_mint(&rune_info).await;
-
-        let al = allocated_runes[vout as usize]
-            .entry(rune_info.rune.clone())
-            .or_default();
-        al.mint += amount;
-        true
+        Some((rune_info, amount))
     }
 
+    /// names of the runes moved by the edicts, `None` if the edicts are invalid
     async fn handle_rune_edicts(
         &mut self,
         tx_info: &TxInfo,
-        edicts: Vec<Edict>,
-        allocated_runes: &mut [HashMap<String, Allocation>],
-    ) -> bool {
+        edicts: &[Edict],
+    ) -> anyhow::Result<Option<HashMap<RuneId, String>>> {
+        let mut runes = HashMap::new();
         for edict in edicts.iter() {
             if edict.id.block == 0 && edict.id.tx == 0 {
                 // this is special edict related to etching
@@ -573,40 +854,33 @@ impl EtchingIndexer {
             );
 
             if self.filter_runes && !self.runes_ids_watchlist.contains(&edict.id) {
-                return false;
+                return Ok(None);
             }
 
-            let Some(rune) = self.service_repo.get_rune_name_by_id(&edict.id).await else {
+            let service_repo = &mut self.service_repo;
+            let rune = edict_rune(&self.block_etchings, &edict.id, |id| async move {
+                service_repo.get_rune_name_by_id(&id).await
+            })
+            .await?;
+            let Some(rune) = rune else {
+                if self.cfg.skip_unknown_edict_runes() {
+                    warn!(
+                        "RUNE of edict isn't indexed, skipped {:?} block={}:{}",
+                        edict, tx_info.block, tx_info.tx_n
+                    );
+                    continue;
+                }
                 error!(
                     "RUNE is not in cache! edict action {:?} block={}:{}",
                     edict, tx_info.block, tx_info.tx_n
                 );
-                return false;
+                return Ok(None);
             };
 
-            if edict.output as usize == tx_info.tx.output.len() {
-                // note that this allows `output == tx.output.len()`, which means to divide
-                // amount between all non-OP_RETURN outputs
-                let outs = get_non_opreturn_outputs(&tx_info.tx);
-
-                let amount = edict.amount / outs.len() as u128;
-                for (vout, _out) in outs.iter() {
-                    let al = allocated_runes[*vout as usize]
-                        .entry(rune.clone())
-                        .or_default();
-                    al.edict += amount;
-                }
-            } else {
-                let vout = edict.output;
-
-                let al = allocated_runes[vout as usize]
-                    .entry(rune.clone())
-                    .or_default();
-                al.edict += edict.amount;
-            }
+            runes.insert(edict.id, rune);
         }
 
-        true
+        Ok(Some(runes))
     }
 
     async fn apply_allocations(
@@ -616,34 +890,7 @@ impl EtchingIndexer {
         tx_info: &TxInfo,
         pointer: Option<u32>,
     ) -> bool {
-        {
-            let mut total_out: HashMap<String, u128> = HashMap::new();
-
-            for a in allocated_runes.iter() {
-                if a.is_empty() {
-                    continue;
-                }
-                a.iter()
-                    .for_each(|(k, al)| *total_out.entry(k.to_owned()).or_default() += al.edict)
-            }
-
-            for (k, out_value) in total_out.iter() {
-                let in_value = match unalocated_runes.get(k) {
-                    Some(b) => *b,
-                    None => 0,
-                };
-
-                if *out_value > in_value {
-                    debug!(
-                        "trying to spend more than have {} out={} > in={}",
-                        k, out_value, in_value
-                    );
-                    // trying to spend more than have
-                    return false;
-                }
-            }
-        }
-
+        // validate_allocations() is called before, so only the db writes are left here
         let mut unalocated_runes = unalocated_runes.clone();
         for (vout, a) in allocated_runes.iter().enumerate() {
             if a.is_empty() {
@@ -651,6 +898,13 @@ impl EtchingIndexer {
             }
 
             let out = &tx_info.tx.output[vout];
+            if out.script_pubkey.is_op_return() {
+                for (rune, al) in a.iter() {
+                    *unalocated_runes.entry(rune.to_owned()).or_default() -= al.edict;
+                }
+                continue;
+            }
+
             let address = match Address::from_script(&out.script_pubkey, self.net) {
                 Ok(a) => a,
                 Err(err) => {
@@ -683,7 +937,7 @@ impl EtchingIndexer {
 
                 if let Err(err) = self
                     .service_repo
-                    .store_new_runes_utxo(&rune_utxo, action)
+                    .store_new_runes_utxo(&rune_utxo, action, tx_info.timestamp)
                     .await
                 {
                     error!("Failed to insert the rune utxo: error={}", err);
@@ -693,12 +947,36 @@ impl EtchingIndexer {
             }
         }
 
-        let Some(vout) = get_change_output(&tx_info.tx, pointer) else {
+        for (rune, amount) in burned_allocations(allocated_runes, &tx_info.tx) {
             debug!(
-                "RUNE mint tx has no change output block={}:{} tx={}",
-                tx_info.block, tx_info.tx_n, tx_info.txid
+                "RUNE({}) {} burned by OP_RETURN allocation tx={}",
+                rune, amount, tx_info.txid
             );
-            return false;
+            if let Err(err) = self
+                .service_repo
+                .burn_rune_in_tx(&rune, amount, &tx_info.txid, tx_info.block)
+                .await
+            {
+                error!("Can't burn rune {} {} error={}", rune, amount, err);
+            }
+        }
+
+        let Some(vout) = spendable_change_output(&tx_info.tx, pointer) else {
+            // the pointer is an OP_RETURN or there is no other output, the remainder is burned
+            for (rune, amount) in unalocated_runes.iter().filter(|(_, amount)| **amount > 0) {
+                debug!(
+                    "RUNE({}) {} has no change output, burned block={}:{} tx={}",
+                    rune, amount, tx_info.block, tx_info.tx_n, tx_info.txid
+                );
+                if let Err(err) = self
+                    .service_repo
+                    .burn_rune_in_tx(rune, *amount, &tx_info.txid, tx_info.block)
+                    .await
+                {
+                    error!("Can't burn rune {} {} error={}", rune, amount, err);
+                }
+            }
+            return true;
         };
 
         let out = &tx_info.tx.output[vout as usize];
@@ -731,7 +1009,7 @@ impl EtchingIndexer {
 
             if let Err(err) = self
                 .service_repo
-                .store_new_runes_utxo(&rune_utxo, db::RuneLog::INCOME)
+                .store_new_runes_utxo(&rune_utxo, db::RuneLog::INCOME, tx_info.timestamp)
                 .await
             {
                 error!("Failed to insert the rune utxo: error={}", err);
@@ -753,29 +1031,58 @@ impl EtchingIndexer {
         }
     }
 
-    async fn collect_and_spend_runes_inputs(&mut self, tx: &Transaction) -> HashMap<String, u128> {
+    async fn collect_and_spend_runes_inputs(
+        &mut self,
+        tx_info: &TxInfo,
+    ) -> anyhow::Result<HashMap<String, u128>> {
         let mut input_amounts: HashMap<String, u128> = HashMap::new();
+        let tx = &tx_info.tx;
 
         for input in tx.input.iter() {
             // it doesn't matter whether this burn or
             // not we can mark inputs as spent and decrease balances
-            let Some(utxo_list) = self
+            let utxo_list = self
                 .service_repo
-                .spent_rune_utxo(input, tx.txid().to_string().as_str())
-                .await
-            else {
-                continue;
-            };
+                .spent_rune_utxo(
+                    input,
+                    tx_info.txid.as_str(),
+                    tx_info.block,
+                    tx_info.timestamp,
+                )
+                .await?;
             for utxo in utxo_list.iter() {
                 let value = input_amounts.entry(utxo.rune.clone()).or_default();
                 *value += utxo.amount;
             }
         }
 
-        input_amounts
+        Ok(input_amounts)
+    }
+
+    async fn store_inscription(&self, tx_info: &TxInfo, rune: &str) {
+        let Some((inscription_id, envelope)) = inscriptions::first_inscription(&tx_info.tx) else {
+            return;
+        };
+
+        let row = db::RuneInscription {
+            rune: rune.to_owned(),
+            inscription_id,
+            content_type: envelope.content_type,
+            content_length: envelope.body.len() as i64,
+            content_hash: inscriptions::content_hash(&envelope.body),
+            block: tx_info.block,
+            ..Default::default()
+        };
+        if let Err(err) = self.service_repo.db().insert_rune_inscription(&row).await {
+            error!("Can't insert rune inscription: error={} rune={}", err, rune);
+        }
     }
 
-    fn validate_commitment(&self, tx_info: &TxInfo, rune: ordinals::Rune) -> Option<String> {
+    fn validate_commitment(
+        &self,
+        tx_info: &TxInfo,
+        rune: ordinals::Rune,
+    ) -> Option<db::RuneCommitment> {
         let commitment = rune.commitment();
 
         for input in &tx_info.tx.input {
@@ -816,13 +1123,12 @@ impl EtchingIndexer {
                     }
                 };
 
-                let taproot = commitment_tx_info.vout[input.previous_output.vout as usize]
+                let script = commitment_tx_info.vout[input.previous_output.vout as usize]
                     .script_pub_key
                     .script()
-                    .unwrap_or_default()
-                    .is_v1_p2tr();
+                    .unwrap_or_default();
 
-                if !taproot {
+                if !script.is_v1_p2tr() {
                     continue;
                 }
 
@@ -843,7 +1149,14 @@ impl EtchingIndexer {
                 let confirmations = tx_info.block - commit_tx_height as i64 + 1;
 
                 if confirmations >= Runestone::COMMIT_CONFIRMATIONS.into() {
-                    return Some(commitment_tx.to_string());
+                    return Some(db::RuneCommitment {
+                        commitment_tx: commitment_tx.to_string(),
+                        vout: input.previous_output.vout as i32,
+                        pk_script: script.to_hex_string(),
+                        commit_block: commit_tx_height as i64,
+                        confirmations,
+                        ..Default::default()
+                    });
                 }
             }
         }
@@ -852,41 +1165,625 @@ impl EtchingIndexer {
     }
 }
 
-fn extract_premine_address(runestone: &Runestone, tx: &Transaction) -> Option<u32> {
-    if let Some(pointer) = runestone.pointer {
-        if (pointer as usize) > tx.output.len() {
-            return None;
+/// Walks the checkpoints newest first. Returns None when the newest one matches and
+/// the height of the first matching one below a mismatch, fails if none of them matches.
+async fn last_good_checkpoint<F, Fut>(
+    checkpoints: &[db::IndexCheckpoint],
+    mut mismatch: F,
+) -> anyhow::Result<Option<i64>>
+where
+    F: FnMut(db::IndexCheckpoint) -> Fut,
+    Fut: std::future::Future<Output = anyhow::Result<Option<String>>>,
+{
+    let mut broken: Option<(i64, String)> = None;
+    for checkpoint in checkpoints.iter() {
+        let Some(reason) = mismatch(checkpoint.clone()).await? else {
+            info!("Index checkpoint verified: height={}", checkpoint.height);
+            return Ok(broken.map(|_| checkpoint.height));
+        };
+
+        warn!(
+            "Index checkpoint doesn't match: height={} {}",
+            checkpoint.height, reason
+        );
+        broken.get_or_insert((checkpoint.height, reason));
+    }
+
+    match broken {
+        Some((height, reason)) => anyhow::bail!(
+            "checkpoint at {} doesn't match: {}. None of the last {} checkpoints is good",
+            height,
+            reason,
+            checkpoints.len()
+        ),
+        None => Ok(None),
+    }
+}
+
+fn is_checkpoint_height(height: i64, interval: i64) -> bool {
+    interval > 0 && height % interval == 0
+}
+
+/// with edicts handling disabled the tx is indexed as if it had no edicts,
+/// premine edicts of an etching are applied by `premine_allocations` either way
+/// and the input runes of the tx aren't allocated, see `skips_input_runes`
+fn edicts_to_handle(handle_edicts: bool, edicts: Vec<Edict>) -> Vec<Edict> {
+    if handle_edicts {
+        edicts
+    } else {
+        Vec::new()
+    }
+}
+
+/// with edicts handling disabled a tx with edicts leaves its input runes on the spent utxos,
+/// moving them to the pointer output instead would credit an address the runes weren't sent to.
+/// Its etching and mint are still indexed.
+fn skips_input_runes(handle_edicts: bool, artifact: &Option<Artifact>) -> bool {
+    match artifact {
+        Some(Artifact::Runestone(runestone)) => !handle_edicts && !runestone.edicts.is_empty(),
+        _ => false,
+    }
+}
+
+/// non-coinbase txs of the block in block order. They have to be processed one by one:
+/// a tx can spend rune outputs of an earlier tx of the same block, which are
+/// visible only once that tx is stored.
+fn block_tx_infos(height: i64, block: &bitcoin::Block) -> Vec<TxInfo> {
+    block
+        .txdata
+        .iter()
+        .enumerate()
+        .filter(|(_, tx)| !tx.is_coin_base())
+        .map(|(txi, tx)| TxInfo {
+            block: height,
+            tx_n: txi as i32,
+            txid: tx.txid().to_string(),
+            tx: tx.clone(),
+            timestamp: block.header.time as i64,
+        })
+        .collect()
+}
+
+/// smallest rune name unlocked for etchings at `block`
+pub fn minimum_etchable_rune(net: bitcoin::Network, block: i64) -> ordinals::Rune {
+    ordinals::Rune::minimum_at_height(net, ordinals::Height(block as u32))
+}
+
+/// explicitly named runes must be unlocked at the etching height and outside of the reserved range
+pub fn is_valid_etched_name(net: bitcoin::Network, block: i64, rune: ordinals::Rune) -> bool {
+    rune >= minimum_etchable_rune(net, block) && !rune.is_reserved()
+}
+
+/// max supply as `Etching::supply()` defines it: premine + cap * amount,
+/// the premine can't exceed the supply unless the sum overflows
+fn etching_supply(etching: &Etching) -> Result<u128, &'static str> {
+    let premine = etching.premine.unwrap_or_default();
+    let (cap, amount) = etching
+        .terms
+        .map(|t| (t.cap.unwrap_or_default(), t.amount.unwrap_or_default()))
+        .unwrap_or_default();
+
+    let mintable = cap.checked_mul(amount).ok_or("cap * amount overflows")?;
+    let supply = premine
+        .checked_add(mintable)
+        .ok_or("premine + cap * amount overflows")?;
+    debug_assert_eq!(etching.supply(), Some(supply));
+
+    Ok(supply)
+}
+
+/// the id of a rune etched by the tx
+fn etched_rune_id(tx_info: &TxInfo) -> RuneId {
+    RuneId {
+        block: tx_info.block as u64,
+        tx: tx_info.tx_n as u32,
+    }
+}
+
+/// runes etched in the block being indexed, so later txs of the block resolve them
+/// even before the cache and the db have them
+#[derive(Debug, Default)]
+struct BlockEtchings(HashMap<RuneId, String>);
+
+impl BlockEtchings {
+    /// the etchings of the previous block are in the index by now
+    fn start_block(&mut self) {
+        self.0.clear();
+    }
+
+    fn insert(&mut self, tx_info: &TxInfo, rune: &str) {
+        self.0.insert(etched_rune_id(tx_info), rune.to_owned());
+    }
+
+    fn get(&self, id: &RuneId) -> Option<&String> {
+        self.0.get(id)
+    }
+}
+
+/// the rune an edict moves, etched earlier in the block or looked up in the index.
+/// A lookup error is returned, as an unknown rune would burn the inputs
+async fn edict_rune<F, Fut>(
+    block_etchings: &BlockEtchings,
+    id: &RuneId,
+    lookup: F,
+) -> anyhow::Result<Option<String>>
+where
+    F: FnOnce(RuneId) -> Fut,
+    Fut: std::future::Future<Output = anyhow::Result<Option<String>>>,
+{
+    match block_etchings.get(id) {
+        Some(rune) => Ok(Some(rune.clone())),
+        None => lookup(*id).await,
+    }
+}
+
+/// runes a runestone refers to, resolved by the caller
+#[derive(Debug, Default)]
+struct RunestoneRunes {
+    /// the etched rune, its etching is already validated
+    etching: Option<String>,
+    /// the minted rune and the mint amount, its terms are already checked
+    mint: Option<(String, u128)>,
+    /// names of the runes moved by edicts, unknown ids are missing
+    edicts: HashMap<RuneId, String>,
+}
+
+#[derive(Debug)]
+struct RunestoneAllocation {
+    outputs: Vec<HashMap<String, Allocation>>,
+    /// premine without a target output, it's burned
+    unallocated_premine: u128,
+}
+
+/// Allocates the premine, the mint and the edicts of a runestone to the tx outputs.
+/// Nothing is stored here: when the tx burns its inputs instead, the anomaly kind
+/// and the reason are returned and the caller drops the etching and the mint
+fn allocate_runestone(
+    net: bitcoin::Network,
+    tx: &Transaction,
+    runestone: &Runestone,
+    edicts: &[Edict],
+    inputs: &HashMap<String, u128>,
+    runes: &RunestoneRunes,
+    skip_unknown_edict_runes: bool,
+) -> Result<RunestoneAllocation, (&'static str, String)> {
+    let mut outputs: Vec<HashMap<String, Allocation>> = vec![HashMap::new(); tx.output.len()];
+
+    let mut unallocated_premine = 0;
+    if let (Some(rune), Some(etching)) = (&runes.etching, runestone.etching) {
+        let (outs, unallocated) =
+            premine_allocations(runestone, tx, etching.premine.unwrap_or_default());
+        for (vout, amount) in outs {
+            outputs[vout as usize]
+                .entry(rune.clone())
+                .or_default()
+                .etching += amount;
         }
-        return Some(pointer);
+        unallocated_premine = unallocated;
     }
 
-    let mut rune_out_found = false;
-    for (vout, out) in tx.output.iter().enumerate() {
-        let mut instructions = out.script_pubkey.instructions();
+    if let Some((rune, amount)) = &runes.mint {
+        let Some(vout) = get_change_output(tx, runestone.pointer) else {
+            return Err((
+                db::IndexerAnomaly::INVALID_MINT,
+                format!(
+                    "mint={:?} pointer={:?} has no change output",
+                    runestone.mint, runestone.pointer
+                ),
+            ));
+        };
+        outputs[vout as usize].entry(rune.clone()).or_default().mint += amount;
+    }
 
-        // payload starts with OP_RETURN
-        if instructions.next() != Some(Ok(Instruction::Op(opcodes::all::OP_RETURN))) {
+    for edict in edicts.iter() {
+        if edict.id == RuneId::default() {
+            // the etched rune, allocated with the premine
             continue;
         }
+        let Some(rune) = runes.edicts.get(&edict.id) else {
+            if skip_unknown_edict_runes {
+                continue;
+            }
+            return Err((
+                db::IndexerAnomaly::INVALID_EDICTS,
+                format!("unknown rune {}", edict.id),
+            ));
+        };
+        allocate_edict(tx, edict, rune, &mut outputs);
+    }
+
+    if !validate_allocations(net, inputs, &outputs, tx, runestone.pointer) {
+        return Err((
+            db::IndexerAnomaly::INVALID_ALLOCATION,
+            format!("inputs={:?}", inputs),
+        ));
+    }
+
+    Ok(RunestoneAllocation {
+        outputs,
+        unallocated_premine,
+    })
+}
+
+fn allocate_edict(
+    tx: &Transaction,
+    edict: &Edict,
+    rune: &str,
+    allocated_runes: &mut [HashMap<String, Allocation>],
+) {
+    if edict.output as usize == tx.output.len() {
+        // note that this allows `output == tx.output.len()`, which means to divide
+        // amount between all non-OP_RETURN outputs
+        let outs = get_non_opreturn_outputs(tx);
+        if outs.is_empty() {
+            return;
+        }
+
+        let amount = edict.amount / outs.len() as u128;
+        for (vout, _out) in outs.iter() {
+            let al = allocated_runes[*vout as usize]
+                .entry(rune.to_owned())
+                .or_default();
+            al.edict += amount;
+        }
+    } else {
+        let al = allocated_runes[edict.output as usize]
+            .entry(rune.to_owned())
+            .or_default();
+        al.edict += edict.amount;
+    }
+}
+
+/// Runes moved by a tx as the indexer would apply them, computed without touching the index.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RunesOutcome {
+    /// runes credited to each output
+    pub outputs: Vec<HashMap<String, u128>>,
+    pub burned: HashMap<String, u128>,
+    /// set when the indexer would burn all the inputs, e.g. for a cenotaph
+    pub burn_reason: Option<String>,
+    /// the etched rune, its name and commitment aren't validated
+    pub etching: Option<String>,
+}
+
+impl RunesOutcome {
+    fn burn_all(tx: &Transaction, inputs: &HashMap<String, u128>, reason: String) -> Self {
+        let mut burned = inputs.clone();
+        burned.retain(|_, amount| *amount > 0);
+        Self {
+            outputs: vec![HashMap::new(); tx.output.len()],
+            burned,
+            burn_reason: Some(reason),
+            etching: None,
+        }
+    }
+}
+
+/// Dry run of `extract_runestone` for a tx that isn't mined yet, e.g. to preview it before
+/// broadcast. `inputs` are the runes of the spent utxos, `runes` maps the ids referenced by
+/// edicts and the mint to the rune name and its mint amount, `None` without mint terms.
+/// `height` and `tx_index` are the expected position of the tx, they name reserved runes.
+/// The allocation is `allocate_runestone`, the same as the indexer's.
+pub fn simulate_runes(
+    net: bitcoin::Network,
+    tx: &Transaction,
+    height: i64,
+    tx_index: u32,
+    cfg: &config::IndexersConfig,
+    inputs: &HashMap<String, u128>,
+    runes: &HashMap<RuneId, (String, Option<u128>)>,
+) -> RunesOutcome {
+    let artifact = Runestone::decipher(tx);
+    let no_inputs = HashMap::new();
+    let inputs = if skips_input_runes(cfg.handle_edicts(), &artifact) {
+        &no_inputs
+    } else {
+        inputs
+    };
+    let runestone = match artifact {
+        None => return RunesOutcome::burn_all(tx, inputs, "no runestone".to_owned()),
+        Some(Artifact::Cenotaph(cenotaph)) => {
+            return RunesOutcome::burn_all(tx, inputs, format!("cenotaph {:?}", cenotaph.flaw))
+        }
+        Some(Artifact::Runestone(runestone)) => runestone,
+    };
+
+    let mut resolved = RunestoneRunes::default();
+    if let Some(e) = runestone.etching {
+        let rune = e
+            .rune
+            .unwrap_or_else(|| ordinals::Rune::reserved(height as u64, tx_index))
+            .to_string();
+        resolved.etching = Some(rune);
+    }
+    if let Some(mint) = runestone.mint {
+        let Some((rune, Some(amount))) = runes.get(&mint).cloned() else {
+            return RunesOutcome::burn_all(
+                tx,
+                inputs,
+                format!(
+                    "{}: no mint terms for {}",
+                    db::IndexerAnomaly::INVALID_MINT,
+                    mint
+                ),
+            );
+        };
+        resolved.mint = Some((rune, amount));
+    }
+    resolved.edicts = runes
+        .iter()
+        .map(|(id, (rune, _))| (*id, rune.clone()))
+        .collect();
+
+    let edicts = edicts_to_handle(cfg.handle_edicts(), runestone.edicts.clone());
+    let allocation = match allocate_runestone(
+        net,
+        tx,
+        &runestone,
+        &edicts,
+        inputs,
+        &resolved,
+        cfg.skip_unknown_edict_runes(),
+    ) {
+        Ok(allocation) => allocation,
+        Err((kind, reason)) => {
+            return RunesOutcome::burn_all(tx, inputs, format!("{}: {}", kind, reason))
+        }
+    };
+    let allocated_runes = allocation.outputs;
+
+    let mut burned: HashMap<String, u128> = HashMap::new();
+    if let Some(rune) = resolved.etching.as_ref() {
+        if allocation.unallocated_premine > 0 {
+            burned.insert(rune.clone(), allocation.unallocated_premine);
+        }
+    }
+
+    let mut outputs = vec![HashMap::new(); tx.output.len()];
+    let mut change = inputs.clone();
+    for (vout, a) in allocated_runes.iter().enumerate() {
+        for (rune, al) in a.iter() {
+            *change.entry(rune.to_owned()).or_default() -= al.edict;
+            if !tx.output[vout].script_pubkey.is_op_return() {
+                *outputs[vout].entry(rune.to_owned()).or_default() +=
+                    al.edict + al.mint + al.etching;
+            }
+        }
+    }
+    for (rune, amount) in burned_allocations(&allocated_runes, tx) {
+        *burned.entry(rune).or_default() += amount;
+    }
+
+    // validate_allocations checked the change output
+    let change = change.into_iter().filter(|(_, amount)| *amount > 0);
+    match spendable_change_output(tx, runestone.pointer) {
+        Some(vout) => {
+            for (rune, amount) in change {
+                *outputs[vout as usize].entry(rune).or_default() += amount;
+            }
+        }
+        None => {
+            for (rune, amount) in change {
+                *burned.entry(rune).or_default() += amount;
+            }
+        }
+    }
+
+    RunesOutcome {
+        outputs,
+        burned,
+        burn_reason: None,
+        etching: resolved.etching,
+    }
+}
 
-        // followed by the protocol identifier, ignoring errors, since OP_RETURN
-        // scripts may be invalid
-        if instructions.next() != Some(Ok(Instruction::Op(Runestone::MAGIC_NUMBER))) {
-            rune_out_found = true;
+/// checks that allocations can be applied: edicts don't spend more than inputs have,
+/// every receiving output has an address and there is an output for the change
+fn validate_allocations(
+    net: bitcoin::Network,
+    unalocated_runes: &HashMap<String, u128>,
+    allocated_runes: &[HashMap<String, Allocation>],
+    tx: &Transaction,
+    pointer: Option<u32>,
+) -> bool {
+    let mut total_out: HashMap<String, u128> = HashMap::new();
+
+    for a in allocated_runes.iter() {
+        if a.is_empty() {
             continue;
         }
+        a.iter()
+            .for_each(|(k, al)| *total_out.entry(k.to_owned()).or_default() += al.edict)
+    }
+
+    for (k, out_value) in total_out.iter() {
+        let in_value = match unalocated_runes.get(k) {
+            Some(b) => *b,
+            None => 0,
+        };
 
-        if rune_out_found {
-            return Some(vout as u32);
+        if *out_value > in_value {
+            debug!(
+                "trying to spend more than have {} out={} > in={}",
+                k, out_value, in_value
+            );
+            // trying to spend more than have
+            return false;
         }
     }
 
-    None
+    for (vout, a) in allocated_runes.iter().enumerate() {
+        if a.is_empty() {
+            continue;
+        }
+
+        // runes allocated to OP_RETURN outputs are burned
+        if tx.output[vout].script_pubkey.is_op_return() {
+            continue;
+        }
+        if let Err(err) = Address::from_script(&tx.output[vout].script_pubkey, net) {
+            debug!("invalid allocation address: vout={} err={}", vout, err);
+            return false;
+        }
+    }
+
+    // without a spendable change output the remainder is burned
+    let Some(vout) = spendable_change_output(tx, pointer) else {
+        return true;
+    };
+
+    Address::from_script(&tx.output[vout as usize].script_pubkey, net).is_ok()
+}
+
+/// amounts allocated to OP_RETURN outputs, e.g. by an edict to the runestone output
+fn burned_allocations(
+    allocated_runes: &[HashMap<String, Allocation>],
+    tx: &Transaction,
+) -> HashMap<String, u128> {
+    let mut burned: HashMap<String, u128> = HashMap::new();
+    for (vout, a) in allocated_runes.iter().enumerate() {
+        if !tx.output[vout].script_pubkey.is_op_return() {
+            continue;
+        }
+        for (rune, al) in a.iter() {
+            *burned.entry(rune.to_owned()).or_default() += al.edict + al.mint + al.etching;
+        }
+    }
+    burned.retain(|_, amount| *amount > 0);
+    burned
+}
+
+/// Splits the premine of the etched rune the way the protocol does: edicts of the etched
+/// rune (id 0:0) go first, the rest goes to the pointer or the first non-OP_RETURN output.
+/// Returns the allocations and the amount left without a target, which is burned.
+fn premine_allocations(
+    runestone: &Runestone,
+    tx: &Transaction,
+    premine: u128,
+) -> (Vec<(u32, u128)>, u128) {
+    fn allocate(res: &mut Vec<(u32, u128)>, remaining: &mut u128, vout: u32, amount: u128) {
+        let amount = amount.min(*remaining);
+        if amount > 0 {
+            *remaining -= amount;
+            res.push((vout, amount));
+        }
+    }
+
+    let mut remaining = premine;
+    let mut res = Vec::new();
+    for edict in runestone.edicts.iter() {
+        if edict.id != RuneId::default() {
+            continue;
+        }
+
+        if edict.output as usize == tx.output.len() {
+            // `output == tx.output.len()` divides the amount between all non-OP_RETURN outputs,
+            // a zero amount splits the rest evenly and the first outputs get the remainder
+            let outs = get_non_opreturn_outputs(tx);
+            if outs.is_empty() {
+                continue;
+            }
+
+            if edict.amount == 0 {
+                let amount = remaining / outs.len() as u128;
+                let extra = (remaining % outs.len() as u128) as usize;
+                for (i, (vout, _out)) in outs.iter().enumerate() {
+                    let amount = if i < extra { amount + 1 } else { amount };
+                    allocate(&mut res, &mut remaining, *vout, amount);
+                }
+            } else {
+                for (vout, _out) in outs.iter() {
+                    allocate(&mut res, &mut remaining, *vout, edict.amount);
+                }
+            }
+        } else {
+            let amount = if edict.amount == 0 {
+                remaining
+            } else {
+                edict.amount
+            };
+            allocate(&mut res, &mut remaining, edict.output, amount);
+        }
+    }
+
+    if let Some(vout) = get_change_output(tx, runestone.pointer) {
+        allocate(&mut res, &mut remaining, vout, remaining);
+    }
+
+    (res, remaining)
+}
+
+/// output starts with OP_RETURN OP_13
+fn is_runestone_output(out: &TxOut) -> bool {
+    let mut instructions = out.script_pubkey.instructions();
+    instructions.next() == Some(Ok(Instruction::Op(opcodes::all::OP_RETURN)))
+        && instructions.next() == Some(Ok(Instruction::Op(Runestone::MAGIC_NUMBER)))
+}
+
+fn find_runestone_output(tx: &Transaction) -> Option<&TxOut> {
+    tx.output.iter().find(|out| is_runestone_output(out))
+}
+
+/// tags of the runes protocol this version knows, up to the body tag the fields are tag/value pairs
+const KNOWN_TAGS: [u128; 17] = [0, 1, 2, 3, 4, 5, 6, 8, 10, 12, 14, 16, 18, 20, 22, 126, 127];
+
+/// odd tags of the runestone fields which aren't in `KNOWN_TAGS`. The protocol ignores
+/// them, it's how a new field looks to an outdated indexer. Unknown even tags make a cenotaph.
+fn unknown_odd_tags(tx: &Transaction) -> Vec<u128> {
+    let Some(out) = find_runestone_output(tx) else {
+        return vec![];
+    };
+    let mut payload = Vec::new();
+    for instruction in out.script_pubkey.instructions().skip(2) {
+        match instruction {
+            Ok(Instruction::PushBytes(push)) => payload.extend_from_slice(push.as_bytes()),
+            _ => return vec![],
+        }
+    }
+
+    let mut integers = Vec::new();
+    let (mut value, mut shift) = (0u128, 0);
+    for byte in payload {
+        if shift > 126 {
+            return vec![];
+        }
+        value |= u128::from(byte & 0x7f) << shift;
+        shift += 7;
+        if byte & 0x80 == 0 {
+            integers.push(value);
+            (value, shift) = (0, 0);
+        }
+    }
+
+    let mut tags = Vec::new();
+    for field in integers.chunks(2) {
+        let tag = field[0];
+        if tag == 0 {
+            break;
+        }
+        if tag % 2 == 1 && !KNOWN_TAGS.contains(&tag) && !tags.contains(&tag) {
+            tags.push(tag);
+        }
+    }
+    tags
+}
+
+fn count_runestone_outputs(tx: &Transaction) -> usize {
+    tx.output
+        .iter()
+        .filter(|out| is_runestone_output(out))
+        .count()
+}
+
+fn raw_runestone_hex(tx: &Transaction) -> String {
+    find_runestone_output(tx)
+        .map(|out| out.script_pubkey.to_hex_string())
+        .unwrap_or_default()
 }
 
 fn get_change_output(tx: &Transaction, pointer: Option<u32>) -> Option<u32> {
     if let Some(pointer) = pointer {
-        if (pointer as usize) > tx.output.len() {
+        if (pointer as usize) >= tx.output.len() {
             return None;
         }
         return Some(pointer);
@@ -905,6 +1802,12 @@ fn get_change_output(tx: &Transaction, pointer: Option<u32>) -> Option<u32> {
     None
 }
 
+/// change output which can receive the unallocated runes, `None` if they are burned
+fn spendable_change_output(tx: &Transaction, pointer: Option<u32>) -> Option<u32> {
+    get_change_output(tx, pointer)
+        .filter(|vout| !tx.output[*vout as usize].script_pubkey.is_op_return())
+}
+
 fn get_non_opreturn_outputs(tx: &Transaction) -> Vec<(u32, TxOut)> {
     let mut res = Vec::new();
 
@@ -920,3 +1823,1233 @@ fn get_non_opreturn_outputs(tx: &Transaction) -> Vec<(u32, TxOut)> {
 
     res
 }
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::{absolute::LockTime, ScriptBuf, Transaction, TxOut};
+    use ordinals::{Edict, Etching, RuneId, Runestone};
+
+    fn etching_tx(runestone: &Runestone) -> Transaction {
+        Transaction {
+            version: 2,
+            lock_time: LockTime::ZERO,
+            input: vec![],
+            output: vec![
+                TxOut {
+                    value: 600,
+                    script_pubkey: ScriptBuf::from_hex(
+                        "0014751e76e8199196d454941c45d1b3a323f1433bd6",
+                    )
+                    .unwrap(),
+                },
+                TxOut {
+                    value: 0,
+                    script_pubkey: runestone.encipher(),
+                },
+            ],
+        }
+    }
+
+    fn indexers_cfg() -> crate::config::IndexersConfig {
+        toml::from_str(
+            r#"
+            btc_starting_height = 0
+            runes_starting_height = 0
+            disable_rune_log = false
+            btc_watchlist = []
+            runes_watchlist = []
+            "#,
+        )
+        .unwrap()
+    }
+
+    fn spend(tx: &Transaction, vout: u32) -> bitcoin::TxIn {
+        bitcoin::TxIn {
+            previous_output: bitcoin::OutPoint {
+                txid: tx.txid(),
+                vout,
+            },
+            script_sig: ScriptBuf::new(),
+            sequence: bitcoin::Sequence::MAX,
+            witness: bitcoin::Witness::new(),
+        }
+    }
+
+    /// block of a coinbase followed by `txs`
+    fn block(txs: Vec<Transaction>) -> bitcoin::Block {
+        use bitcoin::{
+            block::{Header, Version},
+            hash_types::TxMerkleNode,
+            hashes::Hash,
+            BlockHash, CompactTarget, OutPoint,
+        };
+
+        let coinbase = Transaction {
+            input: vec![bitcoin::TxIn {
+                previous_output: OutPoint::null(),
+                script_sig: ScriptBuf::new(),
+                sequence: bitcoin::Sequence::MAX,
+                witness: bitcoin::Witness::new(),
+            }],
+            ..etching_tx(&Runestone::default())
+        };
+        bitcoin::Block {
+            header: Header {
+                version: Version::TWO,
+                prev_blockhash: BlockHash::all_zeros(),
+                merkle_root: TxMerkleNode::all_zeros(),
+                time: 1_713_571_767,
+                bits: CompactTarget::from_consensus(0),
+                nonce: 0,
+            },
+            txdata: [vec![coinbase], txs].concat(),
+        }
+    }
+
+    /// the redis db index of the indexer tests, they never touch another one
+    const TEST_REDIS_DB: u8 = 15;
+
+    /// `address` with its db index replaced by `TEST_REDIS_DB`
+    fn scratch_redis_address(address: &str) -> String {
+        let base = match address.rsplit_once('/') {
+            Some((base, db)) if db.is_empty() || db.parse::<u32>().is_ok() => base,
+            _ => address,
+        };
+        format!("{}/{}", base, TEST_REDIS_DB)
+    }
+
+    /// indexer over the scratch db in `TEST_DB_DSN` and the redis in `TEST_REDIS_ADDR`,
+    /// none when they aren't set. The runes of `heights` are removed, the cache uses
+    /// the `TEST_REDIS_DB` index, which is cleared so other dbs of the server are kept.
+    async fn scratch_indexer(
+        cfg: crate::config::IndexersConfig,
+        heights: std::ops::Range<i64>,
+    ) -> Option<super::EtchingIndexer> {
+        use super::{BlockEtchings, EtchingIndexer};
+        use crate::cache::CacheRepo;
+        use crate::config::RedisConfig;
+        use crate::{db::connect_scratch_db, service::StateProvider};
+        use std::{collections::HashSet, sync::Arc};
+
+        let Ok(address) = std::env::var("TEST_REDIS_ADDR") else {
+            return None;
+        };
+        let repo = connect_scratch_db().await?;
+        for query in [
+            "DELETE FROM runes_balances WHERE rune IN
+                (SELECT rune FROM runes WHERE block >= $1 AND block < $2)",
+            "DELETE FROM runes_log WHERE block >= $1 AND block < $2",
+            "DELETE FROM runes_utxos WHERE block >= $1 AND block < $2",
+            "DELETE FROM runes WHERE block >= $1 AND block < $2",
+        ] {
+            sqlx::query(query)
+                .bind(heights.start)
+                .bind(heights.end)
+                .execute(&repo.pool)
+                .await
+                .unwrap();
+        }
+        let address = scratch_redis_address(&address);
+        let mut redis = redis::Client::open(address.as_str())
+            .unwrap()
+            .get_multiplexed_async_connection()
+            .await
+            .unwrap();
+        redis::cmd("FLUSHDB")
+            .query_async::<_, ()>(&mut redis)
+            .await
+            .unwrap();
+        let cache = CacheRepo::new(RedisConfig { address }).await.unwrap();
+
+        Some(EtchingIndexer {
+            net: bitcoin::Network::Bitcoin,
+            cfg,
+            rpc: bitcoincore_rpc::Client::new("http://127.0.0.1:8332", bitcoincore_rpc::Auth::None)
+                .unwrap(),
+            service_repo: StateProvider::new(Arc::new(repo), cache, false),
+            pending_txs: HashSet::new(),
+            filter_runes: false,
+            runes_watchlist: HashSet::new(),
+            runes_ids_watchlist: HashSet::new(),
+            block_etchings: BlockEtchings::default(),
+            total_stats: Default::default(),
+        })
+    }
+
+    #[test]
+    fn premine_defaults_to_first_non_op_return_output() {
+        use super::premine_allocations;
+
+        let runestone = Runestone {
+            etching: Some(Etching {
+                premine: Some(1000),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let tx = etching_tx(&runestone);
+        assert_eq!(
+            premine_allocations(&runestone, &tx, 1000),
+            (vec![(0, 1000)], 0)
+        );
+
+        // edicts for other runes don't allocate the premine
+        let other = Runestone {
+            edicts: vec![Edict {
+                id: RuneId {
+                    block: 840000,
+                    tx: 1,
+                },
+                amount: 10,
+                output: 0,
+            }],
+            ..runestone.clone()
+        };
+        let tx = etching_tx(&other);
+        assert_eq!(premine_allocations(&other, &tx, 1000), (vec![(0, 1000)], 0));
+
+        // the first non-OP_RETURN output, even when it precedes the runestone
+        let mut tx = etching_tx(&runestone);
+        tx.output.push(tx.output[0].clone());
+        assert_eq!(
+            premine_allocations(&runestone, &tx, 1000),
+            (vec![(0, 1000)], 0)
+        );
+
+        // nothing can receive it
+        tx.output = vec![tx.output[1].clone()];
+        assert_eq!(premine_allocations(&runestone, &tx, 1000), (vec![], 1000));
+    }
+
+    #[test]
+    fn premine_goes_to_etching_edicts_then_pointer() {
+        use super::premine_allocations;
+
+        let runestone = Runestone {
+            etching: Some(Etching {
+                premine: Some(1000),
+                ..Default::default()
+            }),
+            pointer: Some(0),
+            ..Default::default()
+        };
+        let tx = etching_tx(&runestone);
+        assert_eq!(
+            premine_allocations(&runestone, &tx, 1000),
+            (vec![(0, 1000)], 0)
+        );
+
+        // a pointer to the runestone burns it, see `burned_allocations`
+        let to_runestone = Runestone {
+            pointer: Some(1),
+            ..runestone.clone()
+        };
+        let tx = etching_tx(&to_runestone);
+        assert_eq!(
+            premine_allocations(&to_runestone, &tx, 1000),
+            (vec![(1, 1000)], 0)
+        );
+
+        // the edict takes its amount, the rest goes to the default output
+        let edict = |amount, output| Edict {
+            id: RuneId::default(),
+            amount,
+            output,
+        };
+        let with_edict = Runestone {
+            pointer: None,
+            edicts: vec![edict(400, 0)],
+            ..runestone.clone()
+        };
+        let tx = etching_tx(&with_edict);
+        assert_eq!(
+            premine_allocations(&with_edict, &tx, 1000),
+            (vec![(0, 400), (0, 600)], 0)
+        );
+
+        // outputs: 0 and 2 are payments, 1 is the runestone
+        let mut tx = etching_tx(&runestone);
+        tx.output.push(tx.output[0].clone());
+
+        // zero amount to `output == outputs.len()` splits evenly, the remainder goes first
+        let split = Runestone {
+            pointer: Some(2),
+            edicts: vec![edict(0, 3)],
+            ..runestone.clone()
+        };
+        assert_eq!(
+            premine_allocations(&split, &tx, 1001),
+            (vec![(0, 501), (2, 500)], 0)
+        );
+
+        // a fixed amount goes to each output while the premine lasts
+        let each = Runestone {
+            pointer: Some(2),
+            edicts: vec![edict(400, 3)],
+            ..runestone.clone()
+        };
+        assert_eq!(
+            premine_allocations(&each, &tx, 1000),
+            (vec![(0, 400), (2, 400), (2, 200)], 0)
+        );
+
+        // zero amount to a single output takes everything left
+        let all = Runestone {
+            pointer: Some(0),
+            edicts: vec![edict(100, 0), edict(0, 2)],
+            ..runestone
+        };
+        assert_eq!(
+            premine_allocations(&all, &tx, 1000),
+            (vec![(0, 100), (2, 900)], 0)
+        );
+    }
+
+    #[test]
+    fn etched_name_validation() {
+        use super::is_valid_etched_name;
+        use std::str::FromStr;
+
+        let net = bitcoin::Network::Bitcoin;
+        let rune = |name| ordinals::Rune::from_str(name).unwrap();
+
+        assert!(is_valid_etched_name(net, 840000, rune("ZZZZZZZZZZZZZZ")));
+        // short names are locked at the activation height
+        assert!(!is_valid_etched_name(net, 840000, rune("ABC")));
+        assert!(!is_valid_etched_name(
+            net,
+            840000,
+            ordinals::Rune::reserved(840000, 1)
+        ));
+    }
+
+    #[test]
+    fn allocations_are_validated_before_storing() {
+        use super::{validate_allocations, Allocation};
+        use std::collections::HashMap;
+
+        let net = bitcoin::Network::Bitcoin;
+        let runestone = Runestone::default();
+        let tx = etching_tx(&runestone);
+
+        let mut allocated: Vec<HashMap<String, Allocation>> = vec![HashMap::new(); 2];
+        allocated[0].insert(
+            "RUNE".to_owned(),
+            Allocation {
+                edict: 100,
+                ..Default::default()
+            },
+        );
+
+        let inputs = HashMap::from([("RUNE".to_owned(), 100u128)]);
+        assert!(validate_allocations(net, &inputs, &allocated, &tx, None));
+
+        // spends more than inputs have
+        let inputs = HashMap::from([("RUNE".to_owned(), 50u128)]);
+        assert!(!validate_allocations(net, &inputs, &allocated, &tx, None));
+
+        // runes sent to the OP_RETURN output are burned, not an invalid allocation
+        let inputs = HashMap::from([("RUNE".to_owned(), 200u128)]);
+        allocated[1].insert(
+            "RUNE".to_owned(),
+            Allocation {
+                edict: 100,
+                ..Default::default()
+            },
+        );
+        assert!(validate_allocations(net, &inputs, &allocated, &tx, None));
+    }
+
+    #[test]
+    fn edict_to_runestone_output_is_burned() {
+        use super::{burned_allocations, validate_allocations, Allocation};
+        use std::collections::HashMap;
+
+        let id = RuneId {
+            block: 840_000,
+            tx: 1,
+        };
+        // the runestone is the output 1
+        let runestone = Runestone {
+            edicts: vec![
+                Edict {
+                    id,
+                    amount: 30,
+                    output: 1,
+                },
+                Edict {
+                    id,
+                    amount: 70,
+                    output: 0,
+                },
+            ],
+            ..Default::default()
+        };
+        let tx = etching_tx(&runestone);
+        assert!(tx.output[1].script_pubkey.is_op_return());
+
+        let mut allocated: Vec<HashMap<String, Allocation>> = vec![HashMap::new(); 2];
+        for edict in runestone.edicts.iter() {
+            allocated[edict.output as usize]
+                .entry("RUNE".to_owned())
+                .or_default()
+                .edict += edict.amount;
+        }
+
+        let inputs = HashMap::from([("RUNE".to_owned(), 100u128)]);
+        assert!(validate_allocations(
+            bitcoin::Network::Bitcoin,
+            &inputs,
+            &allocated,
+            &tx,
+            None
+        ));
+        assert_eq!(
+            burned_allocations(&allocated, &tx),
+            HashMap::from([("RUNE".to_owned(), 30u128)])
+        );
+
+        // nothing is burned without OP_RETURN allocations
+        allocated[1].clear();
+        assert!(burned_allocations(&allocated, &tx).is_empty());
+    }
+
+    #[test]
+    fn split_edict_without_spendable_outputs() {
+        use super::{allocate_edict, simulate_runes, Allocation};
+        use std::collections::HashMap;
+
+        let id = RuneId {
+            block: 840_000,
+            tx: 1,
+        };
+        // the runestone is the only output, the edict splits between all of them
+        let runestone = Runestone {
+            edicts: vec![Edict {
+                id,
+                amount: 100,
+                output: 1,
+            }],
+            ..Default::default()
+        };
+        let mut tx = etching_tx(&runestone);
+        tx.output.remove(0);
+
+        let mut allocated: Vec<HashMap<String, Allocation>> = vec![HashMap::new()];
+        allocate_edict(&tx, &runestone.edicts[0], "RUNE", &mut allocated);
+        assert!(allocated[0].is_empty());
+
+        // a preview of such a tx must not panic either
+        let inputs = HashMap::from([("RUNE".to_owned(), 100u128)]);
+        let runes = HashMap::from([(id, ("RUNE".to_owned(), None))]);
+        let outcome = simulate_runes(
+            bitcoin::Network::Bitcoin,
+            &tx,
+            840_100,
+            1,
+            &indexers_cfg(),
+            &inputs,
+            &runes,
+        );
+        assert_eq!(outcome.outputs, vec![HashMap::new()]);
+    }
+
+    #[test]
+    fn pointer_to_op_return_burns_only_the_remainder() {
+        use super::simulate_runes;
+        use std::collections::HashMap;
+
+        let id = RuneId {
+            block: 840_000,
+            tx: 1,
+        };
+        // the runestone is the output 1
+        let runestone = Runestone {
+            edicts: vec![Edict {
+                id,
+                amount: 30,
+                output: 0,
+            }],
+            pointer: Some(1),
+            ..Default::default()
+        };
+        let tx = etching_tx(&runestone);
+        let inputs = HashMap::from([("RUNE".to_owned(), 100u128)]);
+        let runes = HashMap::from([(id, ("RUNE".to_owned(), None))]);
+
+        let outcome = simulate_runes(
+            bitcoin::Network::Bitcoin,
+            &tx,
+            840_100,
+            1,
+            &indexers_cfg(),
+            &inputs,
+            &runes,
+        );
+        assert_eq!(outcome.burn_reason, None);
+        assert_eq!(outcome.outputs[0], HashMap::from([("RUNE".to_owned(), 30)]));
+        assert_eq!(outcome.burned, HashMap::from([("RUNE".to_owned(), 70)]));
+    }
+
+    #[test]
+    fn multiple_runestones_are_counted() {
+        use super::{count_runestone_outputs, simulate_runes};
+        use ordinals::Artifact;
+        use std::collections::HashMap;
+
+        let id = RuneId {
+            block: 840_000,
+            tx: 1,
+        };
+        let runestone = Runestone {
+            edicts: vec![Edict {
+                id,
+                amount: 100,
+                output: 0,
+            }],
+            ..Default::default()
+        };
+        let mut tx = etching_tx(&runestone);
+        assert_eq!(count_runestone_outputs(&tx), 1);
+
+        // OP_RETURN without the runes magic number isn't a runestone
+        tx.output.push(TxOut {
+            value: 0,
+            script_pubkey: ScriptBuf::from_bytes(vec![0x6a, 0x03, 1, 2, 3]),
+        });
+        assert_eq!(count_runestone_outputs(&tx), 1);
+
+        tx.output.push(TxOut {
+            value: 0,
+            script_pubkey: Runestone::default().encipher(),
+        });
+        assert_eq!(count_runestone_outputs(&tx), 2);
+        // decipher accepts the first runestone
+        assert!(matches!(
+            Runestone::decipher(&tx),
+            Some(Artifact::Runestone(_))
+        ));
+
+        // as in ord, the inputs follow the first runestone instead of being burned
+        let runes = HashMap::from([(id, ("RUNE".to_owned(), None))]);
+        let inputs = HashMap::from([("RUNE".to_owned(), 100u128)]);
+        let outcome = simulate_runes(
+            bitcoin::Network::Bitcoin,
+            &tx,
+            840_100,
+            1,
+            &indexers_cfg(),
+            &inputs,
+            &runes,
+        );
+        assert_eq!(outcome.burn_reason, None);
+        assert_eq!(
+            outcome.outputs[0],
+            HashMap::from([("RUNE".to_owned(), 100)])
+        );
+        assert!(outcome.burned.is_empty());
+    }
+
+    #[test]
+    fn inconsistent_supply_is_invalid() {
+        use super::etching_supply;
+        use ordinals::Terms;
+
+        let etching = |premine, cap, amount| Etching {
+            premine: Some(premine),
+            terms: Some(Terms {
+                cap: Some(cap),
+                amount: Some(amount),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+
+        assert_eq!(etching_supply(&etching(1000, 10, 5)), Ok(1050));
+        assert_eq!(
+            etching_supply(&Etching {
+                premine: Some(1000),
+                ..Default::default()
+            }),
+            Ok(1000)
+        );
+
+        // a premine beyond the representable supply
+        assert!(etching_supply(&etching(u128::MAX, 1, 1)).is_err());
+        // cap * amount overflows
+        assert!(etching_supply(&etching(0, u128::MAX, 2)).is_err());
+    }
+
+    /// needs a scratch db in `TEST_DB_DSN` and redis in `TEST_REDIS_ADDR`, skipped otherwise
+    #[tokio::test]
+    async fn in_block_dependency_is_processed_in_order() {
+        let heights = 950_000_000..950_000_001;
+        let Some(mut indexer) = scratch_indexer(indexers_cfg(), heights.clone()).await else {
+            return;
+        };
+        let height = heights.start;
+
+        // tx1 etches a rune with the premine on output 0, tx2 spends that output
+        let tx1 = Transaction {
+            lock_time: LockTime::from_consensus(height as u32),
+            ..etching_tx(&Runestone {
+                etching: Some(Etching {
+                    premine: Some(1000),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            })
+        };
+        let tx2 = Transaction {
+            input: vec![spend(&tx1, 0)],
+            ..etching_tx(&Runestone::default())
+        };
+        let stats = indexer
+            .index_block_txs(height, &block(vec![tx1.clone(), tx2.clone()]))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(stats.etches, 1);
+        assert_eq!(stats.burned_txs, 0);
+
+        let db = indexer.service_repo.db();
+        let spent = db
+            .get_rune_utxos_by_outpoint(&tx1.txid().to_string(), 0)
+            .await
+            .unwrap();
+        assert_eq!(spent.len(), 1);
+        assert!(spent[0].spend);
+        assert_eq!(spent[0].spent_block, Some(height));
+
+        let received = db
+            .get_rune_utxos_by_outpoint(&tx2.txid().to_string(), 0)
+            .await
+            .unwrap();
+        assert_eq!(received.len(), 1);
+        assert_eq!(
+            received[0].rune,
+            ordinals::Rune::reserved(height as u64, 1).to_string()
+        );
+        assert_eq!(received[0].amount, "1000");
+        assert!(!received[0].spend);
+    }
+
+    /// needs a scratch db in `TEST_DB_DSN` and redis in `TEST_REDIS_ADDR`, skipped otherwise
+    #[tokio::test]
+    async fn edicts_are_skipped_when_disabled() {
+        let heights = 960_000_000..960_000_002;
+        let Some(mut indexer) = scratch_indexer(indexers_cfg(), heights.clone()).await else {
+            return;
+        };
+        assert!(indexer.cfg.handle_edicts());
+
+        for (handle_edicts, height) in [(true, heights.start), (false, heights.start + 1)] {
+            indexer.cfg.handle_edicts = Some(handle_edicts);
+
+            // the premine lands on output 0, the transfer's edict moves it to output 2
+            let etching = Transaction {
+                lock_time: LockTime::from_consensus(height as u32),
+                ..etching_tx(&Runestone {
+                    etching: Some(Etching {
+                        premine: Some(1000),
+                        ..Default::default()
+                    }),
+                    ..Default::default()
+                })
+            };
+            let mut transfer = Transaction {
+                input: vec![spend(&etching, 0)],
+                ..etching_tx(&Runestone {
+                    edicts: vec![Edict {
+                        id: RuneId {
+                            block: height as u64,
+                            tx: 1,
+                        },
+                        amount: 0,
+                        output: 2,
+                    }],
+                    ..Default::default()
+                })
+            };
+            transfer.output.push(transfer.output[0].clone());
+
+            let stats = indexer
+                .index_block_txs(height, &block(vec![etching, transfer.clone()]))
+                .await
+                .unwrap()
+                .unwrap();
+            assert_eq!(stats.etches, 1);
+            assert_eq!(stats.edicts, handle_edicts as u64);
+
+            let db = indexer.service_repo.db();
+            let txid = transfer.txid().to_string();
+            let moved = db.get_rune_utxos_by_outpoint(&txid, 2).await.unwrap();
+            let change = db.get_rune_utxos_by_outpoint(&txid, 0).await.unwrap();
+            let premine = db
+                .get_rune_utxos_by_outpoint(&transfer.input[0].previous_output.txid.to_string(), 0)
+                .await
+                .unwrap();
+            assert!(change.is_empty(), "handle_edicts={}", handle_edicts);
+            assert_eq!(premine.len(), 1);
+            assert_eq!(premine[0].amount, "1000");
+            if handle_edicts {
+                assert_eq!(moved.len(), 1);
+                assert_eq!(moved[0].amount, "1000");
+                assert!(premine[0].spend);
+            } else {
+                // the tx is a no-op, the runes stay where they were
+                assert!(moved.is_empty());
+                assert!(!premine[0].spend);
+            }
+        }
+    }
+
+    #[test]
+    fn checkpoints_follow_interval() {
+        use super::is_checkpoint_height;
+
+        assert!(is_checkpoint_height(840_000, 1000));
+        assert!(!is_checkpoint_height(840_001, 1000));
+        assert!(is_checkpoint_height(840_001, 1));
+        // 0 disables checkpoints
+        assert!(!is_checkpoint_height(840_000, 0));
+    }
+
+    #[tokio::test]
+    async fn broken_checkpoints_rewind_to_the_last_good_one() {
+        use super::last_good_checkpoint;
+        use crate::db;
+
+        let checkpoint = |height| db::IndexCheckpoint {
+            height,
+            ..Default::default()
+        };
+        let checkpoints = vec![checkpoint(3000), checkpoint(2000), checkpoint(1000)];
+        let verify = |broken: Vec<i64>| {
+            move |c: db::IndexCheckpoint| {
+                let res = broken
+                    .contains(&c.height)
+                    .then(|| "block hash changed".to_owned());
+                async move { Ok(res) }
+            }
+        };
+
+        // the latest one matches, nothing to rewind
+        let res = last_good_checkpoint(&checkpoints, verify(vec![1000])).await;
+        assert_eq!(res.unwrap(), None);
+
+        let res = last_good_checkpoint(&checkpoints, verify(vec![3000])).await;
+        assert_eq!(res.unwrap(), Some(2000));
+        let res = last_good_checkpoint(&checkpoints, verify(vec![3000, 2000])).await;
+        assert_eq!(res.unwrap(), Some(1000));
+
+        let err = last_good_checkpoint(&checkpoints, verify(vec![3000, 2000, 1000]))
+            .await
+            .unwrap_err();
+        assert!(err
+            .to_string()
+            .starts_with("checkpoint at 3000 doesn't match"));
+
+        // lookup errors stop the verification
+        let res = last_good_checkpoint(&checkpoints, |_| async {
+            Err::<Option<String>, _>(anyhow::anyhow!("rpc is down"))
+        })
+        .await;
+        assert!(res.is_err());
+        assert_eq!(
+            last_good_checkpoint(&[], verify(vec![])).await.unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn simulated_transfer_matches_the_indexer_rules() {
+        use super::{simulate_runes, RunesOutcome};
+        use std::collections::HashMap;
+
+        let id = RuneId {
+            block: 840_000,
+            tx: 1,
+        };
+        let runes = HashMap::from([(id, ("RUNE".to_owned(), Some(10u128)))]);
+        let inputs = HashMap::from([("RUNE".to_owned(), 100u128)]);
+        let simulate = |tx: &Transaction| {
+            simulate_runes(
+                bitcoin::Network::Bitcoin,
+                tx,
+                840_100,
+                1,
+                &indexers_cfg(),
+                &inputs,
+                &runes,
+            )
+        };
+
+        // 30 go to the second output, the change to the first non-OP_RETURN one
+        let runestone = Runestone {
+            edicts: vec![Edict {
+                id,
+                amount: 30,
+                output: 2,
+            }],
+            mint: Some(id),
+            ..Default::default()
+        };
+        let mut tx = etching_tx(&runestone);
+        tx.output.push(tx.output[0].clone());
+        let outcome = simulate(&tx);
+        assert_eq!(outcome.burn_reason, None);
+        assert_eq!(outcome.outputs[0], HashMap::from([("RUNE".to_owned(), 80)]));
+        assert!(outcome.outputs[1].is_empty());
+        assert_eq!(outcome.outputs[2], HashMap::from([("RUNE".to_owned(), 30)]));
+        assert!(outcome.burned.is_empty());
+
+        // spending more than the inputs burns everything
+        let overspend = Runestone {
+            edicts: vec![Edict {
+                id,
+                amount: 101,
+                output: 0,
+            }],
+            ..Default::default()
+        };
+        let outcome = simulate(&etching_tx(&overspend));
+        assert!(outcome
+            .burn_reason
+            .as_deref()
+            .unwrap()
+            .starts_with(crate::db::IndexerAnomaly::INVALID_ALLOCATION));
+        assert_eq!(
+            RunesOutcome {
+                burn_reason: None,
+                ..outcome
+            },
+            RunesOutcome {
+                outputs: vec![HashMap::new(); 2],
+                burned: inputs.clone(),
+                burn_reason: None,
+                etching: None,
+            }
+        );
+
+        // edicts to the runestone output are burned
+        let burn = Runestone {
+            edicts: vec![Edict {
+                id,
+                amount: 40,
+                output: 1,
+            }],
+            ..Default::default()
+        };
+        let outcome = simulate(&etching_tx(&burn));
+        assert_eq!(outcome.burned, HashMap::from([("RUNE".to_owned(), 40)]));
+        assert_eq!(outcome.outputs[0], HashMap::from([("RUNE".to_owned(), 60)]));
+
+        // unknown runes and txs without a runestone burn the inputs, like the indexer does
+        let unknown = Runestone {
+            edicts: vec![Edict {
+                id: RuneId { block: 1, tx: 1 },
+                amount: 1,
+                output: 0,
+            }],
+            ..Default::default()
+        };
+        assert!(simulate(&etching_tx(&unknown)).burn_reason.is_some());
+        let mut plain = etching_tx(&Runestone::default());
+        plain.output.pop();
+        assert_eq!(
+            simulate(&plain).burn_reason.as_deref(),
+            Some("no runestone")
+        );
+    }
+
+    #[tokio::test]
+    async fn edict_resolves_rune_etched_earlier_in_block() {
+        use super::{block_tx_infos, edict_rune, etched_rune_id, simulate_runes, BlockEtchings};
+        use bitcoin::{
+            block::{Header, Version},
+            hash_types::TxMerkleNode,
+            hashes::Hash,
+            BlockHash, CompactTarget, OutPoint, Sequence, TxIn, Witness,
+        };
+        use ordinals::Rune;
+        use std::collections::HashMap;
+        use std::str::FromStr;
+
+        let input = |previous_output| TxIn {
+            previous_output,
+            script_sig: ScriptBuf::new(),
+            sequence: Sequence::MAX,
+            witness: Witness::new(),
+        };
+        let coinbase = Transaction {
+            input: vec![input(OutPoint::null())],
+            ..etching_tx(&Runestone::default())
+        };
+        let tx1 = etching_tx(&Runestone {
+            etching: Some(Etching {
+                rune: Some(Rune::from_str("AAAAAAAAAAAAAB").unwrap()),
+                premine: Some(1000),
+                ..Default::default()
+            }),
+            ..Default::default()
+        });
+        let tx2 = etching_tx(&Runestone::default());
+        // tx3 moves 400 of the rune etched by tx1 to its third output
+        let etched = RuneId {
+            block: 840_000,
+            tx: 1,
+        };
+        let mut tx3 = Transaction {
+            input: vec![input(OutPoint {
+                txid: tx1.txid(),
+                vout: 0,
+            })],
+            ..etching_tx(&Runestone {
+                edicts: vec![Edict {
+                    id: etched,
+                    amount: 400,
+                    output: 2,
+                }],
+                ..Default::default()
+            })
+        };
+        tx3.output.push(tx3.output[0].clone());
+
+        let block = bitcoin::Block {
+            header: Header {
+                version: Version::TWO,
+                prev_blockhash: BlockHash::all_zeros(),
+                merkle_root: TxMerkleNode::all_zeros(),
+                time: 1_713_571_767,
+                bits: CompactTarget::from_consensus(0),
+                nonce: 0,
+            },
+            txdata: vec![coinbase, tx1, tx2, tx3],
+        };
+        let txs = block_tx_infos(840_000, &block);
+        assert_eq!(txs.len(), 3);
+        assert_eq!(etched_rune_id(&txs[0]), etched);
+
+        // the block etchings resolve the id before the db is involved
+        let mut block_etchings = BlockEtchings::default();
+        block_etchings.start_block();
+        block_etchings.insert(&txs[0], "AAAAAAAAAAAAAB");
+        let rune = edict_rune(&block_etchings, &etched, |_| async {
+            Err(anyhow::anyhow!("not indexed yet"))
+        })
+        .await
+        .unwrap()
+        .unwrap();
+        let runes: HashMap<RuneId, (String, Option<u128>)> =
+            HashMap::from([(etched, (rune, None))]);
+        let inputs = HashMap::from([("AAAAAAAAAAAAAB".to_owned(), 1000u128)]);
+        let cfg = indexers_cfg();
+        let tx3 = &txs[2].tx;
+        let outcome = simulate_runes(
+            bitcoin::Network::Bitcoin,
+            tx3,
+            840_000,
+            3,
+            &cfg,
+            &inputs,
+            &runes,
+        );
+        assert_eq!(outcome.burn_reason, None);
+        assert_eq!(outcome.outputs[0]["AAAAAAAAAAAAAB"], 600);
+        assert_eq!(outcome.outputs[2]["AAAAAAAAAAAAAB"], 400);
+
+        // an unresolved rune burns the inputs unless such edicts are skipped
+        let unresolved = HashMap::new();
+        let outcome = simulate_runes(
+            bitcoin::Network::Bitcoin,
+            tx3,
+            840_000,
+            3,
+            &cfg,
+            &inputs,
+            &unresolved,
+        );
+        assert_eq!(outcome.burned["AAAAAAAAAAAAAB"], 1000);
+        let cfg = crate::config::IndexersConfig {
+            skip_unknown_edict_runes: Some(true),
+            ..cfg
+        };
+        let outcome = simulate_runes(
+            bitcoin::Network::Bitcoin,
+            tx3,
+            840_000,
+            3,
+            &cfg,
+            &inputs,
+            &unresolved,
+        );
+        assert_eq!(outcome.burn_reason, None);
+        assert_eq!(outcome.outputs[0]["AAAAAAAAAAAAAB"], 1000);
+
+        // the next block asks the index again
+        block_etchings.start_block();
+        let rune = edict_rune(&block_etchings, &etched, |_| async { Ok(None) }).await;
+        assert_eq!(rune.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn edict_rune_lookup_errors_are_returned() {
+        use super::{edict_rune, BlockEtchings};
+        use std::collections::HashMap;
+
+        let etched = RuneId {
+            block: 840_000,
+            tx: 1,
+        };
+        let indexed = RuneId {
+            block: 840_000,
+            tx: 7,
+        };
+        let block_etchings = BlockEtchings(HashMap::from([(etched, "AAAAAAAAAAAAAB".to_owned())]));
+
+        // etched in the same block, the index isn't asked
+        let rune = edict_rune(&block_etchings, &etched, |_| async {
+            Err(anyhow::anyhow!("db is down"))
+        })
+        .await;
+        assert_eq!(rune.unwrap().as_deref(), Some("AAAAAAAAAAAAAB"));
+
+        let rune = edict_rune(&block_etchings, &indexed, |_| async {
+            Ok(Some("UNCOMMON•GOODS".to_owned()))
+        })
+        .await;
+        assert_eq!(rune.unwrap().as_deref(), Some("UNCOMMON•GOODS"));
+
+        // unknown rune, the edict is invalid
+        let rune = edict_rune(&block_etchings, &indexed, |_| async { Ok(None) }).await;
+        assert_eq!(rune.unwrap(), None);
+
+        // a failed lookup isn't an unknown rune, the block has to be retried
+        let rune = edict_rune(&block_etchings, &indexed, |_| async {
+            Err(anyhow::anyhow!("db is down"))
+        })
+        .await;
+        assert!(rune.is_err());
+    }
+
+    #[test]
+    fn invalid_runestones_dont_store_mint_or_etching() {
+        use super::{allocate_runestone, RunestoneRunes};
+        use crate::db::IndexerAnomaly;
+        use std::collections::HashMap;
+
+        let id = RuneId {
+            block: 840_000,
+            tx: 1,
+        };
+        let inputs = HashMap::from([("RUNE".to_owned(), 100u128)]);
+        let allocate = |runestone: &Runestone, tx: &Transaction, runes: &RunestoneRunes| {
+            allocate_runestone(
+                bitcoin::Network::Bitcoin,
+                tx,
+                runestone,
+                &runestone.edicts,
+                &inputs,
+                runes,
+                false,
+            )
+        };
+
+        // the mint goes to the change output
+        let mint = Runestone {
+            mint: Some(id),
+            ..Default::default()
+        };
+        let runes = RunestoneRunes {
+            mint: Some(("RUNE".to_owned(), 10)),
+            ..Default::default()
+        };
+        let allocation = allocate(&mint, &etching_tx(&mint), &runes).unwrap();
+        assert_eq!(allocation.outputs[0]["RUNE"].mint, 10);
+
+        // invalid mint, there is no output for it
+        let mut no_change = etching_tx(&mint);
+        no_change.output.remove(0);
+        let (kind, _) = allocate(&mint, &no_change, &runes).unwrap_err();
+        assert_eq!(kind, IndexerAnomaly::INVALID_MINT);
+
+        // the mint is valid, but the edict overspends, so the mint isn't counted either
+        let overspend = Runestone {
+            mint: Some(id),
+            edicts: vec![Edict {
+                id,
+                amount: 101,
+                output: 0,
+            }],
+            ..Default::default()
+        };
+        let runes = RunestoneRunes {
+            mint: Some(("RUNE".to_owned(), 10)),
+            edicts: HashMap::from([(id, "RUNE".to_owned())]),
+            ..Default::default()
+        };
+        let (kind, _) = allocate(&overspend, &etching_tx(&overspend), &runes).unwrap_err();
+        assert_eq!(kind, IndexerAnomaly::INVALID_ALLOCATION);
+
+        // the etching is valid, the edict of an unknown rune isn't, the rune isn't stored
+        let etching = Runestone {
+            etching: Some(Etching {
+                premine: Some(1000),
+                ..Default::default()
+            }),
+            edicts: vec![Edict {
+                id: RuneId {
+                    block: 840_000,
+                    tx: 9,
+                },
+                amount: 1,
+                output: 0,
+            }],
+            ..Default::default()
+        };
+        let runes = RunestoneRunes {
+            etching: Some("AAAAAAAAAAAAAB".to_owned()),
+            ..Default::default()
+        };
+        let (kind, _) = allocate(&etching, &etching_tx(&etching), &runes).unwrap_err();
+        assert_eq!(kind, IndexerAnomaly::INVALID_EDICTS);
+
+        let etching = Runestone {
+            edicts: vec![],
+            ..etching
+        };
+        let allocation = allocate(&etching, &etching_tx(&etching), &runes).unwrap();
+        assert_eq!(allocation.outputs[0]["AAAAAAAAAAAAAB"].etching, 1000);
+        assert_eq!(allocation.unallocated_premine, 0);
+    }
+
+    #[test]
+    fn unknown_odd_tags_are_reported() {
+        use super::unknown_odd_tags;
+        use bitcoin::{opcodes, script::Builder, script::PushBytesBuf};
+        use ordinals::Artifact;
+
+        let runestone = Runestone {
+            pointer: Some(0),
+            ..Default::default()
+        };
+        let mut tx = etching_tx(&runestone);
+        assert!(unknown_odd_tags(&tx).is_empty());
+
+        // pointer 0, an unknown odd tag 31 twice and the known divisibility tag,
+        // split over two pushes
+        let fields: [u8; 8] = [22, 0, 31, 5, 31, 6, 1, 2];
+        tx.output[1].script_pubkey = Builder::new()
+            .push_opcode(opcodes::all::OP_RETURN)
+            .push_opcode(Runestone::MAGIC_NUMBER)
+            .push_slice(PushBytesBuf::try_from(fields[..3].to_vec()).unwrap())
+            .push_slice(PushBytesBuf::try_from(fields[3..].to_vec()).unwrap())
+            .into_script();
+
+        // the protocol ignores the field
+        let Some(Artifact::Runestone(deciphered)) = Runestone::decipher(&tx) else {
+            panic!("not a runestone");
+        };
+        assert_eq!(deciphered.pointer, Some(0));
+        assert_eq!(unknown_odd_tags(&tx), vec![31]);
+    }
+
+    #[test]
+    fn simulated_etching_follows_the_indexer() {
+        use super::simulate_runes;
+        use std::collections::HashMap;
+
+        let runestone = Runestone {
+            etching: Some(Etching {
+                premine: Some(1000),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let simulate = |tx: &Transaction, tx_index| {
+            simulate_runes(
+                bitcoin::Network::Bitcoin,
+                tx,
+                840_100,
+                tx_index,
+                &indexers_cfg(),
+                &HashMap::new(),
+                &HashMap::new(),
+            )
+        };
+
+        // an unnamed etching gets the reserved rune of its block and tx index
+        let tx = etching_tx(&runestone);
+        let outcome = simulate(&tx, 7);
+        let reserved = ordinals::Rune::reserved(840_100, 7).to_string();
+        assert_eq!(outcome.etching.as_deref(), Some(reserved.as_str()));
+        assert_eq!(outcome.outputs[0], HashMap::from([(reserved, 1000)]));
+        assert_ne!(
+            simulate(&tx, 8).etching,
+            outcome.etching,
+            "the tx index names the rune"
+        );
+
+        // a mint without a change output is invalid, as in allocate_runestone
+        let id = RuneId {
+            block: 840_000,
+            tx: 1,
+        };
+        let mint = Runestone {
+            mint: Some(id),
+            ..Default::default()
+        };
+        let mut tx = etching_tx(&mint);
+        tx.output.remove(0);
+        let outcome = simulate_runes(
+            bitcoin::Network::Bitcoin,
+            &tx,
+            840_100,
+            1,
+            &indexers_cfg(),
+            &HashMap::new(),
+            &HashMap::from([(id, ("RUNE".to_owned(), Some(10)))]),
+        );
+        assert!(outcome
+            .burn_reason
+            .as_deref()
+            .unwrap()
+            .starts_with(crate::db::IndexerAnomaly::INVALID_MINT));
+        assert_eq!(outcome.outputs, vec![HashMap::new()]);
+    }
+
+    #[test]
+    fn scratch_redis_uses_the_test_db() {
+        for address in [
+            "redis://127.0.0.1:6379",
+            "redis://127.0.0.1:6379/",
+            "redis://127.0.0.1:6379/1",
+        ] {
+            assert_eq!(scratch_redis_address(address), "redis://127.0.0.1:6379/15");
+        }
+    }
+
+    #[test]
+    fn total_stats_are_cumulative_counters() {
+        use super::RuneTxsStats;
+
+        let block = RuneTxsStats {
+            etches: 2,
+            invalid_etches: 1,
+            invalid_mints: 3,
+            cenotaphs: 1,
+            ..Default::default()
+        };
+        let mut total = RuneTxsStats::default();
+        total.add(&block);
+        total.add(&block);
+
+        let json = serde_json::to_value(&total).unwrap();
+        assert_eq!(json["etches"], 4);
+        assert_eq!(json["invalid_etches"], 2);
+        assert_eq!(json["invalid_mints"], 6);
+        assert_eq!(json["invalid_edicts"], 0);
+        assert_eq!(json["cenotaphs"], 2);
+    }
+}
@@ -0,0 +1,34 @@
+use crate::{
+    cli_error::CliError,
+    tx::signer::{AddressMode, PKSigner},
+};
+
+#[derive(Debug, clap::Parser)]
+pub struct ImportKeyCmd {
+    /// private key in WIF, its network must match the configured one
+    #[arg(long)]
+    wif: String,
+}
+
+impl ImportKeyCmd {
+    pub async fn run(&self, config_path: &str) -> anyhow::Result<()> {
+        let cfg = crate::config::read_config(config_path)?;
+        let net = cfg.btc.get_network();
+
+        let legacy = PKSigner::from_wif(net, &self.wif, AddressMode::Legacy(true))
+            .map_err(|e| CliError::Validation(format!("invalid WIF key: {}", e)))?;
+        println!("{}:", net);
+        println!("  secret_key:\t{}", legacy.secret_hex());
+        println!("  p2pkh:   \t{}", legacy.address);
+
+        // uncompressed keys have only the legacy address
+        if let AddressMode::Legacy(true) = legacy.address_mode() {
+            let witness = PKSigner::from_wif(net, &self.wif, AddressMode::Witness)?;
+            println!("  p2shwpkh:\t{}", witness.address);
+            let taproot = PKSigner::from_wif(net, &self.wif, AddressMode::Taproot)?;
+            println!("  p2tr:    \t{}", taproot.address);
+        }
+
+        Ok(())
+    }
+}
@@ -10,16 +10,22 @@ use tokio_util::sync::CancellationToken;
 
 mod btc_utxo;
 mod cache;
+mod cli_error;
 mod config;
 mod db;
 mod etcher;
+mod holders_cmd;
 mod indexer;
+mod keys_cmd;
+mod reset_cmd;
 mod rest;
 mod serde_utils;
 mod service;
+mod snapshot_cmd;
 mod tx;
 mod tx_cmd;
 mod utils;
+mod verify_cmd;
 
 use rest::server::run_server;
 
@@ -46,7 +52,13 @@ async fn main() -> anyhow::Result<()> {
             let cfg = config::read_config(&args.config)?;
             run_app(cfg).await
         }
-        Some(subcmd) => subcmd.run(&args.config).await,
+        Some(subcmd) => {
+            if let Err(err) = subcmd.run(&args.config).await {
+                eprintln!("Error: {:?}", err);
+                std::process::exit(cli_error::exit_code(&err));
+            }
+            Ok(())
+        }
     }
 }
 
@@ -64,17 +76,36 @@ enum Subcommand {
     #[command(about = "Start indexer only")]
     Indexer,
 
-    #[command(about = "Cleans all data from the index db")]
-    ResetDB,
+    #[command(about = "Cleans data from the index db")]
+    ResetDB(reset_cmd::ResetDbCmd),
 
     #[command(about = "Generates new keypair")]
     GenKeypair,
 
+    #[command(about = "Print the addresses of a WIF private key")]
+    ImportKey(keys_cmd::ImportKeyCmd),
+
     #[command(about = "Submit raw transaction")]
     SubmitRawTx(tx_cmd::SubmitRawTxCmd),
 
     #[command(about = "Send rune to address")]
     SendRunes(tx_cmd::SendRuneTxCmd),
+
+    #[command(about = "Print holders of the rune")]
+    Holders(holders_cmd::HoldersCmd),
+
+    #[command(about = "Export the runes index state to a file")]
+    ExportSnapshot(snapshot_cmd::ExportSnapshotCmd),
+
+    #[command(about = "Import the runes index state into an empty db")]
+    ImportSnapshot(snapshot_cmd::ImportSnapshotCmd),
+
+    #[command(about = "Compare the indexed rune with its etching tx")]
+    VerifyRune(verify_cmd::VerifyRuneCmd),
+
+    #[command(about = "Compare the rune balances with the ones replayed from the runes log")]
+    ReplayLog(verify_cmd::ReplayLogCmd),
+
     #[command(about = "Warm-up cache data")]
     WarmupCache,
 
@@ -89,9 +120,15 @@ impl Subcommand {
             Subcommand::BtcTx(cmd) => cmd.run(cfg_path).await,
             Subcommand::SubmitRawTx(cmd) => cmd.run(cfg_path).await,
             Subcommand::SendRunes(cmd) => cmd.run(cfg_path).await,
+            Subcommand::Holders(cmd) => cmd.run(cfg_path).await,
+            Subcommand::ExportSnapshot(cmd) => cmd.run(cfg_path).await,
+            Subcommand::ImportSnapshot(cmd) => cmd.run(cfg_path).await,
+            Subcommand::VerifyRune(cmd) => cmd.run(cfg_path).await,
+            Subcommand::ReplayLog(cmd) => cmd.run(cfg_path).await,
+            Subcommand::ImportKey(cmd) => cmd.run(cfg_path).await,
             Subcommand::ApiServer => run_api_server(cfg_path).await,
             Subcommand::Indexer => run_indexer(cfg_path).await,
-            Subcommand::ResetDB => reset_db(cfg_path).await,
+            Subcommand::ResetDB(cmd) => cmd.run(cfg_path).await,
             Subcommand::WarmupCache => warm_up_cache(cfg_path).await,
             Subcommand::GenKeypair => {
                 generate_keypair().await;
@@ -103,32 +140,56 @@ impl Subcommand {
 }
 
 async fn run_app(cfg: config::Config) -> anyhow::Result<()> {
-    let repo: db::Repo = db::open_postgres_db(cfg.db).await?;
+    warn_if_rune_log_disabled(&cfg.indexers);
+    let repo: db::Repo = db::open_postgres_db(cfg.db.clone()).await?;
     let db = Arc::new(repo);
+    let api_db = Arc::new(db::connect_api_db(cfg.db).await?);
     let rcache = cache::CacheRepo::new(cfg.redis).await?;
+    let cancel = CancellationToken::new();
+    let (webhooks, webhooks_handle) = start_webhooks(&cfg.webhooks, db.clone(), cancel.clone())?;
+
     let service_state =
-        service::StateProvider::new(db.clone(), rcache.clone(), cfg.indexers.disable_rune_log);
+        service::StateProvider::new(db.clone(), rcache.clone(), cfg.indexers.disable_rune_log)
+            .with_webhooks(webhooks);
 
-    let btc_indexer = indexer::BtcIndexer::new(&cfg.btc, &cfg.indexers, db.clone());
     let runes_indexer = indexer::EtchingIndexer::new(&cfg.btc, &cfg.indexers, service_state);
 
-    let cancel = CancellationToken::new();
-
-    let btc_handle = btc_indexer.start(cancel.clone());
+    let btc_handle = start_btc_indexer(&cfg.btc, &cfg.indexers, db.clone(), cancel.clone());
+    let indexer_stats = runes_indexer.total_stats();
     let indexer_handle = runes_indexer.start(cancel.clone());
 
-    let signer = tx::signer::PKSigner::new_from_secret(
-        cfg.btc.get_network(),
-        &cfg.signature_provider.local.secret_key,
-        tx::signer::AddressMode::new_from_str(&cfg.signature_provider.local.mode),
-    )?;
+    let signers =
+        tx::signer::MultiSigner::from_config(cfg.btc.get_network(), &cfg.signature_provider)?;
+    info!("local signers: {:?}", signers.addresses());
 
-    let btc_client = btc_utxo::UtxoClient::new(cfg.btc.utxo_provider.clone(), db.clone());
-    let c = Arc::new(RwLock::new(rcache));
-    let api_service = rest::api::Service::new(db.clone(), btc_client, cfg.btc.clone(), signer, c);
-    let admin_api_service = rest::admin_api::Api::new(db.clone());
+    let (consistency_report, consistency_handle) =
+        start_consistency_checker(&cfg.consistency, db.clone(), cancel.clone());
+    let (tip_cache, tip_handle) = start_tip_refresher(&cfg.btc, db.clone(), cancel.clone())?;
 
-    match run_server(cfg.api, api_service, admin_api_service).await {
+    let btc_client = btc_utxo::UtxoClient::new(&cfg.btc, api_db.clone())?;
+    let c = Arc::new(RwLock::new(rcache));
+    let pool_txs = pool_tx_builder(
+        &cfg.btc,
+        &cfg.signature_provider,
+        api_db.clone(),
+        c.clone(),
+        btc_client.clone(),
+        &signers,
+    )?;
+    let api_service = rest::api::Service::new(
+        api_db.clone(),
+        btc_client,
+        cfg.btc.clone(),
+        signers,
+        c,
+        pool_txs,
+        tip_cache.clone(),
+    );
+    let admin_api_service =
+        rest::admin_api::Api::new(api_db, consistency_report, Some(indexer_stats));
+
+    let index_lag = rest::middleware::IndexLagHeader::new(tip_cache);
+    match run_server(cfg.api, api_service, admin_api_service, index_lag).await {
         Ok(_) => (),
         Err(err) => {
             error!("HTTP server failed: {:?}", err);
@@ -137,37 +198,163 @@ async fn run_app(cfg: config::Config) -> anyhow::Result<()> {
     // signal indexer task to stop running
     cancel.cancel();
 
-    btc_handle.await.unwrap();
+    tip_handle.await.unwrap();
+    if let Some(handle) = consistency_handle {
+        handle.await.unwrap();
+    }
+
+    if let Some(handle) = btc_handle {
+        handle.await.unwrap();
+    }
     indexer_handle.await.unwrap();
+    if let Some(handle) = webhooks_handle {
+        handle.await.unwrap();
+    }
 
     log::info!("Application successfully shut down");
 
     Ok(())
 }
 
+fn start_btc_indexer(
+    btc_cfg: &config::BTCConfig,
+    cfg: &config::IndexersConfig,
+    db: Arc<db::Repo>,
+    cancel: CancellationToken,
+) -> Option<tokio::task::JoinHandle<()>> {
+    if !cfg.btc_indexer_enabled(&btc_cfg.utxo_provider) {
+        info!(
+            "BTC indexer is disabled, btc utxos are served by the {} provider",
+            btc_cfg.utxo_provider.mode
+        );
+        return None;
+    }
+    if cfg.disable_btc_indexer.unwrap_or(false) {
+        warn!("BTC indexer can't be disabled with the local utxo provider");
+    }
+
+    let btc_indexer = indexer::BtcIndexer::new(btc_cfg, cfg, db);
+    Some(btc_indexer.start(cancel))
+}
+
+/// the returned report is served by the admin API
+fn start_consistency_checker(
+    cfg: &config::ConsistencyCheckConfig,
+    db: Arc<db::Repo>,
+    cancel: CancellationToken,
+) -> (
+    Option<service::consistency_checker::SharedConsistencyReport>,
+    Option<tokio::task::JoinHandle<()>>,
+) {
+    if !cfg.enabled {
+        return (None, None);
+    }
+
+    let checker = service::consistency_checker::ConsistencyChecker::new(cfg, db);
+    let report = checker.report();
+    (Some(report), Some(checker.start(cancel)))
+}
+
+/// the notifier drops all events when no webhook url is configured
+fn start_webhooks(
+    cfg: &config::WebhookConfig,
+    db: Arc<db::Repo>,
+    cancel: CancellationToken,
+) -> anyhow::Result<(
+    service::webhooks::WebhookNotifier,
+    Option<tokio::task::JoinHandle<()>>,
+)> {
+    if !cfg.enabled() {
+        return Ok((Default::default(), None));
+    }
+
+    let (sender, notifier) = service::webhooks::WebhookSender::new(cfg, db)?;
+    info!("webhooks are sent to {} events={:?}", cfg.url, cfg.events);
+    Ok((notifier, Some(sender.start(cancel))))
+}
+
+/// builds the pool txs of the API, inputs are signed only for the signing allowlist
+fn pool_tx_builder(
+    btc_cfg: &config::BTCConfig,
+    signature_cfg: &config::SignatureProvider,
+    db: Arc<db::Repo>,
+    cache: Arc<RwLock<cache::CacheRepo>>,
+    utxo_provider: btc_utxo::UtxoClient,
+    signers: &tx::signer::MultiSigner,
+) -> anyhow::Result<tx::pool_txs::PoolTxBuilder> {
+    let allowlist = tx::pool_txs::SigningAllowlist::new(
+        btc_cfg.get_network(),
+        &signers.addresses(),
+        &signature_cfg.signable_addresses,
+    )?;
+    tx::pool_txs::PoolTxBuilder::from_config(btc_cfg, db, cache, utxo_provider, allowlist)
+}
+
+/// the returned cache is shared with the API, see `rest::middleware::IndexLagHeader`
+fn start_tip_refresher(
+    btc_cfg: &config::BTCConfig,
+    db: Arc<db::Repo>,
+    cancel: CancellationToken,
+) -> anyhow::Result<(service::tip_cache::TipCache, tokio::task::JoinHandle<()>)> {
+    let tip_cache = service::tip_cache::TipCache::new();
+    let refresher = service::tip_cache::TipRefresher::new(btc_cfg, db, tip_cache.clone())?;
+    Ok((tip_cache, refresher.start(cancel)))
+}
+
+fn warn_if_rune_log_disabled(cfg: &config::IndexersConfig) {
+    if cfg.disable_rune_log {
+        warn!(
+            "rune log is disabled: activity and tx runes endpoints will respond with {}",
+            rest::errors::codes::RUNE_LOG_DISABLED
+        );
+    }
+}
+
 async fn run_api_server(cfg_path: &str) -> anyhow::Result<()> {
     let cfg = config::read_config(cfg_path)?;
-    let repo: db::Repo = db::open_postgres_db(cfg.db).await?;
+    warn_if_rune_log_disabled(&cfg.indexers);
+    let repo: db::Repo = db::open_postgres_db(cfg.db.clone()).await?;
     let db = Arc::new(repo);
+    let api_db = Arc::new(db::connect_api_db(cfg.db).await?);
 
-    let signer = tx::signer::PKSigner::new_from_secret(
-        cfg.btc.get_network(),
-        &cfg.signature_provider.local.secret_key,
-        tx::signer::AddressMode::new_from_str(&cfg.signature_provider.local.mode),
-    )?;
+    let signers =
+        tx::signer::MultiSigner::from_config(cfg.btc.get_network(), &cfg.signature_provider)?;
+    info!("local signers: {:?}", signers.addresses());
 
     let cancel = CancellationToken::new();
+    let (webhooks, webhooks_handle) = start_webhooks(&cfg.webhooks, db.clone(), cancel.clone())?;
 
-    let tx_watchdog = service::tx_watchdog::TxWatchdog::new(&cfg.btc, db.clone());
+    let tx_watchdog = service::tx_watchdog::TxWatchdog::new(&cfg.btc, &cfg.watchdog, db.clone())
+        .with_webhooks(webhooks);
     let watchdog_handle = tx_watchdog.start(cancel.clone());
+    let (consistency_report, consistency_handle) =
+        start_consistency_checker(&cfg.consistency, db.clone(), cancel.clone());
+    let (tip_cache, tip_handle) = start_tip_refresher(&cfg.btc, db.clone(), cancel.clone())?;
 
-    let btc_client = btc_utxo::UtxoClient::new(cfg.btc.utxo_provider.clone(), db.clone());
+    let btc_client = btc_utxo::UtxoClient::new(&cfg.btc, api_db.clone())?;
     let rcache = cache::CacheRepo::new(cfg.redis).await?;
     let c = Arc::new(RwLock::new(rcache));
-    let api_service = rest::api::Service::new(db.clone(), btc_client, cfg.btc.clone(), signer, c);
-    let admin_api_service = rest::admin_api::Api::new(db.clone());
-
-    match run_server(cfg.api, api_service, admin_api_service).await {
+    let pool_txs = pool_tx_builder(
+        &cfg.btc,
+        &cfg.signature_provider,
+        api_db.clone(),
+        c.clone(),
+        btc_client.clone(),
+        &signers,
+    )?;
+    let api_service = rest::api::Service::new(
+        api_db.clone(),
+        btc_client,
+        cfg.btc.clone(),
+        signers,
+        c,
+        pool_txs,
+        tip_cache.clone(),
+    );
+    let admin_api_service = rest::admin_api::Api::new(api_db, consistency_report, None);
+
+    let index_lag = rest::middleware::IndexLagHeader::new(tip_cache);
+    match run_server(cfg.api, api_service, admin_api_service, index_lag).await {
         Ok(_) => (),
         Err(err) => {
             error!("HTTP server failed: {:?}", err);
@@ -175,6 +362,13 @@ async fn run_api_server(cfg_path: &str) -> anyhow::Result<()> {
     }
     cancel.cancel();
     watchdog_handle.await.unwrap();
+    tip_handle.await.unwrap();
+    if let Some(handle) = consistency_handle {
+        handle.await.unwrap();
+    }
+    if let Some(handle) = webhooks_handle {
+        handle.await.unwrap();
+    }
 
     log::info!("Application successfully shut down");
 
@@ -187,44 +381,31 @@ async fn run_indexer(cfg_path: &str) -> anyhow::Result<()> {
     let repo: db::Repo = db::open_postgres_db(cfg.db).await?;
     let db = Arc::new(repo);
     let rcache = cache::CacheRepo::new(cfg.redis).await?;
+    let cancel = CancellationToken::new();
+    let (webhooks, webhooks_handle) = start_webhooks(&cfg.webhooks, db.clone(), cancel.clone())?;
+
     let service_state =
-        service::StateProvider::new(db.clone(), rcache, cfg.indexers.disable_rune_log);
+        service::StateProvider::new(db.clone(), rcache, cfg.indexers.disable_rune_log)
+            .with_webhooks(webhooks);
 
-    let btc_indexer = indexer::BtcIndexer::new(&cfg.btc, &cfg.indexers, db.clone());
     let runes_indexer = indexer::EtchingIndexer::new(&cfg.btc, &cfg.indexers, service_state);
 
-    let cancel = CancellationToken::new();
-
-    let btc_handle = btc_indexer.start(cancel.clone());
+    let btc_handle = start_btc_indexer(&cfg.btc, &cfg.indexers, db.clone(), cancel.clone());
     let indexer_handle = runes_indexer.start(cancel.clone());
 
     tokio::signal::ctrl_c().await?;
     // signal indexer task to stop running
     cancel.cancel();
 
-    btc_handle.await.unwrap();
+    if let Some(handle) = btc_handle {
+        handle.await.unwrap();
+    }
     indexer_handle.await.unwrap();
-
-    log::info!("Application successfully shut down");
-
-    Ok(())
-}
-
-async fn reset_db(cfg_path: &str) -> anyhow::Result<()> {
-    let mut cfg = config::read_config(cfg_path)?;
-    cfg.db.automigrate = false;
-
-    let repo: db::Repo = db::open_postgres_db(cfg.db).await?;
-
-    repo.reset_schema().await?;
-    repo.insert_seed_data().await?;
-
-    for address in cfg.indexers.btc_watchlist {
-        repo.insert_btc_balance(&address).await?;
+    if let Some(handle) = webhooks_handle {
+        handle.await.unwrap();
     }
 
-    let mut rcache = cache::CacheRepo::new(cfg.redis).await?;
-    rcache.flush_all().await?;
+    log::info!("Application successfully shut down");
 
     Ok(())
 }
@@ -254,6 +435,11 @@ async fn generate_keypair() {
         println!("mainnet:");
         let pk = PrivateKey::new(secret_key, bitcoin::Network::Bitcoin);
         println!("  secret_key:\t{}", hex_secret);
+        println!("  wif:     \t{}", pk.to_wif());
+        println!(
+            "  wif_uncompressed:\t{}",
+            PrivateKey::new_uncompressed(secret_key, bitcoin::Network::Bitcoin).to_wif()
+        );
 
         let address = Address::p2shwpkh(&pk.public_key(&secp), bitcoin::Network::Bitcoin).unwrap();
         println!("  p2shwpkh:\t{}", address);
@@ -269,6 +455,7 @@ async fn generate_keypair() {
     {
         println!("testnet:");
         let pk = PrivateKey::new(recovered_secret, bitcoin::Network::Testnet);
+        println!("  wif:     \t{}", pk.to_wif());
 
         let address = Address::p2shwpkh(&pk.public_key(&secp), bitcoin::Network::Testnet).unwrap();
         println!("  p2shwpkh:\t{}", address);
@@ -281,6 +468,7 @@ async fn generate_keypair() {
     {
         println!("regtest");
         let pk = PrivateKey::new(recovered_secret, bitcoin::Network::Regtest);
+        println!("  wif:     \t{}", pk.to_wif());
 
         let address = Address::p2shwpkh(&pk.public_key(&secp), bitcoin::Network::Regtest).unwrap();
         println!("  p2shwpkh:\t{}", address);
@@ -322,3 +510,61 @@ async fn test_indexer(cfg_path: &str) -> anyhow::Result<()> {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    /// needs a scratch db in `TEST_DB_DSN`, skipped otherwise
+    #[tokio::test]
+    async fn btc_indexer_is_skipped_only_with_external_provider() {
+        use super::start_btc_indexer;
+        use crate::config::{BTCConfig, BtcUtxoProvider, IndexersConfig};
+        use crate::db::connect_scratch_db;
+        use std::sync::Arc;
+        use tokio_util::sync::CancellationToken;
+
+        let Some(db) = connect_scratch_db().await.map(Arc::new) else {
+            return;
+        };
+        // nothing listens on the rpc port, a started indexer stops at its first call
+        let btc_cfg = |mode: &str| -> BTCConfig {
+            toml::from_str(&format!(
+                r#"
+                address = "http://127.0.0.1:1"
+                rpc_user = "user"
+                rpc_password = "password"
+                utxo_provider = {{ mode = "{}", api_key = "key" }}
+                "#,
+                mode
+            ))
+            .unwrap()
+        };
+        let mut cfg: IndexersConfig = toml::from_str(
+            r#"
+            btc_starting_height = 0
+            runes_starting_height = 0
+            disable_rune_log = false
+            btc_watchlist = []
+            runes_watchlist = []
+            "#,
+        )
+        .unwrap();
+        let cancel = CancellationToken::new();
+
+        let handle = start_btc_indexer(
+            &btc_cfg(BtcUtxoProvider::CRYPTOAPIS),
+            &cfg,
+            db.clone(),
+            cancel.clone(),
+        );
+        handle.expect("enabled by default").await.unwrap();
+
+        cfg.disable_btc_indexer = Some(true);
+        for mode in [BtcUtxoProvider::CRYPTOAPIS, BtcUtxoProvider::BITCOIND] {
+            let handle = start_btc_indexer(&btc_cfg(mode), &cfg, db.clone(), cancel.clone());
+            assert!(handle.is_none(), "{}", mode);
+        }
+        // the local provider serves utxos from the indexer tables, the flag is ignored
+        let handle = start_btc_indexer(&btc_cfg("local"), &cfg, db, cancel);
+        handle.expect("needed by the local provider").await.unwrap();
+    }
+}
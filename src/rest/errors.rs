@@ -27,6 +27,14 @@ impl std::convert::From<ApiError> for HttpResponse {
 
 impl std::convert::From<ApiError> for ApiErrorData {
     fn from(error: ApiError) -> Self {
+        let mut data = Self::from_api_error(error);
+        data.reason = super::middleware::with_request_id(data.reason);
+        data
+    }
+}
+
+impl ApiErrorData {
+    fn from_api_error(error: ApiError) -> Self {
         match error {
             ApiError::NotFound => ApiErrorData {
                 code: codes::ResultCode::NotFound,
@@ -105,6 +113,39 @@ pub fn bad_request(msg: &str, reason: Option<String>) -> HttpResponse {
     ApiError::Validation(msg.to_string(), reason).into()
 }
 
+/// for endpoints which read `runes_log` while `indexers.disable_rune_log` is set
+pub fn rune_log_disabled() -> HttpResponse {
+    ApiError::Generic(
+        codes::ResultCode::Other(501),
+        codes::RUNE_LOG_DISABLED,
+        Some("rune logging is disabled on this deployment".to_string()),
+    )
+    .into()
+}
+
+/// for requests which need the node tip before the tip refresher got it
+pub fn tip_unknown() -> HttpResponse {
+    ApiError::Generic(
+        codes::ResultCode::Other(503),
+        codes::TIP_UNKNOWN,
+        Some("node tip is unknown yet, retry later".to_string()),
+    )
+    .into()
+}
+
+/// for requests cancelled by their endpoint's timeout
+pub fn request_timeout(timeout: std::time::Duration) -> HttpResponse {
+    ApiError::Generic(
+        codes::ResultCode::Other(504),
+        codes::REQUEST_TIMEOUT,
+        Some(format!(
+            "request took longer than {}s, narrow it down and retry",
+            timeout.as_secs_f64()
+        )),
+    )
+    .into()
+}
+
 pub fn internal_error(description: &str) -> HttpResponse {
     ApiError::Generic(
         codes::ResultCode::ServerError,
@@ -134,6 +175,9 @@ pub mod codes {
     pub const INTERNAL_ERROR: &str = "INTERNAL_SERVER_ERROR";
     pub const NOT_FOUND: &str = "NOT_FOUND";
     pub const INVALID_PAYLOAD: &str = "INVALID_PAYLOAD";
+    pub const RUNE_LOG_DISABLED: &str = "RUNE_LOG_DISABLED";
+    pub const REQUEST_TIMEOUT: &str = "REQUEST_TIMEOUT";
+    pub const TIP_UNKNOWN: &str = "TIP_UNKNOWN";
 
     #[derive(Clone, Debug)]
     pub enum ResultCode {
@@ -0,0 +1,193 @@
+use actix_web::{
+    http::header::{Charset, ContentDisposition, DispositionParam, DispositionType, ExtendedValue},
+    web::Bytes,
+    HttpResponse,
+};
+use futures::stream;
+use std::sync::Arc;
+
+use crate::db::{Repo, RuneUtxo};
+
+const EXPORT_BATCH_SIZE: i32 = 1000;
+
+struct UtxoExportState {
+    db: Arc<Repo>,
+    rune: String,
+    last_id: i64,
+    header_sent: bool,
+    done: bool,
+}
+
+/// Streams all unspent utxos of the rune as CSV,
+/// the db is queried in batches so the whole set is never buffered.
+pub fn runes_utxo_csv(db: Arc<Repo>, rune: &str) -> HttpResponse {
+    let state = UtxoExportState {
+        db,
+        rune: rune.to_owned(),
+        last_id: 0,
+        header_sent: false,
+        done: false,
+    };
+
+    let body = stream::unfold(state, |mut state| async move {
+        if state.done {
+            return None;
+        }
+
+        if !state.header_sent {
+            state.header_sent = true;
+            let header = "address,txid,vout,amount,btc_amount,block\n";
+            return Some((Ok(Bytes::from(header)), state));
+        }
+
+        // a permit per batch, a slow client doesn't hold it while the chunk is sent
+        let batch = {
+            let _permit = state.db.bulk_permit().await;
+            state
+                .db
+                .select_runes_utxo_batch(&state.rune, state.last_id, EXPORT_BATCH_SIZE)
+                .await
+        };
+        let batch = match batch {
+            Ok(batch) => batch,
+            Err(err) => {
+                error!(
+                    "utxo export failed: rune={} last_id={} error={}",
+                    state.rune, state.last_id, err
+                );
+                state.done = true;
+                return Some((Err(actix_web::error::ErrorInternalServerError(err)), state));
+            }
+        };
+
+        if batch.is_empty() {
+            return None;
+        }
+        state.done = batch.len() < EXPORT_BATCH_SIZE as usize;
+        state.last_id = batch.last().map(|u| u.id).unwrap_or(state.last_id);
+
+        let chunk = utxos_to_csv(&batch).map_err(actix_web::error::ErrorInternalServerError);
+        Some((chunk, state))
+    });
+
+    HttpResponse::Ok()
+        .content_type("text/csv")
+        .insert_header(export_disposition(rune))
+        .streaming(body)
+}
+
+/// The rune comes from the request path, so the filename is quoted and escaped by the
+/// typed header. Names outside of ASCII get an `_` fallback and a percent-encoded `filename*`.
+fn export_disposition(rune: &str) -> ContentDisposition {
+    let filename = format!("{}-utxos.csv", rune);
+    let fallback = filename
+        .chars()
+        .map(|c| {
+            if c.is_ascii() && !c.is_ascii_control() {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+
+    let mut parameters = vec![DispositionParam::Filename(fallback)];
+    if !filename.is_ascii() {
+        parameters.push(DispositionParam::FilenameExt(ExtendedValue {
+            charset: Charset::Ext("UTF-8".to_owned()),
+            language_tag: None,
+            value: filename.into_bytes(),
+        }));
+    }
+
+    ContentDisposition {
+        disposition: DispositionType::Attachment,
+        parameters,
+    }
+}
+
+fn utxos_to_csv(utxos: &[RuneUtxo]) -> anyhow::Result<Bytes> {
+    let mut wr = csv::WriterBuilder::new()
+        .has_headers(false)
+        .from_writer(Vec::new());
+    for u in utxos.iter() {
+        wr.write_record([
+            u.address.as_str(),
+            u.tx_hash.as_str(),
+            u.output_n.to_string().as_str(),
+            u.amount.as_str(),
+            u.btc_amount.to_string().as_str(),
+            u.block.to_string().as_str(),
+        ])?;
+    }
+
+    Ok(Bytes::from(wr.into_inner()?))
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn export_filename_is_escaped() {
+        use super::export_disposition;
+        use actix_web::http::header::{ContentDisposition, TryIntoHeaderValue};
+
+        let parse = |rune: &str| {
+            let value = export_disposition(rune).try_into_value().unwrap();
+            ContentDisposition::from_raw(&value).unwrap()
+        };
+
+        let cd = parse("UNCOMMONGOODS");
+        assert!(cd.is_attachment());
+        assert_eq!(cd.get_filename(), Some("UNCOMMONGOODS-utxos.csv"));
+        assert!(cd.get_filename_ext().is_none());
+
+        // quotes and header separators stay inside the quoted filename
+        let cd = parse("A\"; filename=\"x.exe");
+        assert_eq!(cd.get_filename(), Some("A\"; filename=\"x.exe-utxos.csv"));
+
+        let cd = parse("UNCOMMON•GOODS\r\nSet-Cookie: a=b");
+        assert_eq!(
+            cd.get_filename(),
+            Some("UNCOMMON_GOODS__Set-Cookie: a=b-utxos.csv")
+        );
+        assert_eq!(
+            cd.get_filename_ext().unwrap().value,
+            "UNCOMMON•GOODS\r\nSet-Cookie: a=b-utxos.csv".as_bytes()
+        );
+    }
+
+    /// needs a scratch db in `TEST_DB_DSN`, skipped otherwise
+    #[actix_web::test]
+    async fn export_waits_for_a_bulk_permit() {
+        use super::runes_utxo_csv;
+        use crate::config::DBConfig;
+        use crate::db::{connect_postgres_db, scratch_db_config};
+        use std::{sync::Arc, time::Duration};
+
+        let Some(cfg) = scratch_db_config() else {
+            return;
+        };
+        let db = Arc::new(
+            connect_postgres_db(DBConfig {
+                bulk_permits: Some(1),
+                ..cfg
+            })
+            .await
+            .unwrap(),
+        );
+
+        let permit = db.bulk_permit().await;
+        let body = runes_utxo_csv(db.clone(), "NOSUCHRUNEEXPORT").into_body();
+        let blocked =
+            tokio::time::timeout(Duration::from_millis(200), actix_web::body::to_bytes(body)).await;
+        assert!(blocked.is_err(), "the export didn't wait for the permit");
+
+        drop(permit);
+        let body = runes_utxo_csv(db.clone(), "NOSUCHRUNEEXPORT").into_body();
+        let csv = tokio::time::timeout(Duration::from_secs(5), actix_web::body::to_bytes(body))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(csv, "address,txid,vout,amount,btc_amount,block\n");
+    }
+}
@@ -0,0 +1,238 @@
+use std::future::{ready, Ready};
+use std::time::Instant;
+
+use actix_web::{
+    dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform},
+    http::header::{HeaderName, HeaderValue},
+    Error, HttpMessage,
+};
+use futures::future::LocalBoxFuture;
+
+use crate::service::tip_cache::TipCache;
+
+pub const INDEX_LAG_HEADER: &str = "x-index-lag";
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+tokio::task_local! {
+    static REQUEST_ID: String;
+}
+
+/// id of the request being handled, `None` outside of `RequestLogger`
+pub fn current_request_id() -> Option<String> {
+    REQUEST_ID.try_with(|id| id.clone()).ok()
+}
+
+/// appends the current request id to an error reason
+pub fn with_request_id(reason: Option<String>) -> Option<String> {
+    let Some(id) = current_request_id() else {
+        return reason;
+    };
+    match reason {
+        Some(r) => Some(format!("{} (request_id={})", r, id)),
+        None => Some(format!("request_id={}", id)),
+    }
+}
+
+/// Request id of the current request, also available as `web::ReqData<RequestId>`.
+#[derive(Debug, Clone)]
+pub struct RequestId(pub String);
+
+/// Adds `X-Index-Lag` with the number of blocks the index is behind the node tip,
+/// so clients can tell that the returned data may be incomplete during sync.
+pub struct IndexLagHeader {
+    tip: TipCache,
+}
+
+impl IndexLagHeader {
+    pub fn new(tip: TipCache) -> Self {
+        Self { tip }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for IndexLagHeader
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = IndexLagHeaderMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(IndexLagHeaderMiddleware {
+            service,
+            tip: self.tip.clone(),
+        }))
+    }
+}
+
+pub struct IndexLagHeaderMiddleware<S> {
+    service: S,
+    tip: TipCache,
+}
+
+impl<S, B> Service<ServiceRequest> for IndexLagHeaderMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let tip = self.tip.clone();
+        let fut = self.service.call(req);
+
+        Box::pin(async move {
+            let mut res = fut.await?;
+            if let Some(lag) = tip.index_lag() {
+                res.headers_mut().insert(
+                    HeaderName::from_static(INDEX_LAG_HEADER),
+                    HeaderValue::from(lag),
+                );
+            }
+            Ok(res)
+        })
+    }
+}
+
+/// Logs every request with its id, method, path, status and duration,
+/// and returns the id in `X-Request-Id`. A valid incoming `X-Request-Id` is reused.
+pub struct RequestLogger {
+    level: Option<log::Level>,
+}
+
+impl RequestLogger {
+    /// `None` level disables the log lines, ids are still assigned
+    pub fn new(level: Option<log::Level>) -> Self {
+        Self { level }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for RequestLogger
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = RequestLoggerMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RequestLoggerMiddleware {
+            service,
+            level: self.level,
+        }))
+    }
+}
+
+pub struct RequestLoggerMiddleware<S> {
+    service: S,
+    level: Option<log::Level>,
+}
+
+impl<S, B> Service<ServiceRequest> for RequestLoggerMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let request_id = req
+            .headers()
+            .get(REQUEST_ID_HEADER)
+            .and_then(|v| v.to_str().ok())
+            .filter(|v| is_valid_request_id(v))
+            .map(|v| v.to_owned())
+            .unwrap_or_else(|| ulid::Ulid::new().to_string());
+
+        let level = self.level;
+        let method = req.method().to_string();
+        let path = req.path().to_owned();
+        let started = Instant::now();
+
+        req.extensions_mut().insert(RequestId(request_id.clone()));
+        let fut = self.service.call(req);
+
+        Box::pin(REQUEST_ID.scope(request_id.clone(), async move {
+            let res = fut.await;
+            let status = match &res {
+                Ok(r) => r.status().as_u16(),
+                Err(e) => e.as_response_error().status_code().as_u16(),
+            };
+            if let Some(level) = level {
+                log!(
+                    level,
+                    "request_id={} method={} path={} status={} duration_ms={}",
+                    request_id,
+                    method,
+                    path,
+                    status,
+                    started.elapsed().as_millis()
+                );
+            }
+
+            let mut res = res?;
+            if let Ok(value) = HeaderValue::from_str(&request_id) {
+                res.headers_mut()
+                    .insert(HeaderName::from_static(REQUEST_ID_HEADER), value);
+            }
+            Ok(res)
+        }))
+    }
+}
+
+fn is_valid_request_id(id: &str) -> bool {
+    !id.is_empty()
+        && id.len() <= 64
+        && id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn request_id_is_added_to_error_reason() {
+        use super::{with_request_id, REQUEST_ID};
+
+        assert_eq!(
+            with_request_id(Some("bad".to_owned())),
+            Some("bad".to_owned())
+        );
+
+        REQUEST_ID.sync_scope("01HRQ".to_owned(), || {
+            assert_eq!(
+                with_request_id(Some("bad".to_owned())),
+                Some("bad (request_id=01HRQ)".to_owned())
+            );
+            assert_eq!(with_request_id(None), Some("request_id=01HRQ".to_owned()));
+        });
+    }
+
+    #[test]
+    fn incoming_request_id_is_validated() {
+        use super::is_valid_request_id;
+
+        assert!(is_valid_request_id("01HRQ5J6X3-abc_1"));
+        assert!(!is_valid_request_id(""));
+        assert!(!is_valid_request_id("id with spaces"));
+        assert!(!is_valid_request_id(&"a".repeat(65)));
+    }
+}
@@ -1,8 +1,13 @@
+use actix_web::HttpResponse;
 use serde::{Deserialize, Serialize};
+use std::future::Future;
+use std::time::Duration;
 
 pub mod admin_api;
 pub mod api;
 pub mod errors;
+pub mod export;
+pub mod middleware;
 pub mod server;
 
 mod api_pools;
@@ -19,12 +24,64 @@ pub struct ListResponseMeta {
     pub total_records: i64,
 }
 
+impl ListResponseMeta {
+    /// `page` starts at 1, `returned` is the number of records on this page
+    pub fn new(page: i32, limit: i32, returned: usize, total_records: i64) -> Self {
+        let offset = (page - 1).saturating_mul(limit);
+        Self {
+            page,
+            limit,
+            offset,
+            has_more: (offset as i64 + returned as i64) < total_records,
+            total_records,
+        }
+    }
+}
+
 #[derive(Clone, Serialize)]
 pub struct ListResult<T: Serialize> {
     pub meta: Option<ListResponseMeta>,
     pub records: Vec<T>,
 }
 
+impl<T: Serialize> ListResult<T> {
+    /// Runs the count and the page queries and fills in the meta, `page` starts at 1.
+    /// A page past the `i32` offsets is a bad request, a failed query answers `error`.
+    pub async fn paginate<C, F, Fut>(
+        page: i32,
+        limit: i32,
+        count: C,
+        fetch: F,
+        error: &str,
+    ) -> Result<Self, HttpResponse>
+    where
+        C: Future<Output = sqlx::Result<i64>>,
+        F: FnOnce(i32, i32) -> Fut,
+        Fut: Future<Output = sqlx::Result<Vec<T>>>,
+    {
+        let page = page.max(1);
+        let limit = limit.max(1);
+        let offset = page_offset(page, limit)?;
+
+        let failed = |e: sqlx::Error| {
+            error!("request failed error={}", e);
+            errors::internal_error(error)
+        };
+        let total_records = count.await.map_err(failed)?;
+        let records = fetch(limit, offset).await.map_err(failed)?;
+
+        Ok(ListResult {
+            meta: Some(ListResponseMeta::new(
+                page,
+                limit,
+                records.len(),
+                total_records,
+            )),
+            records,
+        })
+    }
+}
+
 impl<T: Serialize> From<Vec<T>> for ListResult<T> {
     fn from(val: Vec<T>) -> Self {
         ListResult {
@@ -34,12 +91,56 @@ impl<T: Serialize> From<Vec<T>> for ListResult<T> {
     }
 }
 
+/// offset of the 1-based `page`, rejected when it doesn't fit the `i32` of the queries
+pub fn page_offset(page: i32, limit: i32) -> Result<i32, HttpResponse> {
+    (page.max(1) - 1).checked_mul(limit).ok_or_else(|| {
+        errors::bad_request(
+            "page is out of range",
+            Some(format!("max page is {}", i32::MAX / limit.max(1) + 1)),
+        )
+    })
+}
+
+/// `limit` of a list request, values above `max` are rejected instead of clamped
+pub fn page_limit(limit: Option<i32>, default: i32, max: i32) -> Result<i32, HttpResponse> {
+    let limit = limit.unwrap_or(default.min(max));
+    if limit > max {
+        return Err(errors::bad_request(
+            "limit exceeds the maximum page size",
+            Some(format!("max limit is {}", max)),
+        ));
+    }
+    Ok(limit.max(1))
+}
+
+/// Runs a request with a deadline, a request over it is dropped and answered with 504.
+/// `None` disables it. Dropping stops waiting only: postgres keeps running a query already
+/// sent until `db.statement_timeout` cancels it, so that one should not be longer.
+pub async fn with_timeout<T, F>(timeout: Option<Duration>, request: F) -> Result<T, HttpResponse>
+where
+    F: Future<Output = Result<T, HttpResponse>>,
+{
+    let Some(timeout) = timeout else {
+        return request.await;
+    };
+
+    match tokio::time::timeout(timeout, request).await {
+        Ok(res) => res,
+        Err(_) => {
+            warn!("request cancelled by its timeout={:?}", timeout);
+            Err(errors::request_timeout(timeout))
+        }
+    }
+}
+
 #[derive(Clone, Default, Deserialize)]
 pub struct PageParams {
     pub order: Option<String>,
     pub limit: Option<i32>,
     pub page: Option<i32>,
     pub name: Option<String>,
+    /// skip runes without circulating supply
+    pub active_only: Option<bool>,
 }
 
 impl PageParams {
@@ -50,10 +151,94 @@ impl PageParams {
             .unwrap_or("ASC".to_string())
             .to_uppercase();
 
-        if &o == "ASC" || &o == "DESC" {
+        if &o == "ASC" || &o == "DESC" || &o == "ACTIVITY" {
             o
         } else {
             "ASC".to_string()
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn list_meta_has_more_boundary() {
+        use super::ListResponseMeta;
+
+        // 2 full pages of 10 out of 20 records
+        let meta = ListResponseMeta::new(1, 10, 10, 20);
+        assert_eq!(meta.offset, 0);
+        assert!(meta.has_more);
+
+        let meta = ListResponseMeta::new(2, 10, 10, 20);
+        assert_eq!(meta.offset, 10);
+        assert!(!meta.has_more);
+
+        let meta = ListResponseMeta::new(2, 10, 10, 21);
+        assert!(meta.has_more);
+
+        let meta = ListResponseMeta::new(3, 10, 0, 20);
+        assert!(!meta.has_more);
+    }
+
+    #[tokio::test]
+    async fn paginate_passes_limit_and_offset() {
+        use super::ListResult;
+
+        let all: Vec<i32> = (0..25).collect();
+        let fetch = |limit: i32, offset: i32| {
+            let page = all
+                .iter()
+                .skip(offset as usize)
+                .take(limit as usize)
+                .copied()
+                .collect();
+            async move { Ok(page) }
+        };
+        let res = ListResult::paginate(3, 10, async { Ok(all.len() as i64) }, fetch, "failed")
+            .await
+            .unwrap();
+
+        assert_eq!(res.records, vec![20, 21, 22, 23, 24]);
+        let meta = res.meta.unwrap();
+        assert_eq!((meta.page, meta.offset, meta.total_records), (3, 20, 25));
+        assert!(!meta.has_more);
+
+        // the offset of a huge page doesn't fit in i32
+        let err = ListResult::paginate(i32::MAX, 500, async { Ok(0) }, fetch, "failed")
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(err.status(), actix_web::http::StatusCode::BAD_REQUEST);
+        assert_eq!(super::page_offset(4_294_968, 500).unwrap(), 2_147_483_500);
+        assert!(super::page_offset(4_294_969, 500).is_err());
+    }
+
+    #[tokio::test]
+    async fn slow_request_times_out() {
+        use super::with_timeout;
+        use actix_web::{http::StatusCode, HttpResponse};
+        use std::time::Duration;
+
+        let slow_query = || async {
+            tokio::time::sleep(Duration::from_millis(500)).await;
+            Ok::<_, HttpResponse>(42)
+        };
+
+        let err = with_timeout(Some(Duration::from_millis(10)), slow_query())
+            .await
+            .unwrap_err();
+        assert_eq!(err.status(), StatusCode::GATEWAY_TIMEOUT);
+
+        let res = with_timeout(Some(Duration::from_secs(5)), slow_query()).await;
+        assert_eq!(res.unwrap(), 42);
+        assert_eq!(with_timeout(None, slow_query()).await.unwrap(), 42);
+
+        // errors of the request itself pass through
+        let failing = async { Err::<i32, _>(super::errors::bad_request("invalid", None)) };
+        let err = with_timeout(Some(Duration::from_secs(5)), failing)
+            .await
+            .unwrap_err();
+        assert_eq!(err.status(), StatusCode::BAD_REQUEST);
+    }
+}
@@ -1,16 +1,121 @@
 use actix_web::HttpResponse;
 use bitcoin::address::NetworkChecked;
 use bitcoin::{Address, Network};
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::str::FromStr;
 use std::sync::Arc;
+use std::time::Duration;
 
-use super::errors;
-use crate::{db::Repo, serde_utils::number_from_string, service::entities};
+use super::{errors, page_limit, with_timeout, ListResponseMeta, ListResult, PageParams};
+use crate::{
+    btc_utxo::UtxoClient,
+    db::{self, Repo},
+    indexer,
+    serde_utils::{number_from_string, option_number_from_string},
+    service::{
+        entities,
+        tip_cache::TipCache,
+        top_runes_cache::TopRunesCache,
+        tx_watchdog::{resolve_liquidity_request, AlreadyResolved},
+    },
+    tx::{
+        fee::FeePriority,
+        pool_txs::{
+            select_pending_spends, InputOpts, InsufficientFunds, OutputOpts, PoolTxBuilder,
+            RunestoneTooLarge, TxParams,
+        },
+    },
+    utils::format_amount,
+};
 
 #[derive(Deserialize)]
 pub struct SearchQuery {
     pub s: String,
+    pub limit: Option<i32>,
+}
+
+impl SearchQuery {
+    pub const DEFAULT_LIMIT: i32 = 50;
+    pub const MAX_LIMIT: i32 = 200;
+
+    pub fn get_limit(&self) -> i32 {
+        self.limit
+            .unwrap_or(Self::DEFAULT_LIMIT)
+            .clamp(1, Self::MAX_LIMIT)
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct TopRunesQuery {
+    /// `holders` (default), `supply` or `mints`
+    pub by: Option<String>,
+    pub limit: Option<i32>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TopRune {
+    pub rank: usize,
+    #[serde(flatten)]
+    pub rune: entities::RuneEntity,
+    /// set only when ranked by holders
+    pub holders: Option<i64>,
+}
+
+impl TopRunesQuery {
+    pub const DEFAULT_LIMIT: i32 = 20;
+    pub const MAX_LIMIT: i32 = 100;
+    const RANKINGS: [&'static str; 3] = ["holders", "supply", "mints"];
+
+    pub fn get_limit(&self) -> i32 {
+        self.limit
+            .unwrap_or(Self::DEFAULT_LIMIT)
+            .clamp(1, Self::MAX_LIMIT)
+    }
+
+    pub fn ranking(&self) -> Result<&'static str, HttpResponse> {
+        let by = self.by.as_deref().unwrap_or("holders");
+        Self::RANKINGS
+            .iter()
+            .find(|r| **r == by)
+            .copied()
+            .ok_or_else(|| {
+                errors::bad_request(
+                    "unknown ranking",
+                    Some(format!("by must be one of {}", Self::RANKINGS.join(", "))),
+                )
+            })
+    }
+
+    /// `GET /runes/top?by=holders|supply|mints&limit=N`: the explorer leaderboard,
+    /// `min_holder_balance` is `api.min_holder_balance`. The `MAX_LIMIT` runes of each
+    /// ranking are cached for `top_runes_cache::MAX_AGE` and cut to the limit.
+    pub async fn fetch_top(
+        &self,
+        db: &Arc<Repo>,
+        cache: &TopRunesCache,
+        min_holder_balance: u64,
+    ) -> Result<Vec<TopRune>, HttpResponse> {
+        let by = self.ranking()?;
+        let rows = cache
+            .get(db, by, Self::MAX_LIMIT, &min_holder_balance.to_string())
+            .await
+            .map_err(|e| {
+                error!("request failed error={}", e);
+                errors::internal_error("can't fetch top runes")
+            })?;
+
+        Ok(rows
+            .iter()
+            .take(self.get_limit() as usize)
+            .enumerate()
+            .map(|(i, row)| TopRune {
+                rank: i + 1,
+                rune: entities::RuneEntity::from(&row.rune),
+                holders: row.holders,
+            })
+            .collect())
+    }
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -19,11 +124,75 @@ pub struct PairRequest {
     pub quote: String,
 }
 
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct PoolMetrics {
+    pub base: String,
+    pub quote: String,
+    /// base and quote balances valued in sats at the current pool price, see `TradingPair::tvl`
+    #[serde(with = "number_from_string")]
+    pub tvl: u128,
+    /// quote amount of the swaps completed during the last 24h, in sats
+    pub volume_24h: Option<String>,
+    /// swap fees collected during the last 24h, null until fees are recorded
+    pub fee_revenue_24h: Option<String>,
+    pub prices: Option<entities::PairPrices>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct PriceQuery {
+    /// `number` or `string`, `api.prices` if not set
+    pub prices: Option<String>,
+}
+
+impl PriceQuery {
+    /// whether prices are returned as decimal strings
+    pub fn string_prices(&self, default: bool) -> Result<bool, HttpResponse> {
+        match self.prices.as_deref() {
+            None => Ok(default),
+            Some("number") => Ok(false),
+            Some("string") => Ok(true),
+            Some(_) => Err(errors::bad_request(
+                "invalid prices format",
+                Some("expected number or string".to_owned()),
+            )),
+        }
+    }
+}
+
 impl PairRequest {
+    pub async fn fetch_metrics(
+        &self,
+        db: &Arc<Repo>,
+        string_prices: bool,
+    ) -> Result<PoolMetrics, HttpResponse> {
+        let pair = self.fetch_pair(db).await?;
+
+        let since = chrono::Utc::now().timestamp() - 24 * 3600;
+        let volume_24h = match db.sum_swap_volume(pair.id, since).await {
+            Ok(v) => Some(v),
+            Err(e) => {
+                error!("can't calculate swap volume pair={} error={}", pair.id, e);
+                None
+            }
+        };
+
+        Ok(PoolMetrics {
+            base: self.base.clone(),
+            quote: self.quote.clone(),
+            tvl: pair.tvl(),
+            volume_24h,
+            fee_revenue_24h: None,
+            prices: Some(pair.prices(string_prices)),
+        })
+    }
+
+    /// the base asset metadata is read from the runes table on every request,
+    /// unit prices must not use the divisibility of a stale pair entry
     pub async fn fetch_pair(&self, db: &Arc<Repo>) -> Result<entities::TradingPair, HttpResponse> {
         match db.get_trading_pair(&self.base, &self.quote).await {
             Ok(p) => {
-                let rune = match db.get_rune(&p.base_asset).await {
+                let name = entities::TradingPair::base_rune_name(&p);
+                let rune = match db.get_rune(&name).await {
                     Ok(p) => p,
                     Err(e) => match e {
                         sqlx::Error::RowNotFound => return Err(errors::ApiError::NotFound.into()),
@@ -50,30 +219,1904 @@ impl PairRequest {
         }
     }
 }
-#[derive(Deserialize)]
-pub struct UtxoRequest {
-    pub asset: String,
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct AnomaliesQuery {
+    pub limit: Option<i32>,
+}
+
+impl AnomaliesQuery {
+    pub const MAX_LIMIT: i32 = 500;
+
+    pub async fn fetch_anomalies(
+        &self,
+        db: &Arc<Repo>,
+    ) -> Result<Vec<crate::db::IndexerAnomaly>, HttpResponse> {
+        let limit = self.limit.unwrap_or(100).clamp(1, Self::MAX_LIMIT);
+        db.select_indexer_anomalies(limit).await.map_err(|e| {
+            error!("request failed error={}", e);
+            errors::internal_error("can't fetch indexer anomalies")
+        })
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct BlockRangeRequest {
+    pub from: i64,
+    pub to: i64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BlockRangeRunes {
+    pub from: i64,
+    pub to: i64,
+    pub etched: Vec<entities::RuneEntity>,
+    pub activity: Vec<crate::db::RuneBlockActivity>,
+}
+
+impl BlockRangeRequest {
+    pub const MAX_SPAN: i64 = 1000;
+
+    pub async fn fetch_runes(&self, db: &Arc<Repo>) -> Result<BlockRangeRunes, HttpResponse> {
+        if self.from < 0 || self.to < self.from {
+            return Err(errors::bad_request("invalid block range", None));
+        }
+        if self.to - self.from >= Self::MAX_SPAN {
+            return Err(errors::bad_request(
+                "block range is too wide",
+                Some(format!("max span is {} blocks", Self::MAX_SPAN)),
+            ));
+        }
+
+        let etched = db
+            .select_runes_in_block_range(self.from, self.to)
+            .await
+            .map_err(|e| {
+                error!("request failed error={}", e);
+                errors::internal_error("can't fetch runes")
+            })?;
+        let activity = db
+            .select_rune_activity_in_block_range(self.from, self.to)
+            .await
+            .map_err(|e| {
+                error!("request failed error={}", e);
+                errors::internal_error("can't fetch rune activity")
+            })?;
+
+        Ok(BlockRangeRunes {
+            from: self.from,
+            to: self.to,
+            etched: etched.iter().map(entities::RuneEntity::from).collect(),
+            activity,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct BlockRequest {
+    pub height: i64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BlockInfo {
+    pub height: i64,
+    /// the hash the runes index followed, the btc indexer one if only it processed the height
+    pub block_hash: String,
+    pub indexers: Vec<crate::db::IndexedBlock>,
+}
+
+impl BlockRequest {
+    /// `GET /blocks/{height}`: the hash of the block each indexer processed at `height`
+    /// with its stats, clients compare it with their chain to detect reorgs.
+    /// 404 until an indexer processed the height, also for heights indexed before
+    /// the hashes were recorded
+    pub async fn fetch_block(&self, db: &Arc<Repo>) -> Result<BlockInfo, HttpResponse> {
+        if self.height < 0 {
+            return Err(errors::bad_request("height must not be negative", None));
+        }
+
+        let rows = db.select_indexed_blocks(self.height).await.map_err(|e| {
+            error!("request failed error={}", e);
+            errors::internal_error("can't fetch indexed block")
+        })?;
+        block_info(self.height, rows).ok_or_else(|| errors::ApiError::NotFound.into())
+    }
+}
+
+fn block_info(height: i64, indexers: Vec<crate::db::IndexedBlock>) -> Option<BlockInfo> {
+    let block_hash = indexers
+        .iter()
+        .find(|b| b.indexer == indexer::ETCHING_INDEXER_ID)
+        .or(indexers.first())?
+        .block_hash
+        .clone();
+    Some(BlockInfo {
+        height,
+        block_hash,
+        indexers,
+    })
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct TxRunesRequest {
+    pub txid: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct RuneAmount {
     pub address: String,
+    pub amount: String,
 }
 
-#[derive(Debug, Deserialize)]
-pub struct PairPositionRequest {
-    pub base: String,
-    pub quote: String,
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct TxRuneMint {
+    pub rune: String,
+    pub address: String,
+    pub amount: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct TxRuneTransfer {
+    pub rune: String,
+    pub from: Vec<RuneAmount>,
+    pub to: Vec<RuneAmount>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct TxRunes {
+    pub txid: String,
+    pub etched: Vec<entities::RuneEntity>,
+    pub mints: Vec<TxRuneMint>,
+    pub transfers: Vec<TxRuneTransfer>,
+}
+
+impl TxRunesRequest {
+    fn validate(&self, rune_log_enabled: bool) -> Result<(), HttpResponse> {
+        if !rune_log_enabled {
+            return Err(errors::rune_log_disabled());
+        }
+        if bitcoin::Txid::from_str(&self.txid).is_err() {
+            return Err(errors::bad_request("invalid txid", None));
+        }
+        Ok(())
+    }
+
+    /// `GET /tx/{tx_hash}/log`: all runes log entries of the tx in insertion order,
+    /// empty for txs which didn't touch runes
+    pub async fn fetch_tx_log(
+        &self,
+        db: &Arc<Repo>,
+        rune_log_enabled: bool,
+    ) -> Result<Vec<crate::db::RuneLog>, HttpResponse> {
+        self.validate(rune_log_enabled)?;
+
+        db.select_rune_log_by_tx(&self.txid).await.map_err(|e| {
+            error!("request failed error={}", e);
+            errors::internal_error("can't fetch rune log")
+        })
+    }
+
+    pub async fn fetch_tx_runes(
+        &self,
+        db: &Arc<Repo>,
+        rune_log_enabled: bool,
+    ) -> Result<TxRunes, HttpResponse> {
+        self.validate(rune_log_enabled)?;
+
+        let etched = db
+            .select_runes_etched_in_tx(&self.txid)
+            .await
+            .map_err(|e| {
+                error!("request failed error={}", e);
+                errors::internal_error("can't fetch runes")
+            })?;
+        let log = db.select_rune_log_by_tx(&self.txid).await.map_err(|e| {
+            error!("request failed error={}", e);
+            errors::internal_error("can't fetch rune log")
+        })?;
+
+        let mut res = TxRunes {
+            txid: self.txid.clone(),
+            etched: etched.iter().map(entities::RuneEntity::from).collect(),
+            ..Default::default()
+        };
+
+        for entry in log.iter() {
+            let action = entry.action.as_str();
+            if action == crate::db::RuneLog::MINT {
+                res.mints.push(TxRuneMint {
+                    rune: entry.rune.clone(),
+                    address: entry.address.clone(),
+                    amount: entry.value.clone(),
+                });
+                continue;
+            }
+            if action != crate::db::RuneLog::INCOME && action != crate::db::RuneLog::EXPENCE {
+                continue;
+            }
+
+            let idx = match res.transfers.iter().position(|t| t.rune == entry.rune) {
+                Some(i) => i,
+                None => {
+                    res.transfers.push(TxRuneTransfer {
+                        rune: entry.rune.clone(),
+                        ..Default::default()
+                    });
+                    res.transfers.len() - 1
+                }
+            };
+
+            let amount = RuneAmount {
+                address: entry.address.clone(),
+                amount: entry.value.clone(),
+            };
+            if action == crate::db::RuneLog::INCOME {
+                res.transfers[idx].to.push(amount);
+            } else {
+                res.transfers[idx].from.push(amount);
+            }
+        }
+
+        Ok(res)
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct ActivityQuery {
+    pub limit: Option<i32>,
+    pub page: Option<i32>,
+    pub action: Option<String>,
+}
+
+impl ActivityQuery {
+    pub const MAX_LIMIT: i32 = 500;
+    const ACTIONS: [&'static str; 5] = [
+        crate::db::RuneLog::ETCHING,
+        crate::db::RuneLog::MINT,
+        crate::db::RuneLog::INCOME,
+        crate::db::RuneLog::EXPENCE,
+        crate::db::RuneLog::BURN,
+    ];
+
+    pub async fn fetch_activity(
+        &self,
+        db: &Arc<Repo>,
+        rune_log_enabled: bool,
+    ) -> Result<ListResult<crate::db::RuneLog>, HttpResponse> {
+        if !rune_log_enabled {
+            return Err(errors::rune_log_disabled());
+        }
+        let action = self.action()?;
+
+        let limit = self.limit.unwrap_or(50).clamp(1, Self::MAX_LIMIT);
+        let page = self.page.unwrap_or(1);
+
+        ListResult::paginate(
+            page,
+            limit,
+            db.count_global_rune_log(action.clone()),
+            |limit, offset| db.select_global_rune_log(action, limit, offset),
+            "can't fetch rune activity",
+        )
+        .await
+    }
+
+    /// the lowercased `?action=` filter, one of `ACTIONS`
+    fn action(&self) -> Result<Option<String>, HttpResponse> {
+        let Some(a) = &self.action else {
+            return Ok(None);
+        };
+        let a = a.to_lowercase();
+        if !Self::ACTIONS.contains(&a.as_str()) {
+            return Err(errors::bad_request(
+                "unknown action",
+                Some(format!("expected one of {:?}", Self::ACTIONS)),
+            ));
+        }
+        Ok(Some(a))
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct RuneListItem {
+    #[serde(flatten)]
+    pub rune: entities::RuneEntity,
+    /// false once the whole circulating supply is burned
+    pub is_active: bool,
+}
+
+impl From<&db::Rune> for RuneListItem {
+    fn from(row: &db::Rune) -> Self {
+        let rune = entities::RuneEntity::from(row);
+        Self {
+            is_active: rune.is_active(),
+            rune,
+        }
+    }
+}
+
+impl PageParams {
+    pub const DEFAULT_LIMIT: i32 = 50;
+    pub const MAX_LIMIT: i32 = 500;
+
+    /// runes list, `?active_only=true` skips runes without circulating supply
+    pub async fn fetch_runes(
+        &self,
+        db: &Arc<Repo>,
+    ) -> Result<ListResult<RuneListItem>, HttpResponse> {
+        let order = self.get_order();
+        let limit = self
+            .limit
+            .unwrap_or(Self::DEFAULT_LIMIT)
+            .clamp(1, Self::MAX_LIMIT);
+        let page = self.page.unwrap_or(1);
+        let active_only = self.active_only.unwrap_or(false);
+
+        ListResult::paginate(
+            page,
+            limit,
+            db.count_runes_filtered(self.name.clone(), active_only),
+            |limit, offset| async move {
+                let runes = db
+                    .list_runes_filtered(&order, limit, offset, self.name.clone(), active_only)
+                    .await?;
+                Ok(runes.iter().map(RuneListItem::from).collect())
+            },
+            "can't fetch runes",
+        )
+        .await
+    }
+
+    /// trading pairs list, `?name=` filters the base asset. Like `PairRequest::fetch_pair`
+    /// the base asset metadata comes from the runes table.
+    pub async fn fetch_pairs(
+        &self,
+        db: &Arc<Repo>,
+    ) -> Result<ListResult<entities::TradingPair>, HttpResponse> {
+        let order = self.get_order();
+        let limit = self
+            .limit
+            .unwrap_or(Self::DEFAULT_LIMIT)
+            .clamp(1, Self::MAX_LIMIT);
+        let page = self.page.unwrap_or(1);
+
+        ListResult::paginate(
+            page,
+            limit,
+            db.count_trading_pair(self.name.clone()),
+            |limit, offset| async move {
+                let pairs = db
+                    .select_trading_pairs(&order, limit, offset, self.name.clone())
+                    .await?;
+                let mut records = Vec::with_capacity(pairs.len());
+                for pair in pairs.iter() {
+                    let name = entities::TradingPair::base_rune_name(pair);
+                    let rune = db.get_rune(&name).await?;
+                    records.push(entities::TradingPair::new(pair, &rune));
+                }
+                Ok(records)
+            },
+            "can't fetch pairs",
+        )
+        .await
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct CommitmentAddressRequest {
+    pub rune: String,
+    /// x-only public key of the key which signs the etching tx, hex encoded
+    pub pubkey: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CommitmentAddress {
+    pub rune: String,
+    pub commit_tx_address: String,
+    pub reveal_script: String,
+}
+
+impl CommitmentAddressRequest {
+    /// commitment address of a planned etching, the name must be unlocked at the next block
+    pub fn commitment_address(
+        &self,
+        net: Network,
+        tip_height: Option<i64>,
+    ) -> Result<CommitmentAddress, HttpResponse> {
+        let spaced = ordinals::SpacedRune::from_str(&self.rune)
+            .map_err(|e| errors::bad_request("invalid rune name", Some(e.to_string())))?;
+        let pubkey = bitcoin::secp256k1::XOnlyPublicKey::from_str(&self.pubkey)
+            .map_err(|e| errors::bad_request("invalid pubkey", Some(e.to_string())))?;
+
+        let next_block = known_tip(tip_height)? + 1;
+        if !indexer::is_valid_etched_name(net, next_block, spaced.rune) {
+            return Err(errors::bad_request(
+                "rune name is not available",
+                Some(format!(
+                    "names below {} or reserved are locked at block {}",
+                    indexer::minimum_etchable_rune(net, next_block),
+                    next_block
+                )),
+            ));
+        }
+
+        let (address, reveal_script, _) =
+            crate::tx::runes_txs::commitment_address(net, pubkey, spaced.rune);
+        Ok(CommitmentAddress {
+            rune: spaced.rune.to_string(),
+            commit_tx_address: address.to_string(),
+            reveal_script: reveal_script.to_hex_string(),
+        })
+    }
+}
+
+/// the cached node tip, unknown until the tip refresher's first round
+fn known_tip(tip_height: Option<i64>) -> Result<i64, HttpResponse> {
+    tip_height.ok_or_else(errors::tip_unknown)
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct NameAvailabilityQuery {
+    pub name: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct NameAvailability {
+    pub rune: String,
+    pub spaced_rune: String,
+    pub exists: bool,
+    pub reserved: bool,
+    pub below_minimum: bool,
+    pub available: bool,
+    pub next_block: i64,
+    pub minimum_rune: String,
+}
+
+impl NameAvailabilityQuery {
+    /// `tip_height` is the node tip, the name is checked for an etching in the next block
+    pub async fn check(
+        &self,
+        db: &Arc<Repo>,
+        net: Network,
+        tip_height: Option<i64>,
+    ) -> Result<NameAvailability, HttpResponse> {
+        let spaced = ordinals::SpacedRune::from_str(self.name.trim())
+            .map_err(|e| errors::bad_request("invalid rune name", Some(e.to_string())))?;
+        let tip_height = known_tip(tip_height)?;
+
+        let rune = spaced.rune.to_string();
+        let exists = match db.get_rune(&rune).await {
+            Ok(_) => true,
+            Err(sqlx::Error::RowNotFound) => false,
+            Err(e) => {
+                error!("request failed error={}", e);
+                return Err(errors::internal_error("can't fetch rune"));
+            }
+        };
+
+        Ok(NameAvailability::new(spaced, exists, net, tip_height + 1))
+    }
+}
+
+impl NameAvailability {
+    pub fn new(spaced: ordinals::SpacedRune, exists: bool, net: Network, next_block: i64) -> Self {
+        let minimum = indexer::minimum_etchable_rune(net, next_block);
+        let reserved = spaced.rune.is_reserved();
+        let below_minimum = spaced.rune < minimum;
+
+        Self {
+            rune: spaced.rune.to_string(),
+            spaced_rune: spaced.to_string(),
+            exists,
+            reserved,
+            below_minimum,
+            available: !exists && indexer::is_valid_etched_name(net, next_block, spaced.rune),
+            next_block,
+            minimum_rune: minimum.to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct RawTxRequest {
+    pub tx_hash: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RawTxOutput {
+    pub value: u64,
+    pub script_pubkey: String,
+    pub address: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RawTxSummary {
+    pub txid: String,
+    pub version: i32,
+    pub lock_time: u32,
+    pub vsize: usize,
+    pub inputs: Vec<String>,
+    pub outputs: Vec<RawTxOutput>,
+}
+
+impl RawTxSummary {
+    pub fn decode(raw_hex: &str, net: Network) -> anyhow::Result<Self> {
+        use bitcoin::hashes::hex::FromHex;
+
+        let bytes = Vec::<u8>::from_hex(raw_hex)?;
+        let tx: bitcoin::Transaction = bitcoin::consensus::deserialize(&bytes)?;
+
+        Ok(Self {
+            txid: tx.txid().to_string(),
+            version: tx.version,
+            lock_time: tx.lock_time.to_consensus_u32(),
+            vsize: tx.vsize(),
+            inputs: tx
+                .input
+                .iter()
+                .map(|i| i.previous_output.to_string())
+                .collect(),
+            outputs: tx
+                .output
+                .iter()
+                .map(|o| RawTxOutput {
+                    value: o.value,
+                    script_pubkey: o.script_pubkey.to_hex_string(),
+                    address: Address::from_script(&o.script_pubkey, net)
+                        .ok()
+                        .map(|a| a.to_string()),
+                })
+                .collect(),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct TxPreviewRequest {
+    /// signed raw tx
+    pub tx_hex: String,
+    /// expected position in the block, names an unnamed etching, default is 1
+    pub tx_index: Option<u32>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BalanceDelta {
     pub address: String,
+    /// rune name or `BTC`
+    pub asset: String,
+    /// signed amount in base units or sats
+    pub delta: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BurnedRune {
+    pub rune: String,
+    #[serde(with = "number_from_string")]
+    pub amount: u128,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TxPreview {
+    pub txid: String,
+    pub deltas: Vec<BalanceDelta>,
+    pub burned: Vec<BurnedRune>,
+    /// set when the indexer would burn all the rune inputs, e.g. for a cenotaph
+    pub burn_reason: Option<String>,
+    /// the etched rune, its name and commitment aren't validated by the preview
+    pub etching: Option<String>,
+    /// inputs the index doesn't know, e.g. btc of unwatched addresses, they aren't in the deltas
+    pub unknown_inputs: Vec<String>,
+    /// inputs already spent according to the index, the tx can't be mined as is
+    pub spent_inputs: Vec<String>,
+}
+
+impl TxPreviewRequest {
+    pub const BTC: &'static str = "BTC";
+
+    /// `POST /tx/preview`: balance changes the tx would cause once indexed in the next block,
+    /// a dry run of the runes indexer which stores nothing. `tip_height` is the cached node tip.
+    pub async fn preview(
+        &self,
+        db: &Arc<Repo>,
+        net: Network,
+        indexers: &crate::config::IndexersConfig,
+        tip_height: Option<i64>,
+    ) -> Result<TxPreview, HttpResponse> {
+        use bitcoin::hashes::hex::FromHex;
+        use ordinals::{Artifact, RuneId, Runestone};
+
+        let internal = |e: sqlx::Error| {
+            error!("request failed error={}", e);
+            errors::internal_error("can't preview tx")
+        };
+
+        let tx: bitcoin::Transaction = Vec::<u8>::from_hex(&self.tx_hex)
+            .map_err(|e| e.to_string())
+            .and_then(|bytes| bitcoin::consensus::deserialize(&bytes).map_err(|e| e.to_string()))
+            .map_err(|e| errors::bad_request("invalid tx", Some(e)))?;
+
+        let mut deltas: BTreeMap<(String, String), i128> = BTreeMap::new();
+        let mut inputs: HashMap<String, u128> = HashMap::new();
+        let mut unknown_inputs = Vec::new();
+        let mut spent_inputs = Vec::new();
+        for input in tx.input.iter() {
+            let outpoint = input.previous_output;
+            let tx_hash = outpoint.txid.to_string();
+
+            let rune_utxos = db
+                .get_rune_utxos_by_outpoint(&tx_hash, outpoint.vout as i32)
+                .await
+                .map_err(internal)?;
+            for utxo in rune_utxos.iter().filter(|u| !u.spend) {
+                let utxo = entities::RuneUtxo::from(utxo);
+                *inputs.entry(utxo.rune.clone()).or_default() += utxo.amount;
+                *deltas.entry((utxo.address, utxo.rune)).or_default() -= utxo.amount as i128;
+            }
+
+            match db.get_btc_utxo(&tx_hash, outpoint.vout as i32).await {
+                Ok(utxo) if utxo.spend => spent_inputs.push(outpoint.to_string()),
+                Ok(utxo) => {
+                    *deltas
+                        .entry((utxo.address, Self::BTC.to_owned()))
+                        .or_default() -= utxo.amount as i128;
+                }
+                Err(sqlx::Error::RowNotFound) if rune_utxos.is_empty() => {
+                    unknown_inputs.push(outpoint.to_string())
+                }
+                Err(sqlx::Error::RowNotFound) => {}
+                Err(e) => return Err(internal(e)),
+            }
+        }
+
+        // names and mint amounts of the runes the runestone refers to
+        let mut runes: HashMap<RuneId, (String, Option<u128>)> = HashMap::new();
+        if let Some(Artifact::Runestone(runestone)) = Runestone::decipher(&tx) {
+            let ids = runestone.edicts.iter().map(|e| e.id).chain(runestone.mint);
+            for id in ids.filter(|id| *id != RuneId::default()) {
+                match db.get_rune_by_id(id.block as i64, id.tx as i32).await {
+                    Ok(row) => {
+                        let rune = entities::RuneEntity::from(&row);
+                        let mint_amount = rune.terms.map(|t| t.amount.unwrap_or_default());
+                        runes.insert(id, (rune.rune, mint_amount));
+                    }
+                    Err(sqlx::Error::RowNotFound) => {}
+                    Err(e) => return Err(internal(e)),
+                }
+            }
+        }
+
+        let height = tip_height.map(|h| h + 1).unwrap_or_default();
+        let tx_index = self.tx_index.unwrap_or(1);
+        let outcome =
+            indexer::simulate_runes(net, &tx, height, tx_index, indexers, &inputs, &runes);
+
+        for (out, runes) in tx.output.iter().zip(outcome.outputs.iter()) {
+            let Ok(address) = Address::from_script(&out.script_pubkey, net) else {
+                continue;
+            };
+            let address = address.to_string();
+            if out.value > 0 {
+                *deltas
+                    .entry((address.clone(), Self::BTC.to_owned()))
+                    .or_default() += out.value as i128;
+            }
+            for (rune, amount) in runes.iter() {
+                *deltas.entry((address.clone(), rune.clone())).or_default() += *amount as i128;
+            }
+        }
+
+        let mut burned: Vec<BurnedRune> = outcome
+            .burned
+            .into_iter()
+            .map(|(rune, amount)| BurnedRune { rune, amount })
+            .collect();
+        burned.sort_by(|a, b| a.rune.cmp(&b.rune));
+
+        Ok(TxPreview {
+            txid: tx.txid().to_string(),
+            deltas: deltas
+                .into_iter()
+                .filter(|(_, delta)| *delta != 0)
+                .map(|((address, asset), delta)| BalanceDelta {
+                    address,
+                    asset,
+                    delta: delta.to_string(),
+                })
+                .collect(),
+            burned,
+            burn_reason: outcome.burn_reason,
+            etching: outcome.etching,
+            unknown_inputs,
+            spent_inputs,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RawTx {
+    pub tx_hash: String,
+    pub status: String,
+    pub raw_data: String,
+    /// `None` if the stored hex can't be decoded
+    pub summary: Option<RawTxSummary>,
+}
+
+impl RawTxRequest {
+    /// raw hex of a tx submitted by the service, for rebroadcasts and support
+    pub async fn fetch_raw_tx(&self, db: &Arc<Repo>, net: Network) -> Result<RawTx, HttpResponse> {
+        let tx = db
+            .get_submitted_tx(&self.tx_hash)
+            .await
+            .map_err(|e| match e {
+                sqlx::Error::RowNotFound => errors::ApiError::NotFound.into(),
+                _ => {
+                    error!("request failed error={}", e);
+                    errors::internal_error("can't fetch submitted tx")
+                }
+            })?;
+
+        let summary = match RawTxSummary::decode(&tx.raw_data, net) {
+            Ok(s) => Some(s),
+            Err(e) => {
+                warn!(
+                    "can't decode submitted tx tx_hash={} error={}",
+                    tx.tx_hash, e
+                );
+                None
+            }
+        };
+
+        Ok(RawTx {
+            tx_hash: tx.tx_hash,
+            status: tx.status,
+            raw_data: tx.raw_data,
+            summary,
+        })
+    }
 }
 
 #[derive(Debug, Clone, Deserialize)]
-pub struct CalculateQuery {
-    pub base: Option<String>,
-    pub quote: Option<String>,
+pub struct RuneMetadataRequest {
+    pub rune: String,
 }
 
-#[derive(Clone, Debug, Deserialize)]
-pub struct SubmitTxReq {
-    pub psbt: String,
-    pub request_id: Option<String>,
-    pub context: Option<String>,
+impl RuneMetadataRequest {
+    /// 404 for runes etched without an inscription or before `indexers.index_inscriptions` was on
+    pub async fn fetch_metadata(
+        &self,
+        db: &Arc<Repo>,
+    ) -> Result<crate::db::RuneInscription, HttpResponse> {
+        db.get_rune_inscription(&self.rune)
+            .await
+            .map_err(|e| match e {
+                sqlx::Error::RowNotFound => errors::ApiError::NotFound.into(),
+                _ => {
+                    error!("request failed error={}", e);
+                    errors::internal_error("can't fetch rune metadata")
+                }
+            })
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct RuneCommitmentRequest {
+    pub rune: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RuneCommitmentInfo {
+    pub rune: String,
+    pub commitment_tx: String,
+    /// `None` for runes etched before the commitments were indexed, only the txid is known
+    pub commitment: Option<crate::db::RuneCommitment>,
+}
+
+impl RuneCommitmentRequest {
+    pub async fn fetch_commitment(
+        &self,
+        db: &Arc<Repo>,
+    ) -> Result<RuneCommitmentInfo, HttpResponse> {
+        let rune = db.get_rune(&self.rune).await.map_err(|e| match e {
+            sqlx::Error::RowNotFound => errors::ApiError::NotFound.into(),
+            _ => {
+                error!("request failed error={}", e);
+                errors::internal_error("can't fetch rune")
+            }
+        })?;
+        if rune.commitment_tx.is_empty() {
+            return Err(errors::ApiError::NotFound.into());
+        }
+
+        let commitment = match db.get_rune_commitment(&rune.rune).await {
+            Ok(c) => Some(c),
+            Err(sqlx::Error::RowNotFound) => None,
+            Err(e) => {
+                error!("request failed error={}", e);
+                return Err(errors::internal_error("can't fetch rune commitment"));
+            }
+        };
+
+        Ok(RuneCommitmentInfo {
+            rune: rune.rune,
+            commitment_tx: rune.commitment_tx,
+            commitment,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct RuneSupplyRequest {
+    pub rune: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SupplyAmount {
+    /// base units
+    #[serde(with = "number_from_string")]
+    pub raw: u128,
+    pub display: String,
+}
+
+impl SupplyAmount {
+    fn new(raw: u128, divisibility: i32) -> Self {
+        Self {
+            raw,
+            display: format_amount(raw, divisibility),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct RuneSupply {
+    pub rune: String,
+    pub divisibility: i32,
+    pub max_supply: SupplyAmount,
+    /// includes the premine
+    pub minted: SupplyAmount,
+    pub in_circulation: SupplyAmount,
+    pub burned: SupplyAmount,
+    /// share of `max_supply` which has been minted, with 2 decimals
+    pub percent_minted: String,
+    /// share of `minted` which has been burned, with 2 decimals
+    pub percent_burned: String,
+}
+
+impl RuneSupplyRequest {
+    /// `GET /runes/{rune}/supply`
+    pub async fn fetch_supply(&self, db: &Arc<Repo>) -> Result<RuneSupply, HttpResponse> {
+        let row = db.get_rune(&self.rune).await.map_err(|e| match e {
+            sqlx::Error::RowNotFound => errors::ApiError::NotFound.into(),
+            _ => {
+                error!("request failed error={}", e);
+                errors::internal_error("can't fetch rune")
+            }
+        })?;
+        Ok(RuneSupply::new(&entities::RuneEntity::from(&row)))
+    }
+}
+
+impl RuneSupply {
+    fn new(rune: &entities::RuneEntity) -> Self {
+        let d = rune.divisibility;
+        Self {
+            rune: rune.rune.clone(),
+            divisibility: d,
+            max_supply: SupplyAmount::new(rune.max_supply, d),
+            minted: SupplyAmount::new(rune.minted, d),
+            in_circulation: SupplyAmount::new(rune.in_circulation, d),
+            burned: SupplyAmount::new(rune.burned, d),
+            percent_minted: percent(rune.minted, rune.max_supply),
+            percent_burned: percent(rune.burned, rune.minted),
+        }
+    }
+}
+
+/// `part / total * 100` rounded down to 2 decimals, "0" when `total` is 0
+fn percent(part: u128, total: u128) -> String {
+    if total == 0 {
+        return "0".to_owned();
+    }
+    // the whole multiples and the remainder apart, so a part above total can't overflow
+    let (whole, rest) = (part / total, part % total);
+    let fraction = match rest.checked_mul(10_000) {
+        Some(r) => r / total,
+        // rest is above u128::MAX / 10_000, so is total and the division stays precise
+        None => rest / (total / 10_000),
+    };
+    let basis_points = whole.saturating_mul(10_000).saturating_add(fraction);
+    format_amount(basis_points, 2)
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct BalancesRequest {
+    pub address: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct RuneBalanceEntry {
+    pub rune: String,
+    pub balance: String,
+    /// `None` while the node tip is unknown
+    pub confirmed_balance: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct AddressBalances {
+    pub address: String,
+    pub min_confirmations: i64,
+    /// `None` if the address isn't in the btc watchlist
+    pub btc_balance: Option<i64>,
+    pub btc_confirmed_balance: Option<i64>,
+    pub runes: Vec<RuneBalanceEntry>,
+}
+
+/// highest block whose utxos have at least `min_confirmations`,
+/// utxos created above it are not confirmed yet
+pub fn confirmation_threshold(tip_height: i64, min_confirmations: i64) -> i64 {
+    tip_height - min_confirmations + 1
+}
+
+fn confirmed_amount(total: &str, unconfirmed: &str) -> Option<String> {
+    let total = total.parse::<u128>().ok()?;
+    let unconfirmed = unconfirmed.parse::<u128>().ok()?;
+    Some(total.saturating_sub(unconfirmed).to_string())
+}
+
+impl BalancesRequest {
+    pub async fn fetch_balances(
+        &self,
+        db: &Arc<Repo>,
+        tip: &TipCache,
+        min_confirmations: i64,
+        exclude_pending_spends: bool,
+    ) -> Result<AddressBalances, HttpResponse> {
+        let runes = db.get_runes_balances(&self.address).await.map_err(|e| {
+            error!("request failed error={}", e);
+            errors::internal_error("can't fetch runes balances")
+        })?;
+        let btc_balance = match db.get_btc_balance(&self.address).await {
+            Ok(b) => Some(b.balance),
+            Err(sqlx::Error::RowNotFound) => None,
+            Err(e) => {
+                error!("request failed error={}", e);
+                return Err(errors::internal_error("can't fetch btc balance"));
+            }
+        };
+
+        let mut res = AddressBalances {
+            address: self.address.clone(),
+            min_confirmations,
+            btc_balance,
+            btc_confirmed_balance: None,
+            runes: runes
+                .iter()
+                .map(|b| RuneBalanceEntry {
+                    rune: b.rune.clone(),
+                    balance: b.balance.clone(),
+                    confirmed_balance: None,
+                })
+                .collect(),
+        };
+
+        if exclude_pending_spends {
+            self.subtract_pending_spends(db, &mut res).await?;
+        }
+
+        let Some(tip_height) = tip.tip_height() else {
+            return Ok(res);
+        };
+        let threshold = confirmation_threshold(tip_height, min_confirmations);
+
+        let unconfirmed = db
+            .sum_runes_utxo_above_block(&self.address, threshold)
+            .await
+            .map_err(|e| {
+                error!("request failed error={}", e);
+                errors::internal_error("can't fetch unconfirmed runes utxos")
+            })?;
+        for entry in res.runes.iter_mut() {
+            let pending = unconfirmed
+                .iter()
+                .find(|(rune, _)| *rune == entry.rune)
+                .map(|(_, total)| total.as_str())
+                .unwrap_or("0");
+            entry.confirmed_balance = confirmed_amount(&entry.balance, pending);
+        }
+
+        if let Some(balance) = res.btc_balance {
+            let pending = db
+                .sum_btc_utxo_above_block(&self.address, threshold)
+                .await
+                .map_err(|e| {
+                    error!("request failed error={}", e);
+                    errors::internal_error("can't fetch unconfirmed btc utxos")
+                })?;
+            res.btc_confirmed_balance = Some((balance - pending).max(0));
+        }
+
+        Ok(res)
+    }
+
+    /// utxos spent by our submitted txs still count in the indexed balances,
+    /// they are subtracted until the indexer marks them spent
+    async fn subtract_pending_spends(
+        &self,
+        db: &Arc<Repo>,
+        res: &mut AddressBalances,
+    ) -> Result<(), HttpResponse> {
+        let outpoints: Vec<bitcoin::OutPoint> = select_pending_spends(db)
+            .await
+            .map_err(|e| {
+                error!("request failed error={}", e);
+                errors::internal_error("can't fetch pending txs")
+            })?
+            .into_iter()
+            .collect();
+        if outpoints.is_empty() {
+            return Ok(());
+        }
+
+        let rune_utxos = db
+            .select_unspent_rune_utxos_at(&self.address, &outpoints)
+            .await
+            .map_err(|e| {
+                error!("request failed error={}", e);
+                errors::internal_error("can't fetch pending spends")
+            })?;
+        let btc_utxos = db
+            .select_unspent_btc_utxos_at(&self.address, &outpoints)
+            .await
+            .map_err(|e| {
+                error!("request failed error={}", e);
+                errors::internal_error("can't fetch pending spends")
+            })?;
+
+        apply_pending_spends(res, &rune_utxos, &btc_utxos);
+        Ok(())
+    }
+}
+
+/// subtracts the unspent utxos of the address, spent by pending txs, from its balances
+fn apply_pending_spends(
+    res: &mut AddressBalances,
+    rune_utxos: &[db::RuneUtxo],
+    btc_utxos: &[db::BtcUtxo],
+) {
+    for u in rune_utxos.iter() {
+        if let Some(entry) = res.runes.iter_mut().find(|e| e.rune == u.rune) {
+            if let Some(balance) = confirmed_amount(&entry.balance, &u.amount) {
+                entry.balance = balance;
+            }
+        }
+    }
+
+    for u in btc_utxos.iter() {
+        if let Some(balance) = res.btc_balance.as_mut() {
+            *balance = (*balance - u.amount).max(0);
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct RuneUtxosQuery {
+    pub address: Option<String>,
+    pub order: Option<String>,
+    pub limit: Option<i32>,
+    pub page: Option<i32>,
+    /// confirmations required for `confirmed`, `api.min_confirmations` if not set
+    pub depth: Option<i64>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RuneUtxoStatus {
+    #[serde(flatten)]
+    pub utxo: crate::db::RuneUtxo,
+    /// `None` while the node tip is unknown
+    pub confirmations: Option<i64>,
+    pub confirmed: bool,
+}
+
+impl RuneUtxoStatus {
+    pub fn new(utxo: crate::db::RuneUtxo, tip_height: Option<i64>, depth: i64) -> Self {
+        let confirmations = tip_height.map(|tip| (tip - utxo.block + 1).max(0));
+        let confirmed = tip_height
+            .map(|tip| utxo.block <= confirmation_threshold(tip, depth))
+            .unwrap_or(false);
+
+        Self {
+            utxo,
+            confirmations,
+            confirmed,
+        }
+    }
+}
+
+#[derive(Clone, Serialize)]
+pub struct RuneUtxosMeta {
+    #[serde(flatten)]
+    pub page: ListResponseMeta,
+    pub depth: i64,
+    pub tip_height: Option<i64>,
+}
+
+#[derive(Clone, Serialize)]
+pub struct RuneUtxosResult {
+    pub meta: RuneUtxosMeta,
+    pub records: Vec<RuneUtxoStatus>,
+}
+
+impl RuneUtxosQuery {
+    pub const DEFAULT_LIMIT: i32 = 50;
+
+    /// `max_limit` is `api.max_page_size.utxos`
+    pub async fn fetch_utxos(
+        &self,
+        rune: &str,
+        db: &Arc<Repo>,
+        tip: &TipCache,
+        min_confirmations: i64,
+        max_limit: i32,
+        timeout: Option<Duration>,
+    ) -> Result<RuneUtxosResult, HttpResponse> {
+        with_timeout(
+            timeout,
+            self.utxos_page(rune, db, tip, min_confirmations, max_limit),
+        )
+        .await
+    }
+
+    async fn utxos_page(
+        &self,
+        rune: &str,
+        db: &Arc<Repo>,
+        tip: &TipCache,
+        min_confirmations: i64,
+        max_limit: i32,
+    ) -> Result<RuneUtxosResult, HttpResponse> {
+        let depth = self.depth.unwrap_or(min_confirmations).max(1);
+        let order = match self.order.as_deref().map(|o| o.to_uppercase()) {
+            Some(o) if o == "DESC" => "DESC",
+            _ => "ASC",
+        };
+        let limit = page_limit(self.limit, Self::DEFAULT_LIMIT, max_limit)?;
+        let page = self.page.unwrap_or(1);
+
+        // `?address=` lists all the utxos of the rune
+        let address = self
+            .address
+            .as_deref()
+            .map(str::trim)
+            .filter(|a| !a.is_empty())
+            .map(str::to_owned);
+
+        let utxos = ListResult::paginate(
+            page,
+            limit,
+            db.count_runes_utxo(rune, address.clone()),
+            |limit, offset| {
+                db.select_runes_utxo_with_pagination(rune, address.clone(), order, limit, offset)
+            },
+            "can't fetch runes utxos",
+        )
+        .await?;
+
+        let tip_height = tip.tip_height();
+        Ok(RuneUtxosResult {
+            meta: RuneUtxosMeta {
+                page: utxos.meta.unwrap(),
+                depth,
+                tip_height,
+            },
+            records: utxos
+                .records
+                .into_iter()
+                .map(|u| RuneUtxoStatus::new(u, tip_height, depth))
+                .collect(),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct HoldersQuery {
+    pub limit: Option<i32>,
+    pub page: Option<i32>,
+}
+
+impl HoldersQuery {
+    pub const DEFAULT_LIMIT: i32 = 100;
+
+    /// `max_limit` is `api.max_page_size.holders`, `min_balance` is `api.min_holder_balance`
+    pub async fn fetch_holders(
+        &self,
+        rune: &str,
+        db: &Arc<Repo>,
+        max_limit: i32,
+        min_balance: u64,
+        timeout: Option<Duration>,
+    ) -> Result<ListResult<crate::db::RunesBalance>, HttpResponse> {
+        with_timeout(timeout, self.holders_page(rune, db, max_limit, min_balance)).await
+    }
+
+    async fn holders_page(
+        &self,
+        rune: &str,
+        db: &Arc<Repo>,
+        max_limit: i32,
+        min_balance: u64,
+    ) -> Result<ListResult<crate::db::RunesBalance>, HttpResponse> {
+        let limit = page_limit(self.limit, Self::DEFAULT_LIMIT, max_limit)?;
+        let page = self.page.unwrap_or(1);
+        let min_balance = min_balance.to_string();
+
+        ListResult::paginate(
+            page,
+            limit,
+            db.count_runes_holders(rune, &min_balance),
+            |limit, offset| db.select_runes_holders_by_address(rune, &min_balance, limit, offset),
+            "can't fetch rune holders",
+        )
+        .await
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct UtxoDistributionQuery {
+    /// comma separated increasing amounts in base units splitting the buckets,
+    /// powers of ten up to the max supply if not set
+    pub bounds: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct UtxoBucket {
+    /// inclusive
+    #[serde(with = "number_from_string")]
+    pub from: u128,
+    /// exclusive, `None` for the last bucket
+    #[serde(with = "option_number_from_string")]
+    pub to: Option<u128>,
+    pub utxos: i64,
+    /// sum of the utxo amounts
+    pub total: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct UtxoDistribution {
+    pub rune: String,
+    pub utxos: i64,
+    pub buckets: Vec<UtxoBucket>,
+}
+
+impl UtxoDistributionQuery {
+    pub const MAX_BUCKETS: usize = 64;
+
+    /// the bounds of the request, or powers of ten covering `max_supply`
+    pub fn bounds(&self, max_supply: u128) -> Result<Vec<u128>, HttpResponse> {
+        let Some(raw) = self.bounds.as_deref().filter(|b| !b.trim().is_empty()) else {
+            let digits = max_supply.max(1).to_string().len() as u32;
+            return Ok((0..digits).map(|exp| 10u128.pow(exp)).collect());
+        };
+
+        let bounds = raw
+            .split(',')
+            .map(|b| b.trim().parse::<u128>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| errors::bad_request("invalid bounds", Some(e.to_string())))?;
+
+        if bounds.len() >= Self::MAX_BUCKETS {
+            return Err(errors::bad_request(
+                "too many buckets",
+                Some(format!("at most {} bounds", Self::MAX_BUCKETS - 1)),
+            ));
+        }
+        if bounds.windows(2).any(|w| w[0] >= w[1]) {
+            return Err(errors::bad_request(
+                "invalid bounds",
+                Some("bounds must be increasing".to_owned()),
+            ));
+        }
+        Ok(bounds)
+    }
+
+    /// `GET /runes/{rune}/utxo-distribution?bounds=`: unspent utxos of the rune by amount,
+    /// empty buckets included
+    pub async fn fetch_distribution(
+        &self,
+        rune: &str,
+        db: &Arc<Repo>,
+    ) -> Result<UtxoDistribution, HttpResponse> {
+        let row = db.get_rune(rune).await.map_err(|e| match e {
+            sqlx::Error::RowNotFound => errors::ApiError::NotFound.into(),
+            _ => {
+                error!("request failed error={}", e);
+                errors::internal_error("can't fetch rune")
+            }
+        })?;
+        let bounds = self.bounds(row.max_supply.parse().unwrap_or(u128::MAX))?;
+
+        let params: Vec<String> = bounds.iter().map(|b| b.to_string()).collect();
+        let _permit = db.bulk_permit().await;
+        let counts = db
+            .count_runes_utxo_by_amount(&row.rune, &params)
+            .await
+            .map_err(|e| {
+                error!("request failed error={}", e);
+                errors::internal_error("can't fetch utxo distribution")
+            })?;
+
+        let buckets = utxo_buckets(&bounds, &counts);
+        Ok(UtxoDistribution {
+            rune: row.rune,
+            utxos: buckets.iter().map(|b| b.utxos).sum(),
+            buckets,
+        })
+    }
+}
+
+/// all `bounds.len() + 1` buckets from the non-empty ones counted by the db
+fn utxo_buckets(bounds: &[u128], counts: &[(i32, i64, String)]) -> Vec<UtxoBucket> {
+    (0..=bounds.len())
+        .map(|i| {
+            let (utxos, total) = counts
+                .iter()
+                .find(|(bucket, _, _)| *bucket as usize == i)
+                .map(|(_, count, total)| (*count, total.clone()))
+                .unwrap_or((0, "0".to_owned()));
+            UtxoBucket {
+                from: if i == 0 { 0 } else { bounds[i - 1] },
+                to: bounds.get(i).copied(),
+                utxos,
+                total,
+            }
+        })
+        .collect()
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct RuneSnapshotQuery {
+    pub height: i64,
+    pub limit: Option<i32>,
+    pub page: Option<i32>,
+}
+
+impl RuneSnapshotQuery {
+    pub const DEFAULT_LIMIT: i32 = 100;
+
+    /// `GET /runes/{rune}/snapshot?height=`: unspent rune utxos as of the end of `height`,
+    /// `max_limit` is `api.max_page_size.utxos`
+    pub async fn fetch_snapshot(
+        &self,
+        rune: &str,
+        db: &Arc<Repo>,
+        max_limit: i32,
+        timeout: Option<Duration>,
+    ) -> Result<ListResult<crate::db::RuneUtxo>, HttpResponse> {
+        with_timeout(timeout, self.snapshot_page(rune, db, max_limit)).await
+    }
+
+    async fn snapshot_page(
+        &self,
+        rune: &str,
+        db: &Arc<Repo>,
+        max_limit: i32,
+    ) -> Result<ListResult<crate::db::RuneUtxo>, HttpResponse> {
+        let limit = page_limit(self.limit, Self::DEFAULT_LIMIT, max_limit)?;
+        let page = self.page.unwrap_or(1);
+
+        let (first, indexed) = snapshot_heights(db).await?;
+        check_snapshot_height(self.height, first, indexed)?;
+
+        let _permit = db.bulk_permit().await;
+        ListResult::paginate(
+            page,
+            limit,
+            db.count_runes_utxo_at_height(rune, self.height),
+            |limit, offset| db.select_runes_utxo_at_height(rune, self.height, limit, offset),
+            "can't fetch rune snapshot",
+        )
+        .await
+    }
+}
+
+/// the first complete snapshot height and the indexed height
+async fn snapshot_heights(db: &Repo) -> Result<(i64, i64), HttpResponse> {
+    let indexed = db
+        .get_last_indexed_block(indexer::ETCHING_INDEXER_ID)
+        .await
+        .map_err(|e| {
+            error!("request failed error={}", e);
+            errors::internal_error("can't fetch indexed height")
+        })?;
+    let first = db
+        .get_spent_block_since(indexer::ETCHING_INDEXER_ID)
+        .await
+        .map_err(|e| {
+            error!("request failed error={}", e);
+            errors::internal_error("can't fetch indexed height")
+        })?;
+    Ok((first, indexed.height))
+}
+
+/// a snapshot above the indexed height would miss utxos and spends,
+/// below `first_height` the spend heights are unknown
+fn check_snapshot_height(
+    height: i64,
+    first_height: i64,
+    indexed_height: i64,
+) -> Result<(), HttpResponse> {
+    if height < 0 {
+        return Err(errors::bad_request("height must not be negative", None));
+    }
+    if height < first_height {
+        return Err(errors::bad_request(
+            "height is below the recorded spend history",
+            Some(format!("first snapshot height is {}", first_height)),
+        ));
+    }
+    if height > indexed_height {
+        return Err(errors::bad_request(
+            "height is not indexed yet",
+            Some(format!("indexed height is {}", indexed_height)),
+        ));
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct BalancesAtRequest {
+    pub height: i64,
+    pub addresses: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AddressBalance {
+    pub address: String,
+    pub balance: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BalancesAt {
+    pub rune: String,
+    pub height: i64,
+    pub balances: Vec<AddressBalance>,
+}
+
+impl BalancesAtRequest {
+    pub const MAX_ADDRESSES: usize = 1000;
+    /// about a year of blocks
+    pub const MAX_LOOKBACK: i64 = 52_560;
+
+    /// `POST /runes/{rune}/balances-at`: balances of `addresses` as of the end of `height`,
+    /// addresses without utxos at that height get a zero balance
+    pub async fn fetch_balances(
+        &self,
+        rune: &str,
+        db: &Arc<Repo>,
+        timeout: Option<Duration>,
+    ) -> Result<BalancesAt, HttpResponse> {
+        with_timeout(timeout, self.balances_at(rune, db)).await
+    }
+
+    async fn balances_at(&self, rune: &str, db: &Arc<Repo>) -> Result<BalancesAt, HttpResponse> {
+        let (first, indexed) = snapshot_heights(db).await?;
+        let addresses = self.validate(first, indexed)?;
+
+        let _permit = db.bulk_permit().await;
+        let totals: HashMap<String, String> = db
+            .sum_runes_utxo_at_height(rune, self.height, &addresses)
+            .await
+            .map_err(|e| {
+                error!("request failed error={}", e);
+                errors::internal_error("can't fetch balances")
+            })?
+            .into_iter()
+            .collect();
+
+        let balances = addresses
+            .into_iter()
+            .map(|address| AddressBalance {
+                balance: totals.get(&address).cloned().unwrap_or("0".to_owned()),
+                address,
+            })
+            .collect();
+
+        Ok(BalancesAt {
+            rune: rune.to_owned(),
+            height: self.height,
+            balances,
+        })
+    }
+
+    /// checks the height and the address list, returns the addresses without duplicates
+    fn validate(
+        &self,
+        first_height: i64,
+        indexed_height: i64,
+    ) -> Result<Vec<String>, HttpResponse> {
+        check_snapshot_height(self.height, first_height, indexed_height)?;
+        if indexed_height - self.height > Self::MAX_LOOKBACK {
+            return Err(errors::bad_request(
+                "height is too old",
+                Some(format!("max lookback is {} blocks", Self::MAX_LOOKBACK)),
+            ));
+        }
+
+        if self.addresses.is_empty() {
+            return Err(errors::bad_request("addresses must not be empty", None));
+        }
+        if self.addresses.len() > Self::MAX_ADDRESSES {
+            return Err(errors::bad_request(
+                "too many addresses",
+                Some(format!("max is {}", Self::MAX_ADDRESSES)),
+            ));
+        }
+
+        let mut seen = HashSet::new();
+        Ok(self
+            .addresses
+            .iter()
+            .filter(|a| seen.insert(a.as_str()))
+            .cloned()
+            .collect())
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct FeeQuery {
+    pub priority: Option<FeePriority>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FeeRate {
+    pub priority: FeePriority,
+    pub target_blocks: u16,
+    /// sat/vB
+    pub fee_rate: u64,
+}
+
+impl FeeQuery {
+    /// `GET` fee endpoint, `?priority=economy|normal|priority`, default is normal
+    pub async fn fetch_fee(&self, utxo_provider: &UtxoClient) -> Result<FeeRate, HttpResponse> {
+        let priority = self.priority.unwrap_or_default();
+        let target_blocks = priority.target_blocks();
+        let fee_rate = utxo_provider
+            .get_fee_for_target(target_blocks)
+            .await
+            .map_err(|e| {
+                error!("request failed error={}", e);
+                errors::internal_error("can't estimate fee rate")
+            })?;
+
+        Ok(FeeRate {
+            priority,
+            target_blocks,
+            fee_rate,
+        })
+    }
+}
+
+#[derive(Deserialize)]
+pub struct UtxoRequest {
+    pub asset: String,
+    pub address: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UtxoSelectRequest {
+    pub address: String,
+    /// `BTC` or a rune name
+    pub asset: String,
+    #[serde(with = "number_from_string")]
+    pub amount: u128,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SelectedUtxo {
+    pub tx_hash: String,
+    pub vout: i32,
+    /// sats locked in the output
+    pub value: i64,
+    /// amount of the requested asset, equals `value` for BTC
+    #[serde(with = "number_from_string")]
+    pub amount: u128,
+}
+
+#[derive(Debug, Serialize)]
+pub struct UtxoSelection {
+    pub address: String,
+    pub asset: String,
+    #[serde(with = "number_from_string")]
+    pub amount: u128,
+    #[serde(with = "number_from_string")]
+    pub selected_amount: u128,
+    pub utxos: Vec<SelectedUtxo>,
+}
+
+impl UtxoSelectRequest {
+    /// `POST /utxo/select`: the utxos the service would spend for `amount`,
+    /// skipping the ones locked by txs being built or spent by unconfirmed ones
+    pub async fn select(
+        &self,
+        builder: &PoolTxBuilder,
+        net: Network,
+        timeout: Option<Duration>,
+    ) -> Result<UtxoSelection, HttpResponse> {
+        with_timeout(timeout, self.select_utxos(builder, net)).await
+    }
+
+    async fn select_utxos(
+        &self,
+        builder: &PoolTxBuilder,
+        net: Network,
+    ) -> Result<UtxoSelection, HttpResponse> {
+        if let Err(err) = decode_address(&self.address, net) {
+            return Err(errors::bad_request(
+                "address is invalid",
+                Some(err.to_string()),
+            ));
+        }
+        if self.amount == 0 {
+            return Err(errors::bad_request("amount must be positive", None));
+        }
+
+        let mut locked = builder
+            .cache
+            .write()
+            .await
+            .get_locked_utxos(&self.address)
+            .await
+            .map_err(|e| {
+                error!("request failed error={}", e);
+                errors::internal_error("can't fetch locked utxos")
+            })?;
+        locked.extend(builder.pending_spends().await.map_err(|e| {
+            error!("request failed error={}", e);
+            errors::internal_error("can't fetch pending txs")
+        })?);
+
+        let utxos = if self.asset == entities::Asset::btc().name {
+            let amount = u64::try_from(self.amount)
+                .map_err(|_| errors::bad_request("amount is too big", None))?;
+            builder
+                .collect_btc_utxo(&self.address, amount, &locked)
+                .await
+                .map_err(selection_error)?
+                .into_iter()
+                .map(|u| SelectedUtxo {
+                    tx_hash: u.tx_hash,
+                    vout: u.output_n,
+                    value: u.amount,
+                    amount: u.amount as u128,
+                })
+                .collect::<Vec<_>>()
+        } else {
+            builder
+                .collect_runes_utxo(&self.asset, &self.address, self.amount, &locked)
+                .await
+                .map_err(selection_error)?
+                .into_iter()
+                .map(|u| SelectedUtxo {
+                    tx_hash: u.tx_hash,
+                    vout: u.output_n,
+                    value: u.btc_amount,
+                    amount: u.amount,
+                })
+                .collect::<Vec<_>>()
+        };
+
+        Ok(UtxoSelection {
+            address: self.address.clone(),
+            asset: self.asset.clone(),
+            amount: self.amount,
+            selected_amount: utxos.iter().map(|u| u.amount).sum(),
+            utxos,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct SpendableBalanceRequest {
+    pub address: String,
+    pub rune: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SpendableBalance {
+    pub address: String,
+    pub rune: String,
+    /// indexed balance
+    #[serde(with = "number_from_string")]
+    pub balance: u128,
+    /// in utxos locked by txs being built
+    #[serde(with = "number_from_string")]
+    pub locked: u128,
+    /// in utxos spent by our broadcast txs which aren't indexed yet
+    #[serde(with = "number_from_string")]
+    pub pending: u128,
+    #[serde(with = "number_from_string")]
+    pub spendable: u128,
+}
+
+impl SpendableBalanceRequest {
+    /// `GET /address/{address}/rune/{rune}/spendable`: the balance without locked utxos
+    /// and the ones spent by our unconfirmed txs, the same set pool tx selection skips,
+    /// so `pending` is 0 when `exclude_pending_spends` is disabled
+    pub async fn fetch_spendable(
+        &self,
+        db: &Arc<Repo>,
+        builder: &PoolTxBuilder,
+        net: Network,
+    ) -> Result<SpendableBalance, HttpResponse> {
+        if let Err(err) = decode_address(&self.address, net) {
+            return Err(errors::bad_request(
+                "address is invalid",
+                Some(err.to_string()),
+            ));
+        }
+
+        let balance = match db.get_rune_balance(&self.address, &self.rune).await {
+            Ok(b) => b.balance.parse::<u128>().unwrap_or_default(),
+            Err(sqlx::Error::RowNotFound) => 0,
+            Err(e) => {
+                error!("request failed error={}", e);
+                return Err(errors::internal_error("can't fetch rune balance"));
+            }
+        };
+
+        let locked = builder
+            .cache
+            .write()
+            .await
+            .get_locked_utxos(&self.address)
+            .await
+            .map_err(|e| {
+                error!("request failed error={}", e);
+                errors::internal_error("can't fetch locked utxos")
+            })?;
+        let pending = builder.pending_spends().await.map_err(|e| {
+            error!("request failed error={}", e);
+            errors::internal_error("can't fetch pending txs")
+        })?;
+
+        let outpoints: Vec<bitcoin::OutPoint> = locked.union(&pending).copied().collect();
+        let utxos = if outpoints.is_empty() {
+            Vec::new()
+        } else {
+            db.select_unspent_rune_utxos_at(&self.address, &outpoints)
+                .await
+                .map_err(|e| {
+                    error!("request failed error={}", e);
+                    errors::internal_error("can't fetch locked utxos")
+                })?
+        };
+
+        Ok(self.spendable(balance, &utxos, &locked, &pending))
+    }
+
+    /// utxos both locked and pending count as locked, spent or foreign ones are skipped
+    fn spendable(
+        &self,
+        balance: u128,
+        utxos: &[db::RuneUtxo],
+        locked: &HashSet<bitcoin::OutPoint>,
+        pending: &HashSet<bitcoin::OutPoint>,
+    ) -> SpendableBalance {
+        let mut res = SpendableBalance {
+            address: self.address.clone(),
+            rune: self.rune.clone(),
+            balance,
+            locked: 0,
+            pending: 0,
+            spendable: 0,
+        };
+
+        for u in utxos.iter() {
+            if u.spend || u.address != self.address || u.rune != self.rune {
+                continue;
+            }
+            let (Ok(op), Ok(amount)) = (u.out_point(), u.amount.parse::<u128>()) else {
+                continue;
+            };
+            if locked.contains(&op) {
+                res.locked += amount;
+            } else if pending.contains(&op) {
+                res.pending += amount;
+            }
+        }
+
+        res.spendable = balance.saturating_sub(res.locked + res.pending);
+        res
+    }
+}
+
+fn selection_error(err: anyhow::Error) -> HttpResponse {
+    match err.downcast_ref::<InsufficientFunds>() {
+        Some(e) => errors::bad_request(
+            "insufficient funds",
+            Some(format!(
+                "has={} need={} shortfall={}",
+                e.has,
+                e.need,
+                e.shortfall()
+            )),
+        ),
+        None => {
+            error!("request failed error={}", err);
+            errors::internal_error("can't select utxos")
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PairPositionRequest {
+    pub base: String,
+    pub quote: String,
+    pub address: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct CalculateQuery {
+    pub base: Option<String>,
+    pub quote: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct SubmitTxReq {
+    pub psbt: String,
+    pub request_id: Option<String>,
+    pub context: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ResolveLiquidityRequest {
+    /// `done` or `failed`
+    pub status: String,
+    /// must be set, a resolution changes the pool state without checking the chain
+    #[serde(default)]
+    pub confirm: bool,
+}
+
+impl ResolveLiquidityRequest {
+    /// `POST /admin/liquidity-requests/{req_uid}/resolve`
+    pub async fn resolve(
+        &self,
+        req_uid: &str,
+        db: &Arc<Repo>,
+    ) -> Result<db::LiquidityChangeRequest, HttpResponse> {
+        self.validate()?;
+
+        resolve_liquidity_request(db, req_uid, &self.status)
+            .await
+            .map_err(|e| {
+                if let Some(sqlx::Error::RowNotFound) = e.downcast_ref::<sqlx::Error>() {
+                    return errors::ApiError::NotFound.into();
+                }
+                if let Some(resolved) = e.downcast_ref::<AlreadyResolved>() {
+                    return errors::bad_request(
+                        "request is already resolved",
+                        Some(resolved.to_string()),
+                    );
+                }
+                error!("request failed error={}", e);
+                errors::internal_error("can't resolve liquidity request")
+            })
+    }
+
+    fn validate(&self) -> Result<(), HttpResponse> {
+        if !self.confirm {
+            return Err(errors::bad_request(
+                "resolution is not confirmed",
+                Some("set confirm to true".to_owned()),
+            ));
+        }
+        match self.status.as_str() {
+            db::LiquidityChangeRequest::STATUS_DONE | db::LiquidityChangeRequest::STATUS_FAILED => {
+                Ok(())
+            }
+            _ => Err(errors::bad_request(
+                "invalid status",
+                Some("expected done or failed".to_owned()),
+            )),
+        }
+    }
 }
 
 #[derive(Debug, Deserialize)]
@@ -88,7 +2131,172 @@ pub struct AddLiquidityReq {
     pub quote_amount: u128,
 }
 
+/// absorbs the f64 rounding of the rates, so a deposit right at the max delta passes
+const RATE_DELTA_EPSILON: f64 = 1e-9;
+
+#[derive(Debug, Serialize)]
+pub struct LiquidityPsbt {
+    /// pass it as `request_id` when submitting the signed tx
+    pub request_id: String,
+    /// unsigned psbt, hex encoded
+    pub psbt: String,
+    pub fee: u64,
+    #[serde(with = "number_from_string")]
+    pub base_amount: u128,
+    #[serde(with = "number_from_string")]
+    pub quote_amount: u128,
+}
+
 impl AddLiquidityReq {
+    /// the deposit must keep the pool price within `max_delta` percent, the first one sets it.
+    /// `max_delta` is `api.max_liquidity_rate_delta`
+    pub fn check_rate(
+        &self,
+        pair: &entities::TradingPair,
+        max_delta: f64,
+    ) -> Result<(), HttpResponse> {
+        if self.base_amount == 0 || self.quote_amount == 0 {
+            return Err(errors::bad_request(
+                "base_amount and quote_amount must be positive",
+                None,
+            ));
+        }
+
+        match self.rate_delta_above(pair, max_delta) {
+            None => Ok(()),
+            Some(delta) => Err(errors::bad_request(
+                "deposit rate doesn't match the pool price",
+                Some(format!("delta={:.4}% max_delta={}%", delta, max_delta)),
+            )),
+        }
+    }
+
+    /// the delta of the deposit rate in percent when it is above `max_delta`
+    fn rate_delta_above(&self, pair: &entities::TradingPair, max_delta: f64) -> Option<f64> {
+        if pair.base_balance == 0 || pair.quote_balance == 0 {
+            return None;
+        }
+
+        let (exact, delta) = pair.verify_rate(self.base_amount, self.quote_amount);
+        if exact || delta <= max_delta + RATE_DELTA_EPSILON {
+            return None;
+        }
+        Some(delta)
+    }
+
+    /// `POST /pools/{base}/{quote}/add-liquidity`: unsigned psbt depositing the rune and btc
+    /// to the pool address, the tx watchdog applies it to the pool once it is mined
+    pub async fn build_psbt(
+        &self,
+        pair: &entities::TradingPair,
+        builder: &PoolTxBuilder,
+        db: &Arc<Repo>,
+        net: Network,
+        max_rate_delta: f64,
+        timeout: Option<Duration>,
+    ) -> Result<LiquidityPsbt, HttpResponse> {
+        with_timeout(
+            timeout,
+            self.deposit_psbt(pair, builder, db, net, max_rate_delta),
+        )
+        .await
+    }
+
+    async fn deposit_psbt(
+        &self,
+        pair: &entities::TradingPair,
+        builder: &PoolTxBuilder,
+        db: &Arc<Repo>,
+        net: Network,
+        max_rate_delta: f64,
+    ) -> Result<LiquidityPsbt, HttpResponse> {
+        let (base_address, quote_address) = self.parse_addresses(net)?;
+        self.check_rate(pair, max_rate_delta)?;
+
+        let btc_amount = u64::try_from(self.quote_amount)
+            .map_err(|_| errors::bad_request("quote_amount is too big", None))?;
+        let (pool_address, _, _) = pair.get_pool_address(net).map_err(|e| {
+            error!("request failed error={}", e);
+            errors::internal_error("pool address is invalid")
+        })?;
+
+        let rune_name = Some(pair.base_asset.name.clone());
+        let input = |address: &Address, pubkey: &Option<String>, rune_name| InputOpts {
+            address: address.clone(),
+            original_public_key: pubkey.clone(),
+            can_be_signed: false,
+            rune_name,
+            change_address: None,
+            sighash_type: bitcoin::sighash::TapSighashType::All,
+        };
+        let params = TxParams {
+            rune_input: input(&base_address, &self.base_address_pubkey, rune_name.clone()),
+            btc_input: input(&quote_address, &self.quote_address_pubkey, None),
+            btc_fee_input: input(&quote_address, &self.quote_address_pubkey, None),
+            rune_output: OutputOpts {
+                address: pool_address.clone(),
+                rune_name,
+                rune_amount: self.base_amount,
+                btc_amount: 0,
+            },
+            btc_output: OutputOpts {
+                address: pool_address,
+                rune_name: None,
+                rune_amount: 0,
+                btc_amount,
+            },
+            service_fee: None,
+        };
+
+        let container = builder
+            .build_multi_asset_tx(params, net)
+            .await
+            .map_err(|e| {
+                if let Some(too_large) = e.downcast_ref::<RunestoneTooLarge>() {
+                    return errors::bad_request(
+                        "runestone is too large",
+                        Some(too_large.to_string()),
+                    );
+                }
+                match e.downcast_ref::<InsufficientFunds>() {
+                    Some(_) => selection_error(e),
+                    None => {
+                        error!("request failed error={}", e);
+                        errors::internal_error("can't build add-liquidity tx")
+                    }
+                }
+            })?;
+
+        let now = chrono::Utc::now().timestamp();
+        let row = db::LiquidityChangeRequest {
+            req_uid: ulid::Ulid::new().to_string(),
+            base_address: self.base_address.clone(),
+            quote_address: self.quote_address.clone(),
+            trading_pair: pair.id,
+            base_amount: self.base_amount.to_string(),
+            quote_amount: self.quote_amount.to_string(),
+            action: db::LiquidityChangeRequest::ADD_LIQUIDITY.to_owned(),
+            status: db::LiquidityChangeRequest::STATUS_NEW.to_owned(),
+            created_at: now,
+            updated_at: now,
+            ..Default::default()
+        };
+        db.insert_liquidity_change_request(&row)
+            .await
+            .map_err(|e| {
+                error!("request failed error={}", e);
+                errors::internal_error("can't save liquidity change request")
+            })?;
+
+        Ok(LiquidityPsbt {
+            request_id: row.req_uid,
+            psbt: container.psbt.serialize_hex(),
+            fee: container.fee,
+            base_amount: self.base_amount,
+            quote_amount: self.quote_amount,
+        })
+    }
+
     pub fn parse_addresses(&self, net: Network) -> Result<(Address, Address), HttpResponse> {
         let rune_address = match decode_address(&self.base_address, net) {
             Ok(a) => a,
@@ -173,6 +2381,10 @@ pub struct SwapRequest {
     pub ask_address: String,
     #[serde(with = "number_from_string")]
     pub ask_amount: u128,
+    /// allows a partial fill down to this amount, at the same or a better rate.
+    /// Requests can't set it yet: the swap tx is still built for the full amounts.
+    #[serde(default, deserialize_with = "reject_min_ask_amount")]
+    pub min_ask_amount: Option<u128>,
     pub fee_address: String,
     pub fee_address_pubkey: Option<String>,
     pub rate: f64,
@@ -180,6 +2392,20 @@ pub struct SwapRequest {
     pub slippage_tolerance: bool,
 }
 
+/// `min_ask_amount` is refused until the swap tx is built from `SwapRequest::fill`,
+/// a partial fill quoted for it wouldn't match the amounts of the tx
+fn reject_min_ask_amount<'de, D>(deserializer: D) -> Result<Option<u128>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    use serde::de::Error;
+
+    match option_number_from_string::deserialize::<u128, D>(deserializer)? {
+        Some(_) => Err(D::Error::custom("min_ask_amount isn't supported yet")),
+        None => Ok(None),
+    }
+}
+
 impl SwapRequest {
     pub fn extract_addresses(
         &self,
@@ -216,8 +2442,1088 @@ impl SwapRequest {
 
         Ok((bid_address, ask_address, fee_address))
     }
+
+    /// How much of the swap the pool can fill at the requested rate. With the fee taken
+    /// from the bid, the pool pays `out * bid * (1 - fee) / (in + bid * (1 - fee))`, so the
+    /// rate drops as the bid grows: the largest bid that still gets `ask / bid` is
+    /// `out * bid / ask - in / (1 - fee)`. It's computed in integers and rounded down,
+    /// the fee in basis points.
+    pub fn fill(&self, pair: &entities::TradingPair) -> Result<SwapFill, HttpResponse> {
+        if self.ask_amount == 0 || self.bid_amount == 0 {
+            return Err(errors::bad_request(
+                "bid_amount and ask_amount must be positive",
+                None,
+            ));
+        }
+        let min_ask_amount = self.min_ask_amount.unwrap_or(self.ask_amount);
+        if min_ask_amount > self.ask_amount {
+            return Err(errors::bad_request(
+                "min_ask_amount can't exceed ask_amount",
+                None,
+            ));
+        }
+
+        let base = pair.base_balance.saturating_sub(pair.locked_base_balance);
+        let quote = pair.quote_balance.saturating_sub(pair.locked_quote_balance);
+        let (bid_reserve, ask_reserve) = if self.bid_asset == pair.base_asset.name {
+            (base, quote)
+        } else {
+            (quote, base)
+        };
+
+        let fee_bps = (pair.swap_fee_percent * 100.0).round();
+        if !(0.0..10_000.0).contains(&fee_bps) {
+            error!(
+                "invalid swap fee pair={} swap_fee_percent={}",
+                pair.id, pair.swap_fee_percent
+            );
+            return Err(errors::internal_error("can't quote the swap"));
+        }
+        let keep_bps = 10_000 - fee_bps as u128;
+        let too_large = || errors::bad_request("bid_amount or ask_amount is too large", None);
+
+        // the reserve term rounds up, so the bid never exceeds what the curve pays for
+        let bid_reserve_after_fee = bid_reserve
+            .checked_mul(10_000)
+            .ok_or_else(too_large)?
+            .div_ceil(keep_bps);
+        let max_bid = (ask_reserve
+            .checked_mul(self.bid_amount)
+            .ok_or_else(too_large)?
+            / self.ask_amount)
+            .saturating_sub(bid_reserve_after_fee);
+        let bid_amount = max_bid.min(self.bid_amount);
+        let ask_amount = if bid_amount == self.bid_amount {
+            self.ask_amount
+        } else {
+            bid_amount
+                .checked_mul(self.ask_amount)
+                .ok_or_else(too_large)?
+                / self.bid_amount
+        };
+
+        if ask_amount == 0 || ask_amount < min_ask_amount {
+            return Err(errors::bad_request(
+                "pool can't fill the swap",
+                Some(format!(
+                    "max_bid_amount={} ask_amount={} min_ask_amount={}",
+                    bid_amount, ask_amount, min_ask_amount
+                )),
+            ));
+        }
+
+        Ok(SwapFill {
+            bid_amount,
+            ask_amount,
+            partial: ask_amount < self.ask_amount,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SwapFill {
+    #[serde(with = "number_from_string")]
+    pub bid_amount: u128,
+    #[serde(with = "number_from_string")]
+    pub ask_amount: u128,
+    pub partial: bool,
 }
 
 pub fn decode_address(address: &str, net: Network) -> anyhow::Result<Address<NetworkChecked>> {
     Ok(Address::from_str(address)?.require_network(net)?)
 }
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn search_limit_is_clamped() {
+        use super::SearchQuery;
+        let q = |limit| SearchQuery {
+            s: "RUNE".to_owned(),
+            limit,
+        };
+
+        assert_eq!(q(None).get_limit(), SearchQuery::DEFAULT_LIMIT);
+        assert_eq!(q(Some(10)).get_limit(), 10);
+        assert_eq!(q(Some(0)).get_limit(), 1);
+        assert_eq!(q(Some(-5)).get_limit(), 1);
+        assert_eq!(q(Some(10_000)).get_limit(), SearchQuery::MAX_LIMIT);
+    }
+
+    #[test]
+    fn confirmation_threshold_boundary() {
+        use super::confirmation_threshold;
+        let tip = 840_000;
+
+        // a utxo is unconfirmed when its block is above the threshold
+        let threshold = confirmation_threshold(tip, 1);
+        assert!(tip <= threshold);
+
+        let threshold = confirmation_threshold(tip, 3);
+        // tip - 2 has exactly 3 confirmations
+        assert!(tip - 2 <= threshold);
+        assert!(tip - 1 > threshold);
+        assert!(tip > threshold);
+    }
+
+    #[test]
+    fn fresh_utxo_is_unconfirmed_at_depth_two() {
+        use super::RuneUtxoStatus;
+        let tip = 840_000;
+        let utxo = |block| crate::db::RuneUtxo {
+            block,
+            ..Default::default()
+        };
+
+        let fresh = RuneUtxoStatus::new(utxo(tip), Some(tip), 2);
+        assert_eq!(fresh.confirmations, Some(1));
+        assert!(!fresh.confirmed);
+
+        let older = RuneUtxoStatus::new(utxo(tip - 1), Some(tip), 2);
+        assert_eq!(older.confirmations, Some(2));
+        assert!(older.confirmed);
+
+        let unknown_tip = RuneUtxoStatus::new(utxo(tip - 10), None, 2);
+        assert_eq!(unknown_tip.confirmations, None);
+        assert!(!unknown_tip.confirmed);
+    }
+
+    #[test]
+    fn swap_fills() {
+        use super::{SwapFill, SwapRequest};
+        use crate::service::entities::TradingPair;
+
+        let pair = TradingPair {
+            locked_quote_balance: 10_000,
+            ..TradingPair::test_pair(100_000, 60_000)
+        };
+        // sells runes for 0.4 sat each, the spot price is 0.5 sat
+        let swap = |bid_amount: u128, min_ask_amount| SwapRequest {
+            bid_asset: "RRR".to_owned(),
+            bid_amount,
+            ask_amount: bid_amount * 2 / 5,
+            min_ask_amount,
+            ..Default::default()
+        };
+
+        let full = swap(10_000, None).fill(&pair).unwrap();
+        assert_eq!(
+            full,
+            SwapFill {
+                bid_amount: 10_000,
+                ask_amount: 4_000,
+                partial: false
+            }
+        );
+
+        // the pool is too shallow for the whole order: past 24497 runes the curve
+        // pays less than 0.4 sat per rune, although 16000 sats are available
+        let partial = swap(40_000, Some(8_000)).fill(&pair).unwrap();
+        assert_eq!(
+            partial,
+            SwapFill {
+                bid_amount: 24_497,
+                ask_amount: 9_798,
+                partial: true
+            }
+        );
+        // what the curve pays for the fill still meets the requested rate
+        let out = |bid: f64| 50_000.0 * bid * 0.995 / (100_000.0 + bid * 0.995);
+        assert!(out(24_497.0) >= 9_798.0);
+        assert!(out(24_498.0) < 24_498.0 * 0.4);
+
+        assert!(swap(40_000, Some(10_000)).fill(&pair).is_err());
+        assert!(swap(40_000, None).fill(&pair).is_err());
+        assert!(swap(10_000, Some(4_001)).fill(&pair).is_err());
+
+        // a rate above the spot price can't be filled at all
+        let too_good = SwapRequest {
+            ask_amount: 600,
+            min_ask_amount: Some(1),
+            ..swap(1_000, None)
+        };
+        assert!(too_good.fill(&pair).is_err());
+
+        // buys runes with sats
+        let buy = SwapRequest {
+            bid_asset: "BTC".to_owned(),
+            bid_amount: 1_000,
+            ask_amount: 1_900,
+            ..Default::default()
+        };
+        assert!(!buy.fill(&pair).unwrap().partial);
+
+        // products past u128 are a bad request, not a wrapped amount
+        let huge = SwapRequest {
+            bid_amount: u128::MAX,
+            ask_amount: 1,
+            ..swap(1_000, None)
+        };
+        assert_eq!(
+            huge.fill(&pair).unwrap_err().status(),
+            actix_web::http::StatusCode::BAD_REQUEST
+        );
+    }
+
+    #[test]
+    fn swap_request_refuses_min_ask_amount() {
+        use super::SwapRequest;
+
+        let request = |min_ask_amount: &str| {
+            format!(
+                r#"{{"bid_asset":"RRR","bid_amount":"1000","bid_address":"a","ask_address":"b",
+                "ask_amount":"400",{}"fee_address":"c","rate":0.4,"slippage":0.0,
+                "slippage_tolerance":false}}"#,
+                min_ask_amount
+            )
+        };
+        let swap: SwapRequest = serde_json::from_str(&request("")).unwrap();
+        assert_eq!(swap.min_ask_amount, None);
+        let swap: SwapRequest =
+            serde_json::from_str(&request(r#""min_ask_amount":null,"#)).unwrap();
+        assert_eq!(swap.min_ask_amount, None);
+
+        // not applied to the swap tx yet
+        let err = serde_json::from_str::<SwapRequest>(&request(r#""min_ask_amount":"300","#))
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("min_ask_amount"), "{}", err);
+    }
+
+    #[test]
+    fn confirmed_amount_subtracts_pending() {
+        use super::confirmed_amount;
+
+        assert_eq!(confirmed_amount("1000", "0").as_deref(), Some("1000"));
+        assert_eq!(confirmed_amount("1000", "400").as_deref(), Some("600"));
+        // balances and utxos may briefly disagree, never go negative
+        assert_eq!(confirmed_amount("100", "400").as_deref(), Some("0"));
+        assert_eq!(
+            confirmed_amount(&u128::MAX.to_string(), "1").as_deref(),
+            Some((u128::MAX - 1).to_string().as_str())
+        );
+        assert_eq!(confirmed_amount("abc", "0"), None);
+    }
+
+    #[test]
+    fn raw_tx_summary_decodes_stored_hex() {
+        use super::RawTxSummary;
+        use bitcoin::{
+            absolute::LockTime, consensus::encode::serialize_hex, Address, Network, OutPoint,
+            ScriptBuf, Sequence, Transaction, TxIn, TxOut, Witness,
+        };
+        use std::str::FromStr;
+
+        let address = Address::from_str("bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4")
+            .unwrap()
+            .require_network(Network::Bitcoin)
+            .unwrap();
+        let tx = Transaction {
+            version: 2,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: OutPoint::null(),
+                script_sig: ScriptBuf::new(),
+                sequence: Sequence::MAX,
+                witness: Witness::new(),
+            }],
+            output: vec![TxOut {
+                value: 1000,
+                script_pubkey: address.script_pubkey(),
+            }],
+        };
+
+        let summary = RawTxSummary::decode(&serialize_hex(&tx), Network::Bitcoin).unwrap();
+        assert_eq!(summary.txid, tx.txid().to_string());
+        assert_eq!(summary.version, 2);
+        assert_eq!(summary.inputs.len(), 1);
+        assert_eq!(summary.outputs[0].value, 1000);
+        assert_eq!(summary.outputs[0].address, Some(address.to_string()));
+
+        assert!(RawTxSummary::decode("zz", Network::Bitcoin).is_err());
+    }
+
+    /// needs a scratch db in `TEST_DB_DSN`, skipped otherwise
+    #[tokio::test]
+    async fn list_limit_above_max_is_rejected() {
+        use super::{HoldersQuery, RuneUtxosQuery};
+        use crate::config::PageLimitsConfig;
+        use crate::{db::connect_scratch_db, db::Repo, service::tip_cache::TipCache};
+        use actix_web::{http::StatusCode, HttpResponse};
+        use std::sync::Arc;
+
+        /// page size used by the endpoint
+        async fn page_size(
+            endpoint: &str,
+            limit: Option<i32>,
+            max: i32,
+            db: &Arc<Repo>,
+        ) -> Result<i32, HttpResponse> {
+            let rune = "PAGELIMITTEST";
+            let meta = match endpoint {
+                "holders" => {
+                    let query = HoldersQuery { limit, page: None };
+                    query.fetch_holders(rune, db, max, 0, None).await?.meta
+                }
+                _ => {
+                    let query = RuneUtxosQuery {
+                        limit,
+                        ..Default::default()
+                    };
+                    let tip = TipCache::new();
+                    let res = query.fetch_utxos(rune, db, &tip, 1, max, None).await?;
+                    Some(res.meta.page)
+                }
+            };
+            Ok(meta.unwrap().limit)
+        }
+
+        let Some(db) = connect_scratch_db().await.map(Arc::new) else {
+            return;
+        };
+        let limits = PageLimitsConfig::default();
+        assert_eq!((limits.holders(), limits.utxos()), (500, 200));
+
+        // endpoint, requested limit, configured max, used page size or None for 400
+        let cases = [
+            (
+                "holders",
+                None,
+                limits.holders(),
+                Some(HoldersQuery::DEFAULT_LIMIT),
+            ),
+            ("holders", Some(500), limits.holders(), Some(500)),
+            ("holders", Some(0), limits.holders(), Some(1)),
+            ("holders", Some(501), limits.holders(), None),
+            ("utxos", Some(200), limits.utxos(), Some(200)),
+            ("utxos", Some(1_000_000), limits.utxos(), None),
+            // a configured max below the default limit caps the default
+            ("utxos", None, 20, Some(20)),
+        ];
+        for (endpoint, limit, max, expected) in cases {
+            let res = page_size(endpoint, limit, max, &db).await;
+            match expected {
+                Some(size) => assert_eq!(res.unwrap(), size, "{} {:?}", endpoint, limit),
+                None => assert_eq!(
+                    res.unwrap_err().status(),
+                    StatusCode::BAD_REQUEST,
+                    "{} {:?}",
+                    endpoint,
+                    limit
+                ),
+            }
+        }
+    }
+
+    #[test]
+    fn commitment_address_validates_name() {
+        use super::CommitmentAddressRequest;
+        use bitcoin::Network;
+
+        let req = |rune: &str| CommitmentAddressRequest {
+            rune: rune.to_owned(),
+            pubkey: "79be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798".to_owned(),
+        };
+
+        let res = req("AAAAAAAAAAAAAAB")
+            .commitment_address(Network::Bitcoin, Some(840_000))
+            .unwrap();
+        assert!(res.commit_tx_address.starts_with("bc1p"));
+        assert_eq!(
+            res,
+            req("AAAAAAAAAAAAAAB")
+                .commitment_address(Network::Bitcoin, Some(840_000))
+                .unwrap()
+        );
+
+        // short names are still locked
+        assert!(req("ABC")
+            .commitment_address(Network::Bitcoin, Some(840_000))
+            .is_err());
+        assert!(req("not a rune")
+            .commitment_address(Network::Bitcoin, Some(840_000))
+            .is_err());
+
+        // the tip refresher hasn't run yet
+        assert_eq!(
+            req("AAAAAAAAAAAAAAB")
+                .commitment_address(Network::Bitcoin, None)
+                .unwrap_err()
+                .status(),
+            actix_web::http::StatusCode::SERVICE_UNAVAILABLE
+        );
+    }
+
+    #[test]
+    fn unknown_tip_is_unavailable() {
+        use super::known_tip;
+
+        assert_eq!(known_tip(Some(840_000)).unwrap(), 840_000);
+        assert_eq!(
+            known_tip(None).unwrap_err().status(),
+            actix_web::http::StatusCode::SERVICE_UNAVAILABLE
+        );
+    }
+
+    #[test]
+    fn name_availability() {
+        use super::NameAvailability;
+        use bitcoin::Network;
+        use ordinals::SpacedRune;
+        use std::str::FromStr;
+
+        let name = SpacedRune::from_str("FOO•BAR•BAZ•QUUX•X").unwrap();
+        let a = NameAvailability::new(name, false, Network::Bitcoin, 840_001);
+        assert_eq!(a.rune, "FOOBARBAZQUUXX");
+        assert_eq!(a.spaced_rune, "FOO•BAR•BAZ•QUUX•X");
+        assert!(a.available);
+        assert!(!a.below_minimum && !a.reserved);
+
+        let a = NameAvailability::new(name, true, Network::Bitcoin, 840_001);
+        assert!(a.exists && !a.available);
+
+        let short = SpacedRune::from_str("FOO•BAR").unwrap();
+        let a = NameAvailability::new(short, false, Network::Bitcoin, 840_001);
+        assert!(a.below_minimum && !a.available);
+
+        let reserved = SpacedRune::from_str("AAAAAAAAAAAAAAAAAAAAAAAAAAA").unwrap();
+        let a = NameAvailability::new(reserved, false, Network::Bitcoin, 840_001);
+        assert!(a.reserved && !a.available);
+    }
+
+    #[test]
+    fn insufficient_funds_is_a_validation_error() {
+        use super::selection_error;
+        use crate::tx::pool_txs::InsufficientFunds;
+        use actix_web::http::StatusCode;
+
+        let err = InsufficientFunds {
+            asset: "BTC".to_owned(),
+            address: "bc1qaddress".to_owned(),
+            has: 1_500,
+            need: 4_000,
+        };
+        assert_eq!(err.shortfall(), 2_500);
+        let resp = selection_error(err.into());
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+        let resp = selection_error(anyhow::anyhow!("db is down"));
+        assert_eq!(resp.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    #[test]
+    fn rune_supply_is_formatted() {
+        use super::{percent, RuneSupply};
+        use crate::service::entities::RuneEntity;
+        use crate::utils::format_amount;
+
+        let rune = RuneEntity {
+            rune: "UNCOMMONGOODS".to_owned(),
+            divisibility: 0,
+            max_supply: 1_000,
+            minted: 250,
+            in_circulation: 240,
+            burned: 10,
+            ..Default::default()
+        };
+        let supply = RuneSupply::new(&rune);
+        assert_eq!(supply.max_supply.display, "1000");
+        assert_eq!(supply.in_circulation.raw, 240);
+        assert_eq!(supply.percent_minted, "25");
+        assert_eq!(supply.percent_burned, "4");
+
+        let rune = RuneEntity {
+            divisibility: 8,
+            max_supply: 2_100_000_000_000_000,
+            minted: 700_000_000_000_000,
+            in_circulation: 700_000_000_000_000,
+            ..rune
+        };
+        let supply = RuneSupply::new(&rune);
+        assert_eq!(supply.max_supply.display, "21000000");
+        assert_eq!(supply.minted.display, "7000000");
+        assert_eq!(supply.burned.display, "0.0000001");
+        assert_eq!(supply.percent_minted, "33.33");
+
+        assert_eq!(percent(5, 0), "0");
+        assert_eq!(percent(u128::MAX, u128::MAX), "100");
+        assert_eq!(percent(u128::MAX / 2, u128::MAX), "50");
+        // parts above small totals, e.g. burned above minted in inconsistent rows
+        assert_eq!(percent(u128::MAX, u128::MAX / 2), "200");
+        assert_eq!(percent(15_000, 9_999), "150.01");
+        assert_eq!(percent(u128::MAX, 9_999), format_amount(u128::MAX, 2));
+    }
+
+    #[test]
+    fn add_liquidity_keeps_pool_price() {
+        use super::AddLiquidityReq;
+        use crate::service::entities::TradingPair;
+
+        let mut pair = TradingPair::test_pair(10_000, 5_000);
+        let deposit = |base_amount, quote_amount| AddLiquidityReq {
+            base_address: "bc1qbase".to_owned(),
+            base_address_pubkey: None,
+            base_amount,
+            quote_address: "bc1qquote".to_owned(),
+            quote_address_pubkey: None,
+            quote_amount,
+        };
+
+        assert!(deposit(2_000, 1_000).check_rate(&pair, 1.0).is_ok());
+        // 0.5% off the pool price
+        assert!(deposit(2_010, 1_000).check_rate(&pair, 1.0).is_ok());
+        // 1.5% off the pool price
+        assert!(deposit(2_030, 1_000).check_rate(&pair, 1.0).is_err());
+        assert!(deposit(0, 1_000).check_rate(&pair, 1.0).is_err());
+        assert!(deposit(2_000, 0).check_rate(&pair, 1.0).is_err());
+
+        // the first deposit sets the price
+        pair.base_balance = 0;
+        pair.quote_balance = 0;
+        assert!(deposit(2_030, 1_000).check_rate(&pair, 1.0).is_ok());
+    }
+
+    #[test]
+    fn add_liquidity_rate_delta_boundary() {
+        use super::AddLiquidityReq;
+        use crate::service::entities::TradingPair;
+
+        let pair = TradingPair::test_pair(10_000, 5_000);
+        let deposit = |base_amount| AddLiquidityReq {
+            base_address: "bc1qbase".to_owned(),
+            base_address_pubkey: None,
+            base_amount,
+            quote_address: "bc1qquote".to_owned(),
+            quote_address_pubkey: None,
+            quote_amount: 1_000,
+        };
+        let delta = |base_amount, max_delta| {
+            deposit(base_amount)
+                .rate_delta_above(&pair, max_delta)
+                .map(|d: f64| (d * 100.0).round() / 100.0)
+        };
+
+        // exactly 1% off on both sides passes, despite the f64 rounding of the rates
+        assert_eq!(delta(2_020, 1.0), None);
+        assert_eq!(delta(1_980, 1.0), None);
+        assert_eq!(delta(2_021, 1.0), Some(1.05));
+        assert_eq!(delta(1_979, 1.0), Some(1.05));
+        assert!(deposit(2_020).check_rate(&pair, 1.0).is_ok());
+        assert!(deposit(2_021).check_rate(&pair, 1.0).is_err());
+
+        // a tighter limit
+        assert_eq!(delta(2_010, 0.5), None);
+        assert_eq!(delta(2_011, 0.5), Some(0.55));
+        assert_eq!(delta(2_000, 0.0), None);
+        assert_eq!(delta(2_001, 0.0), Some(0.05));
+    }
+
+    #[test]
+    fn top_runes_query_is_validated() {
+        use super::TopRunesQuery;
+        let q = |by: Option<&str>, limit| TopRunesQuery {
+            by: by.map(|b| b.to_owned()),
+            limit,
+        };
+
+        assert_eq!(q(None, None).ranking().unwrap(), "holders");
+        assert_eq!(q(Some("supply"), None).ranking().unwrap(), "supply");
+        assert_eq!(q(Some("mints"), None).ranking().unwrap(), "mints");
+        assert!(q(Some("volume"), None).ranking().is_err());
+
+        assert_eq!(q(None, None).get_limit(), TopRunesQuery::DEFAULT_LIMIT);
+        assert_eq!(q(None, Some(0)).get_limit(), 1);
+        assert_eq!(q(None, Some(10_000)).get_limit(), TopRunesQuery::MAX_LIMIT);
+    }
+
+    #[test]
+    fn snapshot_height_must_be_indexed() {
+        use super::check_snapshot_height;
+
+        assert!(check_snapshot_height(840_000, 0, 840_000).is_ok());
+        assert!(check_snapshot_height(839_000, 0, 840_000).is_ok());
+        assert!(check_snapshot_height(840_001, 0, 840_000).is_err());
+        assert!(check_snapshot_height(-1, 0, 840_000).is_err());
+
+        // spends below the first height have no height
+        assert!(check_snapshot_height(839_000, 839_500, 840_000).is_err());
+        assert!(check_snapshot_height(839_500, 839_500, 840_000).is_ok());
+    }
+
+    #[test]
+    fn balances_at_request_is_capped() {
+        use super::BalancesAtRequest;
+        let req = |height, addresses: Vec<&str>| BalancesAtRequest {
+            height,
+            addresses: addresses.into_iter().map(|a| a.to_owned()).collect(),
+        };
+
+        assert_eq!(
+            req(840_000, vec!["bc1qa", "bc1qb", "bc1qa"])
+                .validate(0, 840_000)
+                .unwrap(),
+            vec!["bc1qa", "bc1qb"]
+        );
+        assert!(req(840_001, vec!["bc1qa"]).validate(0, 840_000).is_err());
+        assert!(req(840_000, vec![]).validate(0, 840_000).is_err());
+
+        let oldest = 840_000 - BalancesAtRequest::MAX_LOOKBACK;
+        assert!(req(oldest, vec!["bc1qa"]).validate(0, 840_000).is_ok());
+        assert!(req(oldest - 1, vec!["bc1qa"]).validate(0, 840_000).is_err());
+
+        let too_many = vec!["bc1qa"; BalancesAtRequest::MAX_ADDRESSES + 1];
+        assert!(req(840_000, too_many).validate(0, 840_000).is_err());
+    }
+
+    #[test]
+    fn pending_spends_are_subtracted() {
+        use super::{apply_pending_spends, AddressBalances, RuneBalanceEntry};
+        use crate::db;
+
+        let address = "bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4";
+        let tx_hash = "4a5e1e4baab89f3a32518a88c31bc87f618f76673e2cc77ab2127b7afdeda33b";
+        let mut res = AddressBalances {
+            address: address.to_owned(),
+            btc_balance: Some(10_000),
+            runes: vec![RuneBalanceEntry {
+                rune: "RUNE".to_owned(),
+                balance: "1000".to_owned(),
+                confirmed_balance: None,
+            }],
+            ..Default::default()
+        };
+        let rune_utxo = |output_n, rune: &str| db::RuneUtxo {
+            tx_hash: tx_hash.to_owned(),
+            output_n,
+            rune: rune.to_owned(),
+            address: address.to_owned(),
+            amount: "400".to_owned(),
+            btc_amount: 600,
+            ..Default::default()
+        };
+        let btc_utxo = |output_n| db::BtcUtxo {
+            tx_hash: tx_hash.to_owned(),
+            output_n,
+            address: address.to_owned(),
+            amount: 600,
+            ..Default::default()
+        };
+
+        // the pending tx spends two rune utxos and a rune the address has no balance of
+        apply_pending_spends(
+            &mut res,
+            &[
+                rune_utxo(1, "RUNE"),
+                rune_utxo(2, "RUNE"),
+                rune_utxo(3, "OTHER"),
+            ],
+            &[btc_utxo(1), btc_utxo(2), btc_utxo(4)],
+        );
+        assert_eq!(res.runes.len(), 1);
+        assert_eq!(res.runes[0].balance, "200");
+        assert_eq!(res.btc_balance, Some(8_200));
+
+        // balances and utxos may briefly disagree, never go negative
+        apply_pending_spends(&mut res, &[rune_utxo(1, "RUNE")], &[]);
+        assert_eq!(res.runes[0].balance, "0");
+    }
+
+    #[test]
+    fn liquidity_resolution_must_be_confirmed() {
+        use super::ResolveLiquidityRequest;
+        let req = |status: &str, confirm| ResolveLiquidityRequest {
+            status: status.to_owned(),
+            confirm,
+        };
+
+        assert!(req("done", true).validate().is_ok());
+        assert!(req("failed", true).validate().is_ok());
+        assert!(req("done", false).validate().is_err());
+        assert!(req("new", true).validate().is_err());
+        assert!(req("mined", true).validate().is_err());
+    }
+
+    #[test]
+    fn utxo_distribution_buckets() {
+        use super::{utxo_buckets, UtxoBucket, UtxoDistributionQuery};
+
+        let query = UtxoDistributionQuery { bounds: None };
+        assert_eq!(
+            query.bounds(21_000).unwrap(),
+            vec![1, 10, 100, 1000, 10_000]
+        );
+        assert_eq!(query.bounds(0).unwrap(), vec![1]);
+
+        let query = UtxoDistributionQuery {
+            bounds: Some("100, 1000".to_owned()),
+        };
+        let bounds = query.bounds(21_000).unwrap();
+        assert_eq!(bounds, vec![100, 1000]);
+        for invalid in ["1000,100", "10,10", "-1", "1,x"] {
+            let query = UtxoDistributionQuery {
+                bounds: Some(invalid.to_owned()),
+            };
+            assert!(query.bounds(21_000).is_err(), "{}", invalid);
+        }
+
+        // the db omits empty buckets
+        let counts = vec![(0, 3, "150".to_owned()), (2, 1, "5000".to_owned())];
+        assert_eq!(
+            utxo_buckets(&bounds, &counts),
+            vec![
+                UtxoBucket {
+                    from: 0,
+                    to: Some(100),
+                    utxos: 3,
+                    total: "150".to_owned(),
+                },
+                UtxoBucket {
+                    from: 100,
+                    to: Some(1000),
+                    utxos: 0,
+                    total: "0".to_owned(),
+                },
+                UtxoBucket {
+                    from: 1000,
+                    to: None,
+                    utxos: 1,
+                    total: "5000".to_owned(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn locked_and_pending_are_not_spendable() {
+        use super::SpendableBalanceRequest;
+        use crate::db;
+        use std::collections::HashSet;
+
+        let address = "bc1p26g4";
+        let req = SpendableBalanceRequest {
+            address: address.to_owned(),
+            rune: "RUNE".to_owned(),
+        };
+        let utxo = |vout, rune: &str, amount: &str, spend| db::RuneUtxo {
+            tx_hash: "5b1e4d8b2e7e5a1f0c3d9e8f7a6b5c4d3e2f1a0b9c8d7e6f5a4b3c2d1e0f9a8b".to_owned(),
+            output_n: vout,
+            rune: rune.to_owned(),
+            address: address.to_owned(),
+            amount: amount.to_owned(),
+            spend,
+            ..Default::default()
+        };
+        let utxos = vec![
+            utxo(0, "RUNE", "100", false),
+            utxo(1, "RUNE", "20", false),
+            utxo(1, "OTHER", "5", false),
+            utxo(2, "RUNE", "30", true),
+        ];
+        let op = |i: usize| utxos[i].out_point().unwrap();
+
+        // subsystems disabled
+        let res = req.spendable(1_000, &utxos, &HashSet::new(), &HashSet::new());
+        assert_eq!((res.locked, res.pending, res.spendable), (0, 0, 1_000));
+
+        let locked = HashSet::from([op(0), op(1)]);
+        let pending = HashSet::from([op(1), op(3)]);
+        let res = req.spendable(1_000, &utxos, &locked, &pending);
+        assert_eq!((res.locked, res.pending, res.spendable), (120, 0, 880));
+
+        let res = req.spendable(1_000, &utxos, &HashSet::from([op(0)]), &pending);
+        assert_eq!((res.locked, res.pending, res.spendable), (100, 20, 880));
+
+        let res = req.spendable(50, &utxos, &locked, &pending);
+        assert_eq!(res.spendable, 0);
+    }
+
+    #[test]
+    fn tx_log_request_is_validated() {
+        use super::TxRunesRequest;
+        use actix_web::http::StatusCode;
+
+        let req = TxRunesRequest {
+            txid: "5b1e4d8b2e7e5a1f0c3d9e8f7a6b5c4d3e2f1a0b9c8d7e6f5a4b3c2d1e0f9a8b".to_owned(),
+        };
+        assert!(req.validate(true).is_ok());
+        assert_eq!(
+            req.validate(false).unwrap_err().status(),
+            StatusCode::NOT_IMPLEMENTED
+        );
+
+        let req = TxRunesRequest {
+            txid: "5b1e4d8b".to_owned(),
+        };
+        assert_eq!(
+            req.validate(true).unwrap_err().status(),
+            StatusCode::BAD_REQUEST
+        );
+    }
+
+    #[test]
+    fn block_info_prefers_the_runes_index_hash() {
+        use super::block_info;
+        use crate::db::IndexedBlock;
+        use crate::indexer::{BTC_INDEXER_ID, ETCHING_INDEXER_ID};
+
+        assert!(block_info(840_000, Vec::new()).is_none());
+
+        let btc = IndexedBlock::new(BTC_INDEXER_ID, 840_000, "00aa", 3_050);
+        let info = block_info(840_000, vec![btc.clone()]).unwrap();
+        assert_eq!(info.block_hash, "00aa");
+        assert_eq!(info.indexers.len(), 1);
+
+        // indexers which followed different chains report different hashes
+        let runes = IndexedBlock {
+            etchings: Some(1),
+            mints: Some(20),
+            ..IndexedBlock::new(ETCHING_INDEXER_ID, 840_000, "00bb", 3_050)
+        };
+        let info = block_info(840_000, vec![btc, runes]).unwrap();
+        assert_eq!(info.block_hash, "00bb");
+        assert_eq!(info.indexers.len(), 2);
+        assert_eq!(info.indexers[0].etchings, None);
+        assert_eq!(info.indexers[1].mints, Some(20));
+    }
+
+    #[test]
+    fn activity_action_filter() {
+        use super::ActivityQuery;
+        use crate::db::RuneLog;
+
+        let q = |action: Option<&str>| ActivityQuery {
+            limit: None,
+            page: None,
+            action: action.map(|a| a.to_owned()),
+        };
+        assert_eq!(q(None).action().unwrap(), None);
+        assert_eq!(
+            q(Some("BURN")).action().unwrap().as_deref(),
+            Some(RuneLog::BURN)
+        );
+        assert_eq!(
+            q(Some("mint")).action().unwrap().as_deref(),
+            Some(RuneLog::MINT)
+        );
+        assert!(q(Some("transfer")).action().is_err());
+    }
+
+    /// needs a scratch db in `TEST_DB_DSN`, skipped otherwise
+    #[actix_web::test]
+    async fn utxo_distribution_waits_for_a_bulk_permit() {
+        use super::UtxoDistributionQuery;
+        use crate::{
+            config::DBConfig,
+            db::{connect_postgres_db, scratch_db_config, Rune},
+        };
+        use std::{sync::Arc, time::Duration};
+
+        let Some(cfg) = scratch_db_config() else {
+            return;
+        };
+        let db = Arc::new(
+            connect_postgres_db(DBConfig {
+                bulk_permits: Some(1),
+                ..cfg
+            })
+            .await
+            .unwrap(),
+        );
+        let rune = "BULKPERMITTEST";
+        sqlx::query("DELETE FROM runes WHERE rune = $1")
+            .bind(rune)
+            .execute(&db.pool)
+            .await
+            .unwrap();
+        db.insert_rune(&Rune {
+            rune: rune.to_owned(),
+            max_supply: "1000".to_owned(),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+
+        let query = UtxoDistributionQuery::default();
+        let permit = db.bulk_permit().await;
+        let blocked = tokio::time::timeout(
+            Duration::from_millis(200),
+            query.fetch_distribution(rune, &db),
+        )
+        .await;
+        assert!(
+            blocked.is_err(),
+            "the distribution didn't wait for the permit"
+        );
+
+        drop(permit);
+        tokio::time::timeout(Duration::from_secs(5), query.fetch_distribution(rune, &db))
+            .await
+            .unwrap()
+            .unwrap();
+    }
+
+    /// needs a scratch db in `TEST_DB_DSN`, skipped otherwise
+    #[actix_web::test]
+    async fn pair_under_spaced_name_takes_the_rune_divisibility() {
+        use super::PairRequest;
+        use crate::db::{connect_scratch_db, Rune};
+        use std::sync::Arc;
+
+        let Some(db) = connect_scratch_db().await.map(Arc::new) else {
+            return;
+        };
+        let (rune, spaced) = ("PAIRDIVISIBILITYTEST", "PAIR•DIVISIBILITY•TEST");
+        sqlx::query("DELETE FROM trading_pair WHERE base_asset = $1")
+            .bind(spaced)
+            .execute(&db.pool)
+            .await
+            .unwrap();
+        sqlx::query("DELETE FROM runes WHERE rune = $1")
+            .bind(rune)
+            .execute(&db.pool)
+            .await
+            .unwrap();
+        db.insert_rune(&Rune {
+            rune: rune.to_owned(),
+            display_name: spaced.to_owned(),
+            divisibility: 2,
+            max_supply: "100000000".to_owned(),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        // the pair row has no metadata of its own, only the spaced name
+        sqlx::query(
+            "INSERT INTO trading_pair (base_asset, quote_asset, pool_address, base_balance,
+                quote_balance, locked_base_balance, locked_quote_balance, fee_address,
+                treasury_address, swap_fee_percent)
+            VALUES ($1, 'BTC', 'pool', '100000', '100000000', '0', '0', 'fee', 'treasury', 0.5)",
+        )
+        .bind(spaced)
+        .execute(&db.pool)
+        .await
+        .unwrap();
+
+        let request = PairRequest {
+            base: spaced.to_owned(),
+            quote: "BTC".to_owned(),
+        };
+        let pair = request.fetch_pair(&db).await.unwrap();
+        assert_eq!(pair.base_asset.name, rune);
+        assert_eq!(pair.base_asset.decimals, 2);
+        assert_eq!(pair.base_asset.display_name.as_deref(), Some(spaced));
+        // 1000 sats per rune with 2 decimals, 10 with none
+        assert_eq!(pair.price_in_units_str(), "1000");
+    }
+
+    /// needs a scratch db in `TEST_DB_DSN`, skipped otherwise
+    #[actix_web::test]
+    async fn pending_spend_is_subtracted_until_indexed() {
+        use super::{AddressBalances, BalancesRequest, RuneBalanceEntry};
+        use crate::db::{self, connect_scratch_db};
+        use bitcoin::consensus::encode::serialize_hex;
+        use bitcoin::{absolute::LockTime, OutPoint, ScriptBuf, Sequence, Transaction, TxIn};
+        use bitcoin::{TxOut, Txid, Witness};
+        use std::{str::FromStr, sync::Arc};
+
+        let Some(db) = connect_scratch_db().await.map(Arc::new) else {
+            return;
+        };
+        let (rune, address) = ("PENDINGINDEXTEST", "bc1ppendingindex");
+        let utxo_hash = "0e3e2357e806b6cdb1f70b54c3a3a17b6714ee1f0e68bebb44a74b1efd512098";
+        for query in [
+            "DELETE FROM runes_utxos WHERE tx_hash = $1",
+            "DELETE FROM btc_utxos WHERE tx_hash = $1",
+        ] {
+            sqlx::query(query)
+                .bind(utxo_hash)
+                .execute(&db.pool)
+                .await
+                .unwrap();
+        }
+        db.insert_rune_utxo(&db::RuneUtxo {
+            block: 840_000,
+            tx_hash: utxo_hash.to_owned(),
+            output_n: 1,
+            rune: rune.to_owned(),
+            address: address.to_owned(),
+            amount: "400".to_owned(),
+            btc_amount: 600,
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        db.insert_btc_utxo(&db::BtcUtxo {
+            block: 840_000,
+            tx_hash: utxo_hash.to_owned(),
+            output_n: 1,
+            address: address.to_owned(),
+            amount: 600,
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+
+        let tx = Transaction {
+            version: 2,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: OutPoint {
+                    txid: Txid::from_str(utxo_hash).unwrap(),
+                    vout: 1,
+                },
+                script_sig: ScriptBuf::new(),
+                sequence: Sequence::MAX,
+                witness: Witness::new(),
+            }],
+            output: vec![TxOut {
+                value: 500,
+                script_pubkey: ScriptBuf::new(),
+            }],
+        };
+        let tx_hash = tx.txid().to_string();
+        sqlx::query("DELETE FROM submitted_txs WHERE tx_hash = $1")
+            .bind(&tx_hash)
+            .execute(&db.pool)
+            .await
+            .unwrap();
+        db.insert_submitted_tx(db::Transaction {
+            tx_hash,
+            raw_data: serialize_hex(&tx),
+            status: db::Transaction::STATUS_PENDING.to_owned(),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+
+        let req = BalancesRequest {
+            address: address.to_owned(),
+        };
+        let balances = |rune_balance: &str, btc_balance| AddressBalances {
+            address: address.to_owned(),
+            btc_balance: Some(btc_balance),
+            runes: vec![RuneBalanceEntry {
+                rune: rune.to_owned(),
+                balance: rune_balance.to_owned(),
+                confirmed_balance: None,
+            }],
+            ..Default::default()
+        };
+
+        // broadcast: the indexed balances still include the spent utxo
+        let mut res = balances("1000", 10_000);
+        req.subtract_pending_spends(&db, &mut res).await.unwrap();
+        assert_eq!(res.runes[0].balance, "600");
+        assert_eq!(res.btc_balance, Some(9_400));
+
+        // indexed: the balances already exclude it, it isn't subtracted twice
+        db.spent_rune_utxo(rune, utxo_hash, 1, 840_001)
+            .await
+            .unwrap();
+        db.spent_btc_utxo(utxo_hash, 1, 840_001).await.unwrap();
+        let mut res = balances("600", 9_400);
+        req.subtract_pending_spends(&db, &mut res).await.unwrap();
+        assert_eq!(res.runes[0].balance, "600");
+        assert_eq!(res.btc_balance, Some(9_400));
+    }
+}
@@ -1,8 +1,12 @@
 pub mod number_from_string {
-    use std::fmt::Display;
+    use std::fmt::{self, Display};
+    use std::marker::PhantomData;
     use std::str::FromStr;
 
-    use serde::{de, Deserialize, Deserializer, Serializer};
+    use serde::{de, Deserializer, Serializer};
+
+    /// largest integer which f64 represents exactly
+    const MAX_SAFE_FLOAT_INT: f64 = 9_007_199_254_740_991.0;
 
     pub fn serialize<T, S>(value: &T, serializer: S) -> Result<S::Ok, S::Error>
     where
@@ -12,14 +16,158 @@ pub mod number_from_string {
         serializer.collect_str(value)
     }
 
+    /// accepts both `"123"` and `123`, numbers beyond the f64 safe range must be sent as strings
     pub fn deserialize<'de, T, D>(deserializer: D) -> Result<T, D::Error>
     where
         T: FromStr,
         T::Err: Display,
         D: Deserializer<'de>,
     {
-        String::deserialize(deserializer)?
-            .parse()
-            .map_err(de::Error::custom)
+        deserializer.deserialize_any(NumberVisitor(PhantomData))
+    }
+
+    struct NumberVisitor<T>(PhantomData<T>);
+
+    impl<T> NumberVisitor<T>
+    where
+        T: FromStr,
+        T::Err: Display,
+    {
+        fn parse<E: de::Error>(v: &str) -> Result<T, E> {
+            v.parse().map_err(de::Error::custom)
+        }
+    }
+
+    impl<'de, T> de::Visitor<'de> for NumberVisitor<T>
+    where
+        T: FromStr,
+        T::Err: Display,
+    {
+        type Value = T;
+
+        fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str("a number or a string containing a number")
+        }
+
+        fn visit_str<E: de::Error>(self, v: &str) -> Result<T, E> {
+            Self::parse(v)
+        }
+
+        fn visit_u64<E: de::Error>(self, v: u64) -> Result<T, E> {
+            Self::parse(&v.to_string())
+        }
+
+        fn visit_i64<E: de::Error>(self, v: i64) -> Result<T, E> {
+            Self::parse(&v.to_string())
+        }
+
+        fn visit_u128<E: de::Error>(self, v: u128) -> Result<T, E> {
+            Self::parse(&v.to_string())
+        }
+
+        fn visit_i128<E: de::Error>(self, v: i128) -> Result<T, E> {
+            Self::parse(&v.to_string())
+        }
+
+        fn visit_f64<E: de::Error>(self, v: f64) -> Result<T, E> {
+            if v.fract() != 0.0 || v.abs() > MAX_SAFE_FLOAT_INT {
+                return Err(de::Error::custom(format!(
+                    "{} can't be represented precisely, pass it as a string",
+                    v
+                )));
+            }
+            Self::parse(&(v as i64).to_string())
+        }
+    }
+}
+
+/// `number_from_string` for optional fields, use with `#[serde(default)]`
+pub mod option_number_from_string {
+    use std::fmt::Display;
+    use std::str::FromStr;
+
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<T, S>(value: &Option<T>, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        T: Display,
+        S: Serializer,
+    {
+        match value {
+            Some(v) => serializer.collect_str(v),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, T, D>(deserializer: D) -> Result<Option<T>, D::Error>
+    where
+        T: FromStr,
+        T::Err: Display,
+        D: Deserializer<'de>,
+    {
+        #[derive(Deserialize)]
+        #[serde(bound(deserialize = "T: FromStr, T::Err: Display"))]
+        struct Number<T>(#[serde(with = "super::number_from_string")] T);
+
+        let value: Option<Number<T>> = Option::deserialize(deserializer)?;
+        Ok(value.map(|n| n.0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Amount {
+        #[serde(with = "super::number_from_string")]
+        value: u128,
+    }
+
+    #[test]
+    fn number_from_string_accepts_strings_and_numbers() {
+        let a: Amount = serde_json::from_str(r#"{"value":"1000"}"#).unwrap();
+        assert_eq!(a.value, 1000);
+        assert_eq!(serde_json::to_string(&a).unwrap(), r#"{"value":"1000"}"#);
+
+        let a: Amount = serde_json::from_str(r#"{"value":1000}"#).unwrap();
+        assert_eq!(a.value, 1000);
+        assert_eq!(serde_json::to_string(&a).unwrap(), r#"{"value":"1000"}"#);
+
+        assert!(serde_json::from_str::<Amount>(r#"{"value":-1}"#).is_err());
+        assert!(serde_json::from_str::<Amount>(r#"{"value":1.5}"#).is_err());
+        assert!(serde_json::from_str::<Amount>(r#"{"value":"abc"}"#).is_err());
+    }
+
+    #[test]
+    fn number_from_string_keeps_large_values_precise() {
+        let big = u128::MAX;
+        let json = format!(r#"{{"value":"{}"}}"#, big);
+        let a: Amount = serde_json::from_str(&json).unwrap();
+        assert_eq!(a.value, big);
+        assert_eq!(serde_json::to_string(&a).unwrap(), json);
+
+        // integers up to u64::MAX are parsed exactly, larger JSON numbers turn into f64
+        let a: Amount = serde_json::from_str(r#"{"value":9007199254740993}"#).unwrap();
+        assert_eq!(a.value, 9_007_199_254_740_993);
+        assert!(serde_json::from_str::<Amount>(r#"{"value":18446744073709551616}"#).is_err());
+    }
+
+    #[test]
+    fn option_number_from_string() {
+        #[derive(Debug, PartialEq, Serialize, Deserialize)]
+        struct OptAmount {
+            #[serde(default, with = "super::option_number_from_string")]
+            value: Option<u128>,
+        }
+
+        let a: OptAmount = serde_json::from_str(r#"{}"#).unwrap();
+        assert_eq!(a.value, None);
+        let a: OptAmount = serde_json::from_str(r#"{"value":null}"#).unwrap();
+        assert_eq!(a.value, None);
+        let a: OptAmount = serde_json::from_str(r#"{"value":"1000"}"#).unwrap();
+        assert_eq!(a.value, Some(1000));
+        let a: OptAmount = serde_json::from_str(r#"{"value":1000}"#).unwrap();
+        assert_eq!(serde_json::to_string(&a).unwrap(), r#"{"value":"1000"}"#);
     }
 }
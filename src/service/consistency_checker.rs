@@ -0,0 +1,193 @@
+use serde::Serialize;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::RwLock;
+use tokio::{task::JoinHandle, time::sleep};
+use tokio_util::sync::CancellationToken;
+
+use crate::{config, db};
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct RuneDivergence {
+    pub rune: String,
+    pub in_circulation: String,
+    pub balances: String,
+    pub unspent_utxos: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ConsistencyReport {
+    pub checked_at: i64,
+    pub sampled: u64,
+    /// runes where sum(balances) != sum(unspent utxos)
+    pub balance_utxo_mismatches: u64,
+    /// runes where sum(balances) != in_circulation
+    pub circulation_mismatches: u64,
+    /// samples which couldn't be checked because of db errors
+    pub failed: u64,
+    pub divergent: Vec<RuneDivergence>,
+}
+
+impl ConsistencyReport {
+    /// records the sums of a sampled rune, returns whether they diverge
+    fn add(&mut self, rune: &str, in_circulation: &str, balances: String, utxos: String) -> bool {
+        self.sampled += 1;
+
+        let balance_utxo_diverged = !same_amount(&balances, &utxos);
+        let circulation_diverged = !same_amount(&balances, in_circulation);
+        if !balance_utxo_diverged && !circulation_diverged {
+            return false;
+        }
+
+        if balance_utxo_diverged {
+            self.balance_utxo_mismatches += 1;
+        }
+        if circulation_diverged {
+            self.circulation_mismatches += 1;
+        }
+        self.divergent.push(RuneDivergence {
+            rune: rune.to_owned(),
+            in_circulation: in_circulation.to_owned(),
+            balances,
+            unspent_utxos: utxos,
+        });
+        true
+    }
+}
+
+pub type SharedConsistencyReport = Arc<RwLock<ConsistencyReport>>;
+
+/// Periodically compares balances, unspent utxos and `in_circulation`
+/// of randomly sampled runes and keeps the result of the last round.
+pub struct ConsistencyChecker {
+    db: Arc<db::Repo>,
+    cfg: config::ConsistencyCheckConfig,
+    report: SharedConsistencyReport,
+}
+
+impl ConsistencyChecker {
+    pub fn new(cfg: &config::ConsistencyCheckConfig, db: Arc<db::Repo>) -> Self {
+        Self {
+            db,
+            cfg: cfg.clone(),
+            report: Arc::new(RwLock::new(ConsistencyReport::default())),
+        }
+    }
+
+    /// the latest report, served by `GET /admin/consistency`
+    pub fn report(&self) -> SharedConsistencyReport {
+        self.report.clone()
+    }
+
+    pub fn start(self, cancel: CancellationToken) -> JoinHandle<()> {
+        tokio::spawn(self.run(cancel.clone()))
+    }
+
+    async fn run(self, stop_signal: CancellationToken) {
+        loop {
+            self.do_job().await;
+
+            tokio::select! {
+                _ = sleep(Duration::from_secs(self.cfg.interval)) => {
+                    continue;
+                }
+
+                _ = stop_signal.cancelled() => {
+                    log::info!("gracefully shutting down consistency checker");
+                    break;
+                }
+            };
+        }
+    }
+
+    async fn do_job(&self) {
+        let runes = match self.db.select_random_runes(self.cfg.sample_size).await {
+            Ok(r) => r,
+            Err(err) => {
+                error!("Failed to sample runes: error={}", err);
+                return;
+            }
+        };
+
+        let mut report = ConsistencyReport {
+            checked_at: chrono::Utc::now().timestamp(),
+            ..Default::default()
+        };
+
+        for rune in runes.iter() {
+            let balances = self.db.sum_runes_balances(&rune.rune).await;
+            let utxos = self.db.sum_unspent_runes_utxo(&rune.rune).await;
+            let (balances, utxos) = match (balances, utxos) {
+                (Ok(b), Ok(u)) => (b, u),
+                (Err(err), _) | (_, Err(err)) => {
+                    error!(
+                        "Failed to check rune consistency: rune={} error={}",
+                        rune.rune, err
+                    );
+                    report.sampled += 1;
+                    report.failed += 1;
+                    continue;
+                }
+            };
+
+            if report.add(
+                &rune.rune,
+                &rune.in_circulation,
+                balances.clone(),
+                utxos.clone(),
+            ) {
+                warn!(
+                    "RUNE state diverged: rune={} in_circulation={} balances={} unspent_utxos={}",
+                    rune.rune, rune.in_circulation, balances, utxos
+                );
+            }
+        }
+
+        if report.divergent.is_empty() && report.failed == 0 {
+            info!("Consistency check passed: sampled={}", report.sampled);
+        } else {
+            warn!(
+                "Consistency check found issues: sampled={} balance_utxo_mismatches={} circulation_mismatches={} failed={}",
+                report.sampled,
+                report.balance_utxo_mismatches,
+                report.circulation_mismatches,
+                report.failed
+            );
+        }
+        *self.report.write().await = report;
+    }
+}
+
+fn same_amount(a: &str, b: &str) -> bool {
+    match (a.parse::<u128>(), b.parse::<u128>()) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => a == b,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn divergent_rune_is_reported() {
+        use super::ConsistencyReport;
+
+        let mut report = ConsistencyReport::default();
+        assert!(!report.add("GOOD", "1000", "1000".to_owned(), "1000".to_owned()));
+        // a spent utxo whose balance wasn't decreased
+        assert!(report.add("BAD", "1000", "1400".to_owned(), "1000".to_owned()));
+
+        assert_eq!(report.sampled, 2);
+        assert_eq!(report.balance_utxo_mismatches, 1);
+        assert_eq!(report.circulation_mismatches, 1);
+        assert_eq!(report.divergent.len(), 1);
+        let divergence = &report.divergent[0];
+        assert_eq!(divergence.rune, "BAD");
+        assert_eq!(
+            (
+                divergence.balances.as_str(),
+                divergence.unspent_utxos.as_str()
+            ),
+            ("1400", "1000")
+        );
+    }
+}
@@ -3,7 +3,7 @@ use bitcoin::{
     locktime::absolute::LockTime, script::Builder, Address, Network, OutPoint, ScriptBuf, Sequence,
     Transaction, TxIn, TxOut, Txid, Witness,
 };
-use ordinals::{Artifact, Runestone, Terms};
+use ordinals::{Artifact, Runestone, SpacedRune, Terms};
 use serde::{Deserialize, Serialize};
 use std::str::FromStr;
 
@@ -94,6 +94,11 @@ impl From<&db::Rune> for RuneEntity {
 }
 
 impl RuneEntity {
+    /// false once the whole circulating supply is burned, derived so cached entries can't go stale
+    pub fn is_active(&self) -> bool {
+        self.in_circulation > 0
+    }
+
     pub fn add_mint(&mut self, amount: u128) -> bool {
         self.mints += 1;
         self.in_circulation += amount;
@@ -131,6 +136,7 @@ impl RuneEntity {
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(into = "BalanceView")]
 pub struct Balance {
     pub asset: Asset,
     pub address: String,
@@ -138,7 +144,32 @@ pub struct Balance {
     pub balance: u128,
 }
 
+/// serialized form of `Balance`, with the balance formatted for display
+#[derive(Serialize)]
+struct BalanceView {
+    asset: Asset,
+    address: String,
+    #[serde(with = "number_from_string")]
+    balance: u128,
+    display_balance: String,
+}
+
+impl From<Balance> for BalanceView {
+    fn from(b: Balance) -> Self {
+        Self {
+            display_balance: b.display_balance(),
+            asset: b.asset,
+            address: b.address,
+            balance: b.balance,
+        }
+    }
+}
+
 impl Balance {
+    pub fn display_balance(&self) -> String {
+        crate::utils::format_amount(self.balance, self.asset.decimals)
+    }
+
     pub fn increase(&mut self, amount: u128) -> bool {
         let r = self.balance.checked_add(amount);
         self.balance = r.unwrap_or(self.balance);
@@ -198,11 +229,17 @@ impl std::convert::From<&RuneUtxo> for db::RuneUtxo {
             amount: row.amount.to_string(),
             btc_amount: row.btc_amount,
             spend: row.spend,
+            spent_block: None,
         }
     }
 }
 
 impl RuneUtxo {
+    /// cache entry older versions wrote for outputs created without runes
+    pub fn is_no_runes(&self) -> bool {
+        self.rune.is_empty() && self.amount == 0
+    }
+
     pub fn tx_parent(&self) -> anyhow::Result<(TxIn, TxOut)> {
         let parent_in = TxIn {
             previous_output: OutPoint {
@@ -290,6 +327,7 @@ pub struct TradingPair {
 }
 
 impl TradingPair {
+    /// the base asset takes the metadata of `rune`, unit prices depend on its divisibility
     pub fn new(row: &db::TradingPair, rune: &db::Rune) -> Self {
         Self {
             id: row.id,
@@ -311,6 +349,14 @@ impl TradingPair {
         }
     }
 
+    /// name of the base rune in the runes table, pairs stored with the spaced name resolve too
+    pub fn base_rune_name(row: &db::TradingPair) -> String {
+        match SpacedRune::from_str(&row.base_asset) {
+            Ok(spaced) => spaced.rune.to_string(),
+            Err(_) => row.base_asset.clone(),
+        }
+    }
+
     pub fn get_pool_address(&self, net: Network) -> anyhow::Result<(Address, Address, Address)> {
         let pool_address = Address::from_str(&self.pool_address)?.require_network(net)?;
         let fee_address = Address::from_str(&self.fee_address)?.require_network(net)?;
@@ -347,6 +393,16 @@ impl TradingPair {
         (false, delta_percentage)
     }
 
+    /// Balances valued in the quote asset at the pool price. The base balance is worth
+    /// `base * quote / base`, exactly the quote balance, so it's twice the quote balance,
+    /// computed without the rounding of the f64 prices. An empty base side adds nothing.
+    pub fn tvl(&self) -> u128 {
+        if self.base_balance == 0 {
+            return self.quote_balance;
+        }
+        self.quote_balance.saturating_mul(2)
+    }
+
     pub fn reverse_price(&self) -> f64 {
         if self.base_balance == 0 {
             return 1.0;
@@ -362,6 +418,109 @@ impl TradingPair {
         (self.quote_balance as f64 / f64::powf(10.0, self.quote_asset.decimals as f64))
             / (self.base_balance as f64 / f64::powf(10.0, self.base_asset.decimals as f64))
     }
+
+    /// `price` as an exact decimal truncated to `PRICE_DECIMALS` digits
+    pub fn price_str(&self) -> String {
+        decimal_ratio(self.base_balance, self.quote_balance, 0)
+    }
+
+    pub fn price_in_units_str(&self) -> String {
+        let exp = self.quote_asset.decimals - self.base_asset.decimals;
+        decimal_ratio(self.base_balance, self.quote_balance, exp)
+    }
+
+    pub fn reverse_price_str(&self) -> String {
+        decimal_ratio(self.quote_balance, self.base_balance, 0)
+    }
+
+    pub fn reverse_price_in_units_str(&self) -> String {
+        let exp = self.base_asset.decimals - self.quote_asset.decimals;
+        decimal_ratio(self.quote_balance, self.base_balance, exp)
+    }
+
+    /// decimal strings keep the precision which f64 loses for large balances
+    pub fn prices(&self, as_strings: bool) -> PairPrices {
+        if as_strings {
+            return PairPrices {
+                price: Price::Decimal(self.price_str()),
+                price_in_units: Price::Decimal(self.price_in_units_str()),
+                reverse_price: Price::Decimal(self.reverse_price_str()),
+                reverse_price_in_units: Price::Decimal(self.reverse_price_in_units_str()),
+            };
+        }
+        PairPrices {
+            price: Price::Float(self.price()),
+            price_in_units: Price::Float(self.price_in_units()),
+            reverse_price: Price::Float(self.reverse_price()),
+            reverse_price_in_units: Price::Float(self.reverse_price_in_units()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(untagged)]
+pub enum Price {
+    Float(f64),
+    Decimal(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PairPrices {
+    pub price: Price,
+    pub price_in_units: Price,
+    pub reverse_price: Price,
+    pub reverse_price_in_units: Price,
+}
+
+/// fractional digits of the decimal prices
+pub const PRICE_DECIMALS: usize = 18;
+
+/// `num / den * 10^exp` truncated to `PRICE_DECIMALS` fractional digits, without trailing zeros.
+/// A zero `den` gives 1, like the f64 prices.
+fn decimal_ratio(num: u128, den: u128, exp: i32) -> String {
+    if den == 0 {
+        return "1".to_owned();
+    }
+
+    // digits of num / den with the decimal point after `point` of them
+    let mut digits = (num / den).to_string();
+    let point = digits.len() as i64 + exp as i64;
+    let frac_digits = (PRICE_DECIMALS as i64 + exp as i64).max(0);
+
+    let mut rem = num % den;
+    for _ in 0..frac_digits {
+        // 10 * rem = digit * den + rem, rem < den so this can't overflow
+        let (mut digit, mut next) = (0u8, 0u128);
+        for _ in 0..10 {
+            if next >= den - rem {
+                next -= den - rem;
+                digit += 1;
+            } else {
+                next += rem;
+            }
+        }
+        digits.push((b'0' + digit) as char);
+        rem = next;
+    }
+
+    // pad so there is at least one integer digit
+    if point <= 0 {
+        digits.insert_str(0, &"0".repeat(1 - point as usize));
+    } else if point as usize > digits.len() {
+        digits.push_str(&"0".repeat(point as usize - digits.len()));
+    }
+    let point = point.max(1) as usize;
+
+    let int = digits[..point].trim_start_matches('0');
+    let int = if int.is_empty() { "0" } else { int };
+    let frac = &digits[point..];
+    let frac = frac[..frac.len().min(PRICE_DECIMALS)].trim_end_matches('0');
+
+    if frac.is_empty() {
+        int.to_owned()
+    } else {
+        format!("{}.{}", int, frac)
+    }
 }
 
 #[derive(Debug, Clone, Default, Serialize)]
@@ -373,8 +532,62 @@ pub struct TxInputData {
     pub value: u64,
 }
 
+#[cfg(test)]
+impl TradingPair {
+    /// a RRR/BTC pool with a 0.5% fee and placeholder addresses
+    pub fn test_pair(base_balance: u128, quote_balance: u128) -> Self {
+        Self {
+            id: 0,
+            base_asset: Asset::rune("RRR", "RRR", "r", 0),
+            quote_asset: Asset::btc(),
+            pool_address: "address".to_owned(),
+            swap_fee_percent: 0.5,
+            fee_address: "address".to_owned(),
+            treasury_address: "address".to_owned(),
+            base_balance,
+            quote_balance,
+            locked_base_balance: 0,
+            locked_quote_balance: 0,
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
+    #[test]
+    fn burned_out_rune_is_inactive() {
+        use super::RuneEntity;
+        use crate::db;
+
+        let row = db::Rune {
+            rune: "BURNEDOUT".to_owned(),
+            premine: "1000".to_owned(),
+            minted: "0".to_owned(),
+            burned: "0".to_owned(),
+            in_circulation: "1000".to_owned(),
+            ..Default::default()
+        };
+        let mut rune = RuneEntity::from(&row);
+        assert!(rune.is_active());
+
+        assert!(rune.burn(1000));
+        assert_eq!(rune.in_circulation, 0);
+        assert!(!rune.is_active());
+
+        let row = db::Rune {
+            burned: "1000".to_owned(),
+            in_circulation: "0".to_owned(),
+            ..row
+        };
+        assert!(!RuneEntity::from(&row).is_active());
+
+        // entries cached before the flag existed have no such field
+        let mut cached = serde_json::to_value(RuneEntity::from(&row)).unwrap();
+        cached["in_circulation"] = "5".into();
+        let cached: RuneEntity = serde_json::from_value(cached).unwrap();
+        assert!(cached.is_active());
+    }
+
     #[test]
     fn price_calculation() {
         use super::{Asset, TradingPair};
@@ -422,6 +635,46 @@ mod tests {
         println!("{}", delta)
     }
 
+    #[test]
+    fn string_prices_are_exact() {
+        use super::{Asset, Price, TradingPair};
+
+        let mut tp = TradingPair {
+            base_asset: Asset::rune("RRR", "RRR", "r", 2),
+            ..TradingPair::test_pair(40, 1)
+        };
+        assert_eq!(tp.price_str(), "40");
+        assert_eq!(tp.reverse_price_str(), "0.025");
+        assert_eq!(tp.price_in_units_str(), "40000000");
+        assert_eq!(tp.reverse_price_in_units_str(), "0.000000025");
+
+        // f64 rounds both the balance and the ratio
+        tp.base_balance = 9_007_199_254_740_993;
+        assert_eq!(tp.price(), 9_007_199_254_740_992.0);
+        assert_eq!(tp.price_str(), "9007199254740993");
+
+        tp.base_balance = u128::MAX;
+        tp.quote_balance = u128::MAX - 1;
+        assert_eq!(tp.price(), 1.0);
+        assert_eq!(tp.price_str(), "1");
+        assert_eq!(tp.reverse_price_str(), "0.999999999999999999");
+
+        tp.base_balance = 1;
+        tp.quote_balance = 3;
+        assert_eq!(tp.price_str(), "0.333333333333333333");
+        assert_eq!(tp.price_in_units_str(), "333333.333333333333333333");
+
+        tp.quote_balance = 0;
+        assert_eq!(tp.price_str(), "1");
+
+        let prices = tp.prices(true);
+        assert_eq!(prices.price, Price::Decimal("1".to_owned()));
+        assert_eq!(
+            serde_json::to_string(&tp.prices(false).price).unwrap(),
+            "1.0"
+        );
+    }
+
     #[test]
     fn balance_serialization() {
         use super::{Asset, Balance};
@@ -459,4 +712,65 @@ mod tests {
 
         //assert_eq!(balance.asset, b.asset);
     }
+
+    #[test]
+    fn base_asset_uses_the_etching_divisibility() {
+        use super::TradingPair;
+        use crate::db;
+
+        let row = db::TradingPair {
+            base_asset: "UNCOMMON•GOODS".to_owned(),
+            base_balance: "100000".to_owned(),
+            quote_balance: "100000000".to_owned(),
+            ..Default::default()
+        };
+        assert_eq!(TradingPair::base_rune_name(&row), "UNCOMMONGOODS");
+        let plain = db::TradingPair {
+            base_asset: "UNCOMMONGOODS".to_owned(),
+            ..row.clone()
+        };
+        assert_eq!(TradingPair::base_rune_name(&plain), "UNCOMMONGOODS");
+
+        let rune = db::Rune {
+            rune: "UNCOMMONGOODS".to_owned(),
+            display_name: "UNCOMMON•GOODS".to_owned(),
+            symbol: "⧉".to_owned(),
+            divisibility: 2,
+            ..Default::default()
+        };
+        let tp = TradingPair::new(&row, &rune);
+        assert_eq!(tp.base_asset.decimals, 2);
+        assert_eq!(
+            tp.base_asset.display_name.as_deref(),
+            Some("UNCOMMON•GOODS")
+        );
+        assert_eq!(tp.price_in_units(), 1000.0);
+        assert_eq!(tp.price_in_units_str(), "1000");
+    }
+
+    #[test]
+    fn tvl_is_exact_for_large_balances() {
+        use super::TradingPair;
+        use crate::db;
+
+        let row = db::TradingPair {
+            base_asset: "UNCOMMONGOODS".to_owned(),
+            base_balance: "3".to_owned(),
+            quote_balance: "100000000000000001".to_owned(),
+            ..Default::default()
+        };
+        let rune = db::Rune {
+            rune: "UNCOMMONGOODS".to_owned(),
+            ..Default::default()
+        };
+        let tp = TradingPair::new(&row, &rune);
+        // f64 can't hold the odd quote balance, nor a third of it
+        assert_eq!(tp.tvl(), 200_000_000_000_000_002);
+
+        let empty_base = TradingPair {
+            base_balance: 0,
+            ..tp.clone()
+        };
+        assert_eq!(empty_base.tvl(), 100_000_000_000_000_001);
+    }
 }
@@ -1,8 +1,12 @@
+pub mod consistency_checker;
 pub mod entities;
 
 mod in_memory_cache;
 mod state_provider;
+pub mod tip_cache;
+pub mod top_runes_cache;
 pub mod tx_watchdog;
+pub mod webhooks;
 
 pub use in_memory_cache::BtcIndexCache;
 pub use state_provider::StateProvider;
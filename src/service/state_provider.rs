@@ -1,7 +1,9 @@
+use std::future::Future;
 use std::str::FromStr;
 use std::sync::Arc;
 
 use super::entities::{self, Asset, Balance, RuneEntity};
+use super::webhooks::{WebhookEvent, WebhookNotifier};
 use crate::cache::CacheRepo;
 use crate::db;
 use crate::db::Repo;
@@ -10,6 +12,9 @@ pub struct StateProvider {
     db: Arc<Repo>,
     cache: CacheRepo,
     disable_rune_log: bool,
+    webhooks: WebhookNotifier,
+    /// events of the block being indexed, sent once it's finished
+    block_events: Vec<WebhookEvent>,
 }
 
 impl StateProvider {
@@ -18,14 +23,54 @@ impl StateProvider {
             db,
             cache,
             disable_rune_log,
+            webhooks: WebhookNotifier::default(),
+            block_events: Vec::new(),
         }
     }
 
+    /// rewinds the runes index to `height`, the cache is flushed as it may hold
+    /// runes, balances and utxos above it. Mints are only known from the rune log,
+    /// so it can't be rewound while the log is disabled.
+    pub async fn rewind(&mut self, indexer_id: &str, height: i64) -> anyhow::Result<()> {
+        if self.disable_rune_log {
+            anyhow::bail!("the runes index can't be rewound with indexers.disable_rune_log set");
+        }
+        // rows indexed before the spend and log heights were recorded have none,
+        // they wouldn't be reverted
+        let since = self.db.get_spent_block_since(indexer_id).await?;
+        if height < since {
+            anyhow::bail!(
+                "can't rewind the runes index to {}, heights are recorded since {}",
+                height,
+                since
+            );
+        }
+        self.db.rewind_runes_index(indexer_id, height).await?;
+        self.cache.flush_all().await?;
+        // the reverted txs are indexed again with their events
+        self.block_events.clear();
+        Ok(())
+    }
+
+    /// sends the events of the finished block, a retried block never sends them twice
+    pub fn send_block_events(&mut self, block_hash: &str) {
+        for event in self.block_events.drain(..) {
+            self.webhooks.notify_in_block(event, block_hash);
+        }
+    }
+
+    pub fn with_webhooks(mut self, webhooks: WebhookNotifier) -> Self {
+        self.webhooks = webhooks;
+        self
+    }
+
     pub fn db(&self) -> Arc<Repo> {
         self.db.clone()
     }
 
     pub async fn warm_up_cache(&mut self) -> anyhow::Result<()> {
+        let db = self.db.clone();
+        let _permit = db.bulk_permit().await;
         let runes_count = self.db.count_runes(None).await?;
         let mut rune_offset = 0_i32;
         let limit = 10000_i32;
@@ -129,11 +174,41 @@ impl StateProvider {
         Ok(entities::RuneEntity::from(rune_row))
     }
 
-    pub async fn get_rune_name_by_id(&mut self, rune_id: &ordinals::RuneId) -> Option<String> {
-        if let Ok(rune) = self.cache.get_rune_name(rune_id.block, rune_id.tx).await {
-            return Some(rune);
-        }
-        None
+    /// falls back to the db on a cache miss and repopulates the cache,
+    /// a missing name makes the edict invalid, so an evicted key or a db error must not burn runes
+    pub async fn get_rune_name_by_id(
+        &mut self,
+        rune_id: &ordinals::RuneId,
+    ) -> anyhow::Result<Option<String>> {
+        let cached = self
+            .cache
+            .get_rune_name(rune_id.block, rune_id.tx)
+            .await
+            .ok();
+        let (db, cache) = (self.db.clone(), &mut self.cache);
+
+        let name = read_through(cached, || async move {
+            let row = db
+                .get_rune_by_id(rune_id.block as i64, rune_id.tx as i32)
+                .await?;
+            warn!(
+                "RUNE({}) name wasn't cached, loaded from db: rune_id={}",
+                row.rune, rune_id
+            );
+            if let Err(err) = cache.set_rune(&RuneEntity::from(&row)).await {
+                error!(
+                    "failed to put rune to cache: error={} rune={}",
+                    err, row.rune
+                );
+            }
+            Ok(row.rune)
+        })
+        .await;
+
+        name.map_err(|err| {
+            error!("can't get rune from db: error={} rune_id={}", err, rune_id);
+            err.into()
+        })
     }
 
     pub async fn store_new_rune(&mut self, rune_row: &db::Rune) -> anyhow::Result<()> {
@@ -142,6 +217,12 @@ impl StateProvider {
         self.cache
             .set_rune(&RuneEntity::from(rune_row.clone()))
             .await?;
+
+        self.block_events.push(WebhookEvent::Etching {
+            rune: rune_row.rune.clone(),
+            block: rune_row.block,
+            tx_hash: rune_row.etching_tx.clone(),
+        });
         Ok(())
     }
 
@@ -160,6 +241,32 @@ impl StateProvider {
 
         Ok(())
     }
+    /// burns runes allocated to an OP_RETURN output of the tx and logs it
+    pub async fn burn_rune_in_tx(
+        &mut self,
+        rune: &str,
+        amount: u128,
+        tx_hash: &str,
+        block: i64,
+    ) -> anyhow::Result<()> {
+        self.burn_rune(rune, amount).await?;
+        if self.disable_rune_log {
+            return Ok(());
+        }
+
+        let log = db::RuneLog {
+            id: 0,
+            tx_hash: tx_hash.to_owned(),
+            rune: rune.to_owned(),
+            address: String::new(),
+            action: db::RuneLog::BURN.to_owned(),
+            value: amount.to_string(),
+            block: Some(block),
+        };
+        self.db.insert_rune_log(&log).await?;
+        Ok(())
+    }
+
     pub async fn update_rune_mint(&mut self, rune: &RuneEntity) -> anyhow::Result<()> {
         self.cache.set_rune(rune).await?;
         self.db
@@ -212,6 +319,7 @@ impl StateProvider {
         &mut self,
         utxo: &entities::RuneUtxo,
         action: &str,
+        timestamp: i64,
     ) -> anyhow::Result<()> {
         // 1. + balance in the cache
         // 2. update balance in the db
@@ -264,6 +372,31 @@ impl StateProvider {
             return Err(err.into());
         }
 
+        if let Err(err) = self.db.touch_rune_activity(&utxo.rune, timestamp).await {
+            error!(
+                "failed to update rune activity: error={} rune={}",
+                err, utxo.rune
+            );
+        }
+
+        match action {
+            db::RuneLog::MINT => self.block_events.push(WebhookEvent::Mint {
+                rune: utxo.rune.clone(),
+                address: utxo.address.clone(),
+                amount: utxo.amount,
+                tx_hash: utxo.tx_hash.clone(),
+                vout: utxo.output_n,
+            }),
+            db::RuneLog::INCOME => self.block_events.push(WebhookEvent::Transfer {
+                rune: utxo.rune.clone(),
+                address: utxo.address.clone(),
+                amount: utxo.amount,
+                tx_hash: utxo.tx_hash.clone(),
+                vout: utxo.output_n,
+            }),
+            _ => {}
+        }
+
         if self.disable_rune_log {
             return Ok(());
         }
@@ -275,6 +408,7 @@ impl StateProvider {
             address: utxo.address.clone(),
             value: utxo.amount.to_string(),
             action: action.to_string(),
+            block: Some(utxo.block),
         };
 
         if let Err(err) = self.db.insert_rune_log(&log).await {
@@ -284,42 +418,66 @@ impl StateProvider {
         Ok(())
     }
 
+    /// `block` and `timestamp` are of the spending tx.
+    /// A db error is returned instead of skipping the input, which would leave its runes unspent
     pub async fn spent_rune_utxo(
         &mut self,
         input: &bitcoin::TxIn,
         new_tx_id: &str,
-    ) -> Option<Vec<entities::RuneUtxo>> {
+        block: i64,
+        timestamp: i64,
+    ) -> anyhow::Result<Vec<entities::RuneUtxo>> {
         let parent_txid = input.previous_output.txid.to_string();
         let vout = input.previous_output.vout;
 
-        //        let Ok(utxos) = self.db.get_runes_utxo(&parent_txid, vout).await else {
-
-        let mut utxos = match self.cache.get_runes_utxos(&parent_txid, vout).await {
+        let cached = self.cache.get_runes_utxos(&parent_txid, vout).await;
+        let db = self.db.clone();
+        let loaded = input_rune_utxos(cached, || async {
+            let rows = db
+                .get_rune_utxos_by_outpoint(&parent_txid, vout as i32)
+                .await?;
+            Ok(rows
+                .iter()
+                .filter(|u| !u.spend)
+                .map(entities::RuneUtxo::from)
+                .collect::<Vec<_>>())
+        })
+        .await;
+
+        let mut utxos = match loaded {
             Ok(u) => u,
             Err(err) => {
-                error!("can't get utxo from cache error={}", err);
-                return None;
+                error!(
+                    "can't get rune utxos from db: error={} tx_hash={} vout={}",
+                    err, parent_txid, vout
+                );
+                return Err(err.into());
             }
         };
-        if utxos.is_empty() {
-            return None;
-        }
 
         let mut res_list = Vec::new();
         for utxo in utxos.iter_mut() {
             if let Err(err) = self
                 .db
-                .spent_rune_utxo(&utxo.rune, &parent_txid, vout as i32)
+                .spent_rune_utxo(&utxo.rune, &parent_txid, vout as i32, block)
                 .await
             {
                 error!(
                     "failed to mark rune utxo as spend: error={} tx_hash={} vout={}",
                     err, parent_txid, vout
                 );
+                return Err(err.into());
             }
             utxo.spend = true;
             let _ = self.cache.set_runes_utxo(utxo).await;
 
+            if let Err(err) = self.db.touch_rune_activity(&utxo.rune, timestamp).await {
+                error!(
+                    "failed to update rune activity: error={} rune={}",
+                    err, utxo.rune
+                );
+            }
+
             let mut balance = self.get_rune_balance(&utxo.rune, &utxo.address).await;
             if !balance.decrease(utxo.amount) {
                 error!("WTF?!");
@@ -335,6 +493,7 @@ impl StateProvider {
                     "failed to update balance: error={} rune={} address={}",
                     err, &utxo.rune, &utxo.address
                 );
+                return Err(err.into());
             }
             if let Err(err) = self.cache.set_balance(&balance).await {
                 error!(
@@ -345,7 +504,7 @@ impl StateProvider {
 
             if self.disable_rune_log {
                 res_list.push(utxo.clone());
-                return Some(res_list);
+                continue;
             }
 
             let res = self
@@ -357,6 +516,7 @@ impl StateProvider {
                     address: utxo.address.clone(),
                     action: db::RuneLog::EXPENCE.into(),
                     value: utxo.amount.to_string(),
+                    block: Some(block),
                 })
                 .await;
             match res {
@@ -366,11 +526,155 @@ impl StateProvider {
                         "failed to add rune log: error={} tx_hash={}",
                         err, new_tx_id,
                     );
-                    return None;
+                    return Err(err.into());
                 }
             }
         }
 
-        Some(res_list)
+        Ok(res_list)
+    }
+}
+
+/// the cached value or, on a miss, the one loaded from the db; `None` if neither has it
+async fn read_through<T, F, Fut>(cached: Option<T>, load: F) -> sqlx::Result<Option<T>>
+where
+    F: FnOnce() -> Fut,
+    Fut: Future<Output = sqlx::Result<T>>,
+{
+    if let Some(value) = cached {
+        return Ok(Some(value));
+    }
+
+    match load().await {
+        Ok(value) => Ok(Some(value)),
+        Err(sqlx::Error::RowNotFound) => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+/// runes of a spent outpoint: the cached utxos or, on a miss, the ones loaded from the db
+/// by the outpoint index. Outputs without runes are always a miss, they aren't cached.
+/// A `no_runes` marker left by older versions or an already spent utxo is a hit with nothing to spend
+async fn input_rune_utxos<E, F, Fut>(
+    cached: Result<Vec<entities::RuneUtxo>, E>,
+    load: F,
+) -> sqlx::Result<Vec<entities::RuneUtxo>>
+where
+    E: std::fmt::Display,
+    F: FnOnce() -> Fut,
+    Fut: Future<Output = sqlx::Result<Vec<entities::RuneUtxo>>>,
+{
+    let cached = cache_hit(cached).map(|list| {
+        list.into_iter()
+            .filter(|u| !u.is_no_runes() && !u.spend)
+            .collect::<Vec<_>>()
+    });
+
+    Ok(read_through(cached, load).await?.unwrap_or_default())
+}
+
+/// an empty list is a miss, outputs known to have no runes are cached as a marker
+fn cache_hit<T, E: std::fmt::Display>(cached: Result<Vec<T>, E>) -> Option<Vec<T>> {
+    match cached {
+        Ok(list) if !list.is_empty() => Some(list),
+        Ok(_) => None,
+        Err(err) => {
+            warn!("can't get utxo from cache error={}", err);
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[tokio::test]
+    async fn cache_miss_reads_from_db() {
+        use super::read_through;
+
+        // cold cache, the rune is in the db
+        let name = read_through(None, || async { Ok("UNCOMMON•GOODS".to_owned()) }).await;
+        assert_eq!(name.unwrap(), Some("UNCOMMON•GOODS".to_owned()));
+
+        // cached values don't touch the db
+        let name = read_through(Some("RUNE".to_owned()), || async {
+            Err(sqlx::Error::PoolClosed)
+        })
+        .await;
+        assert_eq!(name.unwrap(), Some("RUNE".to_owned()));
+
+        let name =
+            read_through::<String, _, _>(None, || async { Err(sqlx::Error::RowNotFound) }).await;
+        assert_eq!(name.unwrap(), None);
+
+        let name =
+            read_through::<String, _, _>(None, || async { Err(sqlx::Error::PoolClosed) }).await;
+        assert!(name.is_err());
+    }
+
+    #[tokio::test]
+    async fn cold_cache_spend_reads_db_utxos() {
+        use super::{cache_hit, read_through};
+
+        let db_utxos = || async { Ok(vec!["RUNE•A", "RUNE•B"]) };
+
+        // nothing cached for the outpoint
+        let cached = cache_hit::<&str, String>(Ok(vec![]));
+        assert_eq!(cached, None);
+        let utxos = read_through(cached, db_utxos).await.unwrap();
+        assert_eq!(utxos, Some(vec!["RUNE•A", "RUNE•B"]));
+
+        // the cache is unreachable
+        let cached = cache_hit::<&str, _>(Err("connection refused"));
+        let utxos = read_through(cached, db_utxos).await.unwrap();
+        assert_eq!(utxos, Some(vec!["RUNE•A", "RUNE•B"]));
+
+        let cached = cache_hit::<_, String>(Ok(vec!["RUNE•A"]));
+        let utxos = read_through(cached, db_utxos).await.unwrap();
+        assert_eq!(utxos, Some(vec!["RUNE•A"]));
+    }
+
+    #[tokio::test]
+    async fn spent_input_lookup() {
+        use super::entities::RuneUtxo;
+        use super::input_rune_utxos;
+
+        let rune_utxo = RuneUtxo {
+            tx_hash: "aa".to_owned(),
+            rune: "RUNE".to_owned(),
+            amount: 100,
+            ..Default::default()
+        };
+
+        // a marker of an output without runes, the db isn't asked
+        let marker = Ok::<_, String>(vec![RuneUtxo {
+            tx_hash: "aa".to_owned(),
+            ..Default::default()
+        }]);
+        let utxos = input_rune_utxos(marker, || async { Err(sqlx::Error::PoolClosed) }).await;
+        assert!(utxos.unwrap().is_empty());
+
+        // unknown outpoint and an empty store
+        let utxos = input_rune_utxos(Ok::<_, String>(vec![]), || async { Ok(vec![]) }).await;
+        assert!(utxos.unwrap().is_empty());
+
+        // a failing store must not look like an input without runes
+        let utxos = input_rune_utxos(Ok::<_, String>(vec![]), || async {
+            Err(sqlx::Error::PoolClosed)
+        })
+        .await;
+        assert!(utxos.is_err());
+        let utxos = input_rune_utxos(Err("connection refused"), || async {
+            Err(sqlx::Error::PoolTimedOut)
+        })
+        .await;
+        assert!(utxos.is_err());
+
+        let utxos = input_rune_utxos(Ok::<_, String>(vec![]), || async {
+            Ok(vec![rune_utxo.clone()])
+        })
+        .await
+        .unwrap();
+        assert_eq!(utxos.len(), 1);
+        assert_eq!(utxos[0].amount, 100);
     }
 }
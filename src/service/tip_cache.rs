@@ -0,0 +1,166 @@
+use bitcoincore_rpc::{Client, RpcApi};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tokio::{task::JoinHandle, time::sleep};
+use tokio_util::sync::CancellationToken;
+
+use crate::{config, db, indexer};
+
+const REFRESH_INTERVAL: u64 = 5;
+/// seconds after which the cached state is ignored, e.g. when the node is unreachable
+pub const MAX_AGE: i64 = 60;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct TipState {
+    pub tip_height: i64,
+    pub indexed_height: i64,
+    pub updated_at: i64,
+}
+
+impl TipState {
+    /// number of blocks the runes index is behind the node tip
+    pub fn index_lag(&self) -> i64 {
+        (self.tip_height - self.indexed_height).max(0)
+    }
+
+    pub fn is_stale(&self, now: i64) -> bool {
+        now - self.updated_at > MAX_AGE
+    }
+}
+
+/// Shared node tip and indexed height, so read endpoints
+/// don't have to call the node on every request.
+#[derive(Clone, Default)]
+pub struct TipCache {
+    state: Arc<RwLock<Option<TipState>>>,
+}
+
+impl TipCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// `None` until the first refresh succeeded or when the last one is older than `MAX_AGE`
+    pub fn get(&self) -> Option<TipState> {
+        self.get_at(chrono::Utc::now().timestamp())
+    }
+
+    fn get_at(&self, now: i64) -> Option<TipState> {
+        self.state.read().unwrap().filter(|s| !s.is_stale(now))
+    }
+
+    pub fn update(&self, tip_height: i64, indexed_height: i64) {
+        self.set(TipState {
+            tip_height,
+            indexed_height,
+            updated_at: chrono::Utc::now().timestamp(),
+        });
+    }
+
+    fn set(&self, state: TipState) {
+        *self.state.write().unwrap() = Some(state);
+    }
+
+    pub fn tip_height(&self) -> Option<i64> {
+        self.get().map(|s| s.tip_height)
+    }
+
+    pub fn index_lag(&self) -> Option<i64> {
+        self.get().map(|s| s.index_lag())
+    }
+}
+
+pub struct TipRefresher {
+    rpc: Arc<Client>,
+    db: Arc<db::Repo>,
+    cache: TipCache,
+}
+
+impl TipRefresher {
+    pub fn new(
+        btc_cfg: &config::BTCConfig,
+        db: Arc<db::Repo>,
+        cache: TipCache,
+    ) -> anyhow::Result<Self> {
+        let rpc = Arc::new(btc_cfg.rpc_client()?);
+        Ok(Self { rpc, db, cache })
+    }
+
+    pub fn start(self, cancel: CancellationToken) -> JoinHandle<()> {
+        tokio::spawn(self.run(cancel.clone()))
+    }
+
+    async fn run(self, stop_signal: CancellationToken) {
+        loop {
+            self.refresh().await;
+
+            tokio::select! {
+                _ = sleep(Duration::from_secs(REFRESH_INTERVAL)) => {
+                    continue;
+                }
+
+                _ = stop_signal.cancelled() => {
+                    log::info!("gracefully shutting down tip refresher");
+                    break;
+                }
+            };
+        }
+    }
+
+    async fn refresh(&self) {
+        // the rpc client blocks, keep it off the async workers
+        let rpc = self.rpc.clone();
+        let tip_height = match tokio::task::spawn_blocking(move || rpc.get_block_count()).await {
+            Ok(Ok(height)) => height as i64,
+            Ok(Err(err)) => {
+                error!("Can't get best BTC block error={}", err);
+                return;
+            }
+            Err(err) => {
+                error!("Best BTC block lookup failed error={}", err);
+                return;
+            }
+        };
+
+        let indexed_height = match self
+            .db
+            .get_last_indexed_block(indexer::ETCHING_INDEXER_ID)
+            .await
+        {
+            Ok(block) => block.height,
+            Err(err) => {
+                error!("Can't get last indexed block error={}", err);
+                return;
+            }
+        };
+
+        self.cache.update(tip_height, indexed_height);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn stale_tip_is_ignored() {
+        use super::{TipCache, TipState, MAX_AGE};
+        let now = 1_700_000_000;
+        let cache = TipCache::new();
+        assert_eq!(cache.get_at(now), None);
+
+        let state = TipState {
+            tip_height: 840_010,
+            indexed_height: 840_000,
+            updated_at: now - MAX_AGE,
+        };
+        cache.set(state);
+        assert_eq!(cache.get_at(now), Some(state));
+        assert_eq!(cache.get_at(now).map(|s| s.index_lag()), Some(10));
+
+        assert_eq!(cache.get_at(now + 1), None);
+
+        // a refresh makes it usable again
+        cache.update(840_011, 840_011);
+        assert_eq!(cache.tip_height(), Some(840_011));
+        assert_eq!(cache.index_lag(), Some(0));
+    }
+}
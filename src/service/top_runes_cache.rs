@@ -0,0 +1,95 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+use crate::db;
+
+/// seconds a ranking is served from memory before it's queried again
+pub const MAX_AGE: i64 = 60;
+
+#[derive(Clone)]
+struct Ranking {
+    rows: Arc<Vec<db::TopRune>>,
+    fetched_at: i64,
+}
+
+/// Leaderboard rankings shared by the top runes requests. Ranking by holders or supply
+/// casts every balance or rune to NUMERIC, so each ranking is queried at most once per
+/// `MAX_AGE` instead of on every request.
+#[derive(Clone, Default)]
+pub struct TopRunesCache {
+    rankings: Arc<Mutex<HashMap<String, Ranking>>>,
+}
+
+impl TopRunesCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The first `limit` runes of the `by` ranking, queried when the cached one is missing
+    /// or older than `MAX_AGE`. Concurrent requests wait for a single query.
+    pub async fn get(
+        &self,
+        db: &db::Repo,
+        by: &str,
+        limit: i32,
+        min_holder_balance: &str,
+    ) -> sqlx::Result<Arc<Vec<db::TopRune>>> {
+        let key = format!("{}:{}:{}", by, limit, min_holder_balance);
+        let now = chrono::Utc::now().timestamp();
+
+        let mut rankings = self.rankings.lock().await;
+        if let Some(rows) = fresh(&rankings, &key, now) {
+            return Ok(rows);
+        }
+
+        let rows = Arc::new(db.select_top_runes(by, limit, min_holder_balance).await?);
+        rankings.insert(
+            key,
+            Ranking {
+                rows: rows.clone(),
+                fetched_at: now,
+            },
+        );
+        Ok(rows)
+    }
+}
+
+fn fresh(
+    rankings: &HashMap<String, Ranking>,
+    key: &str,
+    now: i64,
+) -> Option<Arc<Vec<db::TopRune>>> {
+    rankings
+        .get(key)
+        .filter(|r| now - r.fetched_at <= MAX_AGE)
+        .map(|r| r.rows.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn old_ranking_is_queried_again() {
+        use super::{fresh, Ranking, MAX_AGE};
+        use crate::db;
+        use std::{collections::HashMap, sync::Arc};
+
+        let now = 1_700_000_000;
+        let mut rankings = HashMap::new();
+        assert!(fresh(&rankings, "holders:100:1", now).is_none());
+
+        rankings.insert(
+            "holders:100:1".to_owned(),
+            Ranking {
+                rows: Arc::new(vec![db::TopRune::default()]),
+                fetched_at: now - MAX_AGE,
+            },
+        );
+        assert_eq!(fresh(&rankings, "holders:100:1", now).unwrap().len(), 1);
+        // another ranking or holder threshold isn't served from it
+        assert!(fresh(&rankings, "supply:100:1", now).is_none());
+        assert!(fresh(&rankings, "holders:100:1000", now).is_none());
+
+        assert!(fresh(&rankings, "holders:100:1", now + 1).is_none());
+    }
+}
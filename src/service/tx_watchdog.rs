@@ -1,27 +1,45 @@
 use bitcoin::Txid;
-use bitcoincore_rpc::{Auth, Client, RpcApi};
+use bitcoincore_rpc::{Client, RpcApi};
 use chrono::{TimeZone, Utc};
+use std::collections::HashMap;
+use std::fmt;
 use std::time::Duration;
 use std::{str::FromStr, sync::Arc};
 use tokio::{task::JoinHandle, time::sleep};
 use tokio_util::sync::CancellationToken;
 
+use super::webhooks::{WebhookEvent, WebhookNotifier};
 use crate::{config, db};
 
 pub struct TxWatchdog {
     db: Arc<db::Repo>,
     rpc: Client,
+    cfg: config::TxWatchdogConfig,
+    // tx_hash -> number of consecutive rejected rebroadcasts
+    rebroadcast_failures: HashMap<String, u32>,
+    webhooks: WebhookNotifier,
 }
 
 impl TxWatchdog {
-    pub fn new(btc_cfg: &config::BTCConfig, db: Arc<db::Repo>) -> Self {
-        let rpc = Client::new(
-            &btc_cfg.address,
-            Auth::UserPass(btc_cfg.rpc_user.clone(), btc_cfg.rpc_password.clone()),
-        )
-        .unwrap();
+    pub fn new(
+        btc_cfg: &config::BTCConfig,
+        cfg: &config::TxWatchdogConfig,
+        db: Arc<db::Repo>,
+    ) -> Self {
+        let rpc = btc_cfg.rpc_client().unwrap();
+
+        Self {
+            db,
+            rpc,
+            cfg: cfg.clone(),
+            rebroadcast_failures: HashMap::new(),
+            webhooks: WebhookNotifier::default(),
+        }
+    }
 
-        Self { db, rpc }
+    pub fn with_webhooks(mut self, webhooks: WebhookNotifier) -> Self {
+        self.webhooks = webhooks;
+        self
     }
 
     pub fn start(self, cancel: CancellationToken) -> JoinHandle<()> {
@@ -55,6 +73,7 @@ impl TxWatchdog {
                 return;
             }
         };
+        forget_settled_txs(&mut self.rebroadcast_failures, &pending_txs);
 
         for tx in pending_txs.iter() {
             let txid = match Txid::from_str(&tx.tx_hash) {
@@ -69,18 +88,37 @@ impl TxWatchdog {
                 Ok(info) => info,
                 Err(err) => {
                     let created_at = Utc.timestamp_opt(tx.created_at, 0).unwrap();
-                    let now = Utc::now();
+                    let age = Utc::now().signed_duration_since(created_at);
 
-                    // Calculate the duration between now and created_at
-                    let duration = now.signed_duration_since(created_at);
+                    if age < chrono::TimeDelta::seconds(self.cfg.pending_tx_max_age) {
+                        continue;
+                    }
 
-                    // Check if the duration is 1 hours or more
-                    if duration >= chrono::TimeDelta::hours(1) {
+                    let rebroadcasted = self.rebroadcast(tx);
+                    let failures = self.rebroadcast_failures.get(&tx.tx_hash).copied();
+                    let decision = pending_tx_decision(
+                        &self.cfg,
+                        age.num_seconds(),
+                        rebroadcasted,
+                        failures.unwrap_or_default(),
+                    );
+                    if decision == PendingTxDecision::Fail {
                         error!(
-                            "unable to get tx status: tx_hash={} error={}",
-                            tx.tx_hash, err
+                            "unable to get tx status: tx_hash={} age={}s rebroadcast_failures={} error={}",
+                            tx.tx_hash,
+                            age.num_seconds(),
+                            failures.unwrap_or_default(),
+                            err
                         );
-                        self.fail_tx(tx).await;
+                        match fail_tx(&self.db, tx).await {
+                            Ok(_) => {
+                                self.rebroadcast_failures.remove(&tx.tx_hash);
+                            }
+                            Err(err) => error!(
+                                "Failed to mark tx as failed, it stays pending: context={} request_id={} tx_hash={} error={}",
+                                tx.context, tx.request_id, tx.tx_hash, err
+                            ),
+                        }
                     }
 
                     continue;
@@ -96,231 +134,695 @@ impl TxWatchdog {
                 continue;
             }
 
-            let request = match self.db.get_liquidity_change_request(&tx.request_id).await {
-                Ok(request) => request,
+            let result = match self.db.get_liquidity_change_request(&tx.request_id).await {
+                Ok(request) => match Action::from_request(&request) {
+                    Some(action) => confirm_liquidity_change(&self.db, tx, &request, action).await,
+                    None => Err(anyhow::anyhow!("unknown action({})", request.action)),
+                },
+                Err(err) => Err(anyhow::anyhow!(
+                    "can't get liquidity change request: {}",
+                    err
+                )),
+            };
+
+            match result {
+                Ok(_) => self.webhooks.notify(WebhookEvent::TxConfirmed {
+                    tx_hash: tx.tx_hash.clone(),
+                    context: tx.context.clone(),
+                    request_id: tx.request_id.clone(),
+                }),
                 Err(err) => {
                     error!(
-                        "Can't get liquidity change request: context={} request_id={} error={}",
-                        tx.context, tx.request_id, err
+                        "Failed to process confirmed tx, it stays pending: context={} request_id={} tx_hash={} error={}",
+                        tx.context, tx.request_id, tx.tx_hash, err
                     );
-                    return;
+                    self.register_failed_attempt(tx).await;
                 }
-            };
-            if request.is_add_liquidity() {
-                self.process_change_liquidity(tx, &request, Action::AddLiquidity)
-                    .await;
-            }
-
-            if request.is_direct_swap() {
-                self.process_change_liquidity(tx, &request, Action::Swap)
-                    .await;
-            }
-
-            if request.is_reverse_swap() {
-                self.process_change_liquidity(tx, &request, Action::ReverseSwap)
-                    .await;
-            }
-
-            if request.is_rm_liquidity() {
-                self.process_change_liquidity(tx, &request, Action::RmLiquidity)
-                    .await;
             }
         }
     }
 
-    async fn fail_tx(&self, tx: &db::Transaction) {
-        let mut dbtx = match self.db.pool.begin().await {
-            Ok(tx) => tx,
+    // counts a failed processing tick and moves the tx to the dead-letter
+    // status once the limit is reached, so it can be reviewed manually.
+    async fn register_failed_attempt(&self, tx: &db::Transaction) {
+        let attempts = match self.db.increment_submitted_tx_attempts(&tx.tx_hash).await {
+            Ok(attempts) => attempts,
             Err(err) => {
-                error!("Can't begin new trasaction: error={}", err);
+                error!(
+                    "Failed to count processing attempt: tx_hash={} error={}",
+                    tx.tx_hash, err
+                );
                 return;
             }
         };
 
-        if let Err(err) = self
-            .db
-            .update_submitted_tx(
-                &mut dbtx,
-                &tx.tx_hash,
-                db::LiquidityChangeRequest::STATUS_FAILED,
-            )
-            .await
-        {
-            error!(
-                "Failed to update submitted tx: context={} request_id={}  error={}",
-                tx.context, tx.request_id, err
-            );
+        if !is_dead_letter(attempts, self.cfg.max_process_attempts) {
             return;
         }
 
-        if let Err(err) = self
-            .db
-            .update_liquidity_change_request(
-                &mut dbtx,
-                &tx.request_id,
-                &tx.request_id,
-                db::Transaction::STATUS_FAILED,
-            )
-            .await
-        {
+        error!(
+            "Tx moved to dead-letter after {} failed attempts: context={} request_id={} tx_hash={}",
+            attempts, tx.context, tx.request_id, tx.tx_hash
+        );
+        if let Err(err) = self.dead_letter_tx(tx).await {
             error!(
-                "Failed to update submitted tx: context={} request_id={}  error={}",
+                "Failed to move tx to dead-letter: context={} request_id={} error={}",
                 tx.context, tx.request_id, err
             );
         }
+    }
 
-        if let Err(err) = dbtx.commit().await {
-            error!("Failed to commit dbtx: error={}", err);
-        }
+    async fn dead_letter_tx(&self, tx: &db::Transaction) -> anyhow::Result<()> {
+        let mut dbtx = self.db.pool.begin().await?;
+        self.db
+            .update_submitted_tx(&mut dbtx, &tx.tx_hash, db::Transaction::STATUS_DEAD_LETTER)
+            .await?;
+        dbtx.commit().await?;
+        Ok(())
     }
 
-    async fn process_change_liquidity(
-        &self,
-        tx: &db::Transaction,
-        request: &db::LiquidityChangeRequest,
-        action: Action,
-    ) {
-        let mut dbtx = match self.db.pool.begin().await {
-            Ok(tx) => tx,
-            Err(err) => {
-                error!("Can't begin new trasaction: error={}", err);
-                return;
-            }
-        };
+    // returns whether the node accepted the tx back into its mempool,
+    // `None` when rebroadcasting is disabled
+    fn rebroadcast(&mut self, tx: &db::Transaction) -> Option<bool> {
+        if self.cfg.max_rebroadcast_failures == 0 {
+            return None;
+        }
 
-        let mut trading_pair = match self.db.get_trading_pair_by_id(request.trading_pair).await {
-            Ok(tp) => tp,
+        match self.rpc.send_raw_transaction(tx.raw_data.as_str()) {
+            Ok(txid) => {
+                info!("Pending tx was rebroadcasted: tx_hash={}", txid);
+                self.rebroadcast_failures.remove(&tx.tx_hash);
+                Some(true)
+            }
             Err(err) => {
-                error!(
-                    "Can't get trading pair: context={} request_id={} id={} error={}",
-                    tx.context, tx.request_id, request.trading_pair, err
+                warn!(
+                    "Pending tx rebroadcast was rejected: tx_hash={} error={}",
+                    tx.tx_hash, err
                 );
-                return;
+                *self
+                    .rebroadcast_failures
+                    .entry(tx.tx_hash.clone())
+                    .or_default() += 1;
+                Some(false)
             }
-        };
+        }
+    }
+}
 
-        let base_delta = u128::from_str(&request.base_amount).unwrap_or_default();
-        let quote_delta = u128::from_str(&request.quote_amount).unwrap_or_default();
+// both rows are updated within one db transaction and it's committed only
+// when every update succeeded, otherwise it's dropped (rolled back).
+// A request resolved manually in the meantime keeps its status.
+async fn fail_tx(db: &db::Repo, tx: &db::Transaction) -> anyhow::Result<()> {
+    let mut dbtx = db.pool.begin().await?;
+
+    db.update_submitted_tx(&mut dbtx, &tx.tx_hash, db::Transaction::STATUS_FAILED)
+        .await
+        .map_err(|err| anyhow::anyhow!("can't update submitted tx: {}", err))?;
+
+    let resolved = db
+        .resolve_liquidity_change_request(
+            &mut dbtx,
+            &tx.request_id,
+            Some(&tx.tx_hash),
+            db::LiquidityChangeRequest::STATUS_FAILED,
+        )
+        .await
+        .map_err(|err| anyhow::anyhow!("can't update liquidity change request: {}", err))?;
+    if !resolved {
+        warn!(
+            "Liquidity request is already resolved, its status is kept: request_id={} tx_hash={}",
+            tx.request_id, tx.tx_hash
+        );
+    }
 
-        let pool_base_balance = u128::from_str(&trading_pair.base_balance).unwrap_or_default();
-        let pool_quote_balance = u128::from_str(&trading_pair.quote_balance).unwrap_or_default();
+    dbtx.commit().await?;
+    Ok(())
+}
 
-        match action {
-            Action::AddLiquidity => {
-                trading_pair.base_balance = (pool_base_balance + base_delta).to_string();
-                trading_pair.quote_balance = (pool_quote_balance + quote_delta).to_string();
-            }
-            Action::RmLiquidity => {
-                trading_pair.base_balance = (pool_base_balance - base_delta).to_string();
-                trading_pair.quote_balance = (pool_quote_balance - quote_delta).to_string();
-            }
-            Action::Swap => {
-                // user send base asset and recived quote asset
-                trading_pair.base_balance = (pool_base_balance + base_delta).to_string();
-                trading_pair.quote_balance = (pool_quote_balance - quote_delta).to_string();
-            }
-            Action::ReverseSwap => {
-                // user send quote asset and recived base asset
-                trading_pair.base_balance = (pool_base_balance - base_delta).to_string();
-                trading_pair.quote_balance = (pool_quote_balance + quote_delta).to_string();
-            }
-        }
+// all updates are made within one db transaction, on any error it's dropped
+// (rolled back) and the submitted tx stays pending for the next tick.
+async fn confirm_liquidity_change(
+    db: &db::Repo,
+    tx: &db::Transaction,
+    request: &db::LiquidityChangeRequest,
+    action: Action,
+) -> anyhow::Result<()> {
+    let mut dbtx = db.pool.begin().await?;
+
+    // balances are applied only by whoever resolves the request,
+    // so a manual resolution and the watchdog can't both apply it
+    let resolved = db
+        .resolve_liquidity_change_request(
+            &mut dbtx,
+            &request.req_uid,
+            Some(&tx.tx_hash),
+            db::LiquidityChangeRequest::STATUS_DONE,
+        )
+        .await
+        .map_err(|err| anyhow::anyhow!("can't update liquidity change request: {}", err))?;
+    if resolved {
+        apply_to_balances(db, &mut dbtx, request, &action).await?;
+    } else {
+        warn!(
+            "Liquidity request is already resolved, balances are left as is: req_uid={} tx_hash={}",
+            request.req_uid, tx.tx_hash
+        );
+    }
 
-        if action == Action::AddLiquidity || action == Action::RmLiquidity {
-            let mut lp = match self
-                .db
-                .get_liquidity_provider(request.trading_pair, &request.base_address)
-                .await
-            {
-                Ok(lp) => lp,
-                Err(err) => {
-                    error!(
-                    "Failed to fetch liquidity provider: context={} request_id={} pair_id={} base_address={} error={}",
-                    tx.context, tx.request_id, request.trading_pair, request.base_address, err
-                );
-                    return;
-                }
-            };
-            let lp_base_balance = u128::from_str(&lp.base_amount).unwrap_or_default();
-            let lp_quote_balance = u128::from_str(&lp.quote_amount).unwrap_or_default();
+    db.update_submitted_tx(&mut dbtx, &tx.tx_hash, db::Transaction::STATUS_MINED)
+        .await
+        .map_err(|err| anyhow::anyhow!("can't update submitted tx: {}", err))?;
 
-            match action {
-                Action::AddLiquidity => {
-                    lp.base_amount = (lp_base_balance + base_delta).to_string();
-                    lp.quote_amount = (lp_quote_balance + quote_delta).to_string();
-                }
-                Action::RmLiquidity => {
-                    lp.base_amount = (lp_base_balance - base_delta).to_string();
-                    lp.quote_amount = (lp_quote_balance - quote_delta).to_string();
-                }
-                _ => (),
-            }
+    dbtx.commit().await?;
+    Ok(())
+}
 
-            match self.db.update_liquidity_provider(&mut dbtx, &lp).await {
-                Ok(_) => (),
-                Err(err) => {
-                    error!(
-                    "Failed to update liquidity provider: context={} request_id={} pair_id={} base_address={} error={}",
-                    tx.context, tx.request_id, request.trading_pair, request.base_address, err
-                );
-                    return;
-                }
-            }
+/// returned when a manually resolved request is already done or failed
+#[derive(Debug)]
+pub struct AlreadyResolved {
+    pub req_uid: String,
+    pub status: String,
+}
+
+impl fmt::Display for AlreadyResolved {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "liquidity request({}) is already {}",
+            self.req_uid, self.status
+        )
+    }
+}
+
+impl std::error::Error for AlreadyResolved {}
+
+/// Manual override for a liquidity change request stuck in the watchdog.
+/// `done` applies it to the pool and the LP like a confirmed tx, `failed` only marks it,
+/// its submitted tx gets the matching status. All updates are made within one db transaction.
+pub async fn resolve_liquidity_request(
+    db: &db::Repo,
+    req_uid: &str,
+    status: &str,
+) -> anyhow::Result<db::LiquidityChangeRequest> {
+    let tx_status = match status {
+        db::LiquidityChangeRequest::STATUS_DONE => db::Transaction::STATUS_MINED,
+        db::LiquidityChangeRequest::STATUS_FAILED => db::Transaction::STATUS_FAILED,
+        _ => anyhow::bail!("invalid resolution status({})", status),
+    };
+
+    let mut request = db.get_liquidity_change_request(req_uid).await?;
+    if request.is_resolved() {
+        return Err(AlreadyResolved {
+            req_uid: req_uid.to_owned(),
+            status: request.status,
         }
+        .into());
+    }
 
-        match self.db.update_trading_pair(&mut dbtx, &trading_pair).await {
-            Ok(_) => (),
-            Err(err) => {
-                error!(
-                    "Failed to update trading pair: context={} request_id={} id={} error={}",
-                    tx.context, tx.request_id, request.trading_pair, err
-                );
-                return;
-            }
+    let mut dbtx = db.pool.begin().await?;
+    if !db
+        .resolve_liquidity_change_request(&mut dbtx, req_uid, None, status)
+        .await?
+    {
+        // the watchdog got to it first, the transaction is rolled back
+        let current = db.get_liquidity_change_request(req_uid).await?;
+        return Err(AlreadyResolved {
+            req_uid: req_uid.to_owned(),
+            status: current.status,
+        }
+        .into());
+    }
+    if status == db::LiquidityChangeRequest::STATUS_DONE {
+        let Some(action) = Action::from_request(&request) else {
+            anyhow::bail!("unknown action({})", request.action);
         };
-
-        match self
-            .db
-            .update_liquidity_change_request(
-                &mut dbtx,
-                &request.req_uid,
-                &tx.tx_hash,
-                db::LiquidityChangeRequest::STATUS_DONE,
-            )
+        apply_to_balances(db, &mut dbtx, &request, &action).await?;
+    }
+    if let Some(tx_hash) = &request.tx_hash {
+        db.update_submitted_tx(&mut dbtx, tx_hash, tx_status)
             .await
-        {
-            Ok(_) => (),
-            Err(err) => {
-                error!("Failed to update liquidity_change request tx: context={} request_id={} id={} error={}",
-                        tx.context, tx.request_id, request.trading_pair, err);
-                return;
-            }
-        }
+            .map_err(|err| anyhow::anyhow!("can't update submitted tx: {}", err))?;
+    }
+    dbtx.commit().await?;
+
+    warn!(
+        "MANUAL liquidity request resolution: req_uid={} action={} status={} -> {} tx_hash={:?}",
+        req_uid, request.action, request.status, status, request.tx_hash
+    );
+    request.status = status.to_owned();
+    Ok(request)
+}
 
-        match self
-            .db
-            .update_submitted_tx(&mut dbtx, &tx.tx_hash, db::Transaction::STATUS_MINED)
+// applies the request to the pool balances and, for liquidity changes, to the LP,
+// their rows are locked by `dbtx` from the read to the commit
+async fn apply_to_balances(
+    db: &db::Repo,
+    dbtx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    request: &db::LiquidityChangeRequest,
+    action: &Action,
+) -> anyhow::Result<()> {
+    let mut trading_pair = db
+        .get_trading_pair_for_update(dbtx, request.trading_pair)
+        .await
+        .map_err(|err| anyhow::anyhow!("can't get trading pair: {}", err))?;
+
+    let base_delta = u128::from_str(&request.base_amount).unwrap_or_default();
+    let quote_delta = u128::from_str(&request.quote_amount).unwrap_or_default();
+
+    let pool_base_balance = u128::from_str(&trading_pair.base_balance).unwrap_or_default();
+    let pool_quote_balance = u128::from_str(&trading_pair.quote_balance).unwrap_or_default();
+
+    let (base_balance, quote_balance) = apply_liquidity_change(
+        action,
+        (pool_base_balance, pool_quote_balance),
+        (base_delta, quote_delta),
+    )?;
+    trading_pair.base_balance = base_balance.to_string();
+    trading_pair.quote_balance = quote_balance.to_string();
+
+    if *action == Action::AddLiquidity || *action == Action::RmLiquidity {
+        let mut lp = db
+            .get_liquidity_provider_for_update(dbtx, request.trading_pair, &request.base_address)
             .await
-        {
-            Ok(_) => (),
-            Err(err) => {
-                error!(
-                    "Failed to update submitted tx: context={} request_id={} id={} error={}",
-                    tx.context, tx.request_id, request.trading_pair, err
-                );
-            }
-        }
+            .map_err(|err| {
+                anyhow::anyhow!(
+                    "can't fetch liquidity provider base_address={}: {}",
+                    request.base_address,
+                    err
+                )
+            })?;
+        let lp_base_balance = u128::from_str(&lp.base_amount).unwrap_or_default();
+        let lp_quote_balance = u128::from_str(&lp.quote_amount).unwrap_or_default();
+
+        let (base_amount, quote_amount) = apply_liquidity_change(
+            action,
+            (lp_base_balance, lp_quote_balance),
+            (base_delta, quote_delta),
+        )?;
+        lp.base_amount = base_amount.to_string();
+        lp.quote_amount = quote_amount.to_string();
+
+        db.update_liquidity_provider(dbtx, &lp)
+            .await
+            .map_err(|err| anyhow::anyhow!("can't update liquidity provider: {}", err))?;
+    }
 
-        if let Err(err) = dbtx.commit().await {
-            error!("Failed to commit dbtx: error={}", err);
-        }
+    db.update_trading_pair(dbtx, &trading_pair)
+        .await
+        .map_err(|err| anyhow::anyhow!("can't update trading pair: {}", err))?;
+    Ok(())
+}
+
+// applies the request deltas to the (base, quote) balances of the pool or the LP
+fn apply_liquidity_change(
+    action: &Action,
+    (base, quote): (u128, u128),
+    (base_delta, quote_delta): (u128, u128),
+) -> anyhow::Result<(u128, u128)> {
+    let res = match action {
+        Action::AddLiquidity => (base.checked_add(base_delta), quote.checked_add(quote_delta)),
+        Action::RmLiquidity => (base.checked_sub(base_delta), quote.checked_sub(quote_delta)),
+        // user send base asset and recived quote asset
+        Action::Swap => (base.checked_add(base_delta), quote.checked_sub(quote_delta)),
+        // user send quote asset and recived base asset
+        Action::ReverseSwap => (base.checked_sub(base_delta), quote.checked_add(quote_delta)),
+    };
+
+    match res {
+        (Some(base), Some(quote)) => Ok((base, quote)),
+        _ => anyhow::bail!(
+            "balance overflow: base={} quote={} base_delta={} quote_delta={}",
+            base,
+            quote,
+            base_delta,
+            quote_delta
+        ),
+    }
+}
+
+// drops the rebroadcast failures of txs which aren't pending anymore,
+// e.g. confirmed, dead-lettered or resolved manually.
+fn forget_settled_txs(failures: &mut HashMap<String, u32>, pending_txs: &[db::Transaction]) {
+    failures.retain(|tx_hash, _| pending_txs.iter().any(|tx| &tx.tx_hash == tx_hash));
+}
+
+fn is_dead_letter(attempts: i32, max_attempts: u32) -> bool {
+    max_attempts > 0 && attempts >= max_attempts as i32
+}
+
+#[derive(Debug, PartialEq)]
+enum PendingTxDecision {
+    Wait,
+    Fail,
+}
+
+// decides what to do with a tx that is older than `pending_tx_max_age`
+// and still can't be found by the node.
+fn pending_tx_decision(
+    cfg: &config::TxWatchdogConfig,
+    age_secs: i64,
+    rebroadcasted: Option<bool>,
+    failures: u32,
+) -> PendingTxDecision {
+    if age_secs >= cfg.pending_tx_hard_max_age {
+        return PendingTxDecision::Fail;
+    }
+
+    match rebroadcasted {
+        // rebroadcast disabled, keep the old age-only policy
+        None => PendingTxDecision::Fail,
+        Some(true) => PendingTxDecision::Wait,
+        Some(false) if failures >= cfg.max_rebroadcast_failures => PendingTxDecision::Fail,
+        Some(false) => PendingTxDecision::Wait,
     }
 }
 
-#[derive(PartialEq)]
+#[derive(Debug, PartialEq)]
 enum Action {
     AddLiquidity,
     RmLiquidity,
     Swap,
     ReverseSwap,
 }
+
+impl Action {
+    fn from_request(request: &db::LiquidityChangeRequest) -> Option<Self> {
+        if request.is_add_liquidity() {
+            return Some(Action::AddLiquidity);
+        }
+        if request.is_direct_swap() {
+            return Some(Action::Swap);
+        }
+        if request.is_reverse_swap() {
+            return Some(Action::ReverseSwap);
+        }
+        if request.is_rm_liquidity() {
+            return Some(Action::RmLiquidity);
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn pending_tx_failure_policy() {
+        use super::{pending_tx_decision, PendingTxDecision};
+        use crate::config::TxWatchdogConfig;
+
+        let mut cfg = TxWatchdogConfig::default();
+        assert_eq!(
+            pending_tx_decision(&cfg, 3600, None, 0),
+            PendingTxDecision::Fail
+        );
+
+        cfg.max_rebroadcast_failures = 3;
+        assert_eq!(
+            pending_tx_decision(&cfg, 7200, Some(true), 0),
+            PendingTxDecision::Wait
+        );
+        assert_eq!(
+            pending_tx_decision(&cfg, 7200, Some(false), 2),
+            PendingTxDecision::Wait
+        );
+        assert_eq!(
+            pending_tx_decision(&cfg, 7200, Some(false), 3),
+            PendingTxDecision::Fail
+        );
+        assert_eq!(
+            pending_tx_decision(&cfg, cfg.pending_tx_hard_max_age, Some(true), 0),
+            PendingTxDecision::Fail
+        );
+    }
+
+    #[test]
+    fn liquidity_change_failure_is_reported() {
+        use super::{apply_liquidity_change, is_dead_letter, Action};
+
+        assert_eq!(
+            apply_liquidity_change(&Action::Swap, (100, 50), (10, 20)).unwrap(),
+            (110, 30)
+        );
+        assert_eq!(
+            apply_liquidity_change(&Action::ReverseSwap, (100, 50), (10, 20)).unwrap(),
+            (90, 70)
+        );
+        // removing more than the pool has must fail instead of panicking,
+        // so the tx stays pending and nothing is written
+        assert!(apply_liquidity_change(&Action::RmLiquidity, (100, 50), (101, 20)).is_err());
+        assert!(apply_liquidity_change(&Action::Swap, (100, 50), (10, 51)).is_err());
+
+        assert!(!is_dead_letter(2, 3));
+        assert!(is_dead_letter(3, 3));
+        assert!(!is_dead_letter(100, 0));
+    }
+
+    /// needs a scratch db with the service schema in `TEST_DB_DSN`, skipped otherwise
+    #[tokio::test]
+    async fn failure_mid_confirmation_keeps_the_tx_pending() {
+        use super::{confirm_liquidity_change, Action};
+        use crate::db::{self, LiquidityChangeRequest, Transaction};
+
+        let Some(repo) = db::connect_scratch_db().await else {
+            return;
+        };
+
+        let suffix = chrono::Utc::now().timestamp_micros();
+        let base_asset = format!("TESTRUNE{}", suffix);
+        let (pair_id,): (i64,) = sqlx::query_as(
+            "INSERT INTO trading_pair (base_asset, quote_asset, pool_address, base_balance,
+                quote_balance, locked_base_balance, locked_quote_balance, fee_address,
+                treasury_address, swap_fee_percent)
+            VALUES ($1, 'BTC', 'pool', '1000', '500', '0', '0', 'fee', 'treasury', 0.5)
+            RETURNING id",
+        )
+        .bind(&base_asset)
+        .fetch_one(&repo.pool)
+        .await
+        .unwrap();
+
+        // the pair is read and locked, then the LP fetch fails: the pool has no such provider
+        let request = LiquidityChangeRequest {
+            req_uid: format!("test-{}", suffix),
+            trading_pair: pair_id,
+            base_address: "unknown-lp".to_owned(),
+            quote_address: "unknown-lp".to_owned(),
+            base_amount: "100".to_owned(),
+            quote_amount: "50".to_owned(),
+            action: LiquidityChangeRequest::REMOVE_LIQUIDITY.to_owned(),
+            status: LiquidityChangeRequest::STATUS_NEW.to_owned(),
+            ..Default::default()
+        };
+        repo.insert_liquidity_change_request(&request)
+            .await
+            .unwrap();
+        let tx = Transaction {
+            tx_hash: format!("{:064x}", suffix),
+            status: Transaction::STATUS_PENDING.to_owned(),
+            request_id: request.req_uid.clone(),
+            ..Default::default()
+        };
+        repo.insert_submitted_tx(tx.clone()).await.unwrap();
+
+        let res = confirm_liquidity_change(&repo, &tx, &request, Action::RmLiquidity).await;
+        assert!(res.is_err());
+
+        // the request was resolved before the failure, that's rolled back as well
+        let stored = repo
+            .get_liquidity_change_request(&request.req_uid)
+            .await
+            .unwrap();
+        assert_eq!(stored.status, LiquidityChangeRequest::STATUS_NEW);
+        assert_eq!(stored.tx_hash, None);
+        let stored = repo.get_submitted_tx(&tx.tx_hash).await.unwrap();
+        assert_eq!(stored.status, Transaction::STATUS_PENDING);
+        let pair = repo.get_trading_pair(&base_asset, "BTC").await.unwrap();
+        assert_eq!(pair.base_balance, "1000");
+        assert_eq!(pair.quote_balance, "500");
+
+        // the next tick retries it, until it's dead-lettered
+        let attempts = repo
+            .increment_submitted_tx_attempts(&tx.tx_hash)
+            .await
+            .unwrap();
+        assert_eq!(attempts, 1);
+
+        for query in [
+            "DELETE FROM submitted_txs WHERE request_id = $1",
+            "DELETE FROM liquidity_change_requests WHERE req_uid = $1",
+        ] {
+            sqlx::query(query)
+                .bind(&request.req_uid)
+                .execute(&repo.pool)
+                .await
+                .unwrap();
+        }
+        sqlx::query("DELETE FROM trading_pair WHERE id = $1")
+            .bind(pair_id)
+            .execute(&repo.pool)
+            .await
+            .unwrap();
+    }
+
+    /// needs a scratch db with the service schema in `TEST_DB_DSN`, skipped otherwise
+    #[tokio::test]
+    async fn failed_last_update_leaves_the_db_unchanged() {
+        use super::{confirm_liquidity_change, fail_tx, Action};
+        use crate::db::{self, LiquidityChangeRequest, Transaction};
+
+        let Some(repo) = db::connect_scratch_db().await else {
+            return;
+        };
+
+        let suffix = chrono::Utc::now().timestamp_micros();
+        let base_asset = format!("TESTRUNE{}", suffix);
+        let (pair_id,): (i64,) = sqlx::query_as(
+            "INSERT INTO trading_pair (base_asset, quote_asset, pool_address, base_balance,
+                quote_balance, locked_base_balance, locked_quote_balance, fee_address,
+                treasury_address, swap_fee_percent)
+            VALUES ($1, 'BTC', 'pool', '1000', '500', '0', '0', 'fee', 'treasury', 0.5)
+            RETURNING id",
+        )
+        .bind(&base_asset)
+        .fetch_one(&repo.pool)
+        .await
+        .unwrap();
+        let request = LiquidityChangeRequest {
+            req_uid: format!("test-{}", suffix),
+            trading_pair: pair_id,
+            base_address: "bc1ptrader".to_owned(),
+            quote_address: "bc1ptrader".to_owned(),
+            base_amount: "100".to_owned(),
+            quote_amount: "40".to_owned(),
+            action: LiquidityChangeRequest::SWAP_DIRECT.to_owned(),
+            status: LiquidityChangeRequest::STATUS_NEW.to_owned(),
+            ..Default::default()
+        };
+        repo.insert_liquidity_change_request(&request)
+            .await
+            .unwrap();
+        let tx = Transaction {
+            tx_hash: format!("{:064x}", suffix),
+            status: Transaction::STATUS_PENDING.to_owned(),
+            request_id: request.req_uid.clone(),
+            ..Default::default()
+        };
+        repo.insert_submitted_tx(tx.clone()).await.unwrap();
+
+        // updates of the rows of this request fail, after the earlier writes of the transaction
+        let trigger = format!("fail_test_{}", suffix);
+        sqlx::query(
+            "CREATE OR REPLACE FUNCTION fail_test_update() RETURNS trigger AS $$
+            BEGIN
+                IF to_jsonb(NEW)->>'request_id' = TG_ARGV[0]
+                    OR to_jsonb(NEW)->>'req_uid' = TG_ARGV[0] THEN
+                    RAISE EXCEPTION 'injected failure';
+                END IF;
+                RETURN NEW;
+            END $$ LANGUAGE plpgsql",
+        )
+        .execute(&repo.pool)
+        .await
+        .unwrap();
+        let fail_updates_of = |table: &str| {
+            format!(
+                "CREATE TRIGGER {} BEFORE UPDATE ON {} FOR EACH ROW
+                EXECUTE FUNCTION fail_test_update('{}')",
+                trigger, table, request.req_uid
+            )
+        };
+        let assert_unchanged = || {
+            let (repo, request, tx, base_asset) = (&repo, &request, &tx, &base_asset);
+            async move {
+                let stored = repo
+                    .get_liquidity_change_request(&request.req_uid)
+                    .await
+                    .unwrap();
+                assert_eq!(stored.status, LiquidityChangeRequest::STATUS_NEW);
+                assert_eq!(stored.tx_hash, None);
+                let stored = repo.get_submitted_tx(&tx.tx_hash).await.unwrap();
+                assert_eq!(stored.status, Transaction::STATUS_PENDING);
+                let pair = repo.get_trading_pair(&base_asset, "BTC").await.unwrap();
+                assert_eq!(
+                    (pair.base_balance.as_str(), pair.quote_balance.as_str()),
+                    ("1000", "500")
+                );
+            }
+        };
+
+        // fail_tx marks the submitted tx failed, then resolving the request fails
+        sqlx::query(&fail_updates_of("liquidity_change_requests"))
+            .execute(&repo.pool)
+            .await
+            .unwrap();
+        let err = fail_tx(&repo, &tx).await.unwrap_err();
+        assert!(err.to_string().contains("injected failure"), "{}", err);
+        sqlx::query(&format!(
+            "DROP TRIGGER {} ON liquidity_change_requests",
+            trigger
+        ))
+        .execute(&repo.pool)
+        .await
+        .unwrap();
+        assert_unchanged().await;
+
+        // the request is resolved and the pool updated, then marking the tx mined fails
+        sqlx::query(&fail_updates_of("submitted_txs"))
+            .execute(&repo.pool)
+            .await
+            .unwrap();
+        let err = confirm_liquidity_change(&repo, &tx, &request, Action::Swap)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("injected failure"), "{}", err);
+        sqlx::query(&format!("DROP TRIGGER {} ON submitted_txs", trigger))
+            .execute(&repo.pool)
+            .await
+            .unwrap();
+        assert_unchanged().await;
+
+        // without the failure both succeed
+        confirm_liquidity_change(&repo, &tx, &request, Action::Swap)
+            .await
+            .unwrap();
+        let pair = repo.get_trading_pair(&base_asset, "BTC").await.unwrap();
+        assert_eq!(
+            (pair.base_balance.as_str(), pair.quote_balance.as_str()),
+            ("1100", "460")
+        );
+
+        for query in [
+            "DELETE FROM submitted_txs WHERE request_id = $1",
+            "DELETE FROM liquidity_change_requests WHERE req_uid = $1",
+        ] {
+            sqlx::query(query)
+                .bind(&request.req_uid)
+                .execute(&repo.pool)
+                .await
+                .unwrap();
+        }
+        sqlx::query("DELETE FROM trading_pair WHERE id = $1")
+            .bind(pair_id)
+            .execute(&repo.pool)
+            .await
+            .unwrap();
+    }
+
+    #[test]
+    fn settled_txs_rebroadcast_failures_are_dropped() {
+        use super::forget_settled_txs;
+        use crate::db;
+        use std::collections::HashMap;
+
+        let mut failures = HashMap::from([("pending".to_owned(), 2), ("confirmed".to_owned(), 1)]);
+        let pending = vec![db::Transaction {
+            tx_hash: "pending".to_owned(),
+            ..Default::default()
+        }];
+        forget_settled_txs(&mut failures, &pending);
+        assert_eq!(failures, HashMap::from([("pending".to_owned(), 2)]));
+
+        forget_settled_txs(&mut failures, &[]);
+        assert!(failures.is_empty());
+    }
+}
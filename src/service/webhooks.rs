@@ -0,0 +1,487 @@
+use awc::http::header;
+use bitcoin::hashes::{hmac, sha256, Hash, HashEngine};
+use serde::Serialize;
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::{sync::mpsc, task::JoinHandle, time::sleep};
+use tokio_util::sync::CancellationToken;
+
+use crate::{config, db, serde_utils::number_from_string};
+
+/// hex HMAC-SHA256 of `{timestamp}.{body}` with `webhooks.secret`, prefixed with `sha256=`
+pub const SIGNATURE_HEADER: &str = "X-Webhook-Signature";
+/// unix seconds of the delivery attempt, receivers should reject stale ones
+pub const TIMESTAMP_HEADER: &str = "X-Webhook-Timestamp";
+/// upper bound of the delay between delivery attempts
+const MAX_RETRY_DELAY: u64 = 300;
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum WebhookEvent {
+    Etching {
+        rune: String,
+        block: i64,
+        tx_hash: String,
+    },
+    Mint {
+        rune: String,
+        address: String,
+        #[serde(with = "number_from_string")]
+        amount: u128,
+        tx_hash: String,
+        vout: i32,
+    },
+    /// runes received by a watched address
+    Transfer {
+        rune: String,
+        address: String,
+        #[serde(with = "number_from_string")]
+        amount: u128,
+        tx_hash: String,
+        vout: i32,
+    },
+    /// a tx submitted by the service is mined and processed by the watchdog
+    TxConfirmed {
+        tx_hash: String,
+        context: String,
+        request_id: String,
+    },
+}
+
+impl WebhookEvent {
+    pub const KINDS: [&'static str; 4] = ["etching", "mint", "transfer", "tx_confirmed"];
+
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::Etching { .. } => "etching",
+            Self::Mint { .. } => "mint",
+            Self::Transfer { .. } => "transfer",
+            Self::TxConfirmed { .. } => "tx_confirmed",
+        }
+    }
+
+    /// stable across redeliveries and reindexing, receivers can dedupe by it
+    pub fn id(&self) -> String {
+        match self {
+            Self::Mint { tx_hash, vout, .. } | Self::Transfer { tx_hash, vout, .. } => {
+                format!("{}:{}:{}", tx_hash, vout, self.kind())
+            }
+            Self::Etching { tx_hash, .. } | Self::TxConfirmed { tx_hash, .. } => {
+                format!("{}:{}", tx_hash, self.kind())
+            }
+        }
+    }
+}
+
+/// the delivered body, the event fields with its id and the hash of the block it's from
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct WebhookMessage {
+    id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    block_hash: Option<String>,
+    #[serde(flatten)]
+    event: WebhookEvent,
+}
+
+impl WebhookMessage {
+    fn new(event: WebhookEvent, block_hash: Option<&str>) -> Self {
+        Self {
+            id: event.id(),
+            block_hash: block_hash.map(str::to_owned),
+            event,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct EventFilter {
+    /// all kinds if empty
+    events: HashSet<String>,
+    watched_addresses: HashSet<String>,
+}
+
+impl EventFilter {
+    fn new(cfg: &config::WebhookConfig) -> anyhow::Result<Self> {
+        if let Some(unknown) = cfg
+            .events
+            .iter()
+            .find(|e| !WebhookEvent::KINDS.contains(&e.as_str()))
+        {
+            anyhow::bail!(
+                "unknown webhook event {}, expected one of {:?}",
+                unknown,
+                WebhookEvent::KINDS
+            );
+        }
+
+        Ok(Self {
+            events: cfg.events.iter().cloned().collect(),
+            watched_addresses: cfg.watched_addresses.iter().cloned().collect(),
+        })
+    }
+
+    fn accepts(&self, event: &WebhookEvent) -> bool {
+        if !self.events.is_empty() && !self.events.contains(event.kind()) {
+            return false;
+        }
+        match event {
+            WebhookEvent::Transfer { address, .. } => self.watched_addresses.contains(address),
+            _ => true,
+        }
+    }
+}
+
+/// error of the events dead-lettered because the queue was full
+const QUEUE_FULL: &str = "webhook queue is full";
+/// error of the events still queued when the sender stopped
+const SENDER_STOPPED: &str = "webhook sender stopped before delivery";
+
+/// Queues indexer and watchdog events for the `WebhookSender`,
+/// the default one drops them.
+#[derive(Clone, Default)]
+pub struct WebhookNotifier {
+    sender: Option<mpsc::Sender<WebhookMessage>>,
+    filter: Arc<EventFilter>,
+    db: Option<Arc<db::Repo>>,
+}
+
+impl WebhookNotifier {
+    /// never blocks, the event is dropped if it's filtered out or the sender is stopped
+    /// and dead-lettered if the queue is full
+    pub fn notify(&self, event: WebhookEvent) {
+        self.notify_message(WebhookMessage::new(event, None));
+    }
+
+    /// `notify` for the events of a finished block
+    pub fn notify_in_block(&self, event: WebhookEvent, block_hash: &str) {
+        self.notify_message(WebhookMessage::new(event, Some(block_hash)));
+    }
+
+    fn notify_message(&self, message: WebhookMessage) {
+        let Some(message) = self.enqueue(message) else {
+            return;
+        };
+        let (Some(db), Some(payload)) = (self.db.clone(), encode(&message)) else {
+            return;
+        };
+        warn!(
+            "Webhook queue is full, event moved to dead-letter: event={} id={}",
+            message.event.kind(),
+            message.id
+        );
+        tokio::spawn(async move {
+            store_dead_letter(&db, &message.event, payload, 0, QUEUE_FULL.to_owned()).await;
+        });
+    }
+
+    /// returns the message if the queue is full
+    fn enqueue(&self, message: WebhookMessage) -> Option<WebhookMessage> {
+        let sender = self.sender.as_ref()?;
+        if !self.filter.accepts(&message.event) {
+            return None;
+        }
+        match sender.try_send(message) {
+            Ok(()) => None,
+            Err(mpsc::error::TrySendError::Full(message)) => Some(message),
+            Err(mpsc::error::TrySendError::Closed(message)) => {
+                warn!(
+                    "webhook sender is stopped, event dropped: event={} id={}",
+                    message.event.kind(),
+                    message.id
+                );
+                None
+            }
+        }
+    }
+}
+
+/// POSTs the queued events one by one in order, retrying failed deliveries with
+/// a doubling delay. Events which still fail, or are still queued on shutdown,
+/// go to the `webhook_dead_letters` table.
+pub struct WebhookSender {
+    cfg: config::WebhookConfig,
+    db: Arc<db::Repo>,
+    events: mpsc::Receiver<WebhookMessage>,
+}
+
+impl WebhookSender {
+    pub fn new(
+        cfg: &config::WebhookConfig,
+        db: Arc<db::Repo>,
+    ) -> anyhow::Result<(Self, WebhookNotifier)> {
+        let filter = EventFilter::new(cfg)?;
+        let (sender, events) = mpsc::channel(cfg.queue_size.max(1));
+        let notifier = WebhookNotifier {
+            sender: Some(sender),
+            filter: Arc::new(filter),
+            db: Some(db.clone()),
+        };
+        let webhooks = Self {
+            cfg: cfg.clone(),
+            db,
+            events,
+        };
+        Ok((webhooks, notifier))
+    }
+
+    /// the awc client isn't `Send`, so the sender runs on the actix runtime
+    pub fn start(self, cancel: CancellationToken) -> JoinHandle<()> {
+        actix_web::rt::spawn(self.run(cancel))
+    }
+
+    async fn run(mut self, stop_signal: CancellationToken) {
+        let client = awc::Client::builder()
+            .timeout(Duration::from_secs(self.cfg.timeout))
+            .finish();
+
+        loop {
+            tokio::select! {
+                message = self.events.recv() => {
+                    let Some(message) = message else {
+                        break;
+                    };
+                    self.deliver(&client, &message, &stop_signal).await;
+                }
+
+                _ = stop_signal.cancelled() => {
+                    log::info!("gracefully shutting down webhook sender");
+                    break;
+                }
+            };
+        }
+
+        self.drain().await;
+    }
+
+    /// dead-letters the queued events, so none is lost on shutdown
+    async fn drain(&mut self) {
+        self.events.close();
+        let mut drained = 0;
+        while let Some(message) = self.events.recv().await {
+            let Some(payload) = encode(&message) else {
+                continue;
+            };
+            store_dead_letter(
+                &self.db,
+                &message.event,
+                payload,
+                0,
+                SENDER_STOPPED.to_owned(),
+            )
+            .await;
+            drained += 1;
+        }
+        if drained > 0 {
+            warn!(
+                "Queued webhook events moved to dead-letter: count={}",
+                drained
+            );
+        }
+    }
+
+    async fn deliver(
+        &self,
+        client: &awc::Client,
+        message: &WebhookMessage,
+        stop_signal: &CancellationToken,
+    ) {
+        let event = &message.event;
+        let Some(body) = encode(message) else {
+            return;
+        };
+
+        let mut attempt = 0;
+        let error = loop {
+            attempt += 1;
+            let mut req = client
+                .post(&self.cfg.url)
+                .insert_header((header::CONTENT_TYPE, "application/json"));
+            if !self.cfg.secret.is_empty() {
+                let timestamp = chrono::Utc::now().timestamp();
+                let signature = sign(&self.cfg.secret, &signed_payload(timestamp, &body));
+                req = req
+                    .insert_header((TIMESTAMP_HEADER, timestamp.to_string()))
+                    .insert_header((SIGNATURE_HEADER, format!("sha256={}", signature)));
+            }
+
+            let err = match req.send_body(body.clone()).await {
+                Ok(resp) if resp.status().is_success() => return,
+                Ok(resp) => format!("responded with {}", resp.status()),
+                Err(err) => err.to_string(),
+            };
+            if attempt >= self.cfg.max_attempts.max(1) {
+                break err;
+            }
+
+            warn!(
+                "Webhook delivery failed: event={} attempt={} error={}",
+                event.kind(),
+                attempt,
+                err
+            );
+            tokio::select! {
+                _ = sleep(retry_delay(self.cfg.retry_delay, attempt)) => {}
+                _ = stop_signal.cancelled() => {
+                    break format!("{}, interrupted by shutdown", err);
+                }
+            };
+        };
+
+        error!(
+            "Webhook event moved to dead-letter: event={} attempts={} error={}",
+            event.kind(),
+            attempt,
+            error
+        );
+        store_dead_letter(&self.db, event, body, attempt, error).await;
+    }
+}
+
+fn encode(message: &WebhookMessage) -> Option<String> {
+    match serde_json::to_string(message) {
+        Ok(body) => Some(body),
+        Err(err) => {
+            error!(
+                "Can't encode webhook event: event={} id={} error={}",
+                message.event.kind(),
+                message.id,
+                err
+            );
+            None
+        }
+    }
+}
+
+async fn store_dead_letter(
+    db: &db::Repo,
+    event: &WebhookEvent,
+    payload: String,
+    attempts: u32,
+    error: String,
+) {
+    let row = db::WebhookDeadLetter {
+        id: 0,
+        event: event.kind().to_owned(),
+        payload,
+        attempts: attempts as i32,
+        error,
+        created_at: chrono::Utc::now().timestamp(),
+    };
+    if let Err(err) = db.insert_webhook_dead_letter(&row).await {
+        error!(
+            "Can't store webhook dead-letter: event={} payload={} error={}",
+            row.event, row.payload, err
+        );
+    }
+}
+
+/// the timestamp is signed along with the body, so a captured delivery can't be replayed later
+fn signed_payload(timestamp: i64, body: &str) -> Vec<u8> {
+    format!("{}.{}", timestamp, body).into_bytes()
+}
+
+/// hex HMAC-SHA256 of `body`
+pub fn sign(secret: &str, body: &[u8]) -> String {
+    let mut engine = hmac::HmacEngine::<sha256::Hash>::new(secret.as_bytes());
+    engine.input(body);
+    hex::encode(hmac::Hmac::<sha256::Hash>::from_engine(engine).to_byte_array())
+}
+
+/// `base` seconds doubled for every failed attempt after the first one
+fn retry_delay(base: u64, attempt: u32) -> Duration {
+    let secs = base.saturating_mul(1 << attempt.saturating_sub(1).min(16));
+    Duration::from_secs(secs.min(MAX_RETRY_DELAY))
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn webhook_events_are_filtered_and_signed() {
+        use super::{retry_delay, sign, signed_payload, EventFilter, WebhookEvent, WebhookMessage};
+        use crate::config::WebhookConfig;
+        use std::time::Duration;
+
+        // RFC 4231 test case 2
+        assert_eq!(
+            sign("Jefe", b"what do ya want for nothing?"),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+        assert_eq!(signed_payload(1700000000, "{}"), b"1700000000.{}".to_vec());
+
+        let transfer = |address: &str| WebhookEvent::Transfer {
+            rune: "UNCOMMONGOODS".to_owned(),
+            address: address.to_owned(),
+            amount: u128::MAX,
+            tx_hash: "00ab".to_owned(),
+            vout: 1,
+        };
+        assert_eq!(
+            serde_json::to_string(&WebhookMessage::new(transfer("bc1pa"), Some("00ff"))).unwrap(),
+            format!(
+                r#"{{"id":"00ab:1:transfer","block_hash":"00ff","event":"transfer","rune":"UNCOMMONGOODS","address":"bc1pa","amount":"{}","tx_hash":"00ab","vout":1}}"#,
+                u128::MAX
+            )
+        );
+
+        let mint = WebhookEvent::Mint {
+            rune: "UNCOMMONGOODS".to_owned(),
+            address: "bc1pb".to_owned(),
+            amount: 1,
+            tx_hash: "00cd".to_owned(),
+            vout: 0,
+        };
+        assert_eq!(mint.id(), "00cd:0:mint");
+        let mut cfg = WebhookConfig {
+            watched_addresses: vec!["bc1pa".to_owned()],
+            ..Default::default()
+        };
+        let filter = EventFilter::new(&cfg).unwrap();
+        assert!(filter.accepts(&mint));
+        assert!(filter.accepts(&transfer("bc1pa")));
+        assert!(!filter.accepts(&transfer("bc1pb")));
+
+        cfg.events = vec!["transfer".to_owned()];
+        let filter = EventFilter::new(&cfg).unwrap();
+        assert!(!filter.accepts(&mint));
+        assert!(filter.accepts(&transfer("bc1pa")));
+
+        cfg.events = vec!["burn".to_owned()];
+        assert!(EventFilter::new(&cfg).is_err());
+
+        assert_eq!(retry_delay(2, 1), Duration::from_secs(2));
+        assert_eq!(retry_delay(2, 3), Duration::from_secs(8));
+        assert_eq!(retry_delay(2, 40), Duration::from_secs(300));
+    }
+
+    #[test]
+    fn full_queue_returns_the_event() {
+        use super::{EventFilter, WebhookEvent, WebhookMessage, WebhookNotifier};
+        use std::sync::Arc;
+        use tokio::sync::mpsc;
+
+        let (sender, mut events) = mpsc::channel(1);
+        let notifier = WebhookNotifier {
+            sender: Some(sender),
+            filter: Arc::new(EventFilter::default()),
+            db: None,
+        };
+        let etching = |block| {
+            let event = WebhookEvent::Etching {
+                rune: "UNCOMMONGOODS".to_owned(),
+                block,
+                tx_hash: "00ab".to_owned(),
+            };
+            WebhookMessage::new(event, None)
+        };
+
+        assert_eq!(notifier.enqueue(etching(1)), None);
+        assert_eq!(notifier.enqueue(etching(2)), Some(etching(2)));
+        assert_eq!(events.try_recv().unwrap(), etching(1));
+        assert_eq!(notifier.enqueue(etching(3)), None);
+
+        // a stopped sender drops the event instead of dead-lettering it
+        drop(events);
+        assert_eq!(notifier.enqueue(etching(4)), None);
+    }
+}
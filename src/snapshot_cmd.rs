@@ -0,0 +1,332 @@
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+
+use crate::{config, db};
+
+const SNAPSHOT_FORMAT: u32 = 3;
+const BATCH_SIZE: i32 = 1000;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct SnapshotHeader {
+    format: u32,
+    schema_version: i64,
+    created_at: i64,
+}
+
+/// One line of the newline-delimited JSON snapshot, the header goes first.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "table", content = "row", rename_all = "snake_case")]
+enum SnapshotEntry {
+    Header(SnapshotHeader),
+    Runes(db::RuneSnapshotRow),
+    RunesUtxos(db::RuneUtxo),
+    RunesBalances(db::RunesBalance),
+    RunesCommitments(db::RuneCommitment),
+    RunesLog(db::RuneLog),
+    RunesInscriptions(db::RuneInscription),
+    IndexCheckpoints(db::IndexCheckpoint),
+    IndexedBlocks(db::IndexedBlock),
+    LastIndexedBlock(db::LastIndexedBlock),
+}
+
+#[derive(Debug, clap::Parser)]
+pub struct ExportSnapshotCmd {
+    /// output file
+    #[arg(long)]
+    file: String,
+}
+
+impl ExportSnapshotCmd {
+    pub async fn run(&self, config_path: &str) -> anyhow::Result<()> {
+        let mut cfg = config::read_config(config_path)?;
+        cfg.db.automigrate = false;
+        let repo = db::open_postgres_db(cfg.db).await?;
+
+        let mut out = BufWriter::new(File::create(&self.file)?);
+        let mut write = |entry: &SnapshotEntry| -> anyhow::Result<()> {
+            serde_json::to_writer(&mut out, entry)?;
+            out.write_all(b"\n")?;
+            Ok(())
+        };
+
+        let mut export = repo.begin_snapshot_export().await?;
+        let blocks = export.last_indexed_blocks().await?;
+        write(&SnapshotEntry::Header(SnapshotHeader {
+            format: SNAPSHOT_FORMAT,
+            schema_version: export.schema_version().await?,
+            created_at: chrono::Utc::now().timestamp(),
+        }))?;
+        for b in blocks.iter() {
+            write(&SnapshotEntry::LastIndexedBlock(b.clone()))?;
+        }
+
+        let (mut runes, mut utxos, mut balances, mut commitments) = (0, 0, 0, 0);
+        let mut after_id = 0;
+        loop {
+            let batch = export.runes_batch(after_id, BATCH_SIZE).await?;
+            let Some(last) = batch.last() else { break };
+            after_id = last.id;
+            for row in batch {
+                write(&SnapshotEntry::Runes(row))?;
+                runes += 1;
+            }
+        }
+
+        let mut after_id = 0;
+        loop {
+            let batch = export
+                .unspent_runes_utxo_batch(after_id, BATCH_SIZE)
+                .await?;
+            let Some(last) = batch.last() else { break };
+            after_id = last.id;
+            for row in batch {
+                write(&SnapshotEntry::RunesUtxos(row))?;
+                utxos += 1;
+            }
+        }
+
+        let mut after_id = 0;
+        loop {
+            let batch = export.runes_balances_batch(after_id, BATCH_SIZE).await?;
+            let Some(last) = batch.last() else { break };
+            after_id = last.id;
+            for row in batch {
+                write(&SnapshotEntry::RunesBalances(row))?;
+                balances += 1;
+            }
+        }
+
+        let mut after_id = 0;
+        loop {
+            let batch = export.runes_commitments_batch(after_id, BATCH_SIZE).await?;
+            let Some(last) = batch.last() else { break };
+            after_id = last.id;
+            for row in batch {
+                write(&SnapshotEntry::RunesCommitments(row))?;
+                commitments += 1;
+            }
+        }
+
+        let (mut log, mut inscriptions, mut checkpoints, mut indexed_blocks) = (0, 0, 0, 0);
+        let mut after_id = 0;
+        loop {
+            let batch = export.runes_log_batch(after_id, BATCH_SIZE).await?;
+            let Some(last) = batch.last() else { break };
+            after_id = last.id;
+            for row in batch {
+                write(&SnapshotEntry::RunesLog(row))?;
+                log += 1;
+            }
+        }
+
+        let mut after_id = 0;
+        loop {
+            let batch = export
+                .runes_inscriptions_batch(after_id, BATCH_SIZE)
+                .await?;
+            let Some(last) = batch.last() else { break };
+            after_id = last.id;
+            for row in batch {
+                write(&SnapshotEntry::RunesInscriptions(row))?;
+                inscriptions += 1;
+            }
+        }
+
+        let mut after_id = 0;
+        loop {
+            let batch = export.index_checkpoints_batch(after_id, BATCH_SIZE).await?;
+            let Some(last) = batch.last() else { break };
+            after_id = last.id;
+            for row in batch {
+                write(&SnapshotEntry::IndexCheckpoints(row))?;
+                checkpoints += 1;
+            }
+        }
+
+        for b in blocks.iter() {
+            let mut after_height = -1;
+            loop {
+                let batch = export
+                    .indexed_blocks_batch(&b.indexer, after_height, BATCH_SIZE)
+                    .await?;
+                let Some(last) = batch.last() else { break };
+                after_height = last.height;
+                for row in batch {
+                    write(&SnapshotEntry::IndexedBlocks(row))?;
+                    indexed_blocks += 1;
+                }
+            }
+        }
+        export.finish().await?;
+
+        out.flush()?;
+        println!(
+            "Exported runes={} utxos={} balances={} commitments={} log={} inscriptions={} checkpoints={} indexed_blocks={} heights={:?}",
+            runes,
+            utxos,
+            balances,
+            commitments,
+            log,
+            inscriptions,
+            checkpoints,
+            indexed_blocks,
+            blocks
+                .iter()
+                .map(|b| (b.indexer.as_str(), b.height))
+                .collect::<Vec<_>>()
+        );
+        Ok(())
+    }
+}
+
+#[derive(Debug, clap::Parser)]
+pub struct ImportSnapshotCmd {
+    /// snapshot file made by export-snapshot
+    #[arg(long)]
+    file: String,
+}
+
+impl ImportSnapshotCmd {
+    pub async fn run(&self, config_path: &str) -> anyhow::Result<()> {
+        let cfg = config::read_config(config_path)?;
+        let repo = db::open_postgres_db(cfg.db).await?;
+
+        let mut lines = BufReader::new(File::open(&self.file)?).lines();
+        let header = match lines.next() {
+            Some(line) => serde_json::from_str::<SnapshotEntry>(&line?)?,
+            None => anyhow::bail!("snapshot is empty"),
+        };
+        let SnapshotEntry::Header(header) = header else {
+            anyhow::bail!("snapshot has no header")
+        };
+        if header.format != SNAPSHOT_FORMAT {
+            anyhow::bail!("unsupported snapshot format {}", header.format)
+        }
+        let schema_version = repo.schema_version().await?;
+        if header.schema_version != schema_version {
+            anyhow::bail!(
+                "snapshot schema version {} doesn't match the db {}",
+                header.schema_version,
+                schema_version
+            )
+        }
+
+        // only the seed runes are allowed, like right after reset-db
+        let seeds = cfg.indexers.seed_runes()?.len() as i64;
+        if repo.count_table_rows("runes").await? > seeds
+            || repo.count_table_rows("runes_utxos").await? > 0
+            || repo.count_table_rows("runes_balances").await? > 0
+            || repo.count_table_rows("runes_commitments").await? > 0
+            || repo.count_table_rows("runes_log").await? > 0
+            || repo.count_table_rows("runes_inscriptions").await? > 0
+            || repo.count_table_rows("index_checkpoints").await? > 0
+            || repo.count_table_rows("indexed_blocks").await? > 0
+        {
+            anyhow::bail!("target db isn't empty, reset it first")
+        }
+
+        let mut import = repo.begin_snapshot_import().await?;
+        import.clear_runes().await?;
+
+        let (mut runes, mut utxos, mut balances, mut commitments) = (0, 0, 0, 0);
+        let (mut log, mut inscriptions, mut checkpoints, mut indexed_blocks) = (0, 0, 0, 0);
+        for (n, line) in lines.enumerate() {
+            let line = line?;
+            if line.is_empty() {
+                continue;
+            }
+            let entry: SnapshotEntry = serde_json::from_str(&line)
+                .map_err(|e| anyhow::anyhow!("invalid snapshot line {}: {}", n + 2, e))?;
+
+            match entry {
+                SnapshotEntry::Header(_) => anyhow::bail!("unexpected header at line {}", n + 2),
+                SnapshotEntry::Runes(row) => {
+                    import.insert_rune(&row).await?;
+                    runes += 1;
+                }
+                SnapshotEntry::RunesUtxos(row) => {
+                    import.insert_rune_utxo(&row).await?;
+                    utxos += 1;
+                }
+                SnapshotEntry::RunesBalances(row) => {
+                    import.insert_runes_balance(&row).await?;
+                    balances += 1;
+                }
+                SnapshotEntry::RunesCommitments(row) => {
+                    import.insert_rune_commitment(&row).await?;
+                    commitments += 1;
+                }
+                SnapshotEntry::RunesLog(row) => {
+                    import.insert_rune_log(&row).await?;
+                    log += 1;
+                }
+                SnapshotEntry::RunesInscriptions(row) => {
+                    import.insert_rune_inscription(&row).await?;
+                    inscriptions += 1;
+                }
+                SnapshotEntry::IndexCheckpoints(row) => {
+                    import.insert_index_checkpoint(&row).await?;
+                    checkpoints += 1;
+                }
+                SnapshotEntry::IndexedBlocks(row) => {
+                    import.insert_indexed_block(&row).await?;
+                    indexed_blocks += 1;
+                }
+                SnapshotEntry::LastIndexedBlock(row) => {
+                    import.set_last_indexed_block(&row).await?;
+                }
+            }
+        }
+
+        import.commit().await?;
+        println!(
+            "Imported runes={} utxos={} balances={} commitments={} log={} inscriptions={} checkpoints={} indexed_blocks={}, run warmup-cache before starting the indexer",
+            runes, utxos, balances, commitments, log, inscriptions, checkpoints, indexed_blocks
+        );
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn snapshot_entry_roundtrip() {
+        use super::SnapshotEntry;
+        use crate::db;
+
+        let entry = SnapshotEntry::RunesBalances(db::RunesBalance {
+            id: 7,
+            address: "bc1q...".to_owned(),
+            rune: "UNCOMMONGOODS".to_owned(),
+            balance: "340282366920938463463374607431768211455".to_owned(),
+        });
+        let line = serde_json::to_string(&entry).unwrap();
+        assert!(line.starts_with(r#"{"table":"runes_balances","row":{"#));
+
+        let SnapshotEntry::RunesBalances(b) = serde_json::from_str(&line).unwrap() else {
+            panic!("wrong entry type");
+        };
+        assert_eq!(b.balance, "340282366920938463463374607431768211455");
+
+        // the activity is exported along with the rune row
+        let entry = SnapshotEntry::Runes(db::RuneSnapshotRow {
+            rune: db::Rune {
+                rune: "UNCOMMONGOODS".to_owned(),
+                timestamp: 100,
+                ..Default::default()
+            },
+            last_activity: 200,
+        });
+        let line = serde_json::to_string(&entry).unwrap();
+        assert!(line.contains(r#""timestamp":100"#));
+        assert!(line.contains(r#""last_activity":200"#));
+        let SnapshotEntry::Runes(row) = serde_json::from_str(&line).unwrap() else {
+            panic!("wrong entry type");
+        };
+        assert_eq!(
+            (row.rune.rune.as_str(), row.last_activity),
+            ("UNCOMMONGOODS", 200)
+        );
+    }
+}
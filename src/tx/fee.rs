@@ -0,0 +1,81 @@
+use serde::{Deserialize, Serialize};
+
+/// Fees are estimated from the vsize of the unsigned tx, signing adds the witnesses
+/// and grows it. The safety factor covers that growth, once the vsize estimate accounts
+/// for the witnesses it should approach 1.0.
+pub const DEFAULT_SAFETY_FACTOR: f64 = 2.0;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct FeeEstimate {
+    /// requested sat/vB
+    pub fee_rate: f64,
+    pub safety_factor: f64,
+    /// vsize of the unsigned tx
+    pub vsize: usize,
+    pub fee: u64,
+}
+
+impl FeeEstimate {
+    pub fn new(fee_rate: f64, vsize: usize, safety_factor: f64) -> Self {
+        Self {
+            fee_rate,
+            safety_factor,
+            vsize,
+            fee: (fee_rate * vsize as f64 * safety_factor).ceil() as u64,
+        }
+    }
+
+    /// sat/vB paid by the signed tx
+    pub fn effective_fee_rate(&self, signed_vsize: usize) -> f64 {
+        effective_fee_rate(self.fee, signed_vsize)
+    }
+}
+
+/// How fast a tx should confirm, selects the fee rate estimate of the utxo provider.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum FeePriority {
+    Economy,
+    #[default]
+    Normal,
+    Priority,
+}
+
+impl FeePriority {
+    /// confirmation target in blocks
+    pub fn target_blocks(&self) -> u16 {
+        match self {
+            Self::Economy => 24,
+            Self::Normal => 6,
+            Self::Priority => 2,
+        }
+    }
+}
+
+pub fn effective_fee_rate(fee: u64, vsize: usize) -> f64 {
+    if vsize == 0 {
+        return 0.0;
+    }
+    fee as f64 / vsize as f64
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn fee_is_scaled_by_safety_factor() {
+        use super::FeeEstimate;
+
+        let estimate = FeeEstimate::new(10.0, 150, 2.0);
+        assert_eq!(estimate.fee, 3_000);
+        // the signed tx is smaller than the estimate, so it pays more than requested
+        assert_eq!(estimate.effective_fee_rate(200), 15.0);
+
+        let exact = FeeEstimate::new(10.0, 150, 1.0);
+        assert_eq!(exact.fee, 1_500);
+        assert_eq!(exact.effective_fee_rate(150), 10.0);
+
+        // fractional fees are rounded up
+        assert_eq!(FeeEstimate::new(1.5, 101, 1.85).fee, 281);
+        assert_eq!(FeeEstimate::new(1.0, 0, 2.0).effective_fee_rate(0), 0.0);
+    }
+}
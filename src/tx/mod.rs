@@ -1,3 +1,4 @@
+pub mod fee;
 pub mod pool_txs;
 pub mod runes_txs;
 pub mod signer;
@@ -1,5 +1,5 @@
 use std::sync::Arc;
-use std::{collections::HashSet, str::FromStr};
+use std::{collections::HashSet, fmt, str::FromStr};
 
 use bitcoin::sighash::TapSighashType;
 use bitcoin::{
@@ -7,34 +7,374 @@ use bitcoin::{
     address::NetworkChecked,
     psbt::{self},
     secp256k1::XOnlyPublicKey,
-    Address, AddressType, Network, OutPoint, PublicKey, ScriptBuf, Transaction, TxOut,
+    Address, AddressType, Network, OutPoint, PublicKey, Script, ScriptBuf, Transaction, TxOut,
 };
-use ordinals::{Edict, RuneId, Runestone};
+use bitcoincore_rpc::RpcApi;
+use ordinals::{Artifact, Edict, RuneId, Runestone};
+use rand::Rng;
 use tokio::sync::RwLock;
 
 use crate::cache::CacheRepo;
 use crate::{
     btc_utxo::UtxoClient,
-    db::Repo,
+    config,
+    db::{self, Repo},
+    indexer::BTC_INDEXER_ID,
     service::entities::{BtcUtxo, RuneUtxo},
-    tx::runes_txs,
+    tx::{
+        fee::{FeeEstimate, FeePriority},
+        runes_txs,
+    },
 };
 
+/// utxos spent by our submitted txs which aren't mined yet
+pub async fn select_pending_spends(db: &Repo) -> anyhow::Result<HashSet<OutPoint>> {
+    let pending = db.select_pending_txs().await?;
+    Ok(pending_spent_outpoints(&pending))
+}
+
+fn pending_spent_outpoints(txs: &[crate::db::Transaction]) -> HashSet<OutPoint> {
+    let mut spent = HashSet::new();
+    for tx in txs.iter() {
+        match tx.spent_outpoints() {
+            Ok(outpoints) => spent.extend(outpoints),
+            Err(err) => warn!(
+                "Can't decode pending tx, its inputs may be reused: tx_hash={} error={}",
+                tx.tx_hash, err
+            ),
+        }
+    }
+    spent
+}
+
+/// returned by the utxo collectors when the unlocked utxos of an address don't cover the amount
+#[derive(Debug)]
+pub struct InsufficientFunds {
+    pub asset: String,
+    pub address: String,
+    pub has: u128,
+    pub need: u128,
+}
+
+impl InsufficientFunds {
+    pub fn shortfall(&self) -> u128 {
+        self.need.saturating_sub(self.has)
+    }
+}
+
+impl fmt::Display for InsufficientFunds {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "account({}) doesn't have enough {} utxos: has={} need={}",
+            self.address, self.asset, self.has, self.need
+        )
+    }
+}
+
+impl std::error::Error for InsufficientFunds {}
+
+/// bitcoind's default `-datacarriersize`, larger OP_RETURN outputs are not relayed
+pub const DEFAULT_MAX_RUNESTONE_SIZE: usize = 83;
+
+/// returned when the enciphered runestone is larger than the OP_RETURN relay limit
+#[derive(Debug)]
+pub struct RunestoneTooLarge {
+    pub size: usize,
+    pub max: usize,
+}
+
+impl fmt::Display for RunestoneTooLarge {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "runestone is {} bytes, the limit is {}: split the transfer into several txs",
+            self.size, self.max
+        )
+    }
+}
+
+impl std::error::Error for RunestoneTooLarge {}
+
+pub fn check_runestone_size(script: &Script, max: usize) -> Result<(), RunestoneTooLarge> {
+    if script.len() > max {
+        return Err(RunestoneTooLarge {
+            size: script.len(),
+            max,
+        });
+    }
+    Ok(())
+}
+
+/// Position of the outputs in built txs. The runestone stays the first output,
+/// its edicts and pointer are remapped to the new positions. Only the outputs are
+/// reordered, the inputs keep the order they were selected in.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum OutputOrder {
+    /// rune outputs, btc outputs, then change
+    #[default]
+    Fixed,
+    /// the output half of BIP69, ascending by value and then by script
+    Bip69Outputs,
+    /// change outputs are moved to random positions
+    RandomChange,
+}
+
+impl FromStr for OutputOrder {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "fixed" => Ok(Self::Fixed),
+            "bip69_outputs" => Ok(Self::Bip69Outputs),
+            "random_change" => Ok(Self::RandomChange),
+            _ => anyhow::bail!("unknown output order ({})", s),
+        }
+    }
+}
+
+/// nLockTime of built txs
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum LockTimePolicy {
+    #[default]
+    Zero,
+    /// consensus value, a block height below 500000000 or a unix timestamp
+    Fixed(u32),
+    /// the current block height, discourages fee sniping like bitcoind wallets do
+    Tip,
+}
+
+impl LockTimePolicy {
+    /// `tip_height` is used only by `Tip`
+    pub fn resolve(&self, tip_height: u32) -> anyhow::Result<LockTime> {
+        match self {
+            Self::Zero => Ok(LockTime::ZERO),
+            Self::Fixed(value) => Ok(LockTime::from_consensus(*value)),
+            Self::Tip => Ok(LockTime::from_height(tip_height)?),
+        }
+    }
+
+    /// resolves at the node's block count, the node is asked only for `Tip`
+    pub fn resolve_from_node(&self, rpc: &impl RpcApi) -> anyhow::Result<LockTime> {
+        let tip_height = match self {
+            Self::Tip => rpc.get_block_count()? as u32,
+            _ => 0,
+        };
+        self.resolve(tip_height)
+    }
+}
+
+impl FromStr for LockTimePolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "zero" => Ok(Self::Zero),
+            "tip" => Ok(Self::Tip),
+            _ => match s.parse::<u32>() {
+                Ok(value) => Ok(Self::Fixed(value)),
+                Err(_) => anyhow::bail!("unknown lock time ({})", s),
+            },
+        }
+    }
+}
+
+/// Reorders the outputs of a built tx and rewrites the runestone at output 0,
+/// so the edicts and the pointer still target the same outputs.
+/// `change_outputs` are the indexes before reordering, the new index of every
+/// output is returned.
+pub fn reorder_outputs<R: Rng>(
+    tx: &mut Transaction,
+    order: OutputOrder,
+    change_outputs: &[usize],
+    rng: &mut R,
+) -> anyhow::Result<Vec<u32>> {
+    if order == OutputOrder::Fixed {
+        return Ok((0..tx.output.len() as u32).collect());
+    }
+
+    let runestone = match Runestone::decipher(tx) {
+        Some(Artifact::Runestone(runestone)) => Some(runestone),
+        Some(Artifact::Cenotaph(_)) => anyhow::bail!("tx has an invalid runestone"),
+        None => None,
+    };
+    if runestone.is_some() && !tx.output[0].script_pubkey.is_op_return() {
+        anyhow::bail!("runestone must be the first output");
+    }
+
+    let first = if runestone.is_some() { 1 } else { 0 };
+    let mut rest: Vec<usize> = (first..tx.output.len()).collect();
+    match order {
+        OutputOrder::Fixed => {}
+        OutputOrder::Bip69Outputs => rest.sort_by(|a, b| {
+            let (a, b) = (&tx.output[*a], &tx.output[*b]);
+            (a.value, a.script_pubkey.as_bytes()).cmp(&(b.value, b.script_pubkey.as_bytes()))
+        }),
+        OutputOrder::RandomChange => {
+            let (change, mut others): (Vec<usize>, Vec<usize>) =
+                rest.into_iter().partition(|i| change_outputs.contains(i));
+            for i in change {
+                let at = rng.gen_range(0..=others.len());
+                others.insert(at, i);
+            }
+            rest = others;
+        }
+    }
+
+    let new_order: Vec<usize> = (0..first).chain(rest).collect();
+    let mut position = vec![0; new_order.len()];
+    for (new, old) in new_order.iter().enumerate() {
+        position[*old] = new as u32;
+    }
+    tx.output = new_order.iter().map(|i| tx.output[*i].clone()).collect();
+
+    if let Some(mut runestone) = runestone {
+        for edict in runestone.edicts.iter_mut() {
+            // `output == len` splits the amount between all outputs
+            if let Some(new) = position.get(edict.output as usize) {
+                edict.output = *new;
+            }
+        }
+        runestone.pointer = runestone.pointer.map(|p| position[p as usize]);
+        tx.output[0].script_pubkey = runestone.encipher();
+    }
+
+    Ok(position)
+}
+
 pub struct PoolTxBuilder {
     db: Arc<Repo>,
     pub cache: Arc<RwLock<CacheRepo>>,
     utxo_provider: UtxoClient,
+    rune_output_value: u64,
+    fee_safety_factor: f64,
+    exclude_pending_spends: bool,
+    skip_immature_coinbase: bool,
+    signing_allowlist: SigningAllowlist,
+    output_order: OutputOrder,
+    max_runestone_size: usize,
+    tx_version: i32,
+    lock_time: LockTimePolicy,
+    /// the tip height of `LockTimePolicy::Tip`
+    node: Option<Arc<bitcoincore_rpc::Client>>,
 }
 
 impl PoolTxBuilder {
-    pub fn new(db: Arc<Repo>, cache: Arc<RwLock<CacheRepo>>, utxo_provider: UtxoClient) -> Self {
+    /// inputs the service signs must belong to `signing_allowlist`
+    pub fn new(
+        db: Arc<Repo>,
+        cache: Arc<RwLock<CacheRepo>>,
+        utxo_provider: UtxoClient,
+        signing_allowlist: SigningAllowlist,
+    ) -> Self {
         Self {
             db,
             cache,
             utxo_provider,
+            rune_output_value: runes_txs::RUNES_OUT_VALUE,
+            fee_safety_factor: crate::tx::fee::DEFAULT_SAFETY_FACTOR,
+            exclude_pending_spends: true,
+            skip_immature_coinbase: true,
+            signing_allowlist,
+            output_order: OutputOrder::Fixed,
+            max_runestone_size: DEFAULT_MAX_RUNESTONE_SIZE,
+            tx_version: runes_txs::DEFAULT_TX_VERSION,
+            lock_time: LockTimePolicy::Zero,
+            node: None,
         }
     }
 
+    /// a builder with the tx options of `[btc]`
+    pub fn from_config(
+        cfg: &config::BTCConfig,
+        db: Arc<Repo>,
+        cache: Arc<RwLock<CacheRepo>>,
+        utxo_provider: UtxoClient,
+        signing_allowlist: SigningAllowlist,
+    ) -> anyhow::Result<Self> {
+        let lock_time = cfg.lock_time()?;
+        let mut builder = Self::new(db, cache, utxo_provider, signing_allowlist)
+            .with_rune_output_value(cfg.rune_output_value())
+            .with_fee_safety_factor(cfg.fee_safety_factor())
+            .with_exclude_pending_spends(cfg.exclude_pending_spends())
+            .with_skip_immature_coinbase(cfg.skip_immature_coinbase())
+            .with_output_order(cfg.output_order()?)
+            .with_max_runestone_size(cfg.max_runestone_size())
+            .with_tx_version(cfg.tx_version()?)
+            .with_lock_time(lock_time);
+        if lock_time == LockTimePolicy::Tip {
+            builder = builder.with_node(Arc::new(cfg.rpc_client()?));
+        }
+        Ok(builder)
+    }
+
+    pub fn with_exclude_pending_spends(mut self, exclude: bool) -> Self {
+        self.exclude_pending_spends = exclude;
+        self
+    }
+
+    /// Utxos spent by our submitted txs which aren't mined yet. The db and the cache
+    /// reflect confirmed state only, spending them again would be rejected as a double spend.
+    pub async fn pending_spends(&self) -> anyhow::Result<HashSet<OutPoint>> {
+        if !self.exclude_pending_spends {
+            return Ok(HashSet::new());
+        }
+        select_pending_spends(&self.db).await
+    }
+
+    pub fn with_fee_safety_factor(mut self, factor: f64) -> Self {
+        self.fee_safety_factor = factor;
+        self
+    }
+
+    pub fn with_rune_output_value(mut self, value: u64) -> Self {
+        self.rune_output_value = value;
+        self
+    }
+
+    pub fn with_output_order(mut self, order: OutputOrder) -> Self {
+        self.output_order = order;
+        self
+    }
+
+    pub fn with_max_runestone_size(mut self, size: usize) -> Self {
+        self.max_runestone_size = size;
+        self
+    }
+
+    pub fn with_skip_immature_coinbase(mut self, skip: bool) -> Self {
+        self.skip_immature_coinbase = skip;
+        self
+    }
+
+    pub fn with_tx_version(mut self, version: i32) -> Self {
+        self.tx_version = version;
+        self
+    }
+
+    pub fn with_lock_time(mut self, policy: LockTimePolicy) -> Self {
+        self.lock_time = policy;
+        self
+    }
+
+    pub fn with_node(mut self, rpc: Arc<bitcoincore_rpc::Client>) -> Self {
+        self.node = Some(rpc);
+        self
+    }
+
+    /// The inputs have non final sequences, so the lock time is enforced.
+    /// `Tip` uses the node's height, the indexed height may lag behind it.
+    async fn lock_time(&self) -> anyhow::Result<LockTime> {
+        if self.lock_time != LockTimePolicy::Tip {
+            return self.lock_time.resolve(0);
+        }
+        let Some(rpc) = self.node.clone() else {
+            anyhow::bail!("lock_time = \"tip\" needs a bitcoind rpc client");
+        };
+        let policy = self.lock_time;
+        tokio::task::spawn_blocking(move || policy.resolve_from_node(rpc.as_ref())).await?
+    }
+
     pub async fn collect_runes_utxo(
         &self,
         rune_name: &str,
@@ -62,13 +402,13 @@ impl PoolTxBuilder {
                 .await?;
 
             if db_utxos.is_empty() {
-                anyhow::bail!(
-                    "account({}) doesn't have enounght runes({}) utxos: has={} need={}",
-                    address,
-                    rune_name,
-                    collected_amount,
-                    amount
-                )
+                return Err(InsufficientFunds {
+                    asset: rune_name.to_owned(),
+                    address: address.to_owned(),
+                    has: collected_amount,
+                    need: amount,
+                }
+                .into());
             }
 
             for u in db_utxos.iter() {
@@ -102,6 +442,8 @@ impl PoolTxBuilder {
         let mut offset = 0;
         let mut collected_amount: u64 = 0;
         let mut result = Vec::new();
+        // fetched with the first coinbase utxo
+        let mut tip_height: Option<i64> = None;
 
         'collector: loop {
             if collected_amount >= amount {
@@ -109,12 +451,13 @@ impl PoolTxBuilder {
             }
             let db_utxos = self.utxo_provider.get_utxo(address, 40, offset).await?;
             if db_utxos.is_empty() {
-                anyhow::bail!(
-                    "account({}) doesn't have enounght btc utxos: has={} need={}",
-                    address,
-                    collected_amount,
-                    amount
-                )
+                return Err(InsufficientFunds {
+                    asset: "BTC".to_owned(),
+                    address: address.to_owned(),
+                    has: collected_amount as u128,
+                    need: amount as u128,
+                }
+                .into());
             }
 
             for u in db_utxos.iter() {
@@ -122,6 +465,19 @@ impl PoolTxBuilder {
                 if locked_utxos.contains(&op) {
                     continue;
                 }
+                if u.coinbase && self.skip_immature_coinbase {
+                    let tip = match tip_height {
+                        Some(tip) => tip,
+                        None => {
+                            let tip = self.db.get_last_indexed_block(BTC_INDEXER_ID).await?.height;
+                            *tip_height.insert(tip)
+                        }
+                    };
+                    if is_immature_coinbase(u, tip) {
+                        debug!("Skip immature coinbase utxo {} tip={}", op, tip);
+                        continue;
+                    }
+                }
 
                 collected_amount += u.amount as u64;
                 result.push(BtcUtxo::from(u));
@@ -140,6 +496,10 @@ impl PoolTxBuilder {
         tx_params: TxParams,
         net: Network,
     ) -> anyhow::Result<PSBTContainer> {
+        self.signing_allowlist.check(&tx_params.rune_input)?;
+        self.signing_allowlist.check(&tx_params.btc_input)?;
+        self.signing_allowlist.check(&tx_params.btc_fee_input)?;
+
         let (btc_amount, rune_amount) = (
             tx_params.btc_output.btc_amount,
             tx_params.rune_output.rune_amount,
@@ -168,9 +528,12 @@ impl PoolTxBuilder {
         for u in used_runes_utxos.into_iter() {
             used_btc_utxos.insert(u);
         }
+        used_btc_utxos.extend(self.pending_spends().await?);
 
         let mut builder_ctx = TxBuilderCtx::new(true);
         builder_ctx.used_btc_utxos = used_btc_utxos;
+        builder_ctx.tx.version = self.tx_version;
+        builder_ctx.tx.lock_time = self.lock_time().await?;
 
         // builder_ctx.used_btc_utxos = cache.get_locked_utxos(asset, address);
         // this is an amount for case if there isn't enough btc on the rune inputs
@@ -224,6 +587,7 @@ impl PoolTxBuilder {
                         &tx_out,
                         &rune_redeem_script,
                         &rune_tr_pubkey,
+                        tx_params.rune_input.sighash_type,
                     ));
                 }
             }
@@ -243,22 +607,29 @@ impl PoolTxBuilder {
                 output: 1,
             }];
 
+            let rune_out_script = tx_params.rune_output.address.script_pubkey();
+            let rune_out_value =
+                runes_txs::rune_output_value(self.rune_output_value, &rune_out_script)?;
             builder_ctx.tx.output.push(TxOut {
-                script_pubkey: tx_params.rune_output.address.script_pubkey(),
-                value: runes_txs::RUNES_OUT_VALUE,
+                script_pubkey: rune_out_script,
+                value: rune_out_value,
             });
-            builder_ctx.btc_out += runes_txs::RUNES_OUT_VALUE;
-
-            let mut rune_btc_change = rune_btc_in_amount - runes_txs::RUNES_OUT_VALUE;
-            if rune_btc_change < runes_txs::RUNES_OUT_VALUE {
-                btc_extra_amount = runes_txs::RUNES_OUT_VALUE - rune_btc_change;
-                rune_btc_change = runes_txs::RUNES_OUT_VALUE;
+            builder_ctx.btc_out += rune_out_value;
+
+            let rune_change_script = tx_params.rune_input.change_script(net)?;
+            let min_change_value =
+                runes_txs::rune_output_value(self.rune_output_value, &rune_change_script)?;
+            let mut rune_btc_change = rune_btc_in_amount.saturating_sub(rune_out_value);
+            if rune_btc_change < min_change_value {
+                btc_extra_amount = min_change_value - rune_btc_change;
+                rune_btc_change = min_change_value;
             }
 
             let pointer = Some(builder_ctx.tx.output.len() as u32);
+            builder_ctx.change_outputs.push(builder_ctx.tx.output.len());
             builder_ctx.tx.output.push(TxOut {
                 value: rune_btc_change,
-                script_pubkey: tx_params.rune_input.address.script_pubkey(),
+                script_pubkey: rune_change_script,
             });
 
             warn!("RUNE_BTC_CHANGE_AMOUNT = {}", rune_btc_change);
@@ -301,10 +672,18 @@ impl PoolTxBuilder {
             builder_ctx.btc_out += service_fee;
         }
 
-        let fee_rate = self.utxo_provider.get_fee().await?;
-        // this is rough estimation of resulting fee for the tx
-        // trying to guess resulting size of the fully set tx
-        let fee = fee_rate * builder_ctx.tx.vsize() as u64 * 2; // 2 stands as rough estim for the size grow of the signed tx
+        // the service's txs aim for the next blocks
+        let fee_rate = self
+            .utxo_provider
+            .get_fee_for_target(FeePriority::Priority.target_blocks())
+            .await?;
+        // the tx isn't complete yet, the safety factor covers btc inputs and signatures added later
+        let fee_estimate = FeeEstimate::new(
+            fee_rate as f64,
+            builder_ctx.tx.vsize(),
+            self.fee_safety_factor,
+        );
+        let fee = fee_estimate.fee;
 
         let total_fee: u64 = fee + service_fee + btc_extra_amount;
 
@@ -372,6 +751,18 @@ impl PoolTxBuilder {
 
         // ----------------------------
 
+        reorder_outputs(
+            &mut builder_ctx.tx,
+            self.output_order,
+            &builder_ctx.change_outputs,
+            &mut rand::thread_rng(),
+        )?;
+        // remapped edict outputs may change the size, so it's checked on the final tx
+        check_runestone_size(
+            &builder_ctx.tx.output[0].script_pubkey,
+            self.max_runestone_size,
+        )?;
+
         let mut psbt = bitcoin::psbt::Psbt::from_unsigned_tx(builder_ctx.tx.clone())?;
         psbt.inputs = builder_ctx.psbt_inputs;
 
@@ -386,6 +777,7 @@ impl PoolTxBuilder {
             tx: builder_ctx.tx,
             psbt,
             fee: total_fee,
+            fee_estimate,
             parent_utxos: builder_ctx.parent_utxos,
         })
     }
@@ -433,6 +825,7 @@ impl PoolTxBuilder {
                     &tx_out,
                     &btc_redeem_script,
                     &btc_tr_pubkey,
+                    input_params.sighash_type,
                 ));
             }
         }
@@ -459,14 +852,7 @@ impl PoolTxBuilder {
         }
 
         let btc_change_value = btc_in_amount - btc_amount;
-        if btc_change_value > 600 {
-            builder_ctx.tx.output.push(TxOut {
-                value: btc_change_value,
-                script_pubkey: input_params.address.script_pubkey(),
-            });
-
-            builder_ctx.btc_out += btc_change_value;
-        }
+        builder_ctx.add_change(input_params.change_script(net)?, btc_change_value);
 
         Ok(())
     }
@@ -481,6 +867,8 @@ pub struct PSBTContainer {
     // (id_of_input, signable)
     pub btc_inputs: Vec<(usize, bool)>,
     pub fee: u64,
+    /// network part of `fee`, with the rate and the safety factor it was estimated with
+    pub fee_estimate: FeeEstimate,
     // (signable, tx_out)
     pub parent_utxos: Vec<(bool, TxOut)>,
 }
@@ -511,9 +899,28 @@ pub struct InputOpts {
     pub original_public_key: Option<String>,
     pub can_be_signed: bool,
     pub rune_name: Option<String>,
+    /// receives the change, the input address is used if not set
+    pub change_address: Option<Address<NetworkChecked>>,
+    /// sighash type of the psbt inputs, use `TapSighashType::All` by default
+    pub sighash_type: TapSighashType,
 }
 
 impl InputOpts {
+    pub fn change_script(&self, net: Network) -> anyhow::Result<ScriptBuf> {
+        let Some(change_address) = &self.change_address else {
+            return Ok(self.address.script_pubkey());
+        };
+
+        if !change_address.as_unchecked().is_valid_for_network(net) {
+            anyhow::bail!(
+                "change address ({}) doesn't belong to the {} network",
+                change_address,
+                net
+            )
+        }
+        Ok(change_address.script_pubkey())
+    }
+
     pub fn psbt_input_extras(
         &self,
         net: Network,
@@ -548,18 +955,67 @@ impl InputOpts {
     }
 }
 
+/// Addresses the service is allowed to sign inputs for.
+#[derive(Clone, Debug)]
+pub struct SigningAllowlist {
+    addresses: HashSet<String>,
+}
+
+impl SigningAllowlist {
+    /// `allowed` falls back to the signer addresses if empty
+    pub fn new(net: Network, signers: &[Address], allowed: &[String]) -> anyhow::Result<Self> {
+        if allowed.is_empty() {
+            return Ok(Self {
+                addresses: signers.iter().map(|s| s.to_string()).collect(),
+            });
+        }
+
+        let mut addresses = HashSet::new();
+        for a in allowed {
+            let address = Address::from_str(a)
+                .map_err(|e| anyhow::anyhow!("invalid signable address ({}): {}", a, e))?
+                .require_network(net)?;
+            addresses.insert(address.to_string());
+        }
+
+        for signer in signers.iter() {
+            if !addresses.contains(&signer.to_string()) {
+                warn!("signer address({}) isn't in the signable addresses", signer);
+            }
+        }
+
+        Ok(Self { addresses })
+    }
+
+    /// an empty allowlist denies signing any input
+    pub fn check(&self, input: &InputOpts) -> anyhow::Result<()> {
+        if !input.can_be_signed {
+            return Ok(());
+        }
+        if self.addresses.is_empty() {
+            anyhow::bail!("no addresses are allowed to be signed by the service")
+        }
+        if !self.addresses.contains(&input.address.to_string()) {
+            anyhow::bail!(
+                "address({}) is not allowed to be signed by the service",
+                input.address
+            )
+        }
+        Ok(())
+    }
+}
+
 fn psbt_input(
     tx_out: &TxOut,
     redeem_script: &Option<ScriptBuf>,
     tap_key: &Option<XOnlyPublicKey>,
+    sighash_type: TapSighashType,
 ) -> bitcoin::psbt::Input {
     bitcoin::psbt::Input {
         witness_utxo: Some(tx_out.clone()),
         redeem_script: redeem_script.clone(),
         tap_internal_key: *tap_key,
-        sighash_type: Some(bitcoin::psbt::PsbtSighashType::from_u32(
-            TapSighashType::All as u32,
-        )),
+        sighash_type: Some(bitcoin::psbt::PsbtSighashType::from(sighash_type)),
         ..Default::default()
     }
 }
@@ -578,6 +1034,8 @@ struct TxBuilderCtx {
     runes_input_indexes: Vec<(usize, bool)>,
     btc_in: u64,
     btc_out: u64,
+    // indexes of the change outputs, including the rune change
+    change_outputs: Vec<usize>,
 }
 
 impl TxBuilderCtx {
@@ -609,6 +1067,602 @@ impl TxBuilderCtx {
             runes_input_indexes: Vec::new(),
             btc_in: 0,
             btc_out: 0,
+            change_outputs: Vec::new(),
+        }
+    }
+
+    /// adds the change to an existing change output to the same script,
+    /// otherwise creates a new one if it isn't dust
+    fn add_change(&mut self, script_pubkey: ScriptBuf, value: u64) {
+        const MIN_CHANGE_VALUE: u64 = 600;
+
+        let existing = self
+            .change_outputs
+            .iter()
+            .find(|i| self.tx.output[**i].script_pubkey == script_pubkey)
+            .copied();
+
+        match existing {
+            Some(i) if value > 0 => self.tx.output[i].value += value,
+            None if value > MIN_CHANGE_VALUE => {
+                self.change_outputs.push(self.tx.output.len());
+                self.tx.output.push(TxOut {
+                    value,
+                    script_pubkey,
+                });
+            }
+            _ => return,
         }
+
+        self.btc_out += value;
+    }
+}
+
+/// blocks after which coinbase outputs can be spent
+pub const COINBASE_MATURITY: i64 = 100;
+
+/// the coinbase output can't be spent by a tx mined in the block after `tip_height`
+pub fn is_immature_coinbase(utxo: &db::BtcUtxo, tip_height: i64) -> bool {
+    utxo.coinbase && tip_height + 1 - utxo.block < COINBASE_MATURITY
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn change_goes_to_change_address() {
+        use super::InputOpts;
+        use bitcoin::{Address, Network};
+        use std::str::FromStr;
+
+        let address = |a: &str| {
+            Address::from_str(a)
+                .unwrap()
+                .require_network(Network::Bitcoin)
+                .unwrap()
+        };
+        let input = address("bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4");
+        let change = address("bc1qar0srrr7xfkvy5l643lydnw9re59gtzzwf5mdq");
+
+        let mut opts = InputOpts {
+            address: input.clone(),
+            original_public_key: None,
+            can_be_signed: false,
+            rune_name: None,
+            change_address: None,
+            sighash_type: bitcoin::sighash::TapSighashType::All,
+        };
+        assert_eq!(
+            opts.change_script(Network::Bitcoin).unwrap(),
+            input.script_pubkey()
+        );
+
+        opts.change_address = Some(change.clone());
+        assert_eq!(
+            opts.change_script(Network::Bitcoin).unwrap(),
+            change.script_pubkey()
+        );
+        assert!(opts.change_script(Network::Testnet).is_err());
+    }
+
+    #[test]
+    fn signing_allowlist_denies_unknown_address() {
+        use super::{InputOpts, SigningAllowlist};
+        use bitcoin::{Address, Network};
+        use std::str::FromStr;
+
+        let address = |a: &str| {
+            Address::from_str(a)
+                .unwrap()
+                .require_network(Network::Bitcoin)
+                .unwrap()
+        };
+        let signer = address("bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4");
+        let other = address("bc1qar0srrr7xfkvy5l643lydnw9re59gtzzwf5mdq");
+        let input = |address: &Address, can_be_signed| InputOpts {
+            address: address.clone(),
+            original_public_key: None,
+            can_be_signed,
+            rune_name: None,
+            change_address: None,
+            sighash_type: bitcoin::sighash::TapSighashType::All,
+        };
+
+        let allowlist = SigningAllowlist::new(Network::Bitcoin, &[signer.clone()], &[]).unwrap();
+        assert!(allowlist.check(&input(&signer, true)).is_ok());
+        assert!(allowlist.check(&input(&other, true)).is_err());
+        // inputs signed by the user are not restricted
+        assert!(allowlist.check(&input(&other, false)).is_ok());
+
+        let allowlist =
+            SigningAllowlist::new(Network::Bitcoin, &[signer.clone()], &[other.to_string()])
+                .unwrap();
+        assert!(allowlist.check(&input(&other, true)).is_ok());
+        assert!(allowlist.check(&input(&signer, true)).is_err());
+
+        assert!(
+            SigningAllowlist::new(Network::Testnet, &[signer.clone()], &[other.to_string()])
+                .is_err()
+        );
+
+        // every local signer is allowed by default
+        let allowlist =
+            SigningAllowlist::new(Network::Bitcoin, &[signer.clone(), other.clone()], &[]).unwrap();
+        assert!(allowlist.check(&input(&signer, true)).is_ok());
+        assert!(allowlist.check(&input(&other, true)).is_ok());
+
+        // nothing to sign with, nothing is signed
+        let allowlist = SigningAllowlist::new(Network::Bitcoin, &[], &[]).unwrap();
+        assert!(allowlist.check(&input(&signer, true)).is_err());
+        assert!(allowlist.check(&input(&other, false)).is_ok());
+    }
+
+    #[test]
+    fn change_to_shared_address_is_merged() {
+        use super::TxBuilderCtx;
+        use bitcoin::{Address, Network, TxOut};
+        use std::str::FromStr;
+
+        let script = |a: &str| {
+            Address::from_str(a)
+                .unwrap()
+                .require_network(Network::Bitcoin)
+                .unwrap()
+                .script_pubkey()
+        };
+        let shared = script("bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4");
+        let other = script("bc1qar0srrr7xfkvy5l643lydnw9re59gtzzwf5mdq");
+
+        let mut ctx = TxBuilderCtx::new(true);
+        // the rune change
+        ctx.change_outputs.push(ctx.tx.output.len());
+        ctx.tx.output.push(TxOut {
+            value: 546,
+            script_pubkey: shared.clone(),
+        });
+        ctx.btc_out += 546;
+
+        ctx.add_change(shared.clone(), 10_000);
+        ctx.add_change(shared.clone(), 100);
+        let shared_outputs: Vec<&TxOut> = ctx
+            .tx
+            .output
+            .iter()
+            .filter(|o| o.script_pubkey == shared)
+            .collect();
+        assert_eq!(shared_outputs.len(), 1);
+        assert_eq!(shared_outputs[0].value, 10_646);
+
+        // a different address gets its own output, dust is dropped
+        ctx.add_change(other.clone(), 100);
+        assert_eq!(ctx.tx.output.len(), 2);
+        ctx.add_change(other.clone(), 5_000);
+        assert_eq!(ctx.tx.output.len(), 3);
+        assert_eq!(ctx.btc_out, 15_646);
+    }
+
+    #[test]
+    fn pending_spent_utxo_is_excluded() {
+        use super::pending_spent_outpoints;
+        use crate::db;
+        use bitcoin::consensus::encode::serialize_hex;
+        use bitcoin::{
+            absolute::LockTime, OutPoint, ScriptBuf, Sequence, Transaction, TxIn, TxOut, Txid,
+            Witness,
+        };
+        use std::str::FromStr;
+
+        let spent = OutPoint {
+            txid: Txid::from_str(
+                "4a5e1e4baab89f3a32518a88c31bc87f618f76673e2cc77ab2127b7afdeda33b",
+            )
+            .unwrap(),
+            vout: 1,
+        };
+        let tx = Transaction {
+            version: 2,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: spent,
+                script_sig: ScriptBuf::new(),
+                sequence: Sequence::MAX,
+                witness: Witness::new(),
+            }],
+            output: vec![TxOut {
+                value: 1_000,
+                script_pubkey: ScriptBuf::new(),
+            }],
+        };
+        let pending = vec![
+            db::Transaction {
+                tx_hash: tx.txid().to_string(),
+                raw_data: serialize_hex(&tx),
+                status: db::Transaction::STATUS_PENDING.to_owned(),
+                ..Default::default()
+            },
+            // undecodable txs are skipped
+            db::Transaction {
+                tx_hash: "broken".to_owned(),
+                raw_data: "zz".to_owned(),
+                ..Default::default()
+            },
+        ];
+
+        let excluded = pending_spent_outpoints(&pending);
+        assert_eq!(excluded.len(), 1);
+        assert!(excluded.contains(&spent));
+        assert!(!excluded.contains(&OutPoint { vout: 0, ..spent }));
+    }
+
+    #[test]
+    fn edicts_follow_reordered_outputs() {
+        use super::{reorder_outputs, OutputOrder};
+        use bitcoin::{absolute::LockTime, ScriptBuf, Transaction, TxOut};
+        use ordinals::{Artifact, Edict, RuneId, Runestone};
+        use rand::{rngs::StdRng, SeedableRng};
+
+        let script = |n: u8| ScriptBuf::from_bytes(vec![0x51, n]);
+        let runestone = Runestone {
+            edicts: vec![Edict {
+                id: RuneId {
+                    block: 840_000,
+                    tx: 1,
+                },
+                amount: 1_000,
+                output: 1,
+            }],
+            pointer: Some(2),
+            ..Default::default()
+        };
+        let tx = Transaction {
+            version: 2,
+            lock_time: LockTime::ZERO,
+            input: Vec::new(),
+            output: vec![
+                TxOut {
+                    value: 0,
+                    script_pubkey: runestone.encipher(),
+                },
+                // rune output, rune change, btc output, btc change
+                TxOut {
+                    value: 600,
+                    script_pubkey: script(1),
+                },
+                TxOut {
+                    value: 600,
+                    script_pubkey: script(0),
+                },
+                TxOut {
+                    value: 50_000,
+                    script_pubkey: script(3),
+                },
+                TxOut {
+                    value: 10_000,
+                    script_pubkey: script(4),
+                },
+            ],
+        };
+
+        let targets = |tx: &Transaction| {
+            let Some(Artifact::Runestone(r)) = Runestone::decipher(tx) else {
+                panic!("runestone is lost");
+            };
+            assert!(tx.output[0].script_pubkey.is_op_return());
+            (
+                tx.output[r.edicts[0].output as usize].script_pubkey.clone(),
+                tx.output[r.pointer.unwrap() as usize].script_pubkey.clone(),
+            )
+        };
+        let mut rng = StdRng::seed_from_u64(7);
+
+        let mut fixed = tx.clone();
+        reorder_outputs(&mut fixed, OutputOrder::Fixed, &[2, 4], &mut rng).unwrap();
+        assert_eq!(fixed, tx);
+
+        let mut bip69 = tx.clone();
+        let position =
+            reorder_outputs(&mut bip69, OutputOrder::Bip69Outputs, &[2, 4], &mut rng).unwrap();
+        assert_eq!(position, vec![0, 2, 1, 4, 3]);
+        let values: Vec<u64> = bip69.output.iter().map(|o| o.value).collect();
+        assert_eq!(values, vec![0, 600, 600, 10_000, 50_000]);
+        // the rune change sorts before the rune output by script
+        assert_eq!(bip69.output[1].script_pubkey, script(0));
+        assert_eq!(targets(&bip69), (script(1), script(0)));
+
+        for _ in 0..20 {
+            let mut random = tx.clone();
+            reorder_outputs(&mut random, OutputOrder::RandomChange, &[2, 4], &mut rng).unwrap();
+            assert_eq!(random.output.len(), tx.output.len());
+            assert_eq!(targets(&random), (script(1), script(0)));
+        }
+    }
+
+    #[test]
+    fn oversized_runestone_is_rejected() {
+        use super::{check_runestone_size, DEFAULT_MAX_RUNESTONE_SIZE};
+        use ordinals::{Edict, RuneId, Runestone};
+
+        let runestone = |edicts: u32| Runestone {
+            edicts: (0..edicts)
+                .map(|i| Edict {
+                    id: RuneId {
+                        block: 840_000 + i as u64,
+                        tx: 1_000 + i,
+                    },
+                    amount: 21_000_000_000_000,
+                    output: i + 1,
+                })
+                .collect(),
+            pointer: Some(1),
+            ..Default::default()
+        };
+
+        let single = runestone(1).encipher();
+        assert!(check_runestone_size(&single, DEFAULT_MAX_RUNESTONE_SIZE).is_ok());
+
+        let many = runestone(10).encipher();
+        let err = check_runestone_size(&many, DEFAULT_MAX_RUNESTONE_SIZE).unwrap_err();
+        assert_eq!(err.size, many.len());
+        assert!(err.size > DEFAULT_MAX_RUNESTONE_SIZE);
+        assert!(err.to_string().contains("split the transfer"));
+
+        // a node with a larger -datacarriersize relays it
+        assert!(check_runestone_size(&many, many.len()).is_ok());
+    }
+
+    #[test]
+    fn immature_coinbase_is_not_spendable() {
+        use super::{is_immature_coinbase, COINBASE_MATURITY};
+        use crate::db;
+
+        let utxo = db::BtcUtxo {
+            block: 1_000,
+            tx_id: 0,
+            amount: 5_000_000_000,
+            coinbase: true,
+            ..Default::default()
+        };
+        assert!(is_immature_coinbase(&utxo, 1_000));
+        assert!(is_immature_coinbase(&utxo, 1_000 + COINBASE_MATURITY - 2));
+        // spendable in the next block
+        assert!(!is_immature_coinbase(&utxo, 1_000 + COINBASE_MATURITY - 1));
+
+        let regular = db::BtcUtxo {
+            coinbase: false,
+            ..utxo
+        };
+        assert!(!is_immature_coinbase(&regular, 1_000));
+    }
+
+    #[test]
+    fn tip_lock_time_uses_the_node_height() {
+        use super::LockTimePolicy;
+        use bitcoin::absolute::LockTime;
+        use bitcoincore_rpc::RpcApi;
+        use std::cell::Cell;
+
+        struct Node {
+            calls: Cell<usize>,
+        }
+
+        impl RpcApi for Node {
+            fn call<T: for<'a> serde::de::Deserialize<'a>>(
+                &self,
+                cmd: &str,
+                _args: &[serde_json::Value],
+            ) -> bitcoincore_rpc::Result<T> {
+                assert_eq!(cmd, "getblockcount");
+                self.calls.set(self.calls.get() + 1);
+                Ok(serde_json::from_value(serde_json::json!(840_123))?)
+            }
+        }
+
+        let node = Node {
+            calls: Cell::new(0),
+        };
+        assert_eq!(
+            LockTimePolicy::Tip.resolve_from_node(&node).unwrap(),
+            LockTime::from_height(840_123).unwrap()
+        );
+        assert_eq!(
+            LockTimePolicy::Fixed(5).resolve_from_node(&node).unwrap(),
+            LockTime::from_consensus(5)
+        );
+        assert_eq!(
+            LockTimePolicy::Zero.resolve_from_node(&node).unwrap(),
+            LockTime::ZERO
+        );
+        // only `Tip` asks the node
+        assert_eq!(node.calls.get(), 1);
+    }
+
+    /// needs a scratch db in `TEST_DB_DSN` and a redis in `TEST_REDIS_ADDR`, skipped otherwise
+    #[tokio::test]
+    async fn selection_skips_utxo_spent_by_pending_tx() {
+        use super::{PoolTxBuilder, SigningAllowlist};
+        use crate::btc_utxo::UtxoClient;
+        use crate::cache::CacheRepo;
+        use crate::config::{BTCConfig, BtcUtxoProvider, RedisConfig};
+        use crate::db::{self, connect_scratch_db, RuneUtxo};
+        use bitcoin::consensus::encode::serialize_hex;
+        use bitcoin::{absolute::LockTime, Network, ScriptBuf, Sequence, Transaction, TxIn, TxOut};
+        use bitcoin::{OutPoint, Txid, Witness};
+        use std::{str::FromStr, sync::Arc};
+        use tokio::sync::RwLock;
+
+        let Ok(address) = std::env::var("TEST_REDIS_ADDR") else {
+            return;
+        };
+        let Some(repo) = connect_scratch_db().await.map(Arc::new) else {
+            return;
+        };
+        let (rune, owner) = ("PENDINGSPENDTEST", "bc1ppendingspend");
+        let utxo_hash = "4a5e1e4baab89f3a32518a88c31bc87f618f76673e2cc77ab2127b7afdeda33b";
+        sqlx::query("DELETE FROM runes_utxos WHERE rune = $1")
+            .bind(rune)
+            .execute(&repo.pool)
+            .await
+            .unwrap();
+        for output_n in [0, 1] {
+            repo.insert_rune_utxo(&RuneUtxo {
+                block: 840_000,
+                tx_id: 1,
+                tx_hash: utxo_hash.to_owned(),
+                output_n,
+                rune: rune.to_owned(),
+                address: owner.to_owned(),
+                amount: "100".to_owned(),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        }
+
+        // our broadcast tx spends output 0, it isn't mined yet
+        let spent = OutPoint {
+            txid: Txid::from_str(utxo_hash).unwrap(),
+            vout: 0,
+        };
+        let tx = Transaction {
+            version: 2,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: spent,
+                script_sig: ScriptBuf::new(),
+                sequence: Sequence::MAX,
+                witness: Witness::new(),
+            }],
+            output: vec![TxOut {
+                value: 1_000,
+                script_pubkey: ScriptBuf::new(),
+            }],
+        };
+        let tx_hash = tx.txid().to_string();
+        sqlx::query("DELETE FROM submitted_txs WHERE tx_hash = $1")
+            .bind(&tx_hash)
+            .execute(&repo.pool)
+            .await
+            .unwrap();
+        repo.insert_submitted_tx(db::Transaction {
+            tx_hash,
+            raw_data: serialize_hex(&tx),
+            status: db::Transaction::STATUS_PENDING.to_owned(),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+
+        let btc_cfg: BTCConfig = toml::from_str(&format!(
+            r#"
+            address = "http://127.0.0.1:1"
+            rpc_user = "user"
+            rpc_password = "password"
+            utxo_provider = {{ mode = "{}", api_key = "key" }}
+            "#,
+            BtcUtxoProvider::CRYPTOAPIS
+        ))
+        .unwrap();
+        let cache = CacheRepo::new(RedisConfig { address }).await.unwrap();
+        let builder = PoolTxBuilder::new(
+            repo.clone(),
+            Arc::new(RwLock::new(cache)),
+            UtxoClient::new(&btc_cfg, repo.clone()).unwrap(),
+            SigningAllowlist::new(Network::Bitcoin, &[], &[owner.to_owned()]).unwrap(),
+        );
+
+        let locked = builder.pending_spends().await.unwrap();
+        assert!(locked.contains(&spent));
+        let selected = builder
+            .collect_runes_utxo(rune, owner, 100, &locked)
+            .await
+            .unwrap();
+        assert_eq!(selected.len(), 1);
+        assert_eq!(selected[0].output_n, 1);
+        // the spent output doesn't count towards the balance
+        assert!(builder
+            .collect_runes_utxo(rune, owner, 200, &locked)
+            .await
+            .is_err());
+
+        let builder = builder.with_exclude_pending_spends(false);
+        let locked = builder.pending_spends().await.unwrap();
+        let selected = builder
+            .collect_runes_utxo(rune, owner, 200, &locked)
+            .await
+            .unwrap();
+        assert_eq!(selected.len(), 2);
+    }
+
+    /// needs a scratch db in `TEST_DB_DSN` and a redis in `TEST_REDIS_ADDR`, skipped otherwise
+    #[tokio::test]
+    async fn btc_change_is_paid_to_the_change_address() {
+        use super::{InputOpts, PoolTxBuilder, SigningAllowlist, TxBuilderCtx};
+        use crate::btc_utxo::UtxoClient;
+        use crate::cache::CacheRepo;
+        use crate::config::RedisConfig;
+        use crate::db::{self, connect_scratch_db};
+        use bitcoin::{Address, Network};
+        use std::{str::FromStr, sync::Arc};
+        use tokio::sync::RwLock;
+
+        let Ok(redis_address) = std::env::var("TEST_REDIS_ADDR") else {
+            return;
+        };
+        let Some(repo) = connect_scratch_db().await.map(Arc::new) else {
+            return;
+        };
+        let address = |a: &str| {
+            Address::from_str(a)
+                .unwrap()
+                .require_network(Network::Bitcoin)
+                .unwrap()
+        };
+        let input = address("bc1qar0srrr7xfkvy5l643lydnw9re59gtzzwf5mdq");
+        let change = address("bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4");
+        sqlx::query("DELETE FROM btc_utxos WHERE address = $1")
+            .bind(input.to_string())
+            .execute(&repo.pool)
+            .await
+            .unwrap();
+        repo.insert_btc_utxo(&db::BtcUtxo {
+            block: 840_000,
+            tx_hash: "e3bf3d07d4b0375638d5f1db5255fe07ba2c4cb067cd81b84ee974b6585fb468".to_owned(),
+            output_n: 0,
+            address: input.to_string(),
+            pk_script: input.script_pubkey().to_hex_string(),
+            amount: 10_000,
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+
+        let cache = CacheRepo::new(RedisConfig {
+            address: redis_address,
+        })
+        .await
+        .unwrap();
+        let builder = PoolTxBuilder::new(
+            repo.clone(),
+            Arc::new(RwLock::new(cache)),
+            UtxoClient::Local(repo.clone()),
+            SigningAllowlist::new(Network::Bitcoin, &[], &[input.to_string()]).unwrap(),
+        );
+        let opts = InputOpts {
+            address: input.clone(),
+            original_public_key: None,
+            can_be_signed: false,
+            rune_name: None,
+            change_address: Some(change.clone()),
+            sighash_type: bitcoin::sighash::TapSighashType::All,
+        };
+
+        let mut ctx = TxBuilderCtx::new(true);
+        builder
+            .add_btc_to_tx(Network::Bitcoin, &mut ctx, opts, None, 4_000)
+            .await
+            .unwrap();
+        assert_eq!(ctx.tx.input.len(), 1);
+        assert_eq!(ctx.tx.output.len(), 1);
+        assert_eq!(ctx.tx.output[0].script_pubkey, change.script_pubkey());
+        assert_eq!(ctx.tx.output[0].value, 6_000);
     }
 }
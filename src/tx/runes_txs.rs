@@ -2,20 +2,41 @@ use std::collections::HashMap;
 
 use bitcoin::{
     absolute::LockTime,
+    hashes::Hash,
     opcodes, script,
     script::Builder,
-    secp256k1::{KeyPair, Message, Secp256k1, XOnlyPublicKey},
-    sighash::{Prevouts, SighashCache, TapSighashType},
-    taproot::{ControlBlock, LeafVersion, Signature, TapLeafHash, TaprootBuilder},
-    Address, Network, OutPoint, ScriptBuf, Sequence, Transaction, TxIn, TxOut, Txid, Witness,
+    secp256k1::{KeyPair, Secp256k1, XOnlyPublicKey},
+    sighash::{Prevouts, TapSighashType},
+    taproot::{ControlBlock, LeafVersion, TaprootBuilder},
+    Address, Network, OutPoint, Script, ScriptBuf, Sequence, Transaction, TxIn, TxOut, Txid,
+    Witness,
 };
-use ordinals::{Etching, Runestone};
+use ordinals::{Etching, Rune, Runestone};
 
-use super::utxo::Utxo;
+use super::{
+    fee::FeeEstimate,
+    pool_txs::{reorder_outputs, OutputOrder},
+    utxo::Utxo,
+};
 
 const PROTOCOL_ID: [u8; 3] = *b"ord";
 pub const COMMITMENT_OUT_VALUE: u64 = 100_000;
 pub const RUNES_OUT_VALUE: u64 = 600;
+pub const DEFAULT_TX_VERSION: i32 = 2;
+
+/// validates the value of a rune output paying to `script`,
+/// it can't go below the dust limit of the script type
+pub fn rune_output_value(value: u64, script: &Script) -> anyhow::Result<u64> {
+    let dust = script.dust_value().to_sat();
+    if value < dust {
+        anyhow::bail!(
+            "rune output value {} is below the dust limit {} of the output script",
+            value,
+            dust
+        )
+    }
+    Ok(value)
+}
 
 #[derive(Clone)]
 pub struct CommitmentOut {
@@ -32,6 +53,12 @@ pub struct RunesTxBuilder {
     commitment_pubkey: XOnlyPublicKey,
     change_address: Address,
     fee_rate: f64,
+    fee_safety_factor: f64,
+    rune_output_value: u64,
+    tx_version: i32,
+    lock_time: LockTime,
+    max_runestone_size: usize,
+    output_order: OutputOrder,
 }
 
 impl RunesTxBuilder {
@@ -48,18 +75,95 @@ impl RunesTxBuilder {
             commitment_pubkey,
             change_address,
             fee_rate,
+            fee_safety_factor: super::fee::DEFAULT_SAFETY_FACTOR,
+            rune_output_value: RUNES_OUT_VALUE,
+            tx_version: DEFAULT_TX_VERSION,
+            lock_time: LockTime::ZERO,
+            max_runestone_size: super::pool_txs::DEFAULT_MAX_RUNESTONE_SIZE,
+            output_order: OutputOrder::Fixed,
         }
     }
 
+    pub fn with_fee_safety_factor(mut self, factor: f64) -> Self {
+        self.fee_safety_factor = factor;
+        self
+    }
+
+    pub fn with_rune_output_value(mut self, value: u64) -> Self {
+        self.rune_output_value = value;
+        self
+    }
+
+    pub fn with_tx_version(mut self, version: i32) -> Self {
+        self.tx_version = version;
+        self
+    }
+
+    pub fn with_lock_time(mut self, lock_time: LockTime) -> Self {
+        self.lock_time = lock_time;
+        self
+    }
+
+    pub fn with_max_runestone_size(mut self, size: usize) -> Self {
+        self.max_runestone_size = size;
+        self
+    }
+
+    /// applies to the commitment tx, the etching tx has a single output after the runestone
+    pub fn with_output_order(mut self, order: OutputOrder) -> Self {
+        self.output_order = order;
+        self
+    }
+
+    /// validates the value of the commitment output of `etching`, it pays to taproot
+    /// and funds the etching tx, see `min_commitment_value`
+    pub fn commitment_output_value(&self, etching: &Etching, value: u64) -> anyhow::Result<u64> {
+        let min = self.min_commitment_value(etching)?;
+        if value < min {
+            anyhow::bail!(
+                "commitment value {} is below {}, the dust limit of the commitment output \
+                 or the rune output plus the etching fee at {} sat/vB",
+                value,
+                min,
+                self.fee_rate
+            )
+        }
+        Ok(value)
+    }
+
+    /// The etching tx spends only the commitment output, so it has to cover the rune
+    /// output and the etching fee at the builder's fee rate, and the taproot dust limit.
+    pub fn min_commitment_value(&self, etching: &Etching) -> anyhow::Result<u64> {
+        let commitment = self.craft_commitment_out(etching, 0, 0);
+        let dust = commitment.out.script_pubkey.dust_value().to_sat();
+
+        let mut etching_tx = self.create_etching_tx(
+            etching,
+            commitment.clone(),
+            Txid::all_zeros(),
+            self.change_address.clone(),
+        )?;
+        // the witness `sign_etching_tx` adds, a signature with its sighash byte
+        etching_tx.input[0].witness = Witness::from_slice(&[
+            vec![0; 65],
+            commitment.reveal_script.to_bytes(),
+            commitment.control_block.serialize(),
+        ]);
+        let fee = FeeEstimate::new(self.fee_rate, etching_tx.vsize(), self.fee_safety_factor).fee;
+        let outputs: u64 = etching_tx.output.iter().map(|o| o.value).sum();
+
+        Ok(dust.max(outputs + fee))
+    }
+
     pub fn create_commitment_tx(
         &self,
         etching_outputs: Vec<Etching>,
         utxo: Vec<Utxo>,
         commitment_value: u64,
-    ) -> (Transaction, HashMap<String, CommitmentOut>, Vec<TxOut>) {
+    ) -> anyhow::Result<(Transaction, HashMap<String, CommitmentOut>, Vec<TxOut>)> {
         let mut tx = Transaction {
-            version: 2,
-            lock_time: LockTime::ZERO,
+            version: self.tx_version,
+            lock_time: self.lock_time,
             input: vec![],
             output: vec![],
         };
@@ -106,13 +210,23 @@ impl RunesTxBuilder {
             script_pubkey: self.change_address.script_pubkey(),
         });
 
-        const SIG_GROW_K: f64 = 1.85;
-        let fee = ((self.fee_rate * tx.vsize() as f64) * SIG_GROW_K) as u64;
+        let fee = FeeEstimate::new(self.fee_rate, tx.vsize(), self.fee_safety_factor).fee;
 
         let change_amount = in_value - out_amount - fee;
         tx.output.last_mut().unwrap().value = change_amount;
 
-        (tx, commitment_outs, used_utxos)
+        let change = tx.output.len() - 1;
+        let position = reorder_outputs(
+            &mut tx,
+            self.output_order,
+            &[change],
+            &mut rand::thread_rng(),
+        )?;
+        for out in commitment_outs.values_mut() {
+            out.vout = position[out.vout] as usize;
+        }
+
+        Ok((tx, commitment_outs, used_utxos))
     }
 
     pub fn create_etching_tx(
@@ -121,10 +235,11 @@ impl RunesTxBuilder {
         commitment_utxo: CommitmentOut,
         txid: Txid,
         dest_address: Address,
-    ) -> Transaction {
+    ) -> anyhow::Result<Transaction> {
         let mut etching_tx = Transaction {
-            version: 2,
-            lock_time: LockTime::ZERO,
+            // the relative lock of the commitment input needs version 2 (BIP68)
+            version: self.tx_version.max(2),
+            lock_time: self.lock_time,
             input: vec![TxIn {
                 previous_output: OutPoint {
                     txid,
@@ -147,6 +262,8 @@ impl RunesTxBuilder {
             pointer: Some(dest_id),
         };
         let rune_script = runestone.encipher();
+        // the commitment is spent by this tx only, a non standard one would strand it
+        super::pool_txs::check_runestone_size(&rune_script, self.max_runestone_size)?;
 
         etching_tx.output.push(TxOut {
             script_pubkey: rune_script,
@@ -154,12 +271,13 @@ impl RunesTxBuilder {
         });
 
         // output with premined runes
+        let script_pubkey = dest_address.script_pubkey();
         etching_tx.output.push(TxOut {
-            script_pubkey: dest_address.script_pubkey(),
-            value: RUNES_OUT_VALUE,
+            value: rune_output_value(self.rune_output_value, &script_pubkey)?,
+            script_pubkey,
         });
 
-        etching_tx
+        Ok(etching_tx)
     }
 
     pub fn sign_etching_tx(
@@ -171,66 +289,27 @@ impl RunesTxBuilder {
     ) -> Transaction {
         let mut etching_tx = otx.clone();
 
-        let mut sighash_cache = SighashCache::new(&mut etching_tx);
-        let sighash = sighash_cache
-            .taproot_script_spend_signature_hash(
-                commitment_input,
-                &Prevouts::All(&[commitment_utxo.out]),
-                TapLeafHash::from_script(&commitment_utxo.reveal_script, LeafVersion::TapScript),
-                TapSighashType::All,
-            )
-            .expect("signature hash should compute");
-
-        let secp256k1 = Secp256k1::new();
-        let sig = secp256k1.sign_schnorr(
-            &Message::from_slice(sighash.as_ref())
-                .expect("should be cryptographically secure hash"),
+        let witness = super::signer::script_path_witness(
             key_pair,
-        );
-
-        let mut witness = Witness::new();
-        witness.push(
-            Signature {
-                sig,
-                hash_ty: TapSighashType::All,
-            }
-            .to_vec(),
-        );
-
-        witness.push(commitment_utxo.reveal_script);
-        witness.push(&commitment_utxo.control_block.serialize());
-
-        //*sighash_cache.witness_mut(commitment_input).unwrap() = witness;
-        //sighash_cache.into_transaction().to_owned()
-
+            otx,
+            commitment_input,
+            &Prevouts::All(&[commitment_utxo.out]),
+            &commitment_utxo.reveal_script,
+            &commitment_utxo.control_block,
+            TapSighashType::All,
+        )
+        .expect("signature hash should compute");
         etching_tx.input[commitment_input].witness = witness;
 
-        etching_tx.clone()
+        etching_tx
     }
 
     fn craft_commitment_out(&self, etching: &Etching, index: usize, value: u64) -> CommitmentOut {
-        let secp256k1 = Secp256k1::new();
-
-        let mut builder = ScriptBuf::builder()
-            .push_slice(self.commitment_pubkey.serialize())
-            .push_opcode(opcodes::all::OP_CHECKSIG);
-        builder = append_reveal_script_to_builder(builder, *etching);
-        let reveal_script = builder.into_script();
+        let (commit_tx_address, reveal_script, control_block) =
+            commitment_address(self.net, self.commitment_pubkey, etching.rune.unwrap());
 
         println!("COMMINTMENT REVEAL SCRIPT -> {}", reveal_script);
 
-        let taproot_spend_info = TaprootBuilder::new()
-            .add_leaf(0, reveal_script.clone())
-            .expect("adding leaf should work")
-            .finalize(&secp256k1, self.commitment_pubkey)
-            .expect("finalizing taproot builder should work");
-
-        let control_block = taproot_spend_info
-            .control_block(&(reveal_script.clone(), LeafVersion::TapScript))
-            .expect("should compute control block");
-
-        let commit_tx_address = Address::p2tr_tweaked(taproot_spend_info.output_key(), self.net);
-
         CommitmentOut {
             vout: index,
             control_block,
@@ -245,8 +324,37 @@ impl RunesTxBuilder {
     }
 }
 
-fn append_reveal_script_to_builder(mut builder: script::Builder, rune: Etching) -> script::Builder {
-    let value = rune.rune.unwrap().commitment();
+/// taproot address the commitment output of `rune` pays to, with the reveal script
+/// and the control block needed to spend it in the etching tx
+pub fn commitment_address(
+    net: Network,
+    commitment_pubkey: XOnlyPublicKey,
+    rune: Rune,
+) -> (Address, ScriptBuf, ControlBlock) {
+    let secp256k1 = Secp256k1::new();
+
+    let mut builder = ScriptBuf::builder()
+        .push_slice(commitment_pubkey.serialize())
+        .push_opcode(opcodes::all::OP_CHECKSIG);
+    builder = append_reveal_script_to_builder(builder, rune);
+    let reveal_script = builder.into_script();
+
+    let taproot_spend_info = TaprootBuilder::new()
+        .add_leaf(0, reveal_script.clone())
+        .expect("adding leaf should work")
+        .finalize(&secp256k1, commitment_pubkey)
+        .expect("finalizing taproot builder should work");
+
+    let control_block = taproot_spend_info
+        .control_block(&(reveal_script.clone(), LeafVersion::TapScript))
+        .expect("should compute control block");
+
+    let address = Address::p2tr_tweaked(taproot_spend_info.output_key(), net);
+    (address, reveal_script, control_block)
+}
+
+fn append_reveal_script_to_builder(mut builder: script::Builder, rune: Rune) -> script::Builder {
+    let value = rune.commitment();
     let tag: [u8; 1] = [13_u8];
 
     builder = builder
@@ -260,3 +368,250 @@ fn append_reveal_script_to_builder(mut builder: script::Builder, rune: Etching)
 
     builder
 }
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn rune_output_value_respects_script_dust() {
+        use super::{rune_output_value, RUNES_OUT_VALUE};
+        use bitcoin::ScriptBuf;
+
+        let p2tr = ScriptBuf::from_hex(
+            "5120a60869f0dbcf1dc659c9cecbaf8050135ea9e8cdc487053f1dc6880949dc684c",
+        )
+        .unwrap();
+        let p2wpkh = ScriptBuf::from_hex("0014751e76e8199196d454941c45d1b3a323f1433bd6").unwrap();
+        let p2pkh =
+            ScriptBuf::from_hex("76a914751e76e8199196d454941c45d1b3a323f1433bd688ac").unwrap();
+
+        for script in [&p2tr, &p2wpkh, &p2pkh] {
+            assert_eq!(
+                rune_output_value(RUNES_OUT_VALUE, script).unwrap(),
+                RUNES_OUT_VALUE
+            );
+        }
+
+        // 330 is the taproot dust limit, p2wpkh allows 294, p2pkh needs 546
+        assert!(rune_output_value(330, &p2tr).is_ok());
+        assert!(rune_output_value(329, &p2tr).is_err());
+        assert!(rune_output_value(294, &p2wpkh).is_ok());
+        assert!(rune_output_value(293, &p2wpkh).is_err());
+        assert!(rune_output_value(400, &p2pkh).is_err());
+    }
+
+    #[test]
+    fn commitment_output_uses_configured_value() {
+        use super::{RunesTxBuilder, COMMITMENT_OUT_VALUE, RUNES_OUT_VALUE};
+        use crate::tx::{pool_txs::OutputOrder, utxo::Utxo};
+        use bitcoin::{
+            secp256k1::{KeyPair, Secp256k1},
+            Address, Network, Txid,
+        };
+        use ordinals::{Etching, Rune};
+        use std::str::FromStr;
+
+        let secp256k1 = Secp256k1::new();
+        let kp = KeyPair::from_seckey_slice(&secp256k1, &[7u8; 32]).unwrap();
+        let (pubkey, _) = kp.x_only_public_key();
+        let change = Address::p2tr(&secp256k1, pubkey, None, Network::Regtest);
+        let builder = RunesTxBuilder::new(Network::Regtest, pubkey, change.clone(), 1.0);
+        let etching = Etching {
+            rune: Some(Rune::from_str("AAAAAAAAAAAAAAB").unwrap()),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            builder
+                .commitment_output_value(&etching, COMMITMENT_OUT_VALUE)
+                .unwrap(),
+            COMMITMENT_OUT_VALUE
+        );
+        // the rune output and the fee of the etching tx, above the 330 dust limit
+        let min = builder.min_commitment_value(&etching).unwrap();
+        assert!(min > RUNES_OUT_VALUE + 100);
+        assert!(builder.commitment_output_value(&etching, min).is_ok());
+        assert!(builder.commitment_output_value(&etching, min - 1).is_err());
+        assert!(builder.commitment_output_value(&etching, 330).is_err());
+
+        // the etching fee grows with the fee rate
+        let fee_at_1 = min - RUNES_OUT_VALUE;
+        let fast = RunesTxBuilder::new(Network::Regtest, pubkey, change.clone(), 10.0);
+        let fee_at_10 = fast.min_commitment_value(&etching).unwrap() - RUNES_OUT_VALUE;
+        assert!(fee_at_10 >= fee_at_1 * 9);
+        let utxo = Utxo {
+            txid: Txid::from_str(
+                "4a5e1e4baab89f3a32518a88c31bc87f618f76673e2cc77ab2127b7afdeda33b",
+            )
+            .unwrap(),
+            vout: 0,
+            value: 50_000,
+            script_pubkey: change.script_pubkey(),
+        };
+
+        let (tx, outs, _) = builder
+            .create_commitment_tx(vec![etching], vec![utxo.clone()], 10_000)
+            .unwrap();
+        let commitment = outs.get("AAAAAAAAAAAAAAB").unwrap();
+        assert_eq!(tx.output[0].value, 10_000);
+        assert_eq!(
+            tx.output[0].script_pubkey,
+            commitment.commit_tx_address.script_pubkey()
+        );
+        // the rest goes back as change
+        assert!(tx.output[1].value < 40_000);
+
+        // the address is deterministic for the rune and the key
+        let (address, _, _) = super::commitment_address(
+            Network::Regtest,
+            pubkey,
+            Rune::from_str("AAAAAAAAAAAAAAB").unwrap(),
+        );
+        assert_eq!(address, commitment.commit_tx_address);
+
+        // the change is smaller than the commitment, so it goes first
+        let builder = builder.with_output_order(OutputOrder::Bip69Outputs);
+        let (tx, outs, _) = builder
+            .create_commitment_tx(vec![etching], vec![utxo], 30_000)
+            .unwrap();
+        let commitment = outs.get("AAAAAAAAAAAAAAB").unwrap();
+        assert_eq!(commitment.vout, 1);
+        assert_eq!(tx.output[1].value, 30_000);
+        assert_eq!(
+            tx.output[1].script_pubkey,
+            commitment.commit_tx_address.script_pubkey()
+        );
+        assert!(tx.output[0].value < 20_000);
+    }
+
+    #[test]
+    fn lock_time_is_applied_to_built_txs() {
+        use super::RunesTxBuilder;
+        use crate::tx::{pool_txs::LockTimePolicy, utxo::Utxo};
+        use bitcoin::{
+            absolute::LockTime,
+            secp256k1::{KeyPair, Secp256k1},
+            Address, Network, Sequence, Txid,
+        };
+        use ordinals::{Etching, Rune, Runestone};
+        use std::str::FromStr;
+
+        assert_eq!(
+            LockTimePolicy::from_str("zero").unwrap(),
+            LockTimePolicy::Zero
+        );
+        assert_eq!(
+            LockTimePolicy::from_str("tip").unwrap(),
+            LockTimePolicy::Tip
+        );
+        assert_eq!(
+            LockTimePolicy::from_str("840000").unwrap(),
+            LockTimePolicy::Fixed(840_000)
+        );
+        assert!(LockTimePolicy::from_str("-1").is_err());
+        assert_eq!(
+            LockTimePolicy::Zero.resolve(840_000).unwrap(),
+            LockTime::ZERO
+        );
+        assert_eq!(
+            LockTimePolicy::Tip.resolve(840_000).unwrap(),
+            LockTime::from_height(840_000).unwrap()
+        );
+        assert!(LockTimePolicy::Tip.resolve(500_000_000).is_err());
+
+        let secp256k1 = Secp256k1::new();
+        let kp = KeyPair::from_seckey_slice(&secp256k1, &[7u8; 32]).unwrap();
+        let (pubkey, _) = kp.x_only_public_key();
+        let change = Address::p2tr(&secp256k1, pubkey, None, Network::Regtest);
+        let lock_time = LockTimePolicy::Tip.resolve(840_000).unwrap();
+        let builder = RunesTxBuilder::new(Network::Regtest, pubkey, change.clone(), 1.0)
+            .with_tx_version(1)
+            .with_lock_time(lock_time);
+
+        let etching = Etching {
+            rune: Some(Rune::from_str("AAAAAAAAAAAAAAB").unwrap()),
+            ..Default::default()
+        };
+        let utxo = Utxo {
+            txid: Txid::from_str(
+                "4a5e1e4baab89f3a32518a88c31bc87f618f76673e2cc77ab2127b7afdeda33b",
+            )
+            .unwrap(),
+            vout: 0,
+            value: 50_000,
+            script_pubkey: change.script_pubkey(),
+        };
+        let (commit_tx, outs, _) = builder
+            .create_commitment_tx(vec![etching], vec![utxo], 10_000)
+            .unwrap();
+        assert_eq!(commit_tx.version, 1);
+        assert_eq!(commit_tx.lock_time, lock_time);
+        // a final sequence would disable the lock time
+        assert!(commit_tx.input.iter().all(|i| i.sequence != Sequence::MAX));
+
+        let commitment = outs.get("AAAAAAAAAAAAAAB").unwrap().clone();
+        let reveal = builder
+            .create_etching_tx(&etching, commitment, commit_tx.txid(), change)
+            .unwrap();
+        assert_eq!(reveal.version, 2);
+        assert_eq!(reveal.lock_time, lock_time);
+        assert_eq!(
+            reveal.input[0].sequence,
+            Sequence::from_height(Runestone::COMMIT_CONFIRMATIONS - 1)
+        );
+    }
+
+    #[test]
+    fn oversized_etching_runestone_is_rejected() {
+        use super::RunesTxBuilder;
+        use crate::tx::{pool_txs::RunestoneTooLarge, utxo::Utxo};
+        use bitcoin::{
+            secp256k1::{KeyPair, Secp256k1},
+            Address, Network, Txid,
+        };
+        use ordinals::{Etching, Rune, Terms};
+        use std::str::FromStr;
+
+        let secp256k1 = Secp256k1::new();
+        let kp = KeyPair::from_seckey_slice(&secp256k1, &[7u8; 32]).unwrap();
+        let (pubkey, _) = kp.x_only_public_key();
+        let change = Address::p2tr(&secp256k1, pubkey, None, Network::Regtest);
+        let etching = Etching {
+            rune: Some(Rune::from_str("AAAAAAAAAAAAAAB").unwrap()),
+            premine: Some(1_000_000),
+            terms: Some(Terms {
+                amount: Some(1_000),
+                cap: Some(1_000),
+                height: (Some(840_000), Some(1_050_000)),
+                offset: (Some(1), Some(210_000)),
+            }),
+            ..Default::default()
+        };
+        let txid =
+            Txid::from_str("4a5e1e4baab89f3a32518a88c31bc87f618f76673e2cc77ab2127b7afdeda33b")
+                .unwrap();
+
+        let utxo = Utxo {
+            txid,
+            vout: 0,
+            value: 50_000,
+            script_pubkey: change.script_pubkey(),
+        };
+
+        let builder = RunesTxBuilder::new(Network::Regtest, pubkey, change.clone(), 1.0);
+        let (_, outs, _) = builder
+            .create_commitment_tx(vec![etching], vec![utxo], 10_000)
+            .unwrap();
+        let commitment = outs.get("AAAAAAAAAAAAAAB").unwrap().clone();
+        let reveal = builder
+            .create_etching_tx(&etching, commitment.clone(), txid, change.clone())
+            .unwrap();
+        let size = reveal.output[0].script_pubkey.len();
+
+        let builder = builder.with_max_runestone_size(size - 1);
+        let err = builder
+            .create_etching_tx(&etching, commitment, txid, change)
+            .unwrap_err();
+        let too_large = err.downcast_ref::<RunestoneTooLarge>().unwrap();
+        assert_eq!((too_large.size, too_large.max), (size, size - 1));
+    }
+}
@@ -6,7 +6,8 @@ use bitcoin::{
     script::{Builder, PushBytes},
     secp256k1::{All, Message, Secp256k1, SecretKey, XOnlyPublicKey},
     sighash::{EcdsaSighashType, Prevouts, SighashCache, TapSighashType},
-    taproot, Address, Network, PrivateKey, Transaction, TxOut, Witness,
+    taproot::{self, ControlBlock, LeafVersion, TapLeafHash, TaprootSpendInfo},
+    Address, Network, PrivateKey, Script, Transaction, TxOut, Witness,
 };
 
 #[derive(Clone)]
@@ -36,6 +37,8 @@ pub struct PKSigner {
     pub net: Network,
     pub kp: KeyPair,
     pub address: Address,
+    /// tap tree with leaves spendable by `kp` via the script path
+    tap_tree: Option<TaprootSpendInfo>,
 }
 
 impl miniscript::bitcoin::psbt::GetKey for PKSigner {
@@ -66,9 +69,35 @@ impl miniscript::bitcoin::psbt::GetKey for PKSigner {
 
 impl PKSigner {
     pub fn new_from_secret(net: Network, secret: &str, mode: AddressMode) -> anyhow::Result<Self> {
-        let secp = Secp256k1::new();
         let data = hex::decode(secret)?;
         let recovered_secret = SecretKey::from_slice(&data)?;
+        Self::new_from_secret_key(net, recovered_secret, mode)
+    }
+
+    /// Legacy mode follows the compression of the WIF key, witness and taproot need a compressed one.
+    /// Testnet WIF keys are accepted for regtest too, they share the prefix.
+    pub fn from_wif(net: Network, wif: &str, mode: AddressMode) -> anyhow::Result<Self> {
+        let pk = PrivateKey::from_wif(wif)?;
+        if !wif_network_matches(pk.network, net) {
+            anyhow::bail!("WIF key is for {}, expected {}", pk.network, net)
+        }
+
+        let mode = match mode {
+            AddressMode::Legacy(_) => AddressMode::Legacy(pk.compressed),
+            _ if !pk.compressed => {
+                anyhow::bail!("witness and taproot addresses need a compressed WIF key")
+            }
+            mode => mode,
+        };
+        Self::new_from_secret_key(net, pk.inner, mode)
+    }
+
+    fn new_from_secret_key(
+        net: Network,
+        recovered_secret: SecretKey,
+        mode: AddressMode,
+    ) -> anyhow::Result<Self> {
+        let secp = Secp256k1::new();
         let kp = KeyPair::from_secret_key(&secp, &recovered_secret);
         let pk: PrivateKey;
 
@@ -101,9 +130,72 @@ impl PKSigner {
             private_key: pk,
             kp,
             address,
+            tap_tree: None,
         })
     }
 
+    pub fn address_mode(&self) -> &AddressMode {
+        &self.address_mode
+    }
+
+    /// the key in WIF, compressed unless the signer is in uncompressed legacy mode
+    pub fn to_wif(&self) -> String {
+        self.private_key.to_wif()
+    }
+
+    pub fn secret_hex(&self) -> String {
+        hex::encode(self.private_key.inner.secret_bytes())
+    }
+
+    /// `spend_info` is built for the x-only key of the signer, see `xonly_pubkey`
+    pub fn with_tap_tree(mut self, spend_info: TaprootSpendInfo) -> Self {
+        self.tap_tree = Some(spend_info);
+        self
+    }
+
+    /// address committing to the tap tree
+    pub fn tap_tree_address(&self) -> Option<Address> {
+        self.tap_tree
+            .as_ref()
+            .map(|t| Address::p2tr_tweaked(t.output_key(), self.net))
+    }
+
+    /// signs `input` spending the `leaf_script` of the tap tree via the script path,
+    /// the other inputs are left as is
+    pub fn sign_script_path(
+        &self,
+        otx: &Transaction,
+        parent_utxos: Vec<TxOut>,
+        input: usize,
+        leaf_script: &Script,
+        sighash_type: TapSighashType,
+    ) -> anyhow::Result<Transaction> {
+        let Some(tap_tree) = &self.tap_tree else {
+            anyhow::bail!("signer doesn't have a tap tree");
+        };
+        let Some(control_block) =
+            tap_tree.control_block(&(leaf_script.to_owned(), LeafVersion::TapScript))
+        else {
+            anyhow::bail!("script isn't a leaf of the tap tree: {}", leaf_script);
+        };
+        if input >= otx.input.len() || input >= parent_utxos.len() {
+            anyhow::bail!("input {} is out of range", input);
+        }
+        check_taproot_sighash(otx, input, &parent_utxos[input], sighash_type)?;
+
+        let mut tx = otx.clone();
+        tx.input[input].witness = script_path_witness(
+            &self.kp,
+            otx,
+            input,
+            &Prevouts::All(&parent_utxos),
+            leaf_script,
+            &control_block,
+            sighash_type,
+        )?;
+        Ok(tx)
+    }
+
     pub fn xonly_pubkey(&self) -> XOnlyPublicKey {
         let (pubkey, _) = XOnlyPublicKey::from_keypair(&self.kp);
         pubkey
@@ -113,6 +205,16 @@ impl PKSigner {
         &self,
         otx: &Transaction,
         parent_utxos: Vec<(bool, TxOut)>,
+    ) -> anyhow::Result<Vec<Option<taproot::Signature>>> {
+        self.partial_sign_with_sighash(otx, parent_utxos, &[])
+    }
+
+    /// `sighash_types` are set per input, inputs without an entry are signed with ALL
+    pub fn partial_sign_with_sighash(
+        &self,
+        otx: &Transaction,
+        parent_utxos: Vec<(bool, TxOut)>,
+        sighash_types: &[TapSighashType],
     ) -> anyhow::Result<Vec<Option<taproot::Signature>>> {
         if let AddressMode::Legacy(_) = self.address_mode {
             anyhow::bail!("Legacy signature mode is unsupported for partial signing!");
@@ -123,7 +225,6 @@ impl PKSigner {
         }
 
         let mut tx = otx.clone();
-        let sighash_type = TapSighashType::All;
         let mut sighasher = SighashCache::new(&mut tx);
         let mut parents = Vec::new();
         for (_, u) in parent_utxos.iter() {
@@ -143,6 +244,8 @@ impl PKSigner {
                 id, input, parent_utxos[id]
             );
 
+            let sighash_type = sighash_type_at(sighash_types, id);
+            check_taproot_sighash(otx, id, &parent_utxos[id].1, sighash_type)?;
             let sighash =
                 sighasher.taproot_key_spend_signature_hash(id, &prevouts, sighash_type)?;
 
@@ -166,9 +269,19 @@ impl PKSigner {
         &self,
         otx: &Transaction,
         parent_utxos: Vec<TxOut>,
+    ) -> anyhow::Result<Transaction> {
+        self.sign_tx_with_sighash(otx, parent_utxos, &[])
+    }
+
+    /// `sighash_types` are set per input, inputs without an entry are signed with ALL
+    pub fn sign_tx_with_sighash(
+        &self,
+        otx: &Transaction,
+        parent_utxos: Vec<TxOut>,
+        sighash_types: &[TapSighashType],
     ) -> anyhow::Result<Transaction> {
         if let AddressMode::Legacy(_) = self.address_mode {
-            return self.legacy_sign_tx(otx, parent_utxos);
+            return self.legacy_sign_tx_with_sighash(otx, parent_utxos, sighash_types);
         }
 
         if let AddressMode::Witness = self.address_mode {
@@ -176,11 +289,12 @@ impl PKSigner {
         }
 
         let mut tx = otx.clone();
-        let sighash_type = TapSighashType::All;
         let prevouts = Prevouts::All(&parent_utxos);
 
         let mut sighasher = SighashCache::new(&mut tx);
         for (id, _input) in otx.input.iter().enumerate() {
+            let sighash_type = sighash_type_at(sighash_types, id);
+            check_taproot_sighash(otx, id, &parent_utxos[id], sighash_type)?;
             let sighash = sighasher
                 .taproot_key_spend_signature_hash(id, &prevouts, sighash_type)
                 .expect("failed to construct sighash");
@@ -212,13 +326,27 @@ impl PKSigner {
         &self,
         otx: &Transaction,
         parent_utxos: Vec<TxOut>,
+    ) -> anyhow::Result<Transaction> {
+        self.legacy_sign_tx_with_sighash(otx, parent_utxos, &[])
+    }
+
+    fn legacy_sign_tx_with_sighash(
+        &self,
+        otx: &Transaction,
+        parent_utxos: Vec<TxOut>,
+        sighash_types: &[TapSighashType],
     ) -> anyhow::Result<Transaction> {
         let secp = Secp256k1::new();
-        let sighash_type = EcdsaSighashType::All;
         let mut tx = otx.clone();
         let public_key = self.private_key.public_key(&secp).to_bytes();
 
         for (input_index, _input) in otx.input.iter().enumerate() {
+            let sighash_type = legacy_sighash(
+                otx,
+                input_index,
+                &parent_utxos[input_index],
+                sighash_type_at(sighash_types, input_index),
+            )?;
             let sb = {
                 let sighash_cache = SighashCache::new(tx.borrow());
                 let sighash = sighash_cache.legacy_signature_hash(
@@ -252,3 +380,506 @@ impl PKSigner {
         Ok(tx)
     }
 }
+
+/// Local signers keyed by address, each input is signed by the signer of its prevout.
+#[derive(Clone)]
+pub struct MultiSigner {
+    signers: Vec<PKSigner>,
+}
+
+impl MultiSigner {
+    pub fn new(signers: Vec<PKSigner>) -> anyhow::Result<Self> {
+        if signers.is_empty() {
+            anyhow::bail!("at least one signer is required")
+        }
+        Ok(Self { signers })
+    }
+
+    pub fn from_config(
+        net: Network,
+        cfg: &crate::config::SignatureProvider,
+    ) -> anyhow::Result<Self> {
+        let mut signers = Vec::new();
+        for s in cfg.local_signers() {
+            let signer =
+                PKSigner::new_from_secret(net, &s.secret()?, AddressMode::new_from_str(&s.mode))?;
+            if signer.address.to_string() != s.address {
+                anyhow::bail!(
+                    "configured signer address({}) doesn't match the key address({})",
+                    s.address,
+                    signer.address
+                )
+            }
+            signers.push(signer);
+        }
+        Self::new(signers)
+    }
+
+    pub fn addresses(&self) -> Vec<Address> {
+        self.signers.iter().map(|s| s.address.clone()).collect()
+    }
+
+    /// signs the signable inputs with the signer owning the prevout script
+    pub fn partial_sign(
+        &self,
+        otx: &Transaction,
+        parent_utxos: Vec<(bool, TxOut)>,
+    ) -> anyhow::Result<Vec<Option<taproot::Signature>>> {
+        for (id, (signable, out)) in parent_utxos.iter().enumerate() {
+            let owned = self
+                .signers
+                .iter()
+                .any(|s| s.address.script_pubkey() == out.script_pubkey);
+            if *signable && !owned {
+                anyhow::bail!("input({}) doesn't belong to any of the local signers", id)
+            }
+        }
+
+        let mut result = vec![None; otx.input.len()];
+        for signer in self.signers.iter() {
+            let script = signer.address.script_pubkey();
+            let own_inputs: Vec<(bool, TxOut)> = parent_utxos
+                .iter()
+                .map(|(signable, out)| (*signable && out.script_pubkey == script, out.clone()))
+                .collect();
+            if !own_inputs.iter().any(|(signable, _)| *signable) {
+                continue;
+            }
+
+            let signatures = signer.partial_sign(otx, own_inputs)?;
+            for (id, sig) in signatures.into_iter().enumerate() {
+                if sig.is_some() {
+                    result[id] = sig;
+                }
+            }
+        }
+
+        Ok(result)
+    }
+}
+
+/// witness of a script path spend: signature of the leaf, the leaf script and the control block
+pub fn script_path_witness<T: Borrow<TxOut>>(
+    kp: &KeyPair,
+    tx: &Transaction,
+    input: usize,
+    prevouts: &Prevouts<T>,
+    leaf_script: &Script,
+    control_block: &ControlBlock,
+    sighash_type: TapSighashType,
+) -> anyhow::Result<Witness> {
+    let sighash = SighashCache::new(tx).taproot_script_spend_signature_hash(
+        input,
+        prevouts,
+        TapLeafHash::from_script(leaf_script, LeafVersion::TapScript),
+        sighash_type,
+    )?;
+    let signature = taproot::Signature {
+        sig: Secp256k1::new().sign_schnorr(&Message::from(sighash), kp),
+        hash_ty: sighash_type,
+    };
+
+    let mut witness = Witness::new();
+    witness.push(signature.to_vec());
+    witness.push(leaf_script);
+    witness.push(control_block.serialize());
+    Ok(witness)
+}
+
+fn sighash_type_at(sighash_types: &[TapSighashType], input: usize) -> TapSighashType {
+    sighash_types
+        .get(input)
+        .copied()
+        .unwrap_or(TapSighashType::All)
+}
+
+/// SINGLE commits to the output with the same index, so it has to exist
+fn check_single_output(
+    tx: &Transaction,
+    input: usize,
+    sighash_type: TapSighashType,
+) -> anyhow::Result<()> {
+    let single = matches!(
+        sighash_type,
+        TapSighashType::Single | TapSighashType::SinglePlusAnyoneCanPay
+    );
+    if single && input >= tx.output.len() {
+        anyhow::bail!(
+            "input({}) can't be signed with {}: there is no output with the same index",
+            input,
+            sighash_type
+        )
+    }
+    Ok(())
+}
+
+/// WIF only tells mainnet from the test networks apart
+fn wif_network_matches(wif_net: Network, net: Network) -> bool {
+    (wif_net == Network::Bitcoin) == (net == Network::Bitcoin)
+}
+
+fn check_taproot_sighash(
+    tx: &Transaction,
+    input: usize,
+    parent: &TxOut,
+    sighash_type: TapSighashType,
+) -> anyhow::Result<()> {
+    if !parent.script_pubkey.is_v1_p2tr() {
+        anyhow::bail!("input({}) isn't a taproot output", input)
+    }
+    check_single_output(tx, input, sighash_type)
+}
+
+fn legacy_sighash(
+    tx: &Transaction,
+    input: usize,
+    parent: &TxOut,
+    sighash_type: TapSighashType,
+) -> anyhow::Result<EcdsaSighashType> {
+    if !parent.script_pubkey.is_p2pkh() {
+        anyhow::bail!("input({}) isn't a p2pkh output", input)
+    }
+    if sighash_type == TapSighashType::Default {
+        anyhow::bail!("sighash DEFAULT is only valid for taproot inputs")
+    }
+    check_single_output(tx, input, sighash_type)?;
+
+    Ok(EcdsaSighashType::from_standard(sighash_type as u32)?)
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn sign_with_anyone_can_pay() {
+        use super::{AddressMode, PKSigner};
+        use bitcoin::{
+            absolute::LockTime,
+            hashes::Hash,
+            key::TapTweak,
+            secp256k1::Message,
+            sighash::{Prevouts, SighashCache, TapSighashType},
+            taproot, OutPoint, ScriptBuf, Sequence, Transaction, TxIn, TxOut, Witness,
+        };
+
+        let signer = PKSigner::new_from_secret(
+            bitcoin::Network::Regtest,
+            &"01".repeat(32),
+            AddressMode::Taproot,
+        )
+        .unwrap();
+        let parent = TxOut {
+            value: 10_000,
+            script_pubkey: signer.address.script_pubkey(),
+        };
+        let input = |vout| TxIn {
+            previous_output: OutPoint {
+                txid: bitcoin::Txid::all_zeros(),
+                vout,
+            },
+            script_sig: ScriptBuf::new(),
+            sequence: Sequence::MAX,
+            witness: Witness::new(),
+        };
+        let tx = Transaction {
+            version: 2,
+            lock_time: LockTime::ZERO,
+            input: vec![input(0), input(1)],
+            output: vec![TxOut {
+                value: 15_000,
+                script_pubkey: signer.address.script_pubkey(),
+            }],
+        };
+        let parents = vec![parent.clone(), parent.clone()];
+
+        let signed = signer
+            .sign_tx_with_sighash(&tx, parents.clone(), &[TapSighashType::AllPlusAnyoneCanPay])
+            .unwrap();
+
+        let sig = taproot::Signature::from_slice(&signed.input[0].witness.to_vec()[0]).unwrap();
+        assert_eq!(sig.hash_ty, TapSighashType::AllPlusAnyoneCanPay);
+        let sig = taproot::Signature::from_slice(&signed.input[1].witness.to_vec()[0]).unwrap();
+        assert_eq!(sig.hash_ty, TapSighashType::All);
+
+        // an ANYONECANPAY signature only commits to its own prevout
+        let sighash = SighashCache::new(&tx)
+            .taproot_key_spend_signature_hash(
+                0,
+                &Prevouts::One(0, &parent),
+                TapSighashType::AllPlusAnyoneCanPay,
+            )
+            .unwrap();
+        let sig = taproot::Signature::from_slice(&signed.input[0].witness.to_vec()[0]).unwrap();
+        let (output_key, _) = signer
+            .kp
+            .tap_tweak(&bitcoin::secp256k1::Secp256k1::new(), None)
+            .to_inner()
+            .x_only_public_key();
+        assert!(bitcoin::secp256k1::Secp256k1::new()
+            .verify_schnorr(&sig.sig, &Message::from(sighash), &output_key)
+            .is_ok());
+
+        // SINGLE needs an output with the same index
+        assert!(signer
+            .sign_tx_with_sighash(&tx, parents, &[TapSighashType::All, TapSighashType::Single])
+            .is_err());
+    }
+
+    #[test]
+    fn multi_signer_picks_signer_by_address() {
+        use super::{AddressMode, MultiSigner, PKSigner};
+        use bitcoin::{
+            absolute::LockTime,
+            hashes::Hash,
+            key::TapTweak,
+            secp256k1::{Message, Secp256k1},
+            sighash::{Prevouts, SighashCache, TapSighashType},
+            OutPoint, ScriptBuf, Sequence, Transaction, TxIn, TxOut, Witness,
+        };
+
+        let net = bitcoin::Network::Regtest;
+        let pool = PKSigner::new_from_secret(net, &"01".repeat(32), AddressMode::Taproot).unwrap();
+        let fee = PKSigner::new_from_secret(net, &"02".repeat(32), AddressMode::Taproot).unwrap();
+        let signers = MultiSigner::new(vec![pool.clone(), fee.clone()]).unwrap();
+
+        let input = |vout| TxIn {
+            previous_output: OutPoint {
+                txid: bitcoin::Txid::all_zeros(),
+                vout,
+            },
+            script_sig: ScriptBuf::new(),
+            sequence: Sequence::MAX,
+            witness: Witness::new(),
+        };
+        let parent = |signer: &PKSigner| TxOut {
+            value: 10_000,
+            script_pubkey: signer.address.script_pubkey(),
+        };
+        let tx = Transaction {
+            version: 2,
+            lock_time: LockTime::ZERO,
+            input: vec![input(0), input(1), input(2)],
+            output: vec![TxOut {
+                value: 25_000,
+                script_pubkey: pool.address.script_pubkey(),
+            }],
+        };
+        // the last input is signed by the user
+        let parents = vec![
+            (true, parent(&pool)),
+            (true, parent(&fee)),
+            (false, parent(&pool)),
+        ];
+
+        let signatures = signers.partial_sign(&tx, parents.clone()).unwrap();
+        assert!(signatures[2].is_none());
+
+        let secp = Secp256k1::new();
+        let outs: Vec<TxOut> = parents.iter().map(|(_, o)| o.clone()).collect();
+        for (id, signer) in [(0, &pool), (1, &fee)] {
+            let sighash = SighashCache::new(&tx)
+                .taproot_key_spend_signature_hash(id, &Prevouts::All(&outs), TapSighashType::All)
+                .unwrap();
+            let (key, _) = signer
+                .kp
+                .tap_tweak(&secp, None)
+                .to_inner()
+                .x_only_public_key();
+            let sig = signatures[id].unwrap();
+            assert!(secp
+                .verify_schnorr(&sig.sig, &Message::from(sighash), &key)
+                .is_ok());
+        }
+
+        // signable inputs of unknown addresses are refused
+        let other = PKSigner::new_from_secret(net, &"03".repeat(32), AddressMode::Taproot).unwrap();
+        let mut parents = parents;
+        parents[2] = (true, parent(&other));
+        assert!(signers.partial_sign(&tx, parents).is_err());
+    }
+
+    #[test]
+    fn sign_single_leaf_script_path() {
+        use super::{AddressMode, PKSigner};
+        use bitcoin::{
+            absolute::LockTime,
+            hashes::Hash,
+            opcodes,
+            script::Builder,
+            secp256k1::{Message, Secp256k1},
+            sighash::{Prevouts, SighashCache, TapSighashType},
+            taproot::{self, ControlBlock, LeafVersion, TapLeafHash, TaprootBuilder},
+            OutPoint, ScriptBuf, Sequence, Transaction, TxIn, TxOut, Witness,
+        };
+
+        let secp = Secp256k1::new();
+        let signer = PKSigner::new_from_secret(
+            bitcoin::Network::Regtest,
+            &"01".repeat(32),
+            AddressMode::Taproot,
+        )
+        .unwrap();
+        let leaf = Builder::new()
+            .push_slice(signer.xonly_pubkey().serialize())
+            .push_opcode(opcodes::all::OP_CHECKSIG)
+            .into_script();
+        let spend_info = TaprootBuilder::new()
+            .add_leaf(0, leaf.clone())
+            .unwrap()
+            .finalize(&secp, signer.xonly_pubkey())
+            .unwrap();
+        let output_key = spend_info.output_key();
+        let signer = signer.with_tap_tree(spend_info);
+        let address = signer.tap_tree_address().unwrap();
+
+        let parent = TxOut {
+            value: 10_000,
+            script_pubkey: address.script_pubkey(),
+        };
+        let tx = Transaction {
+            version: 2,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: OutPoint {
+                    txid: bitcoin::Txid::all_zeros(),
+                    vout: 0,
+                },
+                script_sig: ScriptBuf::new(),
+                sequence: Sequence::MAX,
+                witness: Witness::new(),
+            }],
+            output: vec![TxOut {
+                value: 9_000,
+                script_pubkey: signer.address.script_pubkey(),
+            }],
+        };
+
+        let signed = signer
+            .sign_script_path(&tx, vec![parent.clone()], 0, &leaf, TapSighashType::All)
+            .unwrap();
+        let witness = signed.input[0].witness.to_vec();
+        assert_eq!(witness.len(), 3);
+        assert_eq!(witness[1], leaf.to_bytes());
+
+        let control_block = ControlBlock::decode(&witness[2]).unwrap();
+        assert!(control_block.verify_taproot_commitment(&secp, output_key.to_inner(), &leaf));
+
+        let sighash = SighashCache::new(&tx)
+            .taproot_script_spend_signature_hash(
+                0,
+                &Prevouts::All(&[parent.clone()]),
+                TapLeafHash::from_script(&leaf, LeafVersion::TapScript),
+                TapSighashType::All,
+            )
+            .unwrap();
+        let sig = taproot::Signature::from_slice(&witness[0]).unwrap();
+        assert!(secp
+            .verify_schnorr(&sig.sig, &Message::from(sighash), &signer.xonly_pubkey())
+            .is_ok());
+
+        // only leaves of the tree can be signed
+        let other = Builder::new().push_opcode(opcodes::OP_TRUE).into_script();
+        assert!(signer
+            .sign_script_path(&tx, vec![parent], 0, &other, TapSighashType::All)
+            .is_err());
+    }
+
+    #[test]
+    fn wif_round_trip() {
+        use super::{AddressMode, PKSigner};
+        use bitcoin::Network;
+
+        let secret = "01".repeat(32);
+        for net in [Network::Bitcoin, Network::Testnet, Network::Regtest] {
+            for mode in [
+                "legacy_compressed",
+                "legacy_uncompressed",
+                "witnes",
+                "taproot",
+            ] {
+                let signer =
+                    PKSigner::new_from_secret(net, &secret, AddressMode::new_from_str(mode))
+                        .unwrap();
+                let wif = signer.to_wif();
+                let imported =
+                    PKSigner::from_wif(net, &wif, AddressMode::new_from_str(mode)).unwrap();
+                assert_eq!(imported.secret_hex(), secret);
+                assert_eq!(imported.to_wif(), wif);
+                assert_eq!(imported.address, signer.address, "{} {}", net, mode);
+            }
+        }
+
+        // the well known WIF of the secret 0x01..01
+        let signer =
+            PKSigner::new_from_secret(Network::Bitcoin, &secret, AddressMode::Taproot).unwrap();
+        assert_eq!(
+            signer.to_wif(),
+            "KwFfNUhSDaASSAwtG7ssQM1uVX8RgX5GHWnnLfhfiQDigjioWXHH"
+        );
+        let uncompressed =
+            PKSigner::new_from_secret(Network::Bitcoin, &secret, AddressMode::Legacy(false))
+                .unwrap();
+        assert_eq!(
+            uncompressed.to_wif(),
+            "5HpjE2Hs7vjU4SN3YyPQCdhzCu92WoEeuE6PWNuiPyTu3ESGnzn"
+        );
+
+        // legacy mode follows the WIF compression, the other modes need a compressed key
+        let legacy = PKSigner::from_wif(
+            Network::Bitcoin,
+            &uncompressed.to_wif(),
+            AddressMode::Legacy(true),
+        )
+        .unwrap();
+        assert_eq!(legacy.address, uncompressed.address);
+        assert!(PKSigner::from_wif(
+            Network::Bitcoin,
+            &uncompressed.to_wif(),
+            AddressMode::Taproot
+        )
+        .is_err());
+
+        // testnet keys are valid for regtest, not for mainnet
+        let testnet =
+            PKSigner::new_from_secret(Network::Testnet, &secret, AddressMode::Taproot).unwrap();
+        assert!(
+            PKSigner::from_wif(Network::Regtest, &testnet.to_wif(), AddressMode::Taproot).is_ok()
+        );
+        assert!(
+            PKSigner::from_wif(Network::Bitcoin, &testnet.to_wif(), AddressMode::Taproot).is_err()
+        );
+        assert!(
+            PKSigner::from_wif(Network::Testnet, &signer.to_wif(), AddressMode::Taproot).is_err()
+        );
+    }
+
+    #[test]
+    fn signer_address_mismatch_fails() {
+        use super::{AddressMode, MultiSigner, PKSigner};
+        use crate::config::{LocalSigner, SignatureProvider};
+
+        let net = bitcoin::Network::Regtest;
+        let local_signer = |secret: &str| LocalSigner {
+            address: PKSigner::new_from_secret(net, secret, AddressMode::Taproot)
+                .unwrap()
+                .address
+                .to_string(),
+            secret_key: secret.to_string(),
+            mode: "taproot".to_string(),
+        };
+        let mut cfg = SignatureProvider {
+            local: local_signer(&"01".repeat(32)),
+            signers: vec![local_signer(&"02".repeat(32))],
+            signable_addresses: vec![],
+        };
+        assert_eq!(
+            MultiSigner::from_config(net, &cfg)
+                .unwrap()
+                .addresses()
+                .len(),
+            2
+        );
+
+        cfg.signers[0].address = cfg.local.address.clone();
+        assert!(MultiSigner::from_config(net, &cfg).is_err());
+    }
+}
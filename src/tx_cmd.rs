@@ -1,19 +1,27 @@
 use bitcoin::{
-    absolute::LockTime, script::Builder, Address, Amount, OutPoint, ScriptBuf, Sequence,
-    Transaction, TxIn, TxOut, Txid, Witness,
+    script::Builder, Address, Amount, OutPoint, ScriptBuf, Sequence, Transaction, TxIn, TxOut,
+    Txid, Witness,
 };
-use bitcoincore_rpc::{Auth, Client, RawTx, RpcApi};
+use bitcoincore_rpc::{RawTx, RpcApi};
 use ordinals::{Edict, RuneId, Runestone};
-use std::{collections::HashSet, str::FromStr};
+use std::{collections::HashSet, str::FromStr, sync::Arc};
 
 use crate::{
+    btc_utxo::UtxoClient,
+    cli_error::CliError,
+    config::BTCConfig,
     db,
     tx::{
+        fee::{effective_fee_rate, FeeEstimate, FeePriority},
+        pool_txs::{check_runestone_size, reorder_outputs},
         runes_txs,
         signer::{AddressMode, PKSigner},
     },
 };
 
+/// btc utxos fetched from `btc.utxo_provider` for funding, as many as the etcher takes
+const MAX_BTC_UTXO: i32 = 100;
+
 #[derive(Debug, clap::Parser)]
 pub struct BtcTxCmd {
     #[arg(long)]
@@ -22,8 +30,12 @@ pub struct BtcTxCmd {
     #[arg(long)]
     amount: u64,
 
-    #[arg(long, default_value_t = 42.0)]
-    fee: f64,
+    /// sat/vB, estimated for `--priority` if not set
+    #[arg(long)]
+    fee: Option<f64>,
+
+    #[arg(long, value_enum, default_value_t)]
+    priority: FeePriority,
 
     #[arg(long, default_value_t = false)]
     submit: bool,
@@ -32,17 +44,20 @@ pub struct BtcTxCmd {
 impl BtcTxCmd {
     pub async fn run(&self, config_path: &str) -> anyhow::Result<()> {
         let cfg = crate::config::read_config(config_path)?;
-        let repo = db::open_postgres_db(cfg.db).await?;
+        let repo = Arc::new(db::open_postgres_db(cfg.db).await?);
+        let fee_rate = resolve_fee_rate(self.fee, self.priority, &cfg.btc, repo.clone()).await?;
         let net = cfg.btc.get_network();
         let signer = PKSigner::new_from_secret(
             net,
-            &cfg.signature_provider.local.secret_key,
+            &cfg.signature_provider.local.secret()?,
             AddressMode::new_from_str(&cfg.signature_provider.local.mode),
         )?;
 
         println!("{}", signer.address);
 
-        let utxo = repo.select_btc_utxo(&signer.address.to_string()).await?;
+        let utxo = UtxoClient::new(&cfg.btc, repo.clone())?
+            .get_utxo(&signer.address.to_string(), MAX_BTC_UTXO, 0)
+            .await?;
 
         let mut inputs = Vec::new();
         let mut parent_outs = Vec::new();
@@ -80,7 +95,9 @@ impl BtcTxCmd {
 
         let base_out_val = self.amount / self.dest_address.len() as u64;
         for addr in self.dest_address.clone() {
-            let address = Address::from_str(&addr)?.require_network(net)?;
+            let address = Address::from_str(&addr)
+                .and_then(|a| a.require_network(net))
+                .map_err(|e| CliError::Validation(format!("dest_address({}): {}", addr, e)))?;
 
             outputs.push(TxOut {
                 script_pubkey: address.script_pubkey(),
@@ -89,22 +106,25 @@ impl BtcTxCmd {
         }
 
         let mut tx = Transaction {
-            version: 2,
-            lock_time: LockTime::ZERO,
+            version: cfg.btc.tx_version()?,
+            lock_time: cfg
+                .btc
+                .lock_time()?
+                .resolve_from_node(&cfg.btc.rpc_client()?)?,
             input: inputs,
             output: outputs,
         };
 
-        let fee_val = fee(self.fee, tx.vsize()).to_sat();
+        let fee_val = fee(fee_rate, tx.vsize()).to_sat();
 
         println!("{} {}", total_amount, fee_val);
 
         if total_amount < self.amount + fee_val {
-            error!(
-                "BUG: to small input amount. in={} amount_to_send={} fee={}",
+            return Err(CliError::InsufficientFunds(format!(
+                "in={} amount_to_send={} fee={}",
                 total_amount, self.amount, fee_val
-            );
-            return Ok(());
+            ))
+            .into());
         }
 
         let change_value = total_amount - (self.amount + fee_val);
@@ -135,12 +155,11 @@ impl BtcTxCmd {
         );
 
         if self.submit {
-            let rpc = Client::new(
-                &cfg.btc.address,
-                Auth::UserPass(cfg.btc.rpc_user.clone(), cfg.btc.rpc_password.clone()),
-            )?;
+            let rpc = cfg.btc.rpc_client()?;
 
-            let tx_id = rpc.send_raw_transaction(signed_tx.raw_hex())?;
+            let tx_id = rpc
+                .send_raw_transaction(signed_tx.raw_hex())
+                .map_err(CliError::broadcast)?;
             println!("TX ID ->> {}", tx_id);
         }
 
@@ -152,6 +171,25 @@ pub fn fee(fee_rate: f64, vsize: usize) -> Amount {
     Amount::from_sat((fee_rate * vsize as f64).round() as u64)
 }
 
+/// `--fee` if set, otherwise the utxo provider's estimate for `--priority`
+async fn resolve_fee_rate(
+    fee: Option<f64>,
+    priority: FeePriority,
+    btc_cfg: &BTCConfig,
+    db: Arc<db::Repo>,
+) -> anyhow::Result<f64> {
+    if let Some(fee) = fee {
+        return Ok(fee);
+    }
+
+    let utxo_provider = UtxoClient::new(btc_cfg, db)?;
+    let fee_rate = utxo_provider
+        .get_fee_for_target(priority.target_blocks())
+        .await?;
+    println!("FEE RATE ({:?}) ->> {} sat/vB", priority, fee_rate);
+    Ok(fee_rate as f64)
+}
+
 #[derive(Debug, clap::Parser)]
 pub struct SubmitRawTxCmd {
     #[arg(long)]
@@ -162,12 +200,11 @@ impl SubmitRawTxCmd {
     pub async fn run(&self, cfg_path: &str) -> anyhow::Result<()> {
         let cfg = crate::config::read_config(cfg_path)?;
 
-        let rpc = Client::new(
-            &cfg.btc.address,
-            Auth::UserPass(cfg.btc.rpc_user.clone(), cfg.btc.rpc_password.clone()),
-        )?;
+        let rpc = cfg.btc.rpc_client()?;
 
-        let tx_id = rpc.send_raw_transaction(self.tx.clone())?;
+        let tx_id = rpc
+            .send_raw_transaction(self.tx.clone())
+            .map_err(CliError::broadcast)?;
         println!("TX ID ->> {}", tx_id);
 
         Ok(())
@@ -176,33 +213,73 @@ impl SubmitRawTxCmd {
 
 #[derive(Debug, clap::Parser)]
 pub struct SendRuneTxCmd {
-    #[arg(long)]
+    /// `--amount` is split evenly between the addresses
+    #[arg(long, required_unless_present = "recipients")]
     dest_address: Vec<String>,
 
     #[arg(long)]
     rune: String,
 
+    #[arg(long, required_unless_present = "recipients")]
+    amount: Option<u128>,
+
+    /// `address:amount`, repeated for every recipient
+    #[arg(long, conflicts_with_all = ["dest_address", "amount"])]
+    recipients: Vec<String>,
+
+    /// sat/vB, estimated for `--priority` if not set
     #[arg(long)]
-    amount: u128,
+    fee: Option<f64>,
 
-    #[arg(long, default_value_t = 42.0)]
-    fee: f64,
+    #[arg(long, value_enum, default_value_t)]
+    priority: FeePriority,
 
     #[arg(long, default_value_t = false)]
     submit: bool,
 }
 
 impl SendRuneTxCmd {
+    /// recipients with their amounts, from `--recipients` or `--amount` split between `--dest-address`
+    fn transfers(&self) -> anyhow::Result<Vec<(String, u128)>> {
+        if !self.recipients.is_empty() {
+            return self.recipients.iter().map(|r| parse_recipient(r)).collect();
+        }
+
+        let amount = self.amount.unwrap_or_default();
+        if self.dest_address.is_empty() || amount < self.dest_address.len() as u128 {
+            return Err(CliError::Validation(format!(
+                "can't split {} between {} addresses",
+                amount,
+                self.dest_address.len()
+            ))
+            .into());
+        }
+        let per_address = amount / self.dest_address.len() as u128;
+        Ok(self
+            .dest_address
+            .iter()
+            .map(|addr| (addr.clone(), per_address))
+            .collect())
+    }
+
     pub async fn run(&self, config_path: &str) -> anyhow::Result<()> {
         let cfg = crate::config::read_config(config_path)?;
-        let repo = db::open_postgres_db(cfg.db).await?;
+        let repo = Arc::new(db::open_postgres_db(cfg.db).await?);
+        let fee_rate = resolve_fee_rate(self.fee, self.priority, &cfg.btc, repo.clone()).await?;
         let net = cfg.btc.get_network();
         let signer = PKSigner::new_from_secret(
             net,
-            &cfg.signature_provider.local.secret_key,
+            &cfg.signature_provider.local.secret()?,
             AddressMode::new_from_str(&cfg.signature_provider.local.mode),
         )?;
 
+        let transfers = self
+            .transfers()?
+            .into_iter()
+            .map(|(addr, amount)| Ok((Address::from_str(&addr)?.require_network(net)?, amount)))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let amount = total_amount(&transfers)?;
+
         println!("Send {} runes form {}", self.rune, signer.address);
         let rune_info = repo.get_rune(&self.rune).await?;
 
@@ -221,12 +298,15 @@ impl SendRuneTxCmd {
         println!(
             "Selected {} UTXOs. Amount to send -> {}",
             runes_utxo.len(),
-            self.amount
+            amount
         );
 
         let mut tx = Transaction {
-            version: 2,
-            lock_time: LockTime::ZERO,
+            version: cfg.btc.tx_version()?,
+            lock_time: cfg
+                .btc
+                .lock_time()?
+                .resolve_from_node(&cfg.btc.rpc_client()?)?,
             input: Vec::new(),
             output: vec![
                 // it will be OP_RETURN 13 magic
@@ -243,7 +323,7 @@ impl SendRuneTxCmd {
         let mut btc_input_set: HashSet<OutPoint> = HashSet::new();
 
         for u in runes_utxo {
-            if runes_in_amount > self.amount {
+            if runes_in_amount >= amount {
                 break;
             }
 
@@ -269,52 +349,31 @@ impl SendRuneTxCmd {
             btc_input_set.insert(op);
         }
 
-        let mut btc_out_amount = runes_txs::RUNES_OUT_VALUE * self.dest_address.len() as u64;
-        let rune_amount_per_out = self.amount / self.dest_address.len() as u128;
-
-        let mut edicts: Vec<Edict> = Vec::new();
-        for (id, addr) in self.dest_address.clone().iter().enumerate() {
-            let address = Address::from_str(addr)?.require_network(net)?;
-
-            edicts.push(Edict {
-                id: RuneId {
-                    block: rune_info.block as u64,
-                    tx: rune_info.tx_id as u32,
-                },
-                amount: rune_amount_per_out,
-                output: id as u32 + 1,
-            });
-
-            tx.output.push(TxOut {
-                script_pubkey: address.script_pubkey(),
-                value: runes_txs::RUNES_OUT_VALUE,
-            });
-        }
-
-        let mut pointer: Option<u32> = None;
-        if self.amount < runes_in_amount {
-            btc_out_amount += runes_txs::RUNES_OUT_VALUE;
-            tx.output.push(TxOut {
-                value: runes_txs::RUNES_OUT_VALUE,
-                script_pubkey: signer.address.script_pubkey(),
-            });
-
-            pointer = Some(tx.output.len() as u32);
-        }
-
-        let runestone = Runestone {
-            edicts,
-            etching: None,
-            mint: None,
-            pointer,
+        let rune_id = RuneId {
+            block: rune_info.block as u64,
+            tx: rune_info.tx_id as u32,
         };
+        let (runestone, outputs) = rune_transfer(
+            rune_id,
+            &transfers,
+            runes_in_amount,
+            &signer.address,
+            cfg.btc.rune_output_value(),
+            cfg.btc.max_runestone_size(),
+        )?;
+        let btc_out_amount: u64 = outputs.iter().map(|o| o.value).sum();
+        let mut change_outputs: Vec<usize> =
+            runestone.pointer.map(|p| p as usize).into_iter().collect();
+        tx.output.extend(outputs);
 
         tx.output[0].script_pubkey = runestone.encipher();
 
-        let fee_val = (fee(self.fee, tx.vsize()).to_sat() as f64 * 1.86) as u64; // TODO: fix fee estimation
+        // btc inputs and signatures aren't added yet
+        let safety_factor = cfg.btc.fee_safety_factor();
+        let fee_val = FeeEstimate::new(fee_rate, tx.vsize(), safety_factor).fee;
 
-        let btc_utxo = repo
-            .select_btc_utxo_with_pagination(Some(signer.address.to_string()), "ASC", 20, 0)
+        let btc_utxo = UtxoClient::new(&cfg.btc, repo.clone())?
+            .get_utxo(&signer.address.to_string(), MAX_BTC_UTXO, 0)
             .await?;
 
         for u in btc_utxo.iter() {
@@ -345,21 +404,31 @@ impl SendRuneTxCmd {
         }
 
         if btc_in_amount < btc_out_amount + fee_val {
-            error!(
-                "BUG: to small input amount. in={} amount_to_send={} fee={}",
-                btc_in_amount, self.amount, fee_val
-            );
-            return Ok(());
+            return Err(CliError::InsufficientFunds(format!(
+                "in={} amount_to_send={} fee={}",
+                btc_in_amount, btc_out_amount, fee_val
+            ))
+            .into());
         }
 
         let btc_change_value = btc_in_amount - (btc_out_amount + fee_val);
         if btc_change_value > 800 {
+            change_outputs.push(tx.output.len());
             tx.output.push(TxOut {
                 value: btc_change_value,
                 script_pubkey: signer.address.script_pubkey(),
             })
         }
 
+        reorder_outputs(
+            &mut tx,
+            cfg.btc.output_order()?,
+            &change_outputs,
+            &mut rand::thread_rng(),
+        )?;
+        // remapped edict outputs may change the size
+        check_runestone_size(&tx.output[0].script_pubkey, cfg.btc.max_runestone_size())?;
+
         println!(
             "PREPARING TX: -> size={} in={} fee={} out={}",
             tx.vsize(),
@@ -377,17 +446,23 @@ impl SendRuneTxCmd {
             btc_in_amount,
             fee_val,
             btc_out_amount + btc_change_value,
-           runes_in_amount,
-            self.amount, runes_in_amount - self.amount,
+            runes_in_amount,
+            amount,
+            runes_in_amount - amount,
+        );
+        println!(
+            "TX FEE: -> fee_rate={:.2} requested_fee_rate={} safety_factor={}",
+            effective_fee_rate(fee_val, signed_tx.vsize()),
+            fee_rate,
+            safety_factor
         );
 
         if self.submit {
-            let rpc = Client::new(
-                &cfg.btc.address,
-                Auth::UserPass(cfg.btc.rpc_user.clone(), cfg.btc.rpc_password.clone()),
-            )?;
+            let rpc = cfg.btc.rpc_client()?;
 
-            let tx_id = rpc.send_raw_transaction(signed_tx.raw_hex())?;
+            let tx_id = rpc
+                .send_raw_transaction(signed_tx.raw_hex())
+                .map_err(CliError::broadcast)?;
             println!("TX ID ->> {}", tx_id);
         } else {
             let runestone = Runestone::decipher(&signed_tx).unwrap();
@@ -397,3 +472,191 @@ impl SendRuneTxCmd {
         Ok(())
     }
 }
+
+/// `address:amount`
+fn parse_recipient(value: &str) -> anyhow::Result<(String, u128)> {
+    let invalid = || CliError::Validation(format!("invalid recipient {}", value));
+    let (address, amount) = value.rsplit_once(':').ok_or_else(invalid)?;
+    let amount: u128 = amount.parse().map_err(|_| invalid())?;
+    if address.is_empty() || amount == 0 {
+        return Err(invalid().into());
+    }
+    Ok((address.to_owned(), amount))
+}
+
+/// sum of the recipients' amounts, fails instead of wrapping
+fn total_amount<A>(recipients: &[(A, u128)]) -> anyhow::Result<u128> {
+    recipients
+        .iter()
+        .try_fold(0u128, |total, (_, amount)| total.checked_add(*amount))
+        .ok_or_else(|| CliError::Validation("the recipients' amounts overflow".to_owned()).into())
+}
+
+/// runestone with one edict per recipient and the outputs following the OP_RETURN: one per
+/// recipient, in order, and the change output the pointer sends the remaining runes to.
+/// Fails if the runestone doesn't fit `max_runestone_size`, too many recipients need several txs.
+fn rune_transfer(
+    rune_id: RuneId,
+    recipients: &[(Address, u128)],
+    runes_in: u128,
+    change: &Address,
+    rune_out_value: u64,
+    max_runestone_size: usize,
+) -> anyhow::Result<(Runestone, Vec<TxOut>)> {
+    let total = total_amount(recipients)?;
+    if runes_in < total {
+        return Err(CliError::InsufficientFunds(format!(
+            "runes_in={} amount_to_send={}",
+            runes_in, total
+        ))
+        .into());
+    }
+
+    let mut edicts = Vec::with_capacity(recipients.len());
+    let mut outputs = Vec::with_capacity(recipients.len() + 1);
+    for (address, amount) in recipients.iter() {
+        let script_pubkey = address.script_pubkey();
+        let value = runes_txs::rune_output_value(rune_out_value, &script_pubkey)?;
+        outputs.push(TxOut {
+            script_pubkey,
+            value,
+        });
+        edicts.push(Edict {
+            id: rune_id,
+            amount: *amount,
+            // the OP_RETURN is the first output
+            output: outputs.len() as u32,
+        });
+    }
+
+    let mut pointer = None;
+    if runes_in > total {
+        let script_pubkey = change.script_pubkey();
+        let value = runes_txs::rune_output_value(rune_out_value, &script_pubkey)?;
+        outputs.push(TxOut {
+            script_pubkey,
+            value,
+        });
+        pointer = Some(outputs.len() as u32);
+    }
+
+    let runestone = Runestone {
+        edicts,
+        etching: None,
+        mint: None,
+        pointer,
+    };
+    check_runestone_size(&runestone.encipher(), max_runestone_size)?;
+    Ok((runestone, outputs))
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn runes_are_sent_to_many_recipients() {
+        use super::{parse_recipient, rune_transfer};
+        use crate::tx::pool_txs::{RunestoneTooLarge, DEFAULT_MAX_RUNESTONE_SIZE};
+        use bitcoin::{Address, Network};
+        use ordinals::{Edict, RuneId};
+        use std::str::FromStr;
+
+        let address = |a: &str| {
+            Address::from_str(a)
+                .unwrap()
+                .require_network(Network::Bitcoin)
+                .unwrap()
+        };
+        let recipients = [
+            "bc1qar0srrr7xfkvy5l643lydnw9re59gtzzwf5mdq:100",
+            "bc1p5d7rjq7g6rdk2yhzks9smlaqtedr4dekq08ge8ztwac72sfr9rusxg3297:2500",
+            "1BvBMSEYstWetqTFn5Au4m4GFg7xJaNVN2:7",
+        ]
+        .iter()
+        .map(|r| {
+            let (addr, amount) = parse_recipient(r).unwrap();
+            (address(&addr), amount)
+        })
+        .collect::<Vec<_>>();
+        let change = address("3J98t1WpEZ73CNmQviecrnyiWrnqRhWNLy");
+        let id = RuneId {
+            block: 840_000,
+            tx: 1,
+        };
+
+        let rune_transfer = |runes_in| {
+            rune_transfer(
+                id,
+                &recipients,
+                runes_in,
+                &change,
+                600,
+                DEFAULT_MAX_RUNESTONE_SIZE,
+            )
+        };
+        let (runestone, outputs) = rune_transfer(3_000).unwrap();
+        assert_eq!(
+            runestone.edicts,
+            vec![
+                Edict {
+                    id,
+                    amount: 100,
+                    output: 1
+                },
+                Edict {
+                    id,
+                    amount: 2500,
+                    output: 2
+                },
+                Edict {
+                    id,
+                    amount: 7,
+                    output: 3
+                },
+            ]
+        );
+        // 393 runes of change
+        assert_eq!(runestone.pointer, Some(4));
+        assert_eq!(outputs.len(), 4);
+        assert_eq!(outputs[1].script_pubkey, recipients[1].0.script_pubkey());
+        assert_eq!(outputs[3].script_pubkey, change.script_pubkey());
+
+        // exact amount, no change output
+        let (runestone, outputs) = rune_transfer(2_607).unwrap();
+        assert_eq!(runestone.pointer, None);
+        assert_eq!(outputs.len(), 3);
+
+        assert!(rune_transfer(2_606).is_err());
+
+        for invalid in [
+            "bc1qar0srrr7xfkvy5l643lydnw9re59gtzzwf5mdq",
+            ":10",
+            "bc1q:0",
+            "bc1q:-1",
+        ] {
+            assert!(parse_recipient(invalid).is_err(), "{}", invalid);
+        }
+
+        // too many edicts for a standard OP_RETURN
+        let many = vec![recipients[0].clone(); 40];
+        let err = super::rune_transfer(id, &many, 4_000, &change, 600, DEFAULT_MAX_RUNESTONE_SIZE)
+            .unwrap_err();
+        assert!(err.downcast_ref::<RunestoneTooLarge>().is_some());
+
+        // amounts summing past u128::MAX are rejected, not wrapped
+        let huge = vec![
+            (recipients[0].0.clone(), u128::MAX),
+            (recipients[1].0.clone(), 2),
+        ];
+        assert!(super::total_amount(&huge).is_err());
+        assert!(super::rune_transfer(
+            id,
+            &huge,
+            u128::MAX,
+            &change,
+            600,
+            DEFAULT_MAX_RUNESTONE_SIZE
+        )
+        .is_err());
+        assert_eq!(super::total_amount(&recipients).unwrap(), 2_607);
+    }
+}
@@ -25,3 +25,57 @@ macro_rules! get_app_info {
         }
     }};
 }
+
+/// `raw` amount with the decimal point of the divisibility and without trailing zeros,
+/// e.g. 150 with divisibility 2 is "1.5". Runes allow up to 38 decimals.
+pub fn format_amount(raw: u128, divisibility: i32) -> String {
+    let divisibility = divisibility.clamp(0, 38) as u32;
+    let unit = 10u128.pow(divisibility);
+    let (int, frac) = (raw / unit, raw % unit);
+    if frac == 0 {
+        return int.to_string();
+    }
+
+    let frac = format!("{:0width$}", frac, width = divisibility as usize);
+    format!("{}.{}", int, frac.trim_end_matches('0'))
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn format_amount() {
+        use super::format_amount;
+
+        assert_eq!(format_amount(0, 0), "0");
+        assert_eq!(format_amount(12345, 0), "12345");
+
+        assert_eq!(format_amount(0, 2), "0");
+        assert_eq!(format_amount(5, 2), "0.05");
+        assert_eq!(format_amount(150, 2), "1.5");
+        assert_eq!(format_amount(100, 2), "1");
+
+        assert_eq!(format_amount(1, 8), "0.00000001");
+        assert_eq!(format_amount(2_100_000_000_000_000, 8), "21000000");
+        assert_eq!(format_amount(123_456_789, 8), "1.23456789");
+
+        assert_eq!(
+            format_amount(1, 38),
+            "0.00000000000000000000000000000000000001"
+        );
+        assert_eq!(
+            format_amount(u128::MAX, 0),
+            "340282366920938463463374607431768211455"
+        );
+        assert_eq!(
+            format_amount(u128::MAX, 38),
+            "3.40282366920938463463374607431768211455"
+        );
+        assert_eq!(
+            format_amount(u128::MAX - 455, 3),
+            "340282366920938463463374607431768211"
+        );
+        // out of range divisibility is clamped
+        assert_eq!(format_amount(150, -1), "150");
+        assert_eq!(format_amount(u128::MAX, 50), format_amount(u128::MAX, 38));
+    }
+}
@@ -0,0 +1,331 @@
+use std::collections::BTreeMap;
+use std::str::FromStr;
+
+use bitcoin::{absolute::LockTime, ScriptBuf, Transaction, TxOut, Txid};
+use bitcoincore_rpc::RpcApi;
+use ordinals::{Artifact, Etching, Rune, Runestone, SpacedRune};
+
+use crate::{cli_error::CliError, db};
+
+#[derive(Debug, clap::Parser)]
+pub struct VerifyRuneCmd {
+    /// rune name, without spacers
+    #[arg(long)]
+    rune: String,
+}
+
+impl VerifyRuneCmd {
+    pub async fn run(&self, config_path: &str) -> anyhow::Result<()> {
+        let mut cfg = crate::config::read_config(config_path)?;
+        cfg.db.automigrate = false;
+        let repo = db::open_postgres_db(cfg.db).await?;
+        let row = repo.get_rune(&self.rune).await?;
+
+        let txid = Txid::from_str(&row.etching_tx)?;
+        let rpc = cfg.btc.rpc_client()?;
+        let tx = rpc
+            .get_raw_transaction(&txid, None)
+            .map_err(|e| CliError::Rpc(e.to_string()))?;
+
+        let Some(Artifact::Runestone(Runestone {
+            etching: Some(etching),
+            ..
+        })) = Runestone::decipher(&tx)
+        else {
+            anyhow::bail!("tx {} doesn't etch a rune", txid);
+        };
+
+        let mismatches = etching_mismatches(&row, &etching);
+        if mismatches.is_empty() {
+            println!("RUNE({}) matches its etching tx {}", row.rune, txid);
+            return Ok(());
+        }
+
+        for m in mismatches.iter() {
+            println!("  {}", m);
+        }
+        anyhow::bail!(
+            "RUNE({}) doesn't match its etching tx {}: {} mismatches",
+            row.rune,
+            txid,
+            mismatches.len()
+        )
+    }
+}
+
+const BATCH_SIZE: i32 = 1000;
+
+#[derive(Debug, clap::Parser)]
+pub struct ReplayLogCmd {
+    /// rune name, without spacers
+    #[arg(long)]
+    rune: String,
+}
+
+impl ReplayLogCmd {
+    pub async fn run(&self, config_path: &str) -> anyhow::Result<()> {
+        let mut cfg = crate::config::read_config(config_path)?;
+        if cfg.indexers.disable_rune_log {
+            anyhow::bail!("the runes log is disabled, there is nothing to replay");
+        }
+        cfg.db.automigrate = false;
+        let repo = db::open_postgres_db(cfg.db).await?;
+        let row = repo.get_rune(&self.rune).await?;
+
+        let replayed = repo.replay_runes_log(&row.rune).await?;
+        let mut balances = Vec::new();
+        loop {
+            let batch = repo
+                .select_runes_balances(&row.rune, BATCH_SIZE, balances.len() as i32)
+                .await?;
+            let done = batch.len() < BATCH_SIZE as usize;
+            balances.extend(batch);
+            if done {
+                break;
+            }
+        }
+
+        let mismatches = balance_mismatches(&replayed, &balances);
+        if mismatches.is_empty() {
+            println!(
+                "RUNE({}) balances of {} addresses match the runes log",
+                row.rune,
+                balances.len()
+            );
+            return Ok(());
+        }
+
+        for m in mismatches.iter() {
+            println!("  {}", m);
+        }
+        anyhow::bail!(
+            "RUNE({}) balances don't match the runes log: {} mismatches",
+            row.rune,
+            mismatches.len()
+        )
+    }
+}
+
+/// differences between the stored balances and the ones replayed from the log,
+/// addresses missing on one side count as a zero balance
+fn balance_mismatches(replayed: &[(String, String)], balances: &[db::RunesBalance]) -> Vec<String> {
+    let mut all: BTreeMap<&str, (&str, &str)> = BTreeMap::new();
+    for (address, total) in replayed.iter() {
+        // addressless burns are already covered by the expences of their inputs
+        if address.is_empty() {
+            continue;
+        }
+        all.entry(address).or_insert(("0", "0")).0 = total;
+    }
+    for b in balances.iter() {
+        all.entry(&b.address).or_insert(("0", "0")).1 = &b.balance;
+    }
+
+    all.into_iter()
+        .filter(|(_, (log, stored))| log != stored)
+        .map(|(address, (log, stored))| format!("{}: stored={} log={}", address, stored, log))
+        .collect()
+}
+
+/// differences between the indexed rune and the etching decoded from the chain
+fn etching_mismatches(row: &db::Rune, etching: &Etching) -> Vec<String> {
+    let mut res = Vec::new();
+    let mut check = |field: &str, stored: String, onchain: String| {
+        if stored != onchain {
+            res.push(format!("{}: stored={} onchain={}", field, stored, onchain));
+        }
+    };
+
+    // etchings without a name get the reserved one of their position
+    let rune = etching
+        .rune
+        .unwrap_or_else(|| Rune::reserved(row.block as u64, row.tx_id as u32));
+    let spaced = SpacedRune {
+        rune,
+        spacers: etching.spacers.unwrap_or_default(),
+    };
+    check("rune", row.rune.clone(), rune.to_string());
+    check("spaced_rune", row.display_name.clone(), spaced.to_string());
+    check(
+        "symbol",
+        row.symbol.clone(),
+        etching.symbol.unwrap_or('¤').to_string(),
+    );
+    check(
+        "premine",
+        row.premine.clone(),
+        etching.premine.unwrap_or_default().to_string(),
+    );
+    check(
+        "divisibility",
+        row.divisibility.to_string(),
+        etching.divisibility.unwrap_or_default().to_string(),
+    );
+    check("turbo", row.turbo.to_string(), etching.turbo.to_string());
+
+    // terms are kept only in the stored runestone
+    match stored_etching(&row.raw_data) {
+        Some(stored) => {
+            check(
+                "terms",
+                format!("{:?}", stored.terms),
+                format!("{:?}", etching.terms),
+            );
+            check(
+                "raw_data",
+                format!("{:?}", stored),
+                format!("{:?}", etching),
+            );
+        }
+        None => res.push("raw_data: stored runestone can't be decoded".to_owned()),
+    }
+
+    res
+}
+
+fn stored_etching(raw_data: &[u8]) -> Option<Etching> {
+    let tx = Transaction {
+        version: 2,
+        lock_time: LockTime::ZERO,
+        input: Vec::new(),
+        output: vec![TxOut {
+            value: 0,
+            script_pubkey: ScriptBuf::from_bytes(raw_data.to_vec()),
+        }],
+    };
+    match Runestone::decipher(&tx) {
+        Some(Artifact::Runestone(runestone)) => runestone.etching,
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn etching_mismatches_are_reported() {
+        use super::etching_mismatches;
+        use crate::db;
+        use ordinals::{Etching, Rune, Runestone, Terms};
+        use std::str::FromStr;
+
+        let etching = Etching {
+            rune: Some(Rune::from_str("UNCOMMONGOODS").unwrap()),
+            spacers: Some(0b1000_0000),
+            symbol: Some('⧉'),
+            premine: Some(1_000),
+            divisibility: Some(2),
+            terms: Some(Terms {
+                amount: Some(1),
+                cap: Some(100),
+                ..Default::default()
+            }),
+            turbo: true,
+        };
+        let runestone = Runestone {
+            etching: Some(etching),
+            ..Default::default()
+        };
+        let row = db::Rune {
+            rune: "UNCOMMONGOODS".to_owned(),
+            display_name: "UNCOMMON•GOODS".to_owned(),
+            symbol: "⧉".to_owned(),
+            premine: "1000".to_owned(),
+            divisibility: 2,
+            turbo: true,
+            raw_data: runestone.encipher().into_bytes(),
+            ..Default::default()
+        };
+        assert!(etching_mismatches(&row, &etching).is_empty());
+
+        let corrupted = db::Rune {
+            premine: "999".to_owned(),
+            divisibility: 0,
+            ..row.clone()
+        };
+        let mismatches = etching_mismatches(&corrupted, &etching);
+        assert_eq!(mismatches.len(), 2);
+        assert_eq!(mismatches[0], "premine: stored=999 onchain=1000");
+        assert_eq!(mismatches[1], "divisibility: stored=0 onchain=2");
+
+        // terms are compared with the stored runestone
+        let onchain = Etching {
+            terms: Some(Terms {
+                amount: Some(1),
+                cap: Some(200),
+                ..Default::default()
+            }),
+            ..etching
+        };
+        let mismatches = etching_mismatches(&row, &onchain);
+        assert_eq!(mismatches.len(), 2);
+        assert!(mismatches[0].starts_with("terms:"));
+        assert!(mismatches[1].starts_with("raw_data:"));
+
+        let broken = db::Rune {
+            raw_data: vec![0x6a],
+            ..row.clone()
+        };
+        assert_eq!(
+            etching_mismatches(&broken, &etching),
+            vec!["raw_data: stored runestone can't be decoded"]
+        );
+
+        // unnamed etchings are stored under the reserved name of the etching tx
+        let unnamed = Etching {
+            rune: None,
+            spacers: None,
+            ..etching
+        };
+        let reserved = Rune::reserved(840_000, 7).to_string();
+        let row = db::Rune {
+            rune: reserved.clone(),
+            display_name: reserved.clone(),
+            block: 840_000,
+            tx_id: 7,
+            raw_data: Runestone {
+                etching: Some(unnamed),
+                ..Default::default()
+            }
+            .encipher()
+            .into_bytes(),
+            ..row
+        };
+        assert!(etching_mismatches(&row, &unnamed).is_empty());
+
+        let other_tx = db::Rune { tx_id: 8, ..row };
+        let mismatches = etching_mismatches(&other_tx, &unnamed);
+        assert_eq!(mismatches.len(), 2);
+        assert!(mismatches[0].starts_with("rune:"));
+        assert!(mismatches[1].starts_with("spaced_rune:"));
+    }
+
+    #[test]
+    fn replayed_balances_are_compared() {
+        use super::balance_mismatches;
+        use crate::db;
+
+        let balance = |address: &str, balance: &str| db::RunesBalance {
+            address: address.to_owned(),
+            rune: "UNCOMMONGOODS".to_owned(),
+            balance: balance.to_owned(),
+            ..Default::default()
+        };
+        let replayed = vec![
+            ("".to_owned(), "-50".to_owned()),
+            ("bc1pa".to_owned(), "100".to_owned()),
+            ("bc1pb".to_owned(), "0".to_owned()),
+        ];
+        let balances = vec![balance("bc1pa", "100")];
+        assert!(balance_mismatches(&replayed, &balances).is_empty());
+
+        let balances = vec![
+            balance("bc1pa", "90"),
+            balance("bc1pb", "0"),
+            balance("bc1pc", "5"),
+        ];
+        assert_eq!(
+            balance_mismatches(&replayed, &balances),
+            vec!["bc1pa: stored=90 log=100", "bc1pc: stored=5 log=0"]
+        );
+    }
+}